@@ -1,7 +1,48 @@
-use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId, black_box};
-use PDFcompressor::{compress_pdf_bytes, compress_image_bytes};
+use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId, Throughput, black_box};
+use PDFcompressor::{compress_pdf_bytes, compress_pdf_with_settings, compress_image_bytes, compress_image_with_settings, CompressionSettings, FlateLevel, ImagePolicy, ResampleFilter};
+use PDFcompressor::api::{create_router_with_state, AppState};
+use axum::body::Body;
+use axum::http::Request;
 use lopdf::{Document, Object, Stream, Dictionary};
+use tower::ServiceExt;
 use image::{RgbImage, DynamicImage};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Counts live heap bytes so benchmarks can report peak memory alongside time
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let now = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK_ALLOCATED.fetch_max(now, Ordering::Relaxed);
+        }
+        ptr
+    }
+    
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+    
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            let now = ALLOCATED.fetch_add(new_size, Ordering::Relaxed) + new_size;
+            PEAK_ALLOCATED.fetch_max(now, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
 
 // Helper to generate test PDF
 fn generate_test_pdf() -> Vec<u8> {
@@ -80,6 +121,255 @@ fn generate_test_pdf() -> Vec<u8> {
     output
 }
 
+// Helper to generate a PDF with one large uncompressed content stream
+fn generate_large_stream_pdf(repeats: usize) -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    
+    let pages_id = doc.new_object_id();
+    let page_id = doc.new_object_id();
+    
+    let catalog_id = doc.add_object(
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Catalog".to_vec())),
+            ("Pages", Object::Reference(pages_id)),
+        ])
+    );
+    
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    let mut content = Vec::new();
+    for i in 0..repeats {
+        content.extend_from_slice(
+            format!("{} {} m {} {} l S\n", i % 612, i % 792, (i * 7) % 612, (i * 13) % 792).as_bytes()
+        );
+    }
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), content));
+    
+    doc.objects.insert(
+        page_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Page".to_vec())),
+            ("Parent", Object::Reference(pages_id)),
+            ("MediaBox", Object::Array(vec![
+                Object::Integer(0),
+                Object::Integer(0),
+                Object::Integer(612),
+                Object::Integer(792),
+            ])),
+            ("Contents", Object::Reference(content_id)),
+        ]).into()
+    );
+    
+    doc.objects.insert(
+        pages_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(vec![Object::Reference(page_id)])),
+            ("Count", Object::Integer(1)),
+        ]).into()
+    );
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).unwrap();
+    output
+}
+
+// Helper to generate a PDF whose pages each have an already flate-compressed content stream
+fn generate_flate_streams_pdf(pages: usize) -> Vec<u8> {
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+    
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    
+    let catalog_id = doc.add_object(
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Catalog".to_vec())),
+            ("Pages", Object::Reference(pages_id)),
+        ])
+    );
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    let mut kids = Vec::with_capacity(pages);
+    for page in 0..pages {
+        let mut content = Vec::new();
+        for i in 0..2_000 {
+            content.extend_from_slice(
+                format!("{} {} m {} {} l S\n", (i + page) % 612, i % 792, (i * 7) % 612, (i * 13 + page) % 792).as_bytes()
+            );
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&content).unwrap();
+        let mut dict = Dictionary::new();
+        dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+        let content_id = doc.add_object(Stream::new(dict, encoder.finish().unwrap()));
+        
+        let page_id = doc.add_object(Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Page".to_vec())),
+            ("Parent", Object::Reference(pages_id)),
+            ("MediaBox", Object::Array(vec![
+                Object::Integer(0),
+                Object::Integer(0),
+                Object::Integer(612),
+                Object::Integer(792),
+            ])),
+            ("Contents", Object::Reference(content_id)),
+        ]));
+        kids.push(Object::Reference(page_id));
+    }
+    
+    doc.objects.insert(
+        pages_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Count", Object::Integer(pages as i64)),
+            ("Kids", Object::Array(kids)),
+        ]).into()
+    );
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).unwrap();
+    output
+}
+
+// Helper to generate a one-page PDF holding several large raw RGB image XObjects
+fn generate_many_images_pdf(images: usize, edge: u32) -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    
+    let catalog_id = doc.add_object(
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Catalog".to_vec())),
+            ("Pages", Object::Reference(pages_id)),
+        ])
+    );
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    let mut xobjects = Dictionary::new();
+    let mut content = Vec::new();
+    for index in 0..images {
+        let pixels = RgbImage::from_fn(edge, edge, |x, y| {
+            image::Rgb([(x + index as u32) as u8, y as u8, ((x * y) >> 5) as u8])
+        });
+        let image_id = doc.add_object(Stream::new(
+            Dictionary::from_iter(vec![
+                ("Type", Object::Name(b"XObject".to_vec())),
+                ("Subtype", Object::Name(b"Image".to_vec())),
+                ("Width", Object::Integer(edge as i64)),
+                ("Height", Object::Integer(edge as i64)),
+                ("ColorSpace", Object::Name(b"DeviceRGB".to_vec())),
+                ("BitsPerComponent", Object::Integer(8)),
+            ]),
+            pixels.into_raw(),
+        ));
+        let name = format!("Im{}", index);
+        xobjects.set(name.clone(), Object::Reference(image_id));
+        content.extend_from_slice(format!("q 50 0 0 50 {} 700 cm /{} Do Q\n", 10 + index * 50, name).as_bytes());
+    }
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), content));
+    
+    let page_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Page".to_vec())),
+        ("Parent", Object::Reference(pages_id)),
+        ("Resources", Dictionary::from_iter(vec![("XObject", Object::Dictionary(xobjects))]).into()),
+        ("MediaBox", Object::Array(vec![
+            Object::Integer(0),
+            Object::Integer(0),
+            Object::Integer(612),
+            Object::Integer(792),
+        ])),
+        ("Contents", Object::Reference(content_id)),
+    ]));
+    
+    doc.objects.insert(
+        pages_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(vec![Object::Reference(page_id)])),
+            ("Count", Object::Integer(1)),
+        ]).into()
+    );
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).unwrap();
+    output
+}
+
+// Helper to generate a PDF with `streams` large flate-filtered image streams of noise, every
+// third one a copy of the stream before it, all drawn on a single page
+fn generate_duplicate_streams_pdf(streams: usize, bytes: usize) -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    
+    let catalog_id = doc.add_object(
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Catalog".to_vec())),
+            ("Pages", Object::Reference(pages_id)),
+        ])
+    );
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    let mut seed = 0x9E37_79B9u32;
+    let mut previous: Vec<u8> = Vec::new();
+    let mut xobjects = Dictionary::new();
+    let mut content = Vec::new();
+    for index in 0..streams {
+        let data = if index % 3 == 2 {
+            previous.clone()
+        } else {
+            (0..bytes).map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            }).collect()
+        };
+        previous = data.clone();
+        let image_id = doc.add_object(Stream::new(
+            Dictionary::from_iter(vec![
+                ("Type", Object::Name(b"XObject".to_vec())),
+                ("Subtype", Object::Name(b"Image".to_vec())),
+                ("Width", Object::Integer(1)),
+                ("Height", Object::Integer(1)),
+                ("ColorSpace", Object::Name(b"DeviceGray".to_vec())),
+                ("BitsPerComponent", Object::Integer(8)),
+                ("Filter", Object::Name(b"FlateDecode".to_vec())),
+            ]),
+            data,
+        ));
+        let name = format!("Im{}", index);
+        xobjects.set(name.clone(), Object::Reference(image_id));
+        content.extend_from_slice(format!("q 1 0 0 1 0 0 cm /{} Do Q\n", name).as_bytes());
+    }
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), content));
+    
+    let page_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Page".to_vec())),
+        ("Parent", Object::Reference(pages_id)),
+        ("Resources", Dictionary::from_iter(vec![("XObject", Object::Dictionary(xobjects))]).into()),
+        ("MediaBox", Object::Array(vec![
+            Object::Integer(0),
+            Object::Integer(0),
+            Object::Integer(612),
+            Object::Integer(792),
+        ])),
+        ("Contents", Object::Reference(content_id)),
+    ]));
+    
+    doc.objects.insert(
+        pages_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(vec![Object::Reference(page_id)])),
+            ("Count", Object::Integer(1)),
+        ]).into()
+    );
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).unwrap();
+    output
+}
+
 // Helper to generate test image
 fn generate_test_image(width: u32, height: u32) -> Vec<u8> {
     let mut img = RgbImage::new(width, height);
@@ -124,22 +414,123 @@ fn benchmark_pdf_compression_quality_levels(c: &mut Criterion) {
 
 fn benchmark_pdf_compression_sizes(c: &mut Criterion) {
     let mut group = c.benchmark_group("pdf_compression_sizes");
+    group.sample_size(10);
     
-    // Benchmark different PDF sizes (simulated by multiple operations)
-    for size_multiplier in [1, 2, 4].iter() {
-        let pdf_data = generate_test_pdf();
+    // Size classes double the number of embedded 400x400 images, so the input grows with the multiplier
+    for size_multiplier in [1, 2, 4, 8].iter() {
+        let pdf_data = generate_many_images_pdf(*size_multiplier, 400);
+        group.throughput(Throughput::Bytes(pdf_data.len() as u64));
         
         group.bench_with_input(
-            BenchmarkId::from_parameter(size_multiplier),
-            size_multiplier,
-            |b, _| {
+            BenchmarkId::new("images", size_multiplier),
+            &pdf_data,
+            |b, data| {
                 b.iter(|| {
-                    compress_pdf_bytes(black_box(&pdf_data), black_box(75))
+                    compress_pdf_bytes(black_box(data), black_box(75))
                 });
             },
         );
     }
     
+    // One scan-sized image, where decoding and downsampling dominate rather than per-object work
+    let pdf_data = generate_many_images_pdf(1, 3000);
+    group.throughput(Throughput::Bytes(pdf_data.len() as u64));
+    group.bench_with_input(
+        BenchmarkId::from_parameter("large_image"),
+        &pdf_data,
+        |b, data| {
+            b.iter(|| {
+                compress_pdf_bytes(black_box(data), black_box(75))
+            });
+        },
+    );
+    
+    group.finish();
+}
+
+// Compare flate backends by running once with default features and once with
+// `--features zlib-ng`; the group name records which backend was compiled in
+fn benchmark_flate_levels(c: &mut Criterion) {
+    let pdf_data = generate_large_stream_pdf(200_000);
+    let mut group = c.benchmark_group(format!("flate_levels_{}", PDFcompressor::flate_backend()));
+    group.sample_size(10);
+    
+    for (name, flate_level) in [
+        ("fast", FlateLevel::Fast),
+        ("default", FlateLevel::Default),
+        ("best", FlateLevel::Best),
+    ] {
+        let settings = CompressionSettings { flate_level, ..Default::default() };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                compress_pdf_with_settings(black_box(&pdf_data), black_box(&settings))
+            });
+        });
+    }
+    
+    group.finish();
+}
+
+// Skipping streams that are already FlateDecode avoids an inflate + best-effort deflate per stream
+fn benchmark_existing_flate(c: &mut Criterion) {
+    let pdf_data = generate_flate_streams_pdf(200);
+    let mut group = c.benchmark_group("existing_flate_streams");
+    group.sample_size(10);
+    
+    for (name, recompress_existing_flate) in [("skip", false), ("recompress", true)] {
+        let settings = CompressionSettings { recompress_existing_flate, ..Default::default() };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                compress_pdf_with_settings(black_box(&pdf_data), black_box(&settings))
+            });
+        });
+    }
+    
+    group.finish();
+}
+
+// Sequential low-memory mode vs the default parallel pass; peak heap use is printed per mode
+fn benchmark_low_memory(c: &mut Criterion) {
+    let pdf_data = generate_many_images_pdf(12, 1000);
+    let mut group = c.benchmark_group("low_memory");
+    group.sample_size(10);
+    
+    for (name, low_memory) in [("parallel", false), ("low_memory", true)] {
+        let settings = CompressionSettings { low_memory, ..Default::default() };
+        
+        let baseline = ALLOCATED.load(Ordering::Relaxed);
+        PEAK_ALLOCATED.store(baseline, Ordering::Relaxed);
+        let output = compress_pdf_with_settings(&pdf_data, &settings).unwrap();
+        let peak = PEAK_ALLOCATED.load(Ordering::Relaxed) - baseline;
+        println!("{}: peak heap above baseline {:.1} MiB ({} -> {} bytes)",
+                 name, peak as f64 / (1024.0 * 1024.0), pdf_data.len(), output.len());
+        
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                compress_pdf_with_settings(black_box(&pdf_data), black_box(&settings))
+            });
+        });
+    }
+    
+    group.finish();
+}
+
+// Parallel vs serial content hashing in duplicate removal; images are kept as they are, so
+// dedup is the only per-stream work between loading and saving
+fn benchmark_dedup_hashing(c: &mut Criterion) {
+    let pdf_data = generate_duplicate_streams_pdf(300, 512 * 1024);
+    let mut group = c.benchmark_group("dedup_hashing");
+    group.sample_size(10);
+    
+    for (name, low_memory) in [("parallel", false), ("serial", true)] {
+        let settings = CompressionSettings { image_policy: ImagePolicy::Keep, low_memory, rounds: Some(1), ..Default::default() };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                compress_pdf_with_settings(black_box(&pdf_data), black_box(&settings))
+            });
+        });
+    }
+    
     group.finish();
 }
 
@@ -166,6 +557,72 @@ fn benchmark_image_compression_formats(c: &mut Criterion) {
     group.finish();
 }
 
+// Auto-format tries JPEG and PNG; low_memory runs the two trials one after the other
+fn benchmark_auto_format_trials(c: &mut Criterion) {
+    let mut png_data = Vec::new();
+    DynamicImage::ImageRgb8(RgbImage::from_fn(2400, 1800, |x, y| image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x * y) % 256) as u8])))
+        .write_to(&mut std::io::Cursor::new(&mut png_data), image::ImageFormat::Png)
+        .unwrap();
+    let mut group = c.benchmark_group("auto_format_trials");
+    group.sample_size(10);
+    
+    for (name, low_memory) in [("parallel", false), ("serial", true)] {
+        let settings = CompressionSettings { low_memory, ..Default::default() };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                compress_image_with_settings(black_box(&png_data), None, black_box(&settings))
+            });
+        });
+    }
+    
+    group.finish();
+}
+
+// Compare JPEG encoders by running once with default features and once with
+// `--features mozjpeg`; output sizes are printed so the two runs can be compared
+fn benchmark_jpeg_encoders(c: &mut Criterion) {
+    let jpeg_data = generate_test_image(1200, 900);
+    let mut group = c.benchmark_group(format!("jpeg_encoder_{}", PDFcompressor::jpeg_backend()));
+    group.sample_size(10);
+    
+    for quality in [50, 75, 90] {
+        let settings = CompressionSettings { quality, ..Default::default() };
+        let (output, _) = compress_image_with_settings(&jpeg_data, Some("jpg"), &settings).unwrap();
+        println!("{} quality {}: {} -> {} bytes",
+                 PDFcompressor::jpeg_backend(), quality, jpeg_data.len(), output.len());
+        
+        group.bench_with_input(BenchmarkId::from_parameter(quality), &settings, |b, settings| {
+            b.iter(|| {
+                compress_image_with_settings(black_box(&jpeg_data), Some("jpg"), black_box(settings))
+            });
+        });
+    }
+    
+    group.finish();
+}
+
+fn benchmark_resample_filters(c: &mut Criterion) {
+    let image_data = generate_test_image(2400, 1800);
+    let mut group = c.benchmark_group("resample_filters");
+    group.sample_size(10);
+    
+    for (name, resample_filter) in [
+        ("nearest", ResampleFilter::Nearest),
+        ("triangle", ResampleFilter::Triangle),
+        ("catmullrom", ResampleFilter::CatmullRom),
+        ("lanczos3", ResampleFilter::Lanczos3),
+    ] {
+        let settings = CompressionSettings { resample_filter, max_dimension: Some(800), ..Default::default() };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                compress_image_with_settings(black_box(&image_data), Some("jpg"), black_box(&settings))
+            });
+        });
+    }
+    
+    group.finish();
+}
+
 fn benchmark_image_compression_sizes(c: &mut Criterion) {
     let mut group = c.benchmark_group("image_compression_sizes");
     
@@ -252,6 +709,63 @@ fn benchmark_batch_compression(c: &mut Criterion) {
     group.finish();
 }
 
+// ============================================================================
+// HTTP API Benchmarks
+// ============================================================================
+
+// Multipart upload of one file with a compression level, as clients send it
+fn compress_request(file_name: &str, data: &[u8]) -> Request<Body> {
+    let boundary = "bench-boundary";
+    let mut body = Vec::new();
+    body.extend_from_slice(format!(
+        "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{file_name}\"\r\n\r\n"
+    ).as_bytes());
+    body.extend_from_slice(data);
+    body.extend_from_slice(format!(
+        "\r\n--{boundary}\r\nContent-Disposition: form-data; name=\"level\"\r\n\r\n75\r\n--{boundary}--\r\n"
+    ).as_bytes());
+    
+    Request::builder()
+        .method("POST")
+        .uri("/api/compress")
+        .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+        .body(Body::from(body))
+        .unwrap()
+}
+
+// Full request latency through the router: multipart parsing, spawn_blocking, response building
+// "cold" starts every request with an empty cache, "warm" answers from a primed one
+fn benchmark_http_handler(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let pdf_data = generate_large_stream_pdf(5000);
+    let image_data = generate_test_image(800, 600);
+    
+    let mut group = c.benchmark_group("http_compress");
+    group.sample_size(20);
+    
+    for (kind, file_name, data) in [("pdf", "doc.pdf", &pdf_data), ("image", "photo.jpg", &image_data)] {
+        group.bench_function(format!("{}_cold", kind), |b| {
+            b.iter(|| runtime.block_on(async {
+                let router = create_router_with_state(AppState::new(4).with_cache(64 * 1024 * 1024));
+                let response = router.oneshot(compress_request(file_name, black_box(data))).await.unwrap();
+                axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            }));
+        });
+        
+        let router = create_router_with_state(AppState::new(4).with_cache(64 * 1024 * 1024));
+        let primed = runtime.block_on(router.clone().oneshot(compress_request(file_name, data))).unwrap();
+        assert!(primed.status().is_success(), "{} upload failed: {}", kind, primed.status());
+        group.bench_function(format!("{}_warm", kind), |b| {
+            b.iter(|| runtime.block_on(async {
+                let response = router.clone().oneshot(compress_request(file_name, black_box(data))).await.unwrap();
+                axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            }));
+        });
+    }
+    
+    group.finish();
+}
+
 // ============================================================================
 // Benchmark Groups
 // ============================================================================
@@ -259,12 +773,19 @@ fn benchmark_batch_compression(c: &mut Criterion) {
 criterion_group!(
     pdf_benches,
     benchmark_pdf_compression_quality_levels,
-    benchmark_pdf_compression_sizes
+    benchmark_pdf_compression_sizes,
+    benchmark_flate_levels,
+    benchmark_existing_flate,
+    benchmark_low_memory,
+    benchmark_dedup_hashing
 );
 
 criterion_group!(
     image_benches,
     benchmark_image_compression_formats,
+    benchmark_auto_format_trials,
+    benchmark_jpeg_encoders,
+    benchmark_resample_filters,
     benchmark_image_compression_sizes,
     benchmark_image_compression_quality_levels
 );
@@ -275,5 +796,10 @@ criterion_group!(
     benchmark_batch_compression
 );
 
-criterion_main!(pdf_benches, image_benches, combined_benches);
+criterion_group!(
+    http_benches,
+    benchmark_http_handler
+);
+
+criterion_main!(pdf_benches, image_benches, combined_benches, http_benches);
 
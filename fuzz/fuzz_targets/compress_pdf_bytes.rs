@@ -0,0 +1,9 @@
+// Random bytes must come back as Err, never as a panic or a hang
+// Run with: cargo +nightly fuzz run compress_pdf_bytes -- -timeout=30
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = PDFcompressor::compress_pdf_bytes(data, 50);
+});
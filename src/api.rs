@@ -1,53 +1,274 @@
 use axum::{
-    extract::{DefaultBodyLimit, Multipart, Request},
-    http::{StatusCode, HeaderMap},
+    extract::{multipart::Field, DefaultBodyLimit, Extension, Multipart, Request, State},
+    http::{StatusCode, HeaderMap, HeaderValue},
     response::{IntoResponse, Response},
     routing::post,
     Router, Json,
     middleware::{self, Next},
 };
 use serde::Serialize;
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::{CorsLayer, Any};
+use std::future::Future;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use log::{info, error, warn};
 
+mod auth;
+mod cache;
+mod request_id;
+
+pub use auth::{ApiKeyIdentity, ApiKeys};
+use auth::KeyCheck;
+pub use cache::ResultCache;
+use cache::{CacheKey, CachedResult};
+pub use request_id::{current_request_id, RequestId, REQUEST_ID_HEADER};
+use request_id::with_request_id;
+
 #[derive(Debug, Serialize)]
 struct ErrorResponse {
     error: String,
 }
 
+/// Compressed bytes with their content type and file extension, or why compression failed
+type CompressionOutcome = Result<(Vec<u8>, &'static str, String), String>;
+
+/// How long shutdown waits for running compression jobs when SHUTDOWN_TIMEOUT_SECS is unset
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest request body accepted unless the state sets another limit
+const DEFAULT_BODY_LIMIT: usize = 100 * 1024 * 1024;
+
+/// Largest accepted value of a non-file multipart field
+const MAX_TEXT_FIELD_BYTES: usize = 1024;
+
+/// Default cap on concurrent compression jobs when MAX_CONCURRENT_JOBS is unset
+fn default_max_jobs() -> usize {
+    num_cpus::get() * 2
+}
+
+/// Shared state for API handlers
+#[derive(Clone)]
+pub struct AppState {
+    /// Permits for in-flight compression jobs; requests beyond the limit get 503
+    pub job_permits: Arc<Semaphore>,
+    /// Compressed outputs of recent uploads, if caching is enabled
+    pub cache: Option<Arc<ResultCache>>,
+    /// Accepted API keys; None leaves the API unprotected
+    pub api_keys: Option<Arc<ApiKeys>>,
+    /// Set once graceful shutdown starts; /health then answers 503
+    pub shutting_down: Arc<AtomicBool>,
+    /// Largest request body in bytes; larger uploads get 413
+    pub body_limit: usize,
+    /// Threads each PDF compression may use; None shares the global rayon pool
+    pub threads_per_job: Option<usize>,
+    /// Compression rounds for each PDF; None uses the library default
+    pub compression_rounds: Option<u32>,
+    /// Largest file in bytes that is compressed; larger ones get 413 before any decoding
+    /// Unlike body_limit this caps compression work, not the upload itself
+    pub max_input_bytes: Option<u64>,
+    /// Number of job permits, so shutdown can wait for all of them to come back
+    max_jobs: usize,
+}
+
+impl AppState {
+    pub fn new(max_concurrent_jobs: usize) -> Self {
+        Self {
+            job_permits: Arc::new(Semaphore::new(max_concurrent_jobs.max(1))),
+            cache: None,
+            api_keys: None,
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            body_limit: DEFAULT_BODY_LIMIT,
+            threads_per_job: None,
+            compression_rounds: None,
+            max_input_bytes: None,
+            max_jobs: max_concurrent_jobs.max(1),
+        }
+    }
+    
+    /// Whether graceful shutdown has started
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+    
+    /// Files of one batch compressed at once: half the job slots, so other requests still get the rest
+    pub fn batch_concurrency(&self) -> usize {
+        (self.max_jobs / 2).max(1)
+    }
+    
+    /// Wait until every compression job has released its permit, or `timeout` passes
+    /// Returns false if jobs were still running at the deadline
+    pub async fn drain_jobs(&self, timeout: Duration) -> bool {
+        let all = u32::try_from(self.max_jobs).unwrap_or(u32::MAX);
+        matches!(tokio::time::timeout(timeout, self.job_permits.acquire_many(all)).await, Ok(Ok(_)))
+    }
+    
+    /// Enable the result cache, bounded to `max_bytes` of compressed output
+    pub fn with_cache(mut self, max_bytes: usize) -> Self {
+        self.cache = Some(Arc::new(ResultCache::new(max_bytes)));
+        self
+    }
+    
+    /// Require one of these API keys on protected endpoints
+    pub fn with_api_keys(mut self, keys: ApiKeys) -> Self {
+        self.api_keys = Some(Arc::new(keys));
+        self
+    }
+    
+    /// Reject request bodies larger than `max_bytes`
+    pub fn with_body_limit(mut self, max_bytes: usize) -> Self {
+        self.body_limit = max_bytes;
+        self
+    }
+    
+    /// Cap the threads each PDF compression runs its streams on
+    pub fn with_threads_per_job(mut self, threads: usize) -> Self {
+        self.threads_per_job = Some(threads.max(1));
+        self
+    }
+    
+    /// Run this many compression rounds on each PDF instead of the library default
+    pub fn with_compression_rounds(mut self, rounds: u32) -> Self {
+        self.compression_rounds = Some(rounds);
+        self
+    }
+    
+    /// Refuse to compress files larger than `max_bytes`, even when the upload itself is allowed
+    pub fn with_max_input_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_input_bytes = Some(max_bytes);
+        self
+    }
+    
+    /// Build state from environment variables (MAX_CONCURRENT_JOBS, THREADS_PER_JOB, PDF_COMPRESSION_ROUNDS, MAX_INPUT_MB, CACHE_MAX_MB, API_KEYS/API_KEY)
+    pub fn from_env() -> Self {
+        let max_jobs = std::env::var("MAX_CONCURRENT_JOBS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or_else(default_max_jobs);
+        let mut state = Self::new(max_jobs);
+        state.api_keys = ApiKeys::from_env().map(Arc::new);
+        state.threads_per_job = std::env::var("THREADS_PER_JOB")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|&threads| threads > 0);
+        state.compression_rounds = crate::env_compression_rounds();
+        state.max_input_bytes = std::env::var("MAX_INPUT_MB")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|&mb| mb > 0)
+            .map(|mb| mb * 1024 * 1024);
+        
+        // Caching stays off unless a positive size is configured
+        match std::env::var("CACHE_MAX_MB").ok().and_then(|v| v.parse::<usize>().ok()) {
+            Some(mb) if mb > 0 => state.with_cache(mb * 1024 * 1024),
+            _ => state,
+        }
+    }
+}
+
 /// Create the router for the API server (exposed for testing)
 pub fn create_router() -> Router {
-    Router::new()
+    create_router_with_state(AppState::from_env())
+}
+
+/// Create the router with explicit state (exposed for testing)
+pub fn create_router_with_state(state: AppState) -> Router {
+    let router = Router::new();
+    #[cfg(feature = "pdf-render")]
+    let router = router.route("/api/pdf-to-images", post(pdf_to_images));
+    router
         .route("/api/compress", post(compress_file))
         .route("/api/pdf", post(compress_file)) // Legacy alias
+        .route("/api/images-to-pdf", post(images_to_pdf))
+        .route("/api/merge", post(merge_pdfs))
+        .route("/api/compress-batch", post(compress_batch))
+        .route("/api/extract-images", post(extract_images))
+        .route("/api/validate", post(validate_file))
+        .route("/api/formats", axum::routing::get(formats))
         .route("/health", axum::routing::get(health_check))
         .route("/llm.txt", axum::routing::get(llm_docs))
-        .layer(middleware::from_fn(auth_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
         .layer(CorsLayer::new().allow_origin(Any).allow_methods(Any).allow_headers(Any))
-        .layer(DefaultBodyLimit::max(100 * 1024 * 1024)) // 100 MB max
+        .layer(DefaultBodyLimit::max(state.body_limit))
+        .layer(middleware::from_fn(json_error_bodies))
+        .layer(response_compression())
+        .layer(middleware::from_fn(request_id::propagate_request_id))
+        .with_state(state)
+}
+
+/// Give every error response the `{"error": ...}` shape handlers use
+/// Rejections from axum and tower (413, 404, 405, ...) come as plain text or an empty body;
+/// their text, or the status reason, becomes the error message. Other headers are kept
+async fn json_error_bodies(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    let is_json = response.headers()
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !(status.is_client_error() || status.is_server_error()) || is_json {
+        return response;
+    }
+    
+    let (mut parts, body) = response.into_parts();
+    // Rejection texts are short; anything bigger isn't worth echoing back
+    let text = axum::body::to_bytes(body, 64 * 1024).await
+        .map(|bytes| String::from_utf8_lossy(&bytes).trim().to_string())
+        .unwrap_or_default();
+    let error = if text.is_empty() {
+        status.canonical_reason().unwrap_or("Request failed").to_string()
+    } else {
+        text
+    };
+    parts.headers.remove(axum::http::header::CONTENT_TYPE);
+    parts.headers.remove(axum::http::header::CONTENT_LENGTH);
+    (parts, Json(ErrorResponse { error })).into_response()
+}
+
+/// gzip/br for JSON and text responses when the client accepts it
+/// PDFs and images are already compressed, so file downloads are sent as they are
+fn response_compression() -> CompressionLayer<impl Predicate> {
+    let predicate = DefaultPredicate::new()
+        .and(NotForContentType::const_new("application/pdf"))
+        .and(NotForContentType::const_new("application/octet-stream"));
+    CompressionLayer::new().compress_when(predicate)
 }
 
 pub async fn run_server() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize logger
+    // Lines logged while a request is handled carry its X-Request-Id
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .format_timestamp_millis()
+        .format(|buf, record| {
+            use std::io::Write;
+            let request_id = current_request_id().map(|id| format!(" request_id={}", id)).unwrap_or_default();
+            writeln!(buf, "[{} {:<5} {}{}] {}", buf.timestamp_millis(), record.level(), record.target(), request_id, record.args())
+        })
         .init();
     
     info!("PDF Compressor API starting...");
     
-    // Check if API key is configured
-    let api_key = std::env::var("API_KEY").ok();
-    if let Some(ref key) = api_key {
-        info!("🔐 API Key authentication enabled");
-        info!("   Key: {}***", &key.chars().take(8).collect::<String>());
+    // Build application with routes
+    let state = AppState::from_env();
+    
+    // Check if API keys are configured
+    if let Some(ref keys) = state.api_keys {
+        info!("🔐 API Key authentication enabled ({} key(s))", keys.len());
     } else {
         warn!("⚠️  No API_KEY set - API is unprotected!");
-        warn!("   Set API_KEY environment variable to enable authentication");
+        warn!("   Set API_KEY (or API_KEYS for several keys) to enable authentication");
     }
     
-    // Build application with routes
-    let app = create_router();
+    info!("Max concurrent compression jobs: {}", state.job_permits.available_permits());
+    
+    let drain_timeout = std::env::var("SHUTDOWN_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
     
     // Bind to 0.0.0.0:3000 for container deployment
     let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
@@ -57,35 +278,97 @@ pub async fn run_server() -> Result<(), Box<dyn std::error::Error>> {
     info!("Endpoints:");
     info!("  POST /api/compress - Compress PDF or Image (multipart/form-data) [Protected]");
     info!("  POST /api/pdf     - Legacy alias for /api/compress [Protected]");
+    info!("  POST /api/images-to-pdf - Combine images into one PDF [Protected]");
+    info!("  POST /api/merge   - Merge PDFs into one and compress [Protected]");
+    info!("  POST /api/compress-batch - Compress several files concurrently into a zip [Protected]");
+    info!("  POST /api/extract-images - Extract a PDF's images into a zip [Protected]");
+    info!("  POST /api/validate - Check an upload can be compressed, without compressing it [Protected]");
+    info!("  GET  /api/formats - Supported input/output formats and features [Public]");
     info!("  GET  /health      - Health check [Public]");
     info!("  GET  /llm.txt     - LLM-optimized API documentation [Public]");
     
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    serve_with_shutdown(listener, state, shutdown_signal(), drain_timeout).await?;
+    
+    Ok(())
+}
+
+/// Serve the API until `signal` resolves, then shut down gracefully
+/// New connections are refused and /health answers 503 while open requests finish; compression
+/// jobs whose clients already went away get up to `drain_timeout` before this returns
+pub async fn serve_with_shutdown(
+    listener: tokio::net::TcpListener,
+    state: AppState,
+    signal: impl Future<Output = ()> + Send + 'static,
+    drain_timeout: Duration,
+) -> std::io::Result<()> {
+    let app = create_router_with_state(state.clone());
+    let flag = state.shutting_down.clone();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            signal.await;
+            info!("Shutdown requested, no longer accepting connections");
+            flag.store(true, Ordering::SeqCst);
+        })
+        .await?;
     
+    info!("Waiting up to {:?} for in-flight compression jobs", drain_timeout);
+    if state.drain_jobs(drain_timeout).await {
+        info!("All compression jobs finished, exiting");
+    } else {
+        warn!("Compression jobs still running after {:?}, exiting anyway", drain_timeout);
+    }
     Ok(())
 }
 
+/// Resolves on Ctrl-C, or on SIGTERM where signals exist
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
 // Authentication middleware
 async fn auth_middleware(
+    State(state): State<AppState>,
     headers: HeaderMap,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     let path = request.uri().path();
     
     // Skip authentication for public endpoints
-    if path == "/health" || path == "/llm.txt" {
+    if path == "/health" || path == "/llm.txt" || path == "/api/formats" {
         return Ok(next.run(request).await);
     }
     
-    // Check if API key is configured
-    let expected_key = match std::env::var("API_KEY") {
-        Ok(key) if !key.is_empty() => key,
-        _ => {
-            // No API key configured - allow request (backward compatibility)
-            return Ok(next.run(request).await);
-        }
+    // Check if API keys are configured
+    let Some(api_keys) = state.api_keys.as_ref() else {
+        // No API key configured - allow request (backward compatibility)
+        return Ok(next.run(request).await);
     };
     
     // Check for API key in headers
@@ -95,19 +378,27 @@ async fn auth_middleware(
         .and_then(|h| h.to_str().ok())
         .map(|s| {
             // Support both "Bearer <key>" and direct key
-            if s.starts_with("Bearer ") {
-                &s[7..]
-            } else {
-                s
-            }
+            s.strip_prefix("Bearer ").unwrap_or(s)
         });
     
-    match provided_key {
-        Some(key) if key == expected_key => {
-            // Valid key
+    match provided_key.map(|key| api_keys.check(key)) {
+        Some(KeyCheck::Allowed(identity)) => {
+            // Valid key - downstream handlers can tell who called
+            request.extensions_mut().insert(identity);
             Ok(next.run(request).await)
         }
-        Some(_) => {
+        Some(KeyCheck::OverQuota(retry_after)) => {
+            warn!("🚫 API key over its per-minute quota");
+            let mut response = (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ErrorResponse {
+                    error: "API key quota exceeded, try again later".to_string(),
+                }),
+            ).into_response();
+            response.headers_mut().insert("Retry-After", HeaderValue::from(retry_after));
+            Ok(response)
+        }
+        Some(KeyCheck::Unknown) => {
             // Invalid key
             warn!("🚫 Authentication failed: Invalid API key");
             Err((
@@ -130,8 +421,16 @@ async fn auth_middleware(
     }
 }
 
-async fn health_check() -> &'static str {
-    "OK"
+async fn formats() -> Json<crate::SupportedFormats> {
+    Json(crate::supported_formats())
+}
+
+async fn health_check(State(state): State<AppState>) -> (StatusCode, &'static str) {
+    if state.is_shutting_down() {
+        (StatusCode::SERVICE_UNAVAILABLE, "Shutting down")
+    } else {
+        (StatusCode::OK, "OK")
+    }
 }
 
 async fn llm_docs() -> (StatusCode, [(&'static str, &'static str); 1], &'static str) {
@@ -143,6 +442,18 @@ async fn llm_docs() -> (StatusCode, [(&'static str, &'static str); 1], &'static
     )
 }
 
+/// 503 response returned when every compression job slot is taken
+fn server_busy_response() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [("Retry-After", "1")],
+        Json(ErrorResponse {
+            error: "Server busy: too many concurrent compression jobs, retry shortly".to_string(),
+        }),
+    )
+        .into_response()
+}
+
 /// Sanitize a filename to ensure it's safe for use in filesystem
 /// - Strips any file extension (will be added based on output format)
 /// - Allows only: a-z, A-Z, 0-9, hyphens, underscores, spaces
@@ -178,23 +489,196 @@ fn sanitize_filename(name: &str) -> Result<String, String> {
     Ok(sanitized)
 }
 
-async fn compress_file(mut multipart: Multipart) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
-    let mut file_data: Option<Vec<u8>> = None;
-    let mut compression_level: u8 = 75; // Default 75%
-    let mut output_format: Option<String> = None;
-    let mut output_filename: Option<String> = None;
-    let mut original_filename: Option<String> = None;
-    
-    // Parse multipart form data
-    while let Some(field) = multipart.next_field().await.map_err(|e| {
+/// Placeholders a filename_template may use
+const FILENAME_PLACEHOLDERS: [&str; 4] = ["stem", "ext", "level", "date"];
+
+/// Fill in a filename_template's `{placeholder}`s, rejecting unknown placeholders and stray braces
+fn render_filename_template(template: &str, values: &[(&str, &str)]) -> Result<String, String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..open]);
+        let close = rest[open..].find('}').filter(|_| rest[open..].starts_with('{'))
+            .map(|close| open + close)
+            .ok_or_else(|| "Invalid filename_template: unbalanced braces".to_string())?;
+        let placeholder = &rest[open + 1..close];
+        let value = values.iter()
+            .find(|(name, _)| *name == placeholder)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| format!(
+                "Invalid filename_template: unknown placeholder {{{}}} (expected {})",
+                placeholder,
+                FILENAME_PLACEHOLDERS.map(|name| format!("{{{}}}", name)).join(", ")
+            ))?;
+        rendered.push_str(value);
+        rest = &rest[close + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Today's UTC date as YYYY-MM-DD
+fn utc_date() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() / 86_400)
+        .unwrap_or(0) as i64;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's days_from_civil, inverted)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Parse an RGB colour written as six hex digits, with or without a leading #
+fn parse_hex_color(text: &str) -> Option<[u8; 3]> {
+    let hex = text.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Read a non-file multipart field as text, rejecting values over MAX_TEXT_FIELD_BYTES
+/// Chunks are counted as they arrive, so an oversized field is never buffered whole
+async fn read_text_field(mut field: Field<'_>, description: &str) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+    let mut data = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(|e| {
+        error!("Failed to read {}: {}", description, e);
+        (
+            e.status(),
+            Json(ErrorResponse {
+                error: format!("Failed to read {}: {}", description, e),
+            }),
+        )
+    })? {
+        if data.len() + chunk.len() > MAX_TEXT_FIELD_BYTES {
+            warn!("Rejecting {} over {} bytes", description, MAX_TEXT_FIELD_BYTES);
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!("Invalid {}: too long (at most {} bytes)", description, MAX_TEXT_FIELD_BYTES),
+                }),
+            ));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    String::from_utf8(data).map_err(|_| (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse {
+            error: format!("Failed to read {}: not valid UTF-8", description),
+        }),
+    ))
+}
+
+/// The next multipart field, with a malformed body mapped to its status
+async fn next_field(multipart: &mut Multipart) -> Result<Option<Field<'_>>, (StatusCode, Json<ErrorResponse>)> {
+    multipart.next_field().await.map_err(|e| {
         error!("Failed to read multipart field: {}", e);
         (
-            StatusCode::BAD_REQUEST,
+            e.status(),
             Json(ErrorResponse {
                 error: format!("Invalid multipart data: {}", e),
             }),
         )
-    })? {
+    })
+}
+
+/// Read a file field whole; a body over the upload limit comes back as 413
+async fn read_file_field(field: Field<'_>, description: &str) -> Result<axum::body::Bytes, (StatusCode, Json<ErrorResponse>)> {
+    field.bytes().await.map_err(|e| {
+        error!("Failed to read {} data: {}", description, e);
+        (
+            e.status(),
+            Json(ErrorResponse {
+                error: format!("Failed to read {}: {}", description, e),
+            }),
+        )
+    })
+}
+
+/// Read a true/false (or 1/0) field, rejecting anything else with 400
+async fn read_bool_field(field: Field<'_>, name: &str) -> Result<bool, (StatusCode, Json<ErrorResponse>)> {
+    let text = read_text_field(field, name).await?;
+    match text.trim() {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        other => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Invalid {}: {} (expected true or false)", name, other),
+            }),
+        )),
+    }
+}
+
+/// Take a compression job slot without queueing; None when all are busy and the request should get 503
+fn acquire_job_permit(state: &AppState) -> Option<OwnedSemaphorePermit> {
+    let permit = state.job_permits.clone().try_acquire_owned().ok();
+    if permit.is_none() {
+        warn!("All compression job slots busy, rejecting request");
+    }
+    permit
+}
+
+/// Run blocking work holding a job permit, which PDF loader threads it starts keep until they exit
+/// A load that times out returns early but leaves its parser running, and that still counts as a job
+fn holding_permit<T>(permit: OwnedSemaphorePermit, work: impl FnOnce() -> T) -> T {
+    crate::xref::with_load_guard(Arc::new(permit), work)
+}
+
+/// Reject an uploaded file over the server's max_input_bytes with 413
+/// Called as soon as a file is read, so an oversized one costs no decoding
+fn check_input_size(state: &AppState, description: &str, size: usize) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    match state.max_input_bytes.filter(|&max| size as u64 > max) {
+        Some(max_bytes) => {
+            warn!("Rejecting {} byte {}, over the {} byte compression limit", size, description, max_bytes);
+            Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(ErrorResponse {
+                    error: format!("{} is {} bytes; this server compresses files up to {} bytes", description, size, max_bytes),
+                }),
+            ))
+        }
+        None => Ok(()),
+    }
+}
+
+async fn compress_file(
+    State(state): State<AppState>,
+    identity: Option<Extension<ApiKeyIdentity>>,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let mut file_data: Option<Vec<u8>> = None;
+    let mut compression_level: Option<u8> = None; // Default 75%, or the preset's level
+    let mut preset: Option<crate::QualityPreset> = None;
+    let mut output_format: Option<String> = None;
+    let mut output_filename: Option<String> = None;
+    let mut filename_template: Option<String> = None;
+    let mut original_filename: Option<String> = None;
+    let mut prefer_lossless: Option<f32> = None;
+    let mut min_reduction_pct: Option<f64> = None;
+    let mut jpeg_quality: Option<u8> = None;
+    let mut min_jpeg_quality: Option<u8> = None;
+    let mut max_jpeg_quality: Option<u8> = None;
+    let mut resample_filter = crate::ResampleFilter::default();
+    let mut fallback_original = false;
+    let mut flatten_forms = false;
+    let mut no_resize = false;
+    let mut quantize_png = false;
+    let mut flatten_background = crate::CompressionSettings::default().flatten_background;
+    let mut manifest = false;
+    let mut disposition = "attachment";
+    
+    // Parse multipart form data
+    while let Some(field) = next_field(&mut multipart).await? {
         let name = field.name().unwrap_or("").to_string();
         
         match name.as_str() {
@@ -204,15 +688,7 @@ async fn compress_file(mut multipart: Multipart) -> Result<Response, (StatusCode
                     original_filename = Some(filename.to_string());
                 }
                 
-                let data = field.bytes().await.map_err(|e| {
-                    error!("Failed to read file data: {}", e);
-                    (
-                        StatusCode::BAD_REQUEST,
-                        Json(ErrorResponse {
-                            error: format!("Failed to read file: {}", e),
-                        }),
-                    )
-                })?;
+                let data = read_file_field(field, "file").await?;
                 
                 if data.is_empty() {
                     return Err((
@@ -223,48 +699,165 @@ async fn compress_file(mut multipart: Multipart) -> Result<Response, (StatusCode
                     ));
                 }
                 
+                check_input_size(&state, "File", data.len())?;
+                
                 file_data = Some(data.to_vec());
                 info!("Received file: {} bytes", data.len());
             }
             "compression" | "quality" | "level" => {
-                let text = field.text().await.map_err(|e| {
-                    error!("Failed to read compression parameter: {}", e);
+                let text = read_text_field(field, "compression parameter").await?;
+                
+                let level = text.parse::<u8>().unwrap_or(75).clamp(10, 95);
+                compression_level = Some(level);
+                info!("Compression level set to: {}%", level);
+            }
+            "preset" => {
+                let text = read_text_field(field, "preset").await?;
+                let value: crate::QualityPreset = text.parse().map_err(|e| (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: e,
+                    }),
+                ))?;
+                preset = Some(value);
+                info!("Preset set to: {:?}", value);
+            }
+            "output_format" | "format" => {
+                let text = read_text_field(field, "output format").await?;
+                output_format = Some(text);
+                info!("Output format set to: {:?}", output_format);
+            }
+            "output_filename" | "filename" => {
+                let text = read_text_field(field, "output filename").await?;
+                output_filename = Some(text);
+                info!("Output filename set to: {:?}", output_filename);
+            }
+            "filename_template" => {
+                let text = read_text_field(field, "filename_template").await?;
+                // Check the placeholders now, before any compression work
+                let known = FILENAME_PLACEHOLDERS.map(|name| (name, ""));
+                render_filename_template(&text, &known).map_err(|e| {
+                    error!("{}", e);
                     (
                         StatusCode::BAD_REQUEST,
                         Json(ErrorResponse {
-                            error: format!("Failed to read compression parameter: {}", e),
+                            error: e,
                         }),
                     )
                 })?;
-                
-                compression_level = text.parse::<u8>().unwrap_or(75).min(95).max(10);
-                info!("Compression level set to: {}%", compression_level);
+                filename_template = Some(text);
+                info!("Filename template set to: {:?}", filename_template);
             }
-            "output_format" | "format" => {
-                let text = field.text().await.map_err(|e| {
-                    error!("Failed to read output format: {}", e);
-                    (
+            "prefer_lossless" => {
+                let text = read_text_field(field, "prefer_lossless").await?;
+                let value = text.trim().parse::<f32>().ok()
+                    .filter(|v| v.is_finite() && *v > 0.0)
+                    .ok_or_else(|| (
                         StatusCode::BAD_REQUEST,
                         Json(ErrorResponse {
-                            error: format!("Failed to read output format: {}", e),
+                            error: format!("Invalid prefer_lossless: {} (expected a positive number, e.g. 1.1)", text),
                         }),
-                    )
-                })?;
-                output_format = Some(text);
-                info!("Output format set to: {:?}", output_format);
+                    ))?;
+                prefer_lossless = Some(value);
+                info!("Lossless preference set to: {}", value);
             }
-            "output_filename" | "filename" => {
-                let text = field.text().await.map_err(|e| {
-                    error!("Failed to read output filename: {}", e);
-                    (
+            "min_reduction_pct" => {
+                let text = read_text_field(field, "min_reduction_pct").await?;
+                let value = text.trim().parse::<f64>().ok()
+                    .filter(|v| (0.0..=100.0).contains(v))
+                    .ok_or_else(|| (
                         StatusCode::BAD_REQUEST,
                         Json(ErrorResponse {
-                            error: format!("Failed to read output filename: {}", e),
+                            error: format!("Invalid min_reduction_pct: {} (expected 0-100)", text),
                         }),
-                    )
-                })?;
-                output_filename = Some(text);
-                info!("Output filename set to: {:?}", output_filename);
+                    ))?;
+                min_reduction_pct = Some(value);
+                info!("Minimum reduction set to: {}%", value);
+            }
+            "jpeg_quality" => {
+                let text = read_text_field(field, "jpeg_quality").await?;
+                let value = text.trim().parse::<u8>().ok()
+                    .filter(|v| (1..=100).contains(v))
+                    .ok_or_else(|| (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: format!("Invalid jpeg_quality: {} (expected 1-100)", text),
+                        }),
+                    ))?;
+                jpeg_quality = Some(value);
+                info!("JPEG quality set to: {}", value);
+            }
+            "min_jpeg_quality" | "max_jpeg_quality" => {
+                let text = read_text_field(field, &name).await?;
+                let value = text.trim().parse::<u8>().ok()
+                    .filter(|v| (1..=100).contains(v))
+                    .ok_or_else(|| (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: format!("Invalid {}: {} (expected 1-100)", name, text),
+                        }),
+                    ))?;
+                if name == "min_jpeg_quality" {
+                    min_jpeg_quality = Some(value);
+                } else {
+                    max_jpeg_quality = Some(value);
+                }
+                info!("{} set to: {}", name, value);
+            }
+            "resample_filter" => {
+                let text = read_text_field(field, "resample_filter").await?;
+                resample_filter = text.parse().map_err(|e| (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: e,
+                    }),
+                ))?;
+                info!("Resample filter set to: {:?}", resample_filter);
+            }
+            "fallback_original" => {
+                fallback_original = read_bool_field(field, "fallback_original").await?;
+                info!("Fallback to original on error: {}", fallback_original);
+            }
+            "flatten_forms" => {
+                flatten_forms = read_bool_field(field, "flatten_forms").await?;
+                info!("Flatten form fields: {}", flatten_forms);
+            }
+            "no_resize" => {
+                no_resize = read_bool_field(field, "no_resize").await?;
+                info!("Keep image dimensions: {}", no_resize);
+            }
+            "quantize_png" => {
+                quantize_png = read_bool_field(field, "quantize_png").await?;
+                info!("Quantize PNG output: {}", quantize_png);
+            }
+            "flatten_background" | "background" => {
+                let text = read_text_field(field, "flatten_background").await?;
+                flatten_background = parse_hex_color(&text).ok_or_else(|| (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: format!("Invalid flatten_background: {} (expected a hex colour like #1a2b3c)", text.trim()),
+                    }),
+                ))?;
+                info!("Flatten transparency over {:?}", flatten_background);
+            }
+            "manifest" => {
+                manifest = read_bool_field(field, "manifest").await?;
+                info!("Digest headers requested: {}", manifest);
+            }
+            "disposition" => {
+                let text = read_text_field(field, "disposition").await?;
+                // Inline lets a browser render the result in the tab instead of downloading it
+                disposition = match text.trim() {
+                    "inline" => "inline",
+                    "attachment" => "attachment",
+                    other => return Err((
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: format!("Invalid disposition: {} (expected inline or attachment)", other),
+                        }),
+                    )),
+                };
+                info!("Content disposition set to: {}", disposition);
             }
             _ => {
                 // Ignore unknown fields
@@ -272,6 +865,20 @@ async fn compress_file(mut multipart: Multipart) -> Result<Response, (StatusCode
         }
     }
     
+    // An explicit level wins over the preset's; its resolution limits still apply
+    let compression_level = compression_level.or(preset.map(crate::QualityPreset::level)).unwrap_or(75);
+    
+    if let (Some(min), Some(max)) = (min_jpeg_quality, max_jpeg_quality) {
+        if min > max {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!("min_jpeg_quality {} is above max_jpeg_quality {}", min, max),
+                }),
+            ));
+        }
+    }
+    
     // Ensure we have file data
     let file_data = file_data.ok_or_else(|| {
         error!("No file provided in request");
@@ -284,89 +891,236 @@ async fn compress_file(mut multipart: Multipart) -> Result<Response, (StatusCode
     })?;
     
     let original_size = file_data.len() as u64;
+    // The upload is handed to the worker, so its digest is taken now
+    let input_sha256 = manifest.then(|| crate::sha256_hex(&file_data));
     
-    // Detect file type using magic bytes
+    // Magic bytes first, then the uploaded file name, then a search for a displaced PDF header
     let file_type = infer::get(&file_data);
-    let is_pdf = file_type
-        .as_ref()
-        .map(|t| t.mime_type() == "application/pdf")
-        .unwrap_or_else(|| {
-            // Fallback: check PDF magic bytes
-            file_data.starts_with(b"%PDF")
-        });
+    let input_type = crate::detect_input_type(&file_data, original_filename.as_deref());
+    let is_pdf = input_type == crate::InputType::Pdf;
+    
+    // Anything that isn't a PDF must be an image format this build decodes
+    if input_type == crate::InputType::Unknown {
+        let detected = file_type.map(|t| t.mime_type()).unwrap_or("unknown");
+        warn!("Rejecting upload of unsupported type {}", detected);
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(ErrorResponse {
+                error: format!(
+                    "Unsupported file type: {}. Accepted types: {}",
+                    detected,
+                    crate::supported_formats().input.join(", ")
+                ),
+            }),
+        ));
+    }
     
     info!("Starting compression: {} bytes, level {}%, type: {}", 
           original_size, 
           compression_level,
           if is_pdf { "PDF" } else { "Image" });
+    if let Some(Extension(identity)) = &identity {
+        info!("Requested with API key {}", identity.label);
+    }
     
-    // Compress based on file type - offload CPU-intensive work to blocking thread pool
-    let (compressed_data, content_type, extension): (Vec<u8>, &str, String) = if is_pdf {
-        let compressed = tokio::task::spawn_blocking(move || {
-            crate::compress_pdf_bytes(&file_data, compression_level)
-        })
-        .await
-        .map_err(|e| {
-            error!("PDF compression task failed: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
+    // The level picks the quality band (downsampling, palettes); jpeg_quality only overrides the encoder
+    let defaults = crate::CompressionSettings::default();
+    let settings = crate::CompressionSettings {
+        quality: crate::compression_level_to_quality(compression_level),
+        lossless_preference: prefer_lossless.unwrap_or(defaults.lossless_preference),
+        jpeg_quality,
+        min_jpeg_quality,
+        max_jpeg_quality,
+        resample_filter,
+        flatten_forms,
+        no_resize,
+        quantize_png,
+        flatten_background,
+        target_dpi: preset.map(crate::QualityPreset::target_dpi),
+        max_dimension: preset.and_then(crate::QualityPreset::max_dimension),
+        max_threads: state.threads_per_job,
+        rounds: state.compression_rounds,
+        ..defaults
+    };
+    
+    // Identical uploads with identical settings are served from the cache
+    let cache_key = state.cache.as_ref().map(|_| CacheKey::new(&file_data, output_format.as_deref(), &settings));
+    let cached = match (&state.cache, &cache_key) {
+        (Some(cache), Some(key)) => cache.get(key),
+        _ => None,
+    };
+    let cache_hit = cached.is_some();
+    
+    // The worker hands the original bytes back even on failure, so they can still be returned
+    // Compression time covers the blocking work only; a cache hit took none
+    let mut compression_time = Duration::ZERO;
+    let (outcome, file_data): (CompressionOutcome, Vec<u8>) = if let Some(hit) = cached {
+        info!("Cache hit: {} bytes", hit.data.len());
+        (Ok((hit.data.to_vec(), hit.content_type, hit.extension)), file_data)
+    } else {
+        // Reject fast instead of queueing unboundedly when the blocking pool is saturated
+        let Some(permit) = acquire_job_permit(&state) else {
+            return Ok(server_busy_response());
+        };
+        
+        // Compress based on file type - offload CPU-intensive work to blocking thread pool
+        // The original bytes come back from the worker so they can be returned if the reduction is too small
+        let started = Instant::now();
+        let (outcome, file_data): (CompressionOutcome, Vec<u8>) = if is_pdf {
+            let request_id = current_request_id();
+            // Hold the job slot until the blocking work finishes, even if the client disconnects
+            let (result, file_data) = tokio::task::spawn_blocking(move || with_request_id(request_id, || holding_permit(permit, || {
+                let file_data = Arc::new(file_data);
+                let result = crate::compress_shared_pdf(&file_data, &settings);
+                // Only a loader that timed out still holds the input, and then it has to be copied back
+                (result, Arc::try_unwrap(file_data).unwrap_or_else(|shared| shared.to_vec()))
+            })))
+            .await
+            .map_err(|e| {
+                error!("PDF compression task failed: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: format!("PDF compression task failed: {}", e),
+                    }),
+                )
+            })?;
+            let outcome = result
+                .map(|compressed| (compressed, "application/pdf", "pdf".to_string()))
+                .map_err(|e| {
+                    error!("PDF compression failed: {}", e);
+                    format!("PDF compression failed: {}", e)
+                });
+            (outcome, file_data)
+        } else {
+            let request_id = current_request_id();
+            let (result, file_data) = tokio::task::spawn_blocking(move || with_request_id(request_id, || {
+                let _permit = permit;
+                let result = match input_type {
+                    crate::InputType::Image(format) => crate::compress_image_with_format(
+                        &file_data,
+                        format,
+                        output_format.as_deref(),
+                        &settings,
+                    ),
+                    _ => crate::compress_image_with_settings(&file_data, output_format.as_deref(), &settings),
+                };
+                (result, file_data)
+            }))
+            .await
+            .map_err(|e| {
+                error!("Image compression task failed: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: format!("Image compression task failed: {}", e),
+                    }),
+                )
+            })?;
+            let outcome = result
+                .map(|(compressed, ext)| {
+                    let mime = match ext.as_str() {
+                        "jpg" | "jpeg" => "image/jpeg",
+                        "png" => "image/png",
+                        "webp" => "image/webp",
+                        _ => "application/octet-stream",
+                    };
+                    (compressed, mime, ext)
+                })
+                .map_err(|e| {
+                    error!("Image compression failed: {}", e);
+                    format!("Image compression failed: {}", e)
+                });
+            (outcome, file_data)
+        };
+        compression_time = started.elapsed();
+        
+        if let (Some(cache), Some(key), Ok((compressed_data, content_type, extension))) = (&state.cache, cache_key, &outcome) {
+            cache.insert(key, CachedResult {
+                data: Arc::new(compressed_data.clone()),
+                content_type,
+                extension: extension.clone(),
+            });
+        }
+        (outcome, file_data)
+    };
+    
+    // With fallback_original a failed compression still answers with a usable file
+    let (compressed, compression_error) = match outcome {
+        Ok(compressed) => (Some(compressed), None),
+        Err(e) if fallback_original => {
+            warn!("{}, returning original (fallback_original)", e);
+            (None, Some(e))
+        }
+        Err(e) => {
+            // A PDF the parser rejects or gives up on is a problem with the upload, not the server
+            let status = if e.contains("Failed to load PDF") {
+                StatusCode::UNPROCESSABLE_ENTITY
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            return Err((
+                status,
                 Json(ErrorResponse {
-                    error: format!("PDF compression task failed: {}", e),
+                    error: e,
                 }),
-            )
-        })?
-        .map_err(|e| {
-            error!("PDF compression failed: {}", e);
+            ));
+        }
+    };
+    
+    let measured_reduction = compressed.as_ref()
+        .map(|(compressed_data, _, _)| crate::reduction_percentage(original_size, compressed_data.len() as u64));
+    
+    // Hand back the untouched input when compression failed or the caller's minimum reduction was not reached
+    let (compressed_data, content_type, extension, skipped) = match compressed {
+        Some((compressed_data, content_type, extension))
+            if !min_reduction_pct.zip(measured_reduction).is_some_and(|(min, measured)| measured < min) =>
+        {
+            (compressed_data, content_type, extension, false)
+        }
+        _ => {
+            if let Some(measured_reduction) = measured_reduction {
+                info!("Reduction {:.2}% below requested minimum, returning original", measured_reduction);
+            }
+            let (mime, ext) = match file_type {
+                Some(t) => (t.mime_type(), t.extension().to_string()),
+                None if is_pdf => ("application/pdf", "pdf".to_string()),
+                None => ("application/octet-stream", "bin".to_string()),
+            };
+            (file_data, mime, ext, true)
+        }
+    };
+    
+    // Determine final output filename
+    let final_filename = if let Some(custom) = output_filename {
+        // User provided custom filename - sanitize it
+        let sanitized = sanitize_filename(&custom).map_err(|e| {
+            error!("Invalid output filename: {}", e);
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_REQUEST,
                 Json(ErrorResponse {
-                    error: format!("PDF compression failed: {}", e),
+                    error: e,
                 }),
             )
         })?;
-        (compressed, "application/pdf", "pdf".to_string())
-    } else {
-        let (compressed, ext) = tokio::task::spawn_blocking(move || {
-            crate::compress_image_bytes(
-                &file_data,
-                compression_level,
-                output_format.as_deref(),
-            )
-        })
-        .await
-        .map_err(|e| {
-            error!("Image compression task failed: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Image compression task failed: {}", e),
-                }),
-            )
-        })?
-        .map_err(|e| {
-            error!("Image compression failed: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Image compression failed: {}", e),
-                }),
-            )
-        })?;
-        
-        let mime = match ext.as_str() {
-            "jpg" | "jpeg" => "image/jpeg",
-            "png" => "image/png",
-            "webp" => "image/webp",
-            _ => "application/octet-stream",
-        };
-        (compressed, mime, ext)
-    };
-    
-    // Determine final output filename
-    let final_filename = if let Some(custom) = output_filename {
-        // User provided custom filename - sanitize it
-        let sanitized = sanitize_filename(&custom).map_err(|e| {
-            error!("Invalid output filename: {}", e);
+        format!("{}.{}", sanitized, extension)
+    } else if let Some(template) = filename_template {
+        let stem = original_filename.as_deref()
+            .map(|orig| orig.rfind('.').map_or(orig, |pos| &orig[..pos]))
+            .unwrap_or("compressed");
+        let level = compression_level.to_string();
+        let date = utc_date();
+        let rendered = render_filename_template(&template, &[
+            ("stem", stem),
+            ("ext", &extension),
+            ("level", &level),
+            ("date", &date),
+        ])
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+        // The real extension is always appended, so a trailing .{ext} in the template is not doubled
+        let rendered = rendered.strip_suffix(&format!(".{}", extension)).unwrap_or(&rendered);
+        let sanitized = sanitize_filename(&format!("{}.{}", rendered, extension)).map_err(|e| {
+            error!("Invalid filename_template result: {}", e);
             (
                 StatusCode::BAD_REQUEST,
                 Json(ErrorResponse {
@@ -390,29 +1144,673 @@ async fn compress_file(mut multipart: Multipart) -> Result<Response, (StatusCode
     };
     
     let compressed_size = compressed_data.len() as u64;
-    let reduction = if original_size > 0 {
-        ((original_size - compressed_size) as f64 / original_size as f64) * 100.0
-    } else {
-        0.0
-    };
+    let output_sha256 = manifest.then(|| crate::sha256_hex(&compressed_data));
+    let reduction = if skipped { 0.0 } else { measured_reduction.unwrap_or(0.0) };
     
     info!(
-        "Compression successful: {} bytes -> {} bytes ({:.2}% reduction), output: {}",
-        original_size, compressed_size, reduction, final_filename
+        "Compression successful: {} bytes -> {} bytes ({:.2}% reduction) in {} ms, output: {}",
+        original_size, compressed_size, reduction, compression_time.as_millis(), final_filename
     );
     
     // Return compressed file with metadata in headers
-    Ok((
+    let mut response = (
         StatusCode::OK,
         [
             ("Content-Type", content_type),
-            ("Content-Disposition", &format!("attachment; filename=\"{}\"", final_filename)),
+            ("Content-Disposition", &format!("{}; filename=\"{}\"", disposition, final_filename)),
             ("X-Original-Size", &original_size.to_string()),
             ("X-Compressed-Size", &compressed_size.to_string()),
             ("X-Reduction-Percentage", &format!("{:.2}", reduction)),
+            ("X-Compression-Time-Ms", &compression_time.as_millis().to_string()),
         ],
         compressed_data,
+    )
+        .into_response();
+    
+    if let (Some(input_sha256), Some(output_sha256)) = (input_sha256, output_sha256) {
+        let headers = response.headers_mut();
+        if let (Ok(input), Ok(output)) = (HeaderValue::from_str(&input_sha256), HeaderValue::from_str(&output_sha256)) {
+            headers.insert("X-Input-SHA256", input);
+            headers.insert("X-Output-SHA256", output);
+        }
+    }
+    
+    if state.cache.is_some() {
+        let value = if cache_hit { "HIT" } else { "MISS" };
+        response.headers_mut().insert("X-Cache", HeaderValue::from_static(value));
+    }
+    
+    if skipped {
+        let headers = response.headers_mut();
+        headers.insert("X-Compression-Skipped", HeaderValue::from_static("true"));
+        if let Some(Ok(value)) = measured_reduction.map(|measured| HeaderValue::from_str(&format!("{:.2}", measured))) {
+            headers.insert("X-Measured-Reduction-Percentage", value);
+        }
+        // Header values must be visible ASCII, so anything else in the message is replaced
+        if let Some(error) = compression_error {
+            let printable: String = error.chars()
+                .map(|c| if c.is_ascii_graphic() || c == ' ' { c } else { '?' })
+                .collect();
+            if let Ok(value) = HeaderValue::from_str(&printable) {
+                headers.insert("X-Compression-Error", value);
+            }
+        }
+    }
+    
+    Ok(response)
+}
+
+
+async fn images_to_pdf(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let mut images: Vec<Vec<u8>> = Vec::new();
+    let mut compression_level: u8 = 75;
+    
+    while let Some(field) = next_field(&mut multipart).await? {
+        let name = field.name().unwrap_or("").to_string();
+        
+        match name.as_str() {
+            "file" | "image" | "images" => {
+                let data = read_file_field(field, "image").await?;
+                
+                check_input_size(&state, &format!("Image {}", images.len() + 1), data.len())?;
+                if !data.is_empty() {
+                    info!("Received image {}: {} bytes", images.len() + 1, data.len());
+                    images.push(data.to_vec());
+                }
+            }
+            "compression" | "quality" | "level" => {
+                let text = read_text_field(field, "compression parameter").await?;
+                
+                compression_level = text.parse::<u8>().unwrap_or(75).clamp(10, 95);
+            }
+            _ => {
+                // Ignore unknown fields
+            }
+        }
+    }
+    
+    if images.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "No images provided. Use one or more 'file', 'image', or 'images' fields.".to_string(),
+            }),
+        ));
+    }
+    
+    let Some(permit) = acquire_job_permit(&state) else {
+        return Ok(server_busy_response());
+    };
+    
+    let image_count = images.len();
+    let request_id = current_request_id();
+    let pdf = tokio::task::spawn_blocking(move || with_request_id(request_id, || {
+        let _permit = permit;
+        crate::images_to_pdf(&images, compression_level)
+    }))
+    .await
+    .map_err(|e| {
+        error!("Images-to-PDF task failed: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Images-to-PDF task failed: {}", e),
+            }),
+        )
+    })?
+    .map_err(|e| {
+        error!("Images-to-PDF failed: {}", e);
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse {
+                error: format!("Images-to-PDF failed: {}", e),
+            }),
+        )
+    })?;
+    
+    info!("Built PDF from {} images: {} bytes", image_count, pdf.len());
+    
+    Ok((
+        StatusCode::OK,
+        [
+            ("Content-Type", "application/pdf"),
+            ("Content-Disposition", "attachment; filename=\"images.pdf\""),
+            ("X-Page-Count", &image_count.to_string()),
+        ],
+        pdf,
+    )
+        .into_response())
+}
+
+
+async fn merge_pdfs(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let mut documents: Vec<Vec<u8>> = Vec::new();
+    let mut compression_level: u8 = 75;
+    
+    while let Some(field) = next_field(&mut multipart).await? {
+        let name = field.name().unwrap_or("").to_string();
+        
+        match name.as_str() {
+            "file" | "pdf" | "files" => {
+                let data = read_file_field(field, "PDF").await?;
+                
+                check_input_size(&state, &format!("PDF {}", documents.len() + 1), data.len())?;
+                if !data.is_empty() {
+                    info!("Received PDF {}: {} bytes", documents.len() + 1, data.len());
+                    documents.push(data.to_vec());
+                }
+            }
+            "compression" | "quality" | "level" => {
+                let text = read_text_field(field, "compression parameter").await?;
+                
+                compression_level = text.parse::<u8>().unwrap_or(75).clamp(10, 95);
+            }
+            _ => {
+                // Ignore unknown fields
+            }
+        }
+    }
+    
+    if documents.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "No PDFs provided. Use one or more 'file', 'pdf', or 'files' fields.".to_string(),
+            }),
+        ));
+    }
+    
+    let Some(permit) = acquire_job_permit(&state) else {
+        return Ok(server_busy_response());
+    };
+    
+    let document_count = documents.len();
+    let original_size: usize = documents.iter().map(Vec::len).sum();
+    let request_id = current_request_id();
+    let pdf = tokio::task::spawn_blocking(move || with_request_id(request_id, || holding_permit(permit, || {
+        crate::merge_and_compress_pdfs(&documents, compression_level)
+    })))
+    .await
+    .map_err(|e| {
+        error!("PDF merge task failed: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("PDF merge task failed: {}", e),
+            }),
+        )
+    })?
+    .map_err(|e| {
+        error!("PDF merge failed: {}", e);
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse {
+                error: format!("PDF merge failed: {}", e),
+            }),
+        )
+    })?;
+    
+    info!("Merged {} PDFs: {} bytes -> {} bytes", document_count, original_size, pdf.len());
+    
+    Ok((
+        StatusCode::OK,
+        [
+            ("Content-Type", "application/pdf"),
+            ("Content-Disposition", "attachment; filename=\"merged.pdf\""),
+            ("X-Document-Count", &document_count.to_string()),
+            ("X-Original-Size", &original_size.to_string()),
+            ("X-Compressed-Size", &pdf.len().to_string()),
+        ],
+        pdf,
+    )
+        .into_response())
+}
+
+/// Compress every uploaded file on its own job slot and return the results as a zip
+/// Files wait for free slots instead of being rejected; one that fails is listed in errors.txt
+/// rather than failing the batch. Entries keep upload order
+async fn compress_batch(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut compression_level: u8 = 75;
+    
+    while let Some(field) = next_field(&mut multipart).await? {
+        let name = field.name().unwrap_or("").to_string();
+        
+        match name.as_str() {
+            "file" | "pdf" | "files" => {
+                let filename = field.file_name().unwrap_or("file").to_string();
+                let data = read_file_field(field, "file").await?;
+                
+                check_input_size(&state, &filename, data.len())?;
+                if !data.is_empty() {
+                    info!("Received batch file {}: {} bytes", files.len() + 1, data.len());
+                    files.push((filename, data.to_vec()));
+                }
+            }
+            "compression" | "quality" | "level" => {
+                let text = read_text_field(field, "compression parameter").await?;
+                
+                compression_level = text.parse::<u8>().unwrap_or(75).clamp(10, 95);
+            }
+            _ => {
+                // Ignore unknown fields
+            }
+        }
+    }
+    
+    if files.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "No files provided. Use one or more 'file', 'pdf', or 'files' fields.".to_string(),
+            }),
+        ));
+    }
+    
+    // A batch queues behind its own files, but doesn't start when nothing could run
+    if state.job_permits.available_permits() == 0 {
+        warn!("All compression job slots busy, rejecting batch");
+        return Ok(server_busy_response());
+    }
+    
+    let settings = crate::CompressionSettings {
+        quality: crate::compression_level_to_quality(compression_level),
+        max_threads: state.threads_per_job,
+        rounds: state.compression_rounds,
+        ..Default::default()
+    };
+    let file_count = files.len();
+    let original_size: usize = files.iter().map(|(_, data)| data.len()).sum();
+    let mut names = Vec::with_capacity(file_count);
+    let mut jobs = tokio::task::JoinSet::new();
+    // A large batch would otherwise take every job slot as it frees up and starve /api/compress
+    let batch_slots = Arc::new(Semaphore::new(state.batch_concurrency()));
+    for (index, (filename, data)) in files.into_iter().enumerate() {
+        names.push(filename);
+        let batch_slots = batch_slots.clone();
+        let permits = state.job_permits.clone();
+        let settings = settings.clone();
+        let request_id = current_request_id();
+        jobs.spawn(async move {
+            let _batch_slot = batch_slots.acquire_owned().await;
+            let result = match permits.acquire_owned().await {
+                Ok(permit) => tokio::task::spawn_blocking(move || with_request_id(request_id, || holding_permit(permit, || {
+                    compress_batch_file(&data, &settings)
+                })))
+                .await
+                .unwrap_or_else(|e| Err(format!("Compression task failed: {}", e))),
+                Err(_) => Err("Server is shutting down".to_string()),
+            };
+            (index, result)
+        });
+    }
+    
+    let mut results = vec![None; file_count];
+    while let Some(joined) = jobs.join_next().await {
+        let (index, result) = joined.map_err(|e| {
+            error!("Batch compression task failed: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Batch compression task failed: {}", e),
+                }),
+            )
+        })?;
+        results[index] = Some(result);
+    }
+    
+    let mut entries = Vec::with_capacity(file_count);
+    let mut errors = Vec::new();
+    let mut used = std::collections::HashSet::new();
+    for (filename, result) in names.iter().zip(results) {
+        let Some(result) = result else {
+            error!("Batch compression of {} never reported back", filename);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Batch compression of {} never reported back", filename),
+                }),
+            ));
+        };
+        match result {
+            Ok((compressed, extension)) => {
+                let stem = sanitize_filename(filename).unwrap_or_else(|_| "compressed".to_string());
+                let mut entry = format!("{}-compressed.{}", stem, extension);
+                // Uploads may share a name; later ones get a counter
+                for copy in 2.. {
+                    if used.insert(entry.clone()) {
+                        break;
+                    }
+                    entry = format!("{}-compressed-{}.{}", stem, copy, extension);
+                }
+                entries.push((entry, compressed));
+            }
+            Err(e) => {
+                warn!("Batch file {} failed: {}", filename, e);
+                errors.push(format!("{}: {}", filename, e));
+            }
+        }
+    }
+    
+    if entries.is_empty() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse {
+                error: format!("Batch compression failed for every file: {}", errors.join("; ")),
+            }),
+        ));
+    }
+    let compressed_count = entries.len();
+    if !errors.is_empty() {
+        entries.push(("errors.txt".to_string(), format!("{}\n", errors.join("\n")).into_bytes()));
+    }
+    let archive = zip_entries(&entries).map_err(|e| {
+        error!("{}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e,
+            }),
+        )
+    })?;
+    
+    info!(
+        "Compressed batch of {} files ({} failed): {} bytes -> {} byte archive",
+        file_count, errors.len(), original_size, archive.len()
+    );
+    
+    Ok((
+        StatusCode::OK,
+        [
+            ("Content-Type", "application/zip"),
+            ("Content-Disposition", "attachment; filename=\"compressed.zip\""),
+            ("X-File-Count", &compressed_count.to_string()),
+            ("X-Failed-Count", &errors.len().to_string()),
+        ],
+        archive,
+    )
+        .into_response())
+}
+
+/// Compress one batch file as a PDF or image, by its content
+fn compress_batch_file(data: &[u8], settings: &crate::CompressionSettings) -> Result<(Vec<u8>, String), String> {
+    match crate::detect_input_type(data, None) {
+        crate::InputType::Pdf => crate::compress_pdf_with_settings(data, settings).map(|pdf| (pdf, "pdf".to_string())),
+        crate::InputType::Image(format) => crate::compress_image_with_format(data, format, None, settings),
+        crate::InputType::Unknown => Err("Unsupported file type".to_string()),
+    }
+}
+
+/// Render every page of a PDF to a compressed image and return them in a zip archive
+#[cfg(feature = "pdf-render")]
+async fn pdf_to_images(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let mut pdf: Option<Vec<u8>> = None;
+    let mut compression_level: u8 = 75;
+    let mut format = "webp".to_string();
+    
+    while let Some(field) = next_field(&mut multipart).await? {
+        let name = field.name().unwrap_or("").to_string();
+        
+        match name.as_str() {
+            "file" | "pdf" => {
+                let data = read_file_field(field, "PDF").await?;
+                check_input_size(&state, "PDF", data.len())?;
+                info!("Received PDF to render: {} bytes", data.len());
+                pdf = Some(data.to_vec());
+            }
+            "compression" | "quality" | "level" => {
+                let text = read_text_field(field, "compression parameter").await?;
+                
+                compression_level = text.parse::<u8>().unwrap_or(75).clamp(10, 95);
+            }
+            "output_format" | "format" => {
+                format = read_text_field(field, "output format").await?.trim().to_lowercase();
+            }
+            _ => {
+                // Ignore unknown fields
+            }
+        }
+    }
+    
+    let Some(pdf) = pdf.filter(|pdf| !pdf.is_empty()) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "No PDF provided. Use a 'file' or 'pdf' field.".to_string(),
+            }),
+        ));
+    };
+    if !matches!(format.as_str(), "webp" | "png" | "jpg" | "jpeg") {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Invalid format: {} (expected webp, png or jpg)", format),
+            }),
+        ));
+    }
+    
+    let Some(permit) = acquire_job_permit(&state) else {
+        return Ok(server_busy_response());
+    };
+    
+    let request_id = current_request_id();
+    let (archive, page_count) = tokio::task::spawn_blocking(move || with_request_id(request_id, || {
+        let _permit = permit;
+        let pages = crate::pdf_pages_to_images(&pdf, compression_level, &format)?;
+        let page_count = pages.len();
+        zip_pages(pages).map(|archive| (archive, page_count))
+    }))
+    .await
+    .map_err(|e| {
+        error!("PDF rendering task failed: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("PDF rendering task failed: {}", e),
+            }),
+        )
+    })?
+    .map_err(|e| {
+        error!("PDF rendering failed: {}", e);
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse {
+                error: format!("PDF rendering failed: {}", e),
+            }),
+        )
+    })?;
+    
+    info!("Rendered {} pages into a {} byte archive", page_count, archive.len());
+    
+    Ok((
+        StatusCode::OK,
+        [
+            ("Content-Type", "application/zip"),
+            ("Content-Disposition", "attachment; filename=\"pages.zip\""),
+            ("X-Page-Count", &page_count.to_string()),
+        ],
+        archive,
     )
         .into_response())
 }
 
+/// Where an extracted image came from, listed in the archive's images.json
+#[derive(Serialize)]
+struct ExtractedImageEntry<'a> {
+    file: String,
+    object_id: (u32, u16),
+    width: u32,
+    height: u32,
+    pages: &'a [u32],
+    placed_size: Option<(f32, f32)>, // Points; null for images no parsed content draws
+}
+
+/// Extract every image of a PDF into a zip, with an images.json describing each one
+async fn extract_images(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let mut pdf: Option<Vec<u8>> = None;
+    
+    while let Some(field) = next_field(&mut multipart).await? {
+        if matches!(field.name(), Some("file" | "pdf")) {
+            let data = read_file_field(field, "PDF").await?;
+            check_input_size(&state, "PDF", data.len())?;
+            pdf = Some(data.to_vec());
+        }
+    }
+    
+    let Some(pdf) = pdf.filter(|pdf| !pdf.is_empty()) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "No PDF provided. Use a 'file' or 'pdf' field.".to_string(),
+            }),
+        ));
+    };
+    
+    let Some(permit) = acquire_job_permit(&state) else {
+        return Ok(server_busy_response());
+    };
+    
+    let request_id = current_request_id();
+    let (archive, image_count) = tokio::task::spawn_blocking(move || with_request_id(request_id, || holding_permit(permit, || {
+        let images = crate::extract_images(&pdf)?;
+        let files: Vec<String> = images.iter()
+            .map(|image| format!("image-{}.{}", image.object_id.0, image.extension))
+            .collect();
+        let index: Vec<ExtractedImageEntry> = images.iter().zip(&files)
+            .map(|(image, file)| ExtractedImageEntry {
+                file: file.clone(),
+                object_id: image.object_id,
+                width: image.width,
+                height: image.height,
+                pages: &image.pages,
+                placed_size: image.placed_size,
+            })
+            .collect();
+        let index = serde_json::to_vec_pretty(&index).map_err(|e| format!("Failed to write images.json: {}", e))?;
+        
+        let mut entries = vec![("images.json".to_string(), index)];
+        let image_count = images.len();
+        entries.extend(files.into_iter().zip(images.into_iter().map(|image| image.data)));
+        zip_entries(&entries).map(|archive| (archive, image_count))
+    })))
+    .await
+    .map_err(|e| {
+        error!("Image extraction task failed: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Image extraction task failed: {}", e),
+            }),
+        )
+    })?
+    .map_err(|e| {
+        error!("Image extraction failed: {}", e);
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse {
+                error: format!("Image extraction failed: {}", e),
+            }),
+        )
+    })?;
+    
+    info!("Extracted {} images into a {} byte archive", image_count, archive.len());
+    
+    Ok((
+        StatusCode::OK,
+        [
+            ("Content-Type", "application/zip"),
+            ("Content-Disposition", "attachment; filename=\"images.zip\""),
+            ("X-Image-Count", &image_count.to_string()),
+        ],
+        archive,
+    )
+        .into_response())
+}
+
+/// Pack page images as page-001.webp, page-002.webp, ...
+#[cfg(feature = "pdf-render")]
+fn zip_pages(pages: Vec<(Vec<u8>, String)>) -> Result<Vec<u8>, String> {
+    let entries: Vec<(String, Vec<u8>)> = pages.into_iter().enumerate()
+        .map(|(index, (image, extension))| (format!("page-{:03}.{}", index + 1, extension), image))
+        .collect();
+    zip_entries(&entries)
+}
+
+/// Pack named files into a zip archive, in order
+/// Entries are stored, not deflated: their contents are already compressed
+fn zip_entries(entries: &[(String, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for (name, data) in entries {
+        archive.start_file(name.as_str(), options)
+            .and_then(|_| archive.write_all(data).map_err(Into::into))
+            .map_err(|e| format!("Failed to write {} to the archive: {}", name, e))?;
+    }
+    archive.finish()
+        .map(std::io::Cursor::into_inner)
+        .map_err(|e| format!("Failed to finish the archive: {}", e))
+}
+
+/// Check an upload the way compression would see it, without compressing it
+async fn validate_file(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Json<crate::InputKind>, (StatusCode, Json<ErrorResponse>)> {
+    let mut file: Option<Vec<u8>> = None;
+    
+    while let Some(field) = next_field(&mut multipart).await? {
+        if matches!(field.name(), Some("file" | "pdf")) {
+            let data = read_file_field(field, "file").await?;
+            check_input_size(&state, "File", data.len())?;
+            file = Some(data.to_vec());
+        }
+    }
+    
+    let file = file.ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "No file provided. Use a 'file' or 'pdf' field.".to_string(),
+            }),
+        )
+    })?;
+    
+    let size = file.len();
+    let request_id = current_request_id();
+    // Parsing the cross-reference data of a large PDF shouldn't hold up the async runtime
+    let kind = tokio::task::spawn_blocking(move || with_request_id(request_id, || crate::validate_input(&file)))
+        .await
+        .map_err(|e| {
+            error!("Validation task failed: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Validation task failed: {}", e),
+                }),
+            )
+        })?
+        .map_err(|e| {
+            info!("Rejected {} byte upload: {}", size, e);
+            (StatusCode::UNPROCESSABLE_ENTITY, Json(ErrorResponse { error: e }))
+        })?;
+    info!("Validated {} byte upload: {:?}", size, kind);
+    Ok(Json(kind))
+}
@@ -0,0 +1,135 @@
+// API keys with optional per-minute quotas, loaded from API_KEYS / API_KEY
+use log::error;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const QUOTA_WINDOW: Duration = Duration::from_secs(60);
+
+/// Who made a request, attached to request extensions after authentication
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiKeyIdentity {
+    /// Masked key (first 8 characters), safe to log or use as a metrics label
+    pub label: String,
+    /// Requests allowed per minute, or None for unlimited
+    pub per_minute_quota: Option<u32>,
+}
+
+struct KeyEntry {
+    key: String,
+    identity: ApiKeyIdentity,
+    window: Mutex<(Instant, u32)>, // Start of the current minute and requests counted in it
+}
+
+/// Outcome of checking a presented key
+pub(crate) enum KeyCheck {
+    Allowed(ApiKeyIdentity),
+    /// Valid key over its quota; retry after this many seconds
+    OverQuota(u64),
+    Unknown,
+}
+
+/// The set of accepted API keys
+pub struct ApiKeys {
+    entries: Vec<KeyEntry>,
+}
+
+impl ApiKeys {
+    /// Parse `key[:quota]` entries separated by commas, or a JSON map of key to quota (or null)
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let pairs: Vec<(String, Option<u32>)> = if spec.trim_start().starts_with('{') {
+            let map: HashMap<String, Option<u32>> = serde_json::from_str(spec)
+                .map_err(|e| format!("Invalid API_KEYS JSON: {}", e))?;
+            map.into_iter().collect()
+        } else {
+            spec.split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(|entry| match entry.rsplit_once(':') {
+                    Some((key, quota)) => quota.trim().parse::<u32>()
+                        .map(|quota| (key.trim().to_string(), Some(quota)))
+                        .map_err(|_| format!("Invalid quota for API key {}***: {}", mask(key), quota)),
+                    None => Ok((entry.to_string(), None)),
+                })
+                .collect::<Result<_, _>>()?
+        };
+
+        if pairs.iter().any(|(key, _)| key.is_empty()) {
+            return Err("API_KEYS contains an empty key".to_string());
+        }
+
+        let entries = pairs.into_iter()
+            .map(|(key, per_minute_quota)| KeyEntry {
+                identity: ApiKeyIdentity { label: mask(&key), per_minute_quota },
+                key,
+                window: Mutex::new((Instant::now(), 0)),
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// Keys from API_KEYS, falling back to the single unlimited API_KEY; None disables auth
+    /// An unparsable API_KEYS fails closed: no key is accepted
+    pub fn from_env() -> Option<Self> {
+        let spec = match std::env::var("API_KEYS") {
+            Ok(spec) if !spec.trim().is_empty() => spec,
+            _ => match std::env::var("API_KEY") {
+                // A single key is taken verbatim, so it may contain ':' or ','
+                Ok(key) if !key.is_empty() => return Some(Self::single(key)),
+                _ => return None,
+            },
+        };
+        match Self::parse(&spec) {
+            Ok(keys) => Some(keys),
+            Err(e) => {
+                error!("{} - rejecting all authenticated requests", e);
+                Some(Self { entries: Vec::new() })
+            }
+        }
+    }
+
+    fn single(key: String) -> Self {
+        Self {
+            entries: vec![KeyEntry {
+                identity: ApiKeyIdentity { label: mask(&key), per_minute_quota: None },
+                key,
+                window: Mutex::new((Instant::now(), 0)),
+            }],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Match a presented key and count the request against its quota
+    pub(crate) fn check(&self, provided: &str) -> KeyCheck {
+        let Some(entry) = self.entries.iter().find(|entry| entry.key == provided) else {
+            return KeyCheck::Unknown;
+        };
+        let Some(quota) = entry.identity.per_minute_quota else {
+            return KeyCheck::Allowed(entry.identity.clone());
+        };
+
+        let mut window = entry.window.lock().unwrap_or_else(|e| e.into_inner());
+        let elapsed = window.0.elapsed();
+        if elapsed >= QUOTA_WINDOW {
+            *window = (Instant::now(), 0);
+        }
+        if window.1 >= quota {
+            let retry_after = QUOTA_WINDOW.saturating_sub(window.0.elapsed()).as_secs().max(1);
+            return KeyCheck::OverQuota(retry_after);
+        }
+        window.1 += 1;
+        KeyCheck::Allowed(entry.identity.clone())
+    }
+}
+
+/// First 8 characters of a key, for logs
+fn mask(key: &str) -> String {
+    format!("{}***", key.chars().take(8).collect::<String>())
+}
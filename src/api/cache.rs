@@ -0,0 +1,83 @@
+// Byte-bounded LRU cache of compression results, keyed by upload content and options
+use lru::LruCache;
+use std::sync::{Arc, Mutex};
+
+/// Everything that influences the compressed output of an upload
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    content_hash: [u8; 32],
+    output_format: Option<String>,
+    settings_hash: [u8; 32], // Hash of every settings field, so a new option can't be left out
+}
+
+impl CacheKey {
+    /// Key for an upload compressed into `output_format` with the resolved `settings`
+    pub fn new(file_data: &[u8], output_format: Option<&str>, settings: &crate::CompressionSettings) -> Self {
+        Self {
+            content_hash: *blake3::hash(file_data).as_bytes(),
+            output_format: output_format.map(|f| f.to_lowercase()),
+            settings_hash: *blake3::hash(format!("{:?}", settings).as_bytes()).as_bytes(),
+        }
+    }
+}
+
+/// A cached compressed output with the response metadata needed to replay it
+#[derive(Clone)]
+pub struct CachedResult {
+    pub data: Arc<Vec<u8>>,
+    pub content_type: &'static str,
+    pub extension: String,
+}
+
+struct CacheInner {
+    entries: LruCache<CacheKey, CachedResult>,
+    total_bytes: usize,
+}
+
+/// LRU cache of compressed outputs, evicting least recently used entries past `max_bytes`
+pub struct ResultCache {
+    inner: Mutex<CacheInner>,
+    max_bytes: usize,
+}
+
+impl ResultCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            inner: Mutex::new(CacheInner {
+                entries: LruCache::unbounded(),
+                total_bytes: 0,
+            }),
+            max_bytes,
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<CachedResult> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.entries.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: CacheKey, result: CachedResult) {
+        let size = result.data.len();
+        if size > self.max_bytes {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(previous) = inner.entries.put(key, result) {
+            inner.total_bytes -= previous.data.len();
+        }
+        inner.total_bytes += size;
+
+        while inner.total_bytes > self.max_bytes {
+            match inner.entries.pop_lru() {
+                Some((_, evicted)) => inner.total_bytes -= evicted.data.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Total bytes of compressed output currently held
+    pub fn total_bytes(&self) -> usize {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).total_bytes
+    }
+}
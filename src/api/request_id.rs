@@ -0,0 +1,57 @@
+// Correlation IDs: one per request, echoed in the response and attached to its log lines
+use axum::extract::Request;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::cell::RefCell;
+
+/// Header a client may send its own ID in, and that carries the ID back
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Longest client-supplied ID that is accepted; longer (or non-printable) ones are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The request's correlation ID, available to handlers as an extension
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+tokio::task_local! {
+    static TASK_REQUEST_ID: String;
+}
+
+thread_local! {
+    static WORKER_REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Take the client's X-Request-Id (or generate one), expose it to the handler and its logs,
+/// and send it back on the response
+pub(crate) async fn propagate_request_id(mut request: Request, next: Next) -> Response {
+    let id = request.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|byte| byte.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    request.extensions_mut().insert(RequestId(id.clone()));
+
+    let mut response = TASK_REQUEST_ID.scope(id.clone(), next.run(request)).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// ID of the request being handled on this task, or by this blocking worker
+pub fn current_request_id() -> Option<String> {
+    TASK_REQUEST_ID.try_with(String::clone).ok()
+        .or_else(|| WORKER_REQUEST_ID.with(|id| id.borrow().clone()))
+}
+
+/// Run blocking work with the current request's ID attached to its log lines
+/// Tasks don't carry over to spawn_blocking threads, so the ID is captured before and set here
+pub(crate) fn with_request_id<T>(id: Option<String>, work: impl FnOnce() -> T) -> T {
+    let previous = WORKER_REQUEST_ID.with(|current| current.replace(id));
+    let result = work();
+    WORKER_REQUEST_ID.with(|current| *current.borrow_mut() = previous);
+    result
+}
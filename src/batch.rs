@@ -0,0 +1,37 @@
+// Compressing files from disk, singly or as a parallel batch
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::{compress_image_with_format, compress_pdf_bytes, compression_level_to_quality, detect_input_type, CompressionSettings, InputType};
+
+/// Read and compress one file, picking the PDF or image path with detect_input_type
+/// Returns (compressed_bytes, output_format_extension)
+pub fn compress_path(path: &Path, compression_level: u8) -> Result<(Vec<u8>, String), String> {
+    let input_bytes = std::fs::read(path)
+        .map_err(|e| format!("Failed to read input file: {}", e))?;
+
+    let file_name = path.file_name().and_then(|name| name.to_str());
+    match detect_input_type(&input_bytes, file_name) {
+        InputType::Pdf => compress_pdf_bytes(&input_bytes, compression_level).map(|bytes| (bytes, "pdf".to_string())),
+        InputType::Image(format) => {
+            let settings = CompressionSettings {
+                quality: compression_level_to_quality(compression_level),
+                ..Default::default()
+            };
+            compress_image_with_format(&input_bytes, format, None, &settings)
+        }
+        InputType::Unknown => Err("Unrecognized input type: neither a PDF nor a readable image".to_string()),
+    }
+}
+
+/// Compress several files concurrently on the rayon pool
+/// Each file's stream compression still parallelizes internally; results keep input order
+pub fn compress_paths_parallel(
+    paths: &[PathBuf],
+    compression_level: u8,
+) -> Vec<Result<(Vec<u8>, String), String>> {
+    paths
+        .par_iter()
+        .map(|path| compress_path(path, compression_level))
+        .collect()
+}
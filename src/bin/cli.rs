@@ -0,0 +1,226 @@
+// Command-line interface for PDF and image compression
+use std::ffi::OsString;
+use std::io::{IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use log::info;
+use serde::Serialize;
+use PDFcompressor::{CompressionSettings, InputType};
+
+const USAGE: &str = "Usage: pdfcompressor-cli [OPTIONS] [INPUT]
+
+Without INPUT (or with -), reads from stdin and writes the result to stdout.
+
+Options:
+  -l, --level <10-95>    Compression level (default: 75, overrides the config quality)
+  -c, --config <PATH>    Load compression settings from a JSON file
+  -o, --output <PATH>    Output file (default: <input>-compressed.<ext>, or stdout for stdin input)
+      --dry-run          Compress in memory and print the report without writing output
+      --report           Print a JSON report of per-step savings to stdout (stderr when output goes to stdout)
+  -h, --help             Show this help";
+
+struct CliArgs {
+    input: Option<PathBuf>, // None reads stdin
+    output: Option<PathBuf>,
+    level: Option<u8>,
+    config: Option<PathBuf>,
+    dry_run: bool,
+    report: bool,
+}
+
+/// Sizes printed by --report for an image, which has no per-step breakdown
+#[derive(Serialize)]
+struct ImageReport {
+    original_size: usize,
+    final_size: usize,
+}
+
+/// The parsed arguments, or None when -h/--help asked for the usage text
+/// Paths are kept as OS strings, so names that aren't UTF-8 still work
+fn parse_args(args: impl Iterator<Item = OsString>) -> Result<Option<CliArgs>, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut level = None;
+    let mut config = None;
+    let mut dry_run = false;
+    let mut report = false;
+
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-l" | "--level") => {
+                let value = args.next().ok_or("Missing value for --level")?;
+                level = Some(value.to_str().and_then(|value| value.parse::<u8>().ok())
+                    .ok_or_else(|| format!("Invalid compression level: {}", value.to_string_lossy()))?);
+            }
+            Some("-c" | "--config") => {
+                let value = args.next().ok_or("Missing value for --config")?;
+                config = Some(PathBuf::from(value));
+            }
+            Some("-o" | "--output") => {
+                let value = args.next().ok_or("Missing value for --output")?;
+                output = Some(PathBuf::from(value));
+            }
+            Some("--dry-run") => dry_run = true,
+            Some("--report") => report = true,
+            Some("-h" | "--help") => return Ok(None),
+            // Checked before the unknown-option arm, which `-` would otherwise match
+            Some("-") if input.is_none() => input = Some(None),
+            Some(other) if other.starts_with('-') && other != "-" => {
+                return Err(format!("Unknown option: {}\n\n{}", other, USAGE));
+            }
+            _ => {
+                if input.is_some() {
+                    return Err(format!("Unexpected argument: {}\n\n{}", arg.to_string_lossy(), USAGE));
+                }
+                input = Some(Some(PathBuf::from(arg)));
+            }
+        }
+    }
+
+    // Without an input path, stdin is only used when something is piped in
+    let input = match input {
+        Some(input) => input,
+        None if std::io::stdin().is_terminal() => return Err(USAGE.to_string()),
+        None => None,
+    };
+
+    Ok(Some(CliArgs {
+        input,
+        output,
+        level,
+        config,
+        dry_run,
+        report,
+    }))
+}
+
+fn run(args: CliArgs) -> Result<(), String> {
+    let input_bytes = match &args.input {
+        Some(path) => std::fs::read(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+        None => {
+            let mut bytes = Vec::new();
+            std::io::stdin().read_to_end(&mut bytes)
+                .map_err(|e| format!("Failed to read stdin: {}", e))?;
+            bytes
+        }
+    };
+    if input_bytes.is_empty() {
+        return Err("Input is empty".to_string());
+    }
+
+    // Compressed bytes go to stdout, so the report has to move out of the way
+    let to_stdout = args.input.is_none() && args.output.is_none() && !args.dry_run;
+    let print_report = |text: &str| {
+        if to_stdout {
+            eprintln!("{}", text);
+        } else {
+            println!("{}", text);
+        }
+    };
+
+    let mut settings = match &args.config {
+        Some(path) => {
+            let json = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+            CompressionSettings::parse_json(&json)?
+        }
+        None => CompressionSettings::default(),
+    };
+    if let Some(level) = args.level {
+        settings.quality = PDFcompressor::compression_level_to_quality(level);
+    }
+
+    let file_name = args.input.as_ref().and_then(|path| path.file_name()).and_then(|name| name.to_str());
+    let (output_bytes, extension) = match PDFcompressor::detect_input_type(&input_bytes, file_name) {
+        InputType::Pdf => {
+            let (output, report) = PDFcompressor::compress_pdf_with_settings_reported(&input_bytes, &settings)?;
+
+            if args.report || args.dry_run {
+                let json = serde_json::to_string_pretty(&report)
+                    .map_err(|e| format!("Failed to serialize report: {}", e))?;
+                print_report(&json);
+            }
+            (output, "pdf".to_string())
+        }
+        InputType::Image(format) => {
+            let (output, ext) = PDFcompressor::compress_image_with_format(&input_bytes, format, None, &settings)?;
+
+            if args.report || args.dry_run {
+                let report = ImageReport {
+                    original_size: input_bytes.len(),
+                    final_size: output.len(),
+                };
+                let json = serde_json::to_string_pretty(&report)
+                    .map_err(|e| format!("Failed to serialize report: {}", e))?;
+                print_report(&json);
+            }
+            (output, ext)
+        }
+        InputType::Unknown => return Err(match infer::get(&input_bytes) {
+            Some(kind) => format!("Unsupported input type: {}", kind.mime_type()),
+            None => "Unrecognized input type".to_string(),
+        }),
+    };
+
+    if args.dry_run {
+        info!("Dry run: no output written");
+        return Ok(());
+    }
+
+    if to_stdout {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&output_bytes)
+            .and_then(|()| stdout.flush())
+            .map_err(|e| format!("Failed to write stdout: {}", e))?;
+        info!("Wrote stdout ({} bytes -> {} bytes)", input_bytes.len(), output_bytes.len());
+        return Ok(());
+    }
+
+    let output_path = match (args.output, &args.input) {
+        (Some(output), _) => output,
+        (None, Some(input)) => {
+            let stem = input.file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("output");
+            input.with_file_name(format!("{}-compressed.{}", stem, extension))
+        }
+        (None, None) => unreachable!("stdin input without --output writes to stdout"),
+    };
+
+    std::fs::write(&output_path, &output_bytes)
+        .map_err(|e| format!("Failed to write {}: {}", output_path.display(), e))?;
+
+    info!("Wrote {} ({} bytes -> {} bytes)", output_path.display(), input_bytes.len(), output_bytes.len());
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    // Logs go to stderr so stdout stays clean for reports and piped output
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+        .target(env_logger::Target::Stderr)
+        .format_timestamp(None)
+        .init();
+
+    let args = match parse_args(std::env::args_os().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
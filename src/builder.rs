@@ -0,0 +1,158 @@
+// Fluent construction of CompressionSettings, so callers only name what they change
+use crate::{CompressionSettings, FlateLevel, ImagePolicy, QualityPreset, ResampleFilter};
+use lopdf::ObjectId;
+use std::collections::HashSet;
+
+/// Setters that store their argument as is
+macro_rules! setters {
+    ($($(#[$doc:meta])* $field:ident: $ty:ty;)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $field(mut self, $field: $ty) -> Self {
+                self.settings.$field = $field;
+                self
+            }
+        )*
+    };
+}
+
+/// Setters for optional fields, which take the value and store Some(value)
+macro_rules! optional_setters {
+    ($($(#[$doc:meta])* $field:ident: $ty:ty;)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $field(mut self, $field: $ty) -> Self {
+                self.settings.$field = Some($field);
+                self
+            }
+        )*
+    };
+}
+
+/// Builder for [`CompressionSettings`]; every field left unset keeps its default
+#[derive(Clone, Debug, Default)]
+pub struct CompressionSettingsBuilder {
+    settings: CompressionSettings,
+}
+
+impl CompressionSettings {
+    /// Start from the default settings and change only what's needed
+    pub fn builder() -> CompressionSettingsBuilder {
+        CompressionSettingsBuilder::default()
+    }
+}
+
+impl CompressionSettingsBuilder {
+    /// Finish building
+    pub fn build(self) -> CompressionSettings {
+        self.settings
+    }
+
+    /// Map a 10-95 compression level to the quality it stands for
+    pub fn level(self, level: u8) -> Self {
+        self.quality(crate::compression_level_to_quality(level))
+    }
+
+    /// Apply a Ghostscript-style preset: its level, target DPI and long-edge cap
+    pub fn preset(mut self, preset: QualityPreset) -> Self {
+        self.settings.target_dpi = Some(preset.target_dpi());
+        self.settings.max_dimension = preset.max_dimension();
+        self.level(preset.level())
+    }
+
+    setters! {
+        /// JPEG quality (0-100) that also drives downsampling and palette sizes
+        quality: u8;
+        /// Deflate effort for generic streams
+        flate_level: FlateLevel;
+        /// Re-deflate streams that are already FlateDecode
+        recompress_existing_flate: bool;
+        /// Streams that must be left byte-identical
+        protected_objects: HashSet<ObjectId>;
+        /// Also leave page content streams byte-identical
+        raster_only: bool;
+        /// Delete image XObjects and the Do operators drawing them
+        remove_images: bool;
+        /// Delete zero-length streams and objects unreachable from the trailer
+        aggressive_prune: bool;
+        /// Merge identical dictionary and array objects as well as identical streams
+        dedup_dictionaries: bool;
+        /// Drop resource entries whose objects no longer exist after compression
+        prune_resources: bool;
+        /// Auto format keeps PNG while png_size <= jpeg_size * this
+        lossless_preference: f32;
+        /// Stop rounds early once one shrinks the document by less than this percentage
+        round_convergence_pct: f64;
+        /// Drop XMP/Metadata objects
+        strip_metadata: bool;
+        /// Never re-encode pixels lossily or resize them
+        lossless: bool;
+        /// Re-encode images but keep their exact pixel dimensions
+        no_resize: bool;
+        /// Convert re-encoded images to grayscale
+        force_grayscale: bool;
+        /// RGB colour transparent pixels are composited over for JPEG output
+        flatten_background: [u8; 3];
+        /// What to do with image streams in PDFs
+        image_policy: ImagePolicy;
+        /// Soft per-stream time budget in milliseconds
+        stream_timeout_ms: u64;
+        /// Decode cap per stream
+        max_decompressed_bytes: usize;
+        /// Reject standalone images declaring more pixels than this
+        max_image_pixels: u64;
+        /// Give up on a PDF the parser can't load within this many milliseconds
+        load_timeout_ms: u64;
+        /// Emit progressive JPEGs instead of baseline
+        progressive: bool;
+        /// Losslessly re-pack baseline JPEGs with optimal Huffman tables
+        optimize_huffman: bool;
+        /// Filter used when downsampling images
+        resample_filter: ResampleFilter;
+        /// Reload the output and check it against the input
+        verify: bool;
+        /// On a failed verification, return the input instead of an error
+        verify_fallback: bool;
+        /// Compress streams, and try auto-format encodings, one at a time instead of in parallel
+        low_memory: bool;
+        /// Losslessly shrink gray-only and few-color PNGs
+        reduce_png: bool;
+        /// Quantize PNG output to a palette sized by quality, below quality 90
+        quantize_png: bool;
+        /// Draw form fields into the page content and remove the interactive form
+        flatten_forms: bool;
+        /// Record the crate version and JPEG quality as the document's /Producer
+        set_producer: bool;
+        /// Convert standalone images with a non-sRGB ICC profile into sRGB
+        convert_to_srgb: bool;
+        /// Detect scanned vs digital PDFs and adjust the settings to suit
+        auto_strategy: bool;
+        /// Keep what PDF/A conformance depends on when the input claims PDF/A
+        preserve_pdfa: bool;
+    }
+
+    optional_setters! {
+        /// Also try zopfli on generic streams this large (needs the zopfli feature)
+        zopfli_min_bytes: usize;
+        /// Number of compression rounds
+        rounds: u32;
+        /// Long-edge pixel cap for images
+        max_dimension: u32;
+        /// Short-edge pixel cap for images
+        max_short_edge: u32;
+        /// Return the input unchanged if it shrinks by less than this percentage
+        min_reduction_pct: f64;
+        /// Exact JPEG encoder quality (1-100)
+        jpeg_quality: u8;
+        /// Floor for the JPEG encoder quality
+        min_jpeg_quality: u8;
+        /// Ceiling for the JPEG encoder quality
+        max_jpeg_quality: u8;
+        /// Downsample PDF images to this resolution at their largest placed size
+        target_dpi: u32;
+        /// Compress streams on a private pool of this many threads
+        max_threads: usize;
+        /// Compress the streams of this many pages at a time (loading and output are not chunked)
+        page_chunk_size: usize;
+    }
+}
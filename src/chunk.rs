@@ -0,0 +1,63 @@
+// Grouping a document's streams by the pages that use them, for chunked compression
+// Only stream compression runs per group; the document is still loaded and written whole
+use crate::collect_references;
+use lopdf::{Document, Object, ObjectId, Stream};
+use std::collections::HashSet;
+
+/// Split `candidates` into groups covering `pages_per_chunk` consecutive pages each
+/// An object goes to the first chunk whose pages reach it; objects no page reaches
+/// (outline, document-level streams) form a final group
+pub(crate) fn group_by_pages<T: Copy>(
+    doc: &Document,
+    candidates: Vec<(ObjectId, T)>,
+    pages_per_chunk: usize,
+) -> Vec<Vec<(ObjectId, T)>> {
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let page_tree: HashSet<ObjectId> = page_ids.iter().copied().chain(page_tree_nodes(doc)).collect();
+
+    let mut chunk_of: ahash::AHashMap<ObjectId, usize> = ahash::AHashMap::new();
+    for (chunk, pages) in page_ids.chunks(pages_per_chunk.max(1)).enumerate() {
+        let mut visited = HashSet::new();
+        let mut pending: Vec<ObjectId> = pages.to_vec();
+        while let Some(id) = pending.pop() {
+            // Other pages are reached through links and /P entries, but belong to their own chunk
+            if !visited.insert(id) || (page_tree.contains(&id) && !pages.contains(&id)) {
+                continue;
+            }
+            // Shared with an earlier chunk, which already claimed everything below it
+            if chunk_of.get(&id).is_some_and(|earlier| *earlier != chunk) {
+                continue;
+            }
+            let Ok(object) = doc.get_object(id) else {
+                continue;
+            };
+            chunk_of.insert(id, chunk);
+            // Parent links lead back up to fields and nodes shared by every page
+            let mut references = HashSet::new();
+            match object {
+                Object::Dictionary(dict) | Object::Stream(Stream { dict, .. }) => dict.iter()
+                    .filter(|(key, _)| key.as_slice() != b"Parent")
+                    .for_each(|(_, value)| collect_references(value, &mut references)),
+                other => collect_references(other, &mut references),
+            }
+            pending.extend(references);
+        }
+    }
+
+    let mut groups: Vec<Vec<(ObjectId, T)>> = vec![Vec::new(); page_ids.len().div_ceil(pages_per_chunk.max(1)) + 1];
+    let unplaced = groups.len() - 1;
+    for (id, value) in candidates {
+        groups[chunk_of.get(&id).copied().unwrap_or(unplaced)].push((id, value));
+    }
+    groups.retain(|group| !group.is_empty());
+    groups
+}
+
+fn page_tree_nodes(doc: &Document) -> impl Iterator<Item = ObjectId> + '_ {
+    doc.objects.iter()
+        .filter(|(_, object)| matches!(
+            object.as_dict().and_then(|dict| dict.get(b"Type")).and_then(Object::as_name),
+            Ok(b"Page" | b"Pages")
+        ))
+        .map(|(id, _)| *id)
+}
@@ -0,0 +1,101 @@
+// Normalizing standalone images to sRGB using their embedded ICC profiles
+use image::{DynamicImage, GrayImage, ImageDecoder, ImageFormat, RgbImage, RgbaImage};
+use log::{debug, info, warn};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+use std::io::Cursor;
+
+/// Colorants this close to sRGB's are treated as sRGB (ICC stores them as s15Fixed16)
+const COLORANT_TOLERANCE: f64 = 0.002;
+
+/// Convert an image decoded from `data` into sRGB if `data` embeds an RGB profile for another space
+/// The encoders write no profile, which viewers read as sRGB, so the pixels alone carry the color
+pub(crate) fn convert_to_srgb(img: DynamicImage, data: &[u8], format: ImageFormat) -> DynamicImage {
+    let Some(icc) = embedded_icc_profile(data, format) else {
+        return img;
+    };
+    let profile = match ColorProfile::new_from_slice(&icc) {
+        Ok(profile) => profile,
+        Err(e) => {
+            warn!("Ignoring unreadable ICC profile ({} bytes): {:?}", icc.len(), e);
+            return img;
+        }
+    };
+    if profile.color_space != DataColorSpace::Rgb || is_srgb(&profile) {
+        debug!("ICC profile is {:?} and needs no conversion", profile.color_space);
+        return img;
+    }
+
+    let srgb = ColorProfile::new_srgb();
+    let (width, height) = (img.width(), img.height());
+    let converted = if img.color().has_alpha() {
+        let source = img.to_rgba8();
+        let mut pixels = vec![0u8; source.len()];
+        profile.create_transform_8bit(Layout::Rgba, &srgb, Layout::Rgba, TransformOptions::default())
+            .and_then(|transform| transform.transform(&source, &mut pixels))
+            .ok()
+            .and_then(|_| RgbaImage::from_raw(width, height, pixels))
+            .map(DynamicImage::ImageRgba8)
+    } else {
+        let source = img.to_rgb8();
+        let mut pixels = vec![0u8; source.len()];
+        profile.create_transform_8bit(Layout::Rgb, &srgb, Layout::Rgb, TransformOptions::default())
+            .and_then(|transform| transform.transform(&source, &mut pixels))
+            .ok()
+            .and_then(|_| RgbImage::from_raw(width, height, pixels))
+            .map(DynamicImage::ImageRgb8)
+    };
+
+    match converted {
+        Some(converted) => {
+            info!("Converted {}x{} image from its ICC profile to sRGB", width, height);
+            converted
+        }
+        None => {
+            warn!("Could not build an sRGB transform for the embedded ICC profile, keeping pixels as they are");
+            img
+        }
+    }
+}
+
+/// Composite an image with an alpha channel over `background`, for outputs that have none
+/// Gray images stay single-channel when the background is a gray too
+pub(crate) fn flatten_alpha(img: DynamicImage, background: [u8; 3]) -> DynamicImage {
+    if !img.color().has_alpha() {
+        return img;
+    }
+    let blend = |value: u8, background: u8, alpha: u8| {
+        ((value as u32 * alpha as u32 + background as u32 * (255 - alpha as u32) + 127) / 255) as u8
+    };
+    let (width, height) = (img.width(), img.height());
+
+    let [red, green, blue] = background;
+    if !img.color().has_color() && red == green && green == blue {
+        let pixels = img.to_luma_alpha8().pixels().map(|pixel| blend(pixel[0], red, pixel[1])).collect();
+        return GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8).unwrap_or(img);
+    }
+    let pixels = img.to_rgba8().pixels()
+        .flat_map(|pixel| [blend(pixel[0], red, pixel[3]), blend(pixel[1], green, pixel[3]), blend(pixel[2], blue, pixel[3])])
+        .collect();
+    RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8).unwrap_or(img)
+}
+
+/// ICC profile embedded in a PNG (iCCP) or JPEG (APP2), if any
+fn embedded_icc_profile(data: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    match format {
+        ImageFormat::Png => image::codecs::png::PngDecoder::new(Cursor::new(data)).ok()?.icc_profile(),
+        ImageFormat::Jpeg => image::codecs::jpeg::JpegDecoder::new(Cursor::new(data)).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// Whether a matrix profile has sRGB's colorants, so converting would only add rounding
+fn is_srgb(profile: &ColorProfile) -> bool {
+    let srgb = ColorProfile::new_srgb();
+    let close = |a: &moxcms::Xyzd, b: &moxcms::Xyzd| {
+        (a.x - b.x).abs() < COLORANT_TOLERANCE && (a.y - b.y).abs() < COLORANT_TOLERANCE && (a.z - b.z).abs() < COLORANT_TOLERANCE
+    };
+    profile.lut_a_to_b_perceptual.is_none()
+        && close(&profile.red_colorant, &srgb.red_colorant)
+        && close(&profile.green_colorant, &srgb.green_colorant)
+        && close(&profile.blue_colorant, &srgb.blue_colorant)
+}
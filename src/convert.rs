@@ -0,0 +1,165 @@
+// Conversions between standalone images and PDF documents
+use lopdf::{Dictionary, Document, Object, Stream};
+use image::GenericImageView;
+use log::info;
+
+use crate::{check_image_file_size, compress_image_bytes, CompressionSettings};
+
+/// Resolution PDF pages are rasterized at before the quality band downsamples them
+#[cfg(feature = "pdf-render")]
+const PAGE_RENDER_DPI: f32 = 150.0;
+
+/// Compress each image and wrap them into a PDF with one image per page
+/// Each page is sized to its image (1 pixel = 1 point)
+pub fn images_to_pdf(images: &[Vec<u8>], compression_level: u8) -> Result<Vec<u8>, String> {
+    if images.is_empty() {
+        return Err("No images provided".to_string());
+    }
+
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let mut page_ids = Vec::with_capacity(images.len());
+
+    for (index, image_bytes) in images.iter().enumerate() {
+        let (image_stream, width, height) = image_to_pdf_stream(image_bytes, compression_level)
+            .map_err(|e| format!("Image {}: {}", index + 1, e))?;
+        let image_id = doc.add_object(image_stream);
+
+        let mut xobjects = Dictionary::new();
+        xobjects.set("Im1", Object::Reference(image_id));
+        let mut resources = Dictionary::new();
+        resources.set("XObject", Object::Dictionary(xobjects));
+
+        // Scale the unit square to the full page
+        let content = format!("q {} 0 0 {} 0 0 cm /Im1 Do Q", width, height).into_bytes();
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), content));
+
+        let page_id = doc.add_object(Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Page".to_vec())),
+            ("Parent", Object::Reference(pages_id)),
+            ("Resources", Object::Dictionary(resources)),
+            ("MediaBox", Object::Array(vec![
+                Object::Integer(0),
+                Object::Integer(0),
+                Object::Integer(width as i64),
+                Object::Integer(height as i64),
+            ])),
+            ("Contents", Object::Reference(content_id)),
+        ]));
+        page_ids.push(page_id);
+    }
+
+    let page_count = page_ids.len();
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(page_ids.into_iter().map(Object::Reference).collect())),
+            ("Count", Object::Integer(page_count as i64)),
+        ])),
+    );
+
+    let catalog_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Catalog".to_vec())),
+        ("Pages", Object::Reference(pages_id)),
+    ]));
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    doc.compress();
+
+    let mut output = Vec::new();
+    doc.save_to(&mut output)
+        .map_err(|e| format!("Failed to save: {}", e))?;
+
+    info!("Built {}-page PDF from images: {} bytes", page_count, output.len());
+
+    Ok(output)
+}
+
+/// Compress one image to JPEG and wrap it as a DCTDecode image XObject
+fn image_to_pdf_stream(image_bytes: &[u8], compression_level: u8) -> Result<(Stream, u32, u32), String> {
+    check_image_file_size(image_bytes, None, CompressionSettings::default().max_image_pixels)?;
+
+    // The JPEG encoder composites any transparency over white
+    let (jpeg, _) = compress_image_bytes(image_bytes, compression_level, Some("jpg"))?;
+
+    // Read back the encoded dimensions, which may be downsampled
+    let encoded = image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg)
+        .map_err(|e| format!("Failed to read compressed image: {}", e))?;
+    let (width, height) = encoded.dimensions();
+    let color_space: &[u8] = if encoded.color().channel_count() == 1 {
+        b"DeviceGray"
+    } else {
+        b"DeviceRGB"
+    };
+
+    let dict = Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"XObject".to_vec())),
+        ("Subtype", Object::Name(b"Image".to_vec())),
+        ("Width", Object::Integer(width as i64)),
+        ("Height", Object::Integer(height as i64)),
+        ("ColorSpace", Object::Name(color_space.to_vec())),
+        ("BitsPerComponent", Object::Integer(8)),
+        ("Filter", Object::Name(b"DCTDecode".to_vec())),
+    ]);
+
+    Ok((Stream::new(dict, jpeg).with_compression(false), width, height))
+}
+
+/// Rasterize each page of a PDF and compress it as an image, for galleries and previews
+/// Pages are rendered at PAGE_RENDER_DPI on white, then encoded like standalone images at the
+/// compression level. `format` is "webp", "png" or "jpg"; returns (bytes, extension) per page
+#[cfg(feature = "pdf-render")]
+pub fn pdf_pages_to_images(input: &[u8], compression_level: u8, format: &str) -> Result<Vec<(Vec<u8>, String)>, String> {
+    use pdfium_render::prelude::PdfRenderConfig;
+
+    let (image_format, extension) = match format.to_lowercase().as_str() {
+        "webp" => (image::ImageFormat::WebP, "webp"),
+        "png" => (image::ImageFormat::Png, "png"),
+        "jpg" | "jpeg" => (image::ImageFormat::Jpeg, "jpg"),
+        _ => return Err(format!("Unsupported page image format: {} (expected webp, png or jpg)", format)),
+    };
+    let settings = CompressionSettings {
+        quality: crate::compression_level_to_quality(compression_level.clamp(10, 95)),
+        ..Default::default()
+    };
+
+    let document = pdfium()?
+        .load_pdf_from_byte_slice(input, None)
+        .map_err(|e| format!("Failed to load PDF for rendering: {}", e))?;
+    let config = PdfRenderConfig::new().scale_page_by_factor(PAGE_RENDER_DPI / 72.0);
+
+    let images = document.pages().iter().enumerate()
+        .map(|(index, page)| {
+            let rendered = page.render_with_config(&config)
+                .map_err(|e| format!("Page {}: failed to render: {}", index + 1, e))?
+                .as_image();
+            // Rendered pages are opaque, so the alpha channel only costs bytes
+            let rendered = image::DynamicImage::ImageRgb8(rendered.to_rgb8());
+            let encoded = crate::encode_image_with_quality(&rendered, &settings, image_format)
+                .map_err(|e| format!("Page {}: {}", index + 1, e))?;
+            Ok((encoded, extension.to_string()))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    info!("Rendered {} PDF pages to {} images", images.len(), extension);
+    Ok(images)
+}
+
+/// The pdfium library, bound on first use from PDFIUM_LIB_PATH, the working directory or the system
+/// Binding initializes the library for the whole process, so it happens once
+#[cfg(feature = "pdf-render")]
+fn pdfium() -> Result<&'static pdfium_render::prelude::Pdfium, String> {
+    use pdfium_render::prelude::Pdfium;
+    use std::sync::OnceLock;
+
+    static PDFIUM: OnceLock<Result<Pdfium, String>> = OnceLock::new();
+    PDFIUM.get_or_init(|| {
+        let directory = std::env::var("PDFIUM_LIB_PATH").unwrap_or_else(|_| "./".to_string());
+        Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path(&directory))
+            .or_else(|_| Pdfium::bind_to_system_library())
+            .map(Pdfium::new)
+            .map_err(|e| format!("Failed to load the pdfium library (set PDFIUM_LIB_PATH to its directory): {}", e))
+    })
+    .as_ref()
+    .map_err(Clone::clone)
+}
@@ -0,0 +1,301 @@
+// Bounded stream decoding, guarding against decompression bombs and runaway work
+use lopdf::{Dictionary, Object, Stream};
+use log::{debug, warn};
+use std::borrow::Cow;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// Generic streams may not inflate beyond this many times their stored size
+const MAX_DECOMPRESSION_RATIO: usize = 200;
+/// Generic streams may always inflate to at least this size, so small dense streams aren't rejected
+const MIN_DECOMPRESSION_ALLOWANCE: usize = 16 * 1024 * 1024;
+
+/// Work budget for one stream: a soft deadline checked between steps and a decoded-size cap
+pub(crate) struct StreamBudget {
+    started: Instant,
+    timeout: Duration,
+    max_decompressed: usize,
+}
+
+impl StreamBudget {
+    pub(crate) fn new(timeout: Duration, max_decompressed: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            timeout,
+            max_decompressed,
+        }
+    }
+
+    /// Decoded-size cap for a generic stream of `stored_len` bytes
+    pub(crate) fn generic_limit(&self, stored_len: usize) -> usize {
+        stored_len
+            .saturating_mul(MAX_DECOMPRESSION_RATIO)
+            .max(MIN_DECOMPRESSION_ALLOWANCE)
+            .min(self.max_decompressed)
+    }
+
+    /// Absolute decoded-size cap
+    pub(crate) fn max_decompressed(&self) -> usize {
+        self.max_decompressed
+    }
+
+    /// Fail once the deadline has passed; `stage` names the step for the log
+    pub(crate) fn check(&self, stage: &str) -> Result<(), String> {
+        let elapsed = self.started.elapsed();
+        if elapsed > self.timeout {
+            warn!("Stream timed out after {:?} during {}, keeping original", elapsed, stage);
+            return Err(format!("Timed out after {:?} during {}", elapsed, stage));
+        }
+        Ok(())
+    }
+}
+
+/// Inflate zlib data, failing as soon as the output would exceed `limit` bytes
+pub(crate) fn inflate_bounded(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::new();
+    flate2::read::ZlibDecoder::new(data)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|e| format!("Flate decode failed: {}", e))?;
+
+    if decoded.len() > limit {
+        warn!("Stream inflates past {} bytes from {} stored bytes, keeping original", limit, data.len());
+        return Err(format!("Decompressed size exceeds limit of {} bytes", limit));
+    }
+    Ok(decoded)
+}
+
+/// Decode LZW data (PDF 32000-1, 7.4.4), failing as soon as the output would exceed `limit` bytes
+/// `early_change` is the EarlyChange parameter: code widths grow one code early, as in TIFF
+pub(crate) fn lzw_decode_bounded(data: &[u8], early_change: bool, limit: usize) -> Result<Vec<u8>, String> {
+    use weezl::{decode::Decoder, BitOrder, LzwStatus};
+
+    let mut decoder = if early_change {
+        Decoder::with_tiff_size_switch(BitOrder::Msb, 8)
+    } else {
+        Decoder::new(BitOrder::Msb, 8)
+    };
+    let mut decoded = Vec::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut input = data;
+    loop {
+        let result = decoder.decode_bytes(input, &mut buffer);
+        input = &input[result.consumed_in..];
+        decoded.extend_from_slice(&buffer[..result.consumed_out]);
+        if decoded.len() > limit {
+            warn!("LZW stream decodes past {} bytes from {} stored bytes, keeping original", limit, data.len());
+            return Err(format!("Decompressed size exceeds limit of {} bytes", limit));
+        }
+        match result.status {
+            // Streams missing the end-of-data code just run out of input
+            Ok(LzwStatus::Done | LzwStatus::NoProgress) => return Ok(decoded),
+            Ok(LzwStatus::Ok) => {}
+            Err(e) => return Err(format!("LZW decode failed: {}", e)),
+        }
+    }
+}
+
+/// A stream's filters in the order they are undone, each with its parameter dictionary
+/// None if /Filter or /DecodeParms is malformed or uses indirect parameters
+pub(crate) fn filter_chain(dict: &Dictionary) -> Option<Vec<(&[u8], Option<&Dictionary>)>> {
+    let names: Vec<&[u8]> = match dict.get(b"Filter") {
+        Err(_) | Ok(Object::Null) => Vec::new(),
+        Ok(Object::Name(name)) => vec![name.as_slice()],
+        Ok(Object::Array(filters)) => filters.iter()
+            .map(|filter| filter.as_name().ok())
+            .collect::<Option<_>>()?,
+        Ok(_) => return None,
+    };
+    // A single parameter dictionary, or one entry (dictionary or null) per filter
+    let params: Vec<Option<&Dictionary>> = match dict.get(b"DecodeParms") {
+        Err(_) | Ok(Object::Null) => vec![None; names.len()],
+        Ok(Object::Dictionary(params)) if names.len() == 1 => vec![Some(params)],
+        Ok(Object::Array(params)) if params.len() == names.len() => params.iter()
+            .map(|params| match params {
+                Object::Null => Some(None),
+                Object::Dictionary(params) => Some(Some(params)),
+                _ => None,
+            })
+            .collect::<Option<_>>()?,
+        Ok(_) => return None,
+    };
+    Some(names.into_iter().zip(params).collect())
+}
+
+/// Decode a filtered stream within `limit` bytes, undoing every filter of a chain and any predictor
+/// Ok(None) means the filter chain or its parameters aren't supported, which is not an error
+pub(crate) fn decode_stream_bounded(stream: &Stream, limit: usize) -> Result<Option<Vec<u8>>, String> {
+    let Some(chain) = filter_chain(&stream.dict) else {
+        return Ok(None);
+    };
+    decode_filters(&stream.content, &chain, limit)
+}
+
+/// Undo `chain` over `data`, each step within `limit` bytes
+/// Ok(None) if any filter in it isn't one this module decodes
+pub(crate) fn decode_filters(data: &[u8], chain: &[(&[u8], Option<&Dictionary>)], limit: usize) -> Result<Option<Vec<u8>>, String> {
+    let mut decoded = Cow::Borrowed(data);
+    for (name, params) in chain {
+        let step = match *name {
+            b"FlateDecode" => inflate_bounded(&decoded, limit)?,
+            b"LZWDecode" => {
+                let early_change = params
+                    .and_then(|params| params.get(b"EarlyChange").and_then(Object::as_i64).ok())
+                    .is_none_or(|value| value != 0);
+                lzw_decode_bounded(&decoded, early_change, limit)?
+            }
+            b"ASCII85Decode" => ascii85_decode(&decoded)?,
+            b"ASCIIHexDecode" => ascii_hex_decode(&decoded)?,
+            other => {
+                debug!("No decoder for filter {} in chain, keeping stream as is", String::from_utf8_lossy(other));
+                return Ok(None);
+            }
+        };
+        // Only flate and LZW take predictors; the ASCII filters have no parameters
+        decoded = match params.filter(|_| matches!(*name, b"FlateDecode" | b"LZWDecode")) {
+            Some(params) => match undo_predictor(step, params)? {
+                Some(step) => Cow::Owned(step),
+                None => return Ok(None),
+            },
+            None => Cow::Owned(step),
+        };
+    }
+    Ok(Some(decoded.into_owned()))
+}
+
+/// Decode ASCII base-85 data (PDF 32000-1, 7.4.3), up to the `~>` end marker
+fn ascii85_decode(data: &[u8]) -> Result<Vec<u8>, String> {
+    let data = data.strip_prefix(b"<~").unwrap_or(data);
+    let mut decoded = Vec::with_capacity(data.len() / 5 * 4);
+    let mut group = [0u8; 5];
+    let mut filled = 0;
+    for &byte in data {
+        match byte {
+            b'~' => break,
+            b'z' if filled == 0 => decoded.extend_from_slice(&[0; 4]),
+            b'!'..=b'u' => {
+                group[filled] = byte - b'!';
+                filled += 1;
+                if filled == 5 {
+                    decoded.extend_from_slice(&ascii85_group(&group)?);
+                    filled = 0;
+                }
+            }
+            byte if byte.is_ascii_whitespace() || byte == 0 => {}
+            other => return Err(format!("ASCII85 decode failed: invalid byte {:#04x}", other)),
+        }
+    }
+    // A final partial group of n characters stands for n - 1 bytes, padded with 'u'
+    match filled {
+        0 => {}
+        1 => return Err("ASCII85 decode failed: lone character in final group".to_string()),
+        _ => {
+            group[filled..].fill(b'u' - b'!');
+            decoded.extend_from_slice(&ascii85_group(&group)?[..filled - 1]);
+        }
+    }
+    Ok(decoded)
+}
+
+fn ascii85_group(group: &[u8; 5]) -> Result<[u8; 4], String> {
+    let value = group.iter().try_fold(0u32, |value, &digit| value.checked_mul(85)?.checked_add(digit as u32))
+        .ok_or_else(|| "ASCII85 decode failed: group out of range".to_string())?;
+    Ok(value.to_be_bytes())
+}
+
+/// Decode ASCII hexadecimal data (PDF 32000-1, 7.4.2), up to the `>` end marker
+fn ascii_hex_decode(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::with_capacity(data.len() / 2);
+    let mut high: Option<u8> = None;
+    for &byte in data {
+        let nibble = match byte {
+            b'>' => break,
+            b'0'..=b'9' => byte - b'0',
+            b'a'..=b'f' => byte - b'a' + 10,
+            b'A'..=b'F' => byte - b'A' + 10,
+            byte if byte.is_ascii_whitespace() || byte == 0 => continue,
+            other => return Err(format!("ASCIIHex decode failed: invalid byte {:#04x}", other)),
+        };
+        match high.take() {
+            Some(high) => decoded.push(high << 4 | nibble),
+            None => high = Some(nibble),
+        }
+    }
+    // An odd final digit is followed by an implied 0
+    decoded.extend(high.map(|high| high << 4));
+    Ok(decoded)
+}
+
+/// Reverse the predictor named in a flate or LZW parameter dictionary (PDF 32000-1, 7.4.4.4)
+/// Ok(None) means the predictor isn't supported or the data doesn't fit its row layout
+fn undo_predictor(mut data: Vec<u8>, params: &Dictionary) -> Result<Option<Vec<u8>>, String> {
+    let int = |key: &[u8], default: i64| params.get(key).and_then(Object::as_i64).unwrap_or(default);
+    let predictor = int(b"Predictor", 1);
+    let colors = int(b"Colors", 1);
+    let bits = int(b"BitsPerComponent", 8);
+    let columns = int(b"Columns", 1);
+    if !(1..=32).contains(&colors) || ![1, 2, 4, 8, 16].contains(&bits) || !(1..=1 << 24).contains(&columns) {
+        return Ok(None);
+    }
+    let bits_per_pixel = (colors * bits) as usize;
+    let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+    let row_len = (bits_per_pixel * columns as usize).div_ceil(8);
+
+    match predictor {
+        1 => Ok(Some(data)),
+        // TIFF predictor 2: each sample is stored as the difference from the one to its left
+        2 if bits == 8 && data.len().is_multiple_of(row_len) => {
+            for row in data.chunks_exact_mut(row_len) {
+                for i in bytes_per_pixel..row_len {
+                    row[i] = row[i].wrapping_add(row[i - bytes_per_pixel]);
+                }
+            }
+            Ok(Some(data))
+        }
+        // PNG predictors: every row starts with its own filter type byte
+        10..=15 if data.len().is_multiple_of(row_len + 1) => {
+            let mut decoded = Vec::with_capacity(data.len() / (row_len + 1) * row_len);
+            let mut previous = vec![0u8; row_len];
+            for row in data.chunks_exact(row_len + 1) {
+                let mut current = row[1..].to_vec();
+                for i in 0..row_len {
+                    let left = if i >= bytes_per_pixel { current[i - bytes_per_pixel] } else { 0 };
+                    let up = previous[i];
+                    let up_left = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+                    let prediction = match row[0] {
+                        0 => 0,
+                        1 => left,
+                        2 => up,
+                        3 => ((left as u16 + up as u16) / 2) as u8,
+                        4 => paeth(left, up, up_left),
+                        other => return Err(format!("Invalid PNG predictor row type {}", other)),
+                    };
+                    current[i] = current[i].wrapping_add(prediction);
+                }
+                decoded.extend_from_slice(&current);
+                previous = current;
+            }
+            Ok(Some(decoded))
+        }
+        _ => {
+            debug!("Unsupported predictor {} for {} bytes, keeping stream as is", predictor, data.len());
+            Ok(None)
+        }
+    }
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (to_left, to_up, to_up_left) = (
+        (estimate - left as i16).abs(),
+        (estimate - up as i16).abs(),
+        (estimate - up_left as i16).abs(),
+    );
+    if to_left <= to_up && to_left <= to_up_left {
+        left
+    } else if to_up <= to_up_left {
+        up
+    } else {
+        up_left
+    }
+}
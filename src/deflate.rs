@@ -0,0 +1,46 @@
+// zlib (FlateDecode) compression, with an optional slower zopfli pass (selected via cargo features)
+use crate::FlateLevel;
+use flate2::write::ZlibEncoder;
+use log::debug;
+use std::io::Write;
+
+/// Compress `data` for a FlateDecode stream
+/// Data of at least `zopfli_min_bytes` is also compressed with zopfli, keeping the smaller output
+pub(crate) fn zlib_compress(data: &[u8], level: FlateLevel, zopfli_min_bytes: Option<usize>) -> Option<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), level.to_compression());
+    encoder.write_all(data).ok()?;
+    let flate = encoder.finish().ok()?;
+
+    if zopfli_min_bytes.is_none_or(|min_bytes| data.len() < min_bytes) {
+        return Some(flate);
+    }
+    match zopfli_compress(data) {
+        Some(zopfli) if zopfli.len() < flate.len() => {
+            debug!("zopfli beats flate2: {} vs {} bytes", zopfli.len(), flate.len());
+            Some(zopfli)
+        }
+        _ => Some(flate),
+    }
+}
+
+/// zlib stream from zopfli's exhaustive deflate search, a few percent smaller than flate2's best
+#[cfg(feature = "zopfli")]
+fn zopfli_compress(data: &[u8]) -> Option<Vec<u8>> {
+    use std::num::NonZeroU64;
+
+    // Each iteration is a full pass over the data, so big streams get fewer of them
+    let iterations = if data.len() > 1024 * 1024 { 5 } else { 15 };
+    let options = zopfli::Options {
+        iteration_count: NonZeroU64::new(iterations)?,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    zopfli::compress(options, zopfli::Format::Zlib, data, &mut output).ok()?;
+    Some(output)
+}
+
+#[cfg(not(feature = "zopfli"))]
+fn zopfli_compress(_data: &[u8]) -> Option<Vec<u8>> {
+    debug!("Built without the zopfli feature, using flate2 only");
+    None
+}
@@ -0,0 +1,122 @@
+// Telling PDFs from images, shared by the API, the GUI and the CLI
+use image::ImageFormat;
+use lopdf::{Document, Object};
+use log::debug;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::{placement, verify, CompressionSettings};
+
+/// How far into a file a PDF header is searched for; some writers put junk before it
+const PDF_HEADER_SEARCH_BYTES: usize = 1024;
+
+/// Share of stream bytes that must be image data for a PDF to count as scanned
+const SCANNED_IMAGE_SHARE: f64 = 0.8;
+
+/// Share of the MediaBox a page's only image must cover for the page to look scanned
+const SCANNED_PAGE_COVERAGE: f32 = 0.9;
+
+/// What an input file holds, as far as compression is concerned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputType {
+    Pdf,
+    Image(ImageFormat), // Only formats this build can decode
+    Unknown,
+}
+
+/// Work out whether `bytes` is a PDF or an image
+/// Magic bytes are tried first, then the extension of `filename_hint`, then a search of the
+/// first 1 KB for a PDF header that leading junk (a BOM, a mail header) pushed out of place
+pub fn detect_input_type(bytes: &[u8], filename_hint: Option<&str>) -> InputType {
+    if bytes.starts_with(b"%PDF") || infer::get(bytes).is_some_and(|kind| kind.mime_type() == "application/pdf") {
+        return InputType::Pdf;
+    }
+    if let Some(format) = image::guess_format(bytes).ok().filter(|format| format.reading_enabled()) {
+        return InputType::Image(format);
+    }
+
+    let extension = filename_hint
+        .and_then(|name| Path::new(name).extension())
+        .and_then(|extension| extension.to_str());
+    if let Some(extension) = extension {
+        if extension.eq_ignore_ascii_case("pdf") {
+            debug!("No magic bytes, treating {:?} as a PDF by its extension", filename_hint);
+            return InputType::Pdf;
+        }
+        if let Some(format) = ImageFormat::from_extension(extension).filter(|format| format.reading_enabled()) {
+            debug!("No magic bytes, treating {:?} as {:?} by its extension", filename_hint, format);
+            return InputType::Image(format);
+        }
+    }
+
+    if pdf_header_offset(bytes).is_some() {
+        debug!("Found a PDF header after leading junk");
+        return InputType::Pdf;
+    }
+    InputType::Unknown
+}
+
+/// Offset of the `%PDF-` header within the first 1 KB
+pub(crate) fn pdf_header_offset(bytes: &[u8]) -> Option<usize> {
+    const HEADER: &[u8] = b"%PDF-";
+    bytes[..bytes.len().min(PDF_HEADER_SEARCH_BYTES)]
+        .windows(HEADER.len())
+        .position(|window| window == HEADER)
+}
+
+/// How a PDF's pages were made, as far as choosing a compression strategy is concerned
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PdfKind {
+    Scanned, // Pages are photographs or scans, one image each
+    Digital, // Text and vector graphics, images (if any) are a small part of the file
+}
+
+/// Guess whether a PDF is a scan or was produced digitally
+/// Scanned means image data makes up most of the stream bytes and most pages draw a single
+/// image covering (nearly) the whole MediaBox
+pub fn detect_pdf_kind(doc: &Document) -> PdfKind {
+    let (mut image_bytes, mut total_bytes) = (0usize, 0usize);
+    for object in doc.objects.values() {
+        if let Object::Stream(stream) = object {
+            total_bytes += stream.content.len();
+            if is_image(object) {
+                image_bytes += stream.content.len();
+            }
+        }
+    }
+    if total_bytes == 0 || (image_bytes as f64) < total_bytes as f64 * SCANNED_IMAGE_SHARE {
+        debug!("Images are {} of {} stream bytes, treating the PDF as digital", image_bytes, total_bytes);
+        return PdfKind::Digital;
+    }
+
+    let placed_sizes = placement::image_display_sizes(doc, &CompressionSettings::default());
+    let pages = doc.get_pages();
+    let scanned_pages = pages.values()
+        .filter(|page_id| {
+            let mut images = placement::page_xobjects(doc, **page_id).into_values()
+                .filter(|id| doc.get_object(*id).is_ok_and(is_image));
+            let (Some(image), None) = (images.next(), images.next()) else {
+                return false;
+            };
+            let (Some((width, height)), Some([x0, y0, x1, y1])) = (placed_sizes.get(&image), verify::media_box(doc, **page_id)) else {
+                return false;
+            };
+            width * height >= ((x1 - x0) * (y1 - y0)).abs() * SCANNED_PAGE_COVERAGE
+        })
+        .count();
+    debug!("{} of {} pages draw a single full-page image", scanned_pages, pages.len());
+
+    if scanned_pages * 2 > pages.len() {
+        PdfKind::Scanned
+    } else {
+        PdfKind::Digital
+    }
+}
+
+fn is_image(object: &Object) -> bool {
+    matches!(
+        object.as_stream().and_then(|stream| stream.dict.get(b"Subtype")).and_then(Object::as_name),
+        Ok(b"Image")
+    )
+}
@@ -0,0 +1,139 @@
+// Fast size estimate for PDF compression, without running the full pipeline
+use crate::decode::StreamBudget;
+use crate::xref::load_document;
+use crate::{
+    compression_level_to_quality, decode_image_stream, downsample_limit, encode_jpeg,
+    has_color_key_mask, has_special_color_space, is_image_stream, CompressionSettings,
+};
+use flate2::write::ZlibEncoder;
+use image::DynamicImage;
+use lopdf::{Object, Stream};
+use log::debug;
+use rayon::prelude::*;
+use std::io::Write;
+use std::time::Duration;
+
+/// Tiles per side of the sample mosaic encoded for each image
+const TILE_GRID: u32 = 8;
+/// Edge of each sample tile at the output resolution (a multiple of the JPEG block size)
+const TILE_EDGE: u32 = 32;
+/// Rough size of JPEG headers and tables, which don't scale with pixel count
+const JPEG_OVERHEAD_BYTES: usize = 600;
+/// Bytes of an unfiltered stream that are deflated to measure its ratio
+const FLATE_SAMPLE_BYTES: usize = 64 * 1024;
+
+/// Estimate the compressed size of a PDF at the given compression level (10-95)
+/// Images are modelled by encoding a mosaic of small tiles and scaling its bytes per pixel up
+/// to the size the image would be downsampled to; unfiltered streams by deflating a sample
+pub fn estimate_pdf_compression(input: &[u8], level: u8) -> Result<u64, String> {
+    let settings = CompressionSettings {
+        quality: compression_level_to_quality(level.clamp(10, 95)),
+        ..Default::default()
+    };
+
+    let doc = load_document(input)?;
+
+    let streams: Vec<(&Stream, bool)> = doc
+        .objects
+        .values()
+        .filter_map(|object| object.as_stream().ok())
+        .map(|stream| (stream, is_image_stream(stream) && !has_special_color_space(&doc, stream)))
+        .collect();
+
+    // (stored size, estimated size) per stream
+    let sizes: Vec<(u64, u64)> = streams
+        .par_iter()
+        .map(|(stream, is_image)| {
+            let original = stream.content.len();
+            let estimate = if *is_image {
+                estimate_image_stream(stream, &settings)
+            } else {
+                estimate_generic_stream(stream, &settings)
+            };
+            (original as u64, estimate.unwrap_or(original).min(original) as u64)
+        })
+        .collect();
+
+    let stored: u64 = sizes.iter().map(|(original, _)| original).sum();
+    let estimated: u64 = sizes.iter().map(|(_, estimate)| estimate).sum();
+    let overhead = (input.len() as u64).saturating_sub(stored);
+    debug!("Estimate: {} stream bytes -> {}, {} bytes of structure", stored, estimated, overhead);
+
+    Ok(overhead + estimated)
+}
+
+/// JPEG size of the image at its downsampled dimensions, extrapolated from a mosaic of sample tiles
+fn estimate_image_stream(stream: &Stream, settings: &CompressionSettings) -> Option<usize> {
+    // The pipeline leaves existing JPEGs alone, and keeps color-keyed images lossless
+    if has_color_key_mask(stream) {
+        return None;
+    }
+    if let Ok(Object::Name(filter)) = stream.dict.get(b"Filter") {
+        if filter == b"DCTDecode" {
+            return None;
+        }
+    }
+
+    let budget = StreamBudget::new(
+        Duration::from_millis(settings.stream_timeout_ms),
+        settings.max_decompressed_bytes,
+    );
+    let img = decode_image_stream(stream, &budget, settings.flatten_background).ok()?;
+    let (width, height) = (img.width(), img.height());
+
+    let scale = match downsample_limit(settings, width, height, None) {
+        Some(max_dimension) => max_dimension / width.max(height) as f32,
+        None => 1.0,
+    };
+    let target_pixels = (width as f32 * scale) as usize * (height as f32 * scale) as usize;
+
+    let (target_w, target_h) = (((width as f32 * scale) as u32).max(1), ((height as f32 * scale) as u32).max(1));
+    let filter = settings.resample_filter.to_filter_type();
+    let mosaic_edge = TILE_GRID * TILE_EDGE;
+
+    // Small outputs are cheap enough to encode for real
+    let sample = if target_w <= mosaic_edge && target_h <= mosaic_edge {
+        img.resize_exact(target_w, target_h, filter)
+    } else {
+        // Tiles from every grid cell, resampled like the real pipeline, keep both the source
+        // detail and the downsampling blur; one mosaic means one set of JPEG headers
+        let tile_edge = ((TILE_EDGE as f32 / scale) as u32).max(1);
+        let (tile_w, tile_h) = (width.min(tile_edge), height.min(tile_edge));
+        let mut mosaic = image::RgbImage::new(mosaic_edge, mosaic_edge);
+        for gy in 0..TILE_GRID {
+            for gx in 0..TILE_GRID {
+                let x = (width - tile_w) * (2 * gx + 1) / (2 * TILE_GRID);
+                let y = (height - tile_h) * (2 * gy + 1) / (2 * TILE_GRID);
+                let tile = img.crop_imm(x, y, tile_w, tile_h).resize_exact(TILE_EDGE, TILE_EDGE, filter);
+                image::imageops::replace(&mut mosaic, &tile.to_rgb8(), (gx * TILE_EDGE) as i64, (gy * TILE_EDGE) as i64);
+            }
+        }
+        DynamicImage::ImageRgb8(mosaic)
+    };
+
+    let encoded = encode_jpeg(&sample, settings.jpeg_encoder_quality(), false).ok()?;
+    let sampled_pixels = sample.width() as usize * sample.height() as usize;
+    let payload = encoded.len().saturating_sub(JPEG_OVERHEAD_BYTES) as f64;
+    let jpeg_estimate = JPEG_OVERHEAD_BYTES + (payload * target_pixels as f64 / sampled_pixels as f64) as usize;
+
+    // Images kept at full size fall back to flate when that is smaller, as in the pipeline
+    match scale < 1.0 {
+        true => Some(jpeg_estimate),
+        false => Some(estimate_generic_stream(stream, settings).map_or(jpeg_estimate, |flate| flate.min(jpeg_estimate))),
+    }
+}
+
+/// Deflated size of an unfiltered stream, extrapolated from a leading sample
+/// Already-filtered streams rarely shrink much further, so they count as unchanged
+fn estimate_generic_stream(stream: &Stream, settings: &CompressionSettings) -> Option<usize> {
+    if stream.dict.has(b"Filter") || stream.content.is_empty() {
+        return None;
+    }
+
+    let sample = &stream.content[..stream.content.len().min(FLATE_SAMPLE_BYTES)];
+    let mut encoder = ZlibEncoder::new(Vec::new(), settings.flate_level.to_compression());
+    encoder.write_all(sample).ok()?;
+    let deflated = encoder.finish().ok()?;
+
+    Some((deflated.len() as f64 * stream.content.len() as f64 / sample.len() as f64) as usize)
+}
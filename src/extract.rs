@@ -0,0 +1,99 @@
+// Pulling image XObjects out of a PDF as standalone image files
+use crate::decode::StreamBudget;
+use crate::placement::{image_display_sizes, page_forms, page_xobjects};
+use crate::{quantize, xref, CompressionSettings};
+use lopdf::{Document, Object, ObjectId, Stream};
+use log::{debug, info};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::Duration;
+
+/// An image XObject of a PDF, as a file of its own
+#[derive(Clone, Debug)]
+pub struct ExtractedImage {
+    pub object_id: ObjectId,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+    pub extension: &'static str, // "jpg" for DCTDecode images, whose JPEG data is copied as is; "png" otherwise
+    pub pages: Vec<u32>, // 1-based numbers of the pages whose resources reach the image
+    pub placed_size: Option<(f32, f32)>, // Largest width and height in points it is drawn at, when known
+}
+
+/// Every image XObject of a PDF, in object order, as a JPEG or losslessly compressed PNG
+/// Soft masks and stencil masks belong to the image they mask and are left out, as are images
+/// the crate can't decode (JPEG 2000, palette or separation colour, under 8 bits per sample)
+pub fn extract_images(input: &[u8]) -> Result<Vec<ExtractedImage>, String> {
+    let doc = xref::load_document(input)?;
+    let settings = CompressionSettings::default();
+
+    let masks: HashSet<ObjectId> = doc.objects.values()
+        .filter_map(|object| object.as_stream().ok())
+        .flat_map(|stream| [b"SMask".as_slice(), b"Mask"].map(|key| stream.dict.get(key).and_then(Object::as_reference).ok()))
+        .flatten()
+        .collect();
+    let pages = image_pages(&doc);
+    let placed_sizes = image_display_sizes(&doc, &settings);
+
+    let mut images = Vec::new();
+    for (id, object) in &doc.objects {
+        let Object::Stream(stream) = object else {
+            continue;
+        };
+        if !crate::is_image_stream(stream) || masks.contains(id) {
+            continue;
+        }
+        let budget = StreamBudget::new(Duration::from_millis(settings.stream_timeout_ms), settings.max_decompressed_bytes);
+        let (data, extension, width, height) = match encode_image(&doc, stream, &settings, &budget) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                debug!("Not extracting image {:?}: {}", id, e);
+                continue;
+            }
+        };
+        images.push(ExtractedImage {
+            object_id: *id,
+            width,
+            height,
+            data,
+            extension,
+            pages: pages.get(id).map(|pages| pages.iter().copied().collect()).unwrap_or_default(),
+            placed_size: placed_sizes.get(id).copied().filter(|(w, h)| w.is_finite() && h.is_finite()),
+        });
+    }
+    info!("Extracted {} images", images.len());
+    Ok(images)
+}
+
+/// The image as file bytes, with its extension and pixel size
+fn encode_image(
+    doc: &Document,
+    stream: &Stream,
+    settings: &CompressionSettings,
+    budget: &StreamBudget,
+) -> Result<(Vec<u8>, &'static str, u32, u32), String> {
+    let declared = |key: &[u8]| stream.dict.get(key).and_then(Object::as_i64).ok().and_then(|v| u32::try_from(v).ok());
+    if crate::is_dct_image(stream) {
+        let (jpeg, _) = crate::dct_payload(stream, budget)?;
+        let width = declared(b"Width").ok_or("No width")?;
+        let height = declared(b"Height").ok_or("No height")?;
+        return Ok((jpeg, "jpg", width, height));
+    }
+    if crate::has_special_color_space(doc, stream) {
+        return Err("Samples are not device gray or RGB".to_string());
+    }
+    let img = crate::decode_image_stream(stream, budget, settings.flatten_background)?;
+    let png = quantize::encode_lossless_png(&img, image::codecs::png::CompressionType::Best)?;
+    Ok((png, "png", img.width(), img.height()))
+}
+
+/// Pages each image XObject can be drawn on, directly or through form XObjects
+fn image_pages(doc: &Document) -> HashMap<ObjectId, BTreeSet<u32>> {
+    let mut pages: HashMap<ObjectId, BTreeSet<u32>> = HashMap::new();
+    for (number, page_id) in doc.get_pages() {
+        let forms = page_forms(doc, page_id).into_iter().flat_map(|(_, names)| names.into_values());
+        for id in page_xobjects(doc, page_id).into_values().chain(forms) {
+            pages.entry(id).or_default().insert(number);
+        }
+    }
+    pages
+}
@@ -0,0 +1,182 @@
+// Flattening interactive form fields (AcroForm) into static page content
+use crate::resources::{add_page_xobjects, ensure_own_resources};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use log::{debug, info, warn};
+
+/// Annotation flags (PDF 32000-1, 12.5.3) that keep a widget from being drawn
+const HIDDEN_FLAG: i64 = 1 << 1;
+const NO_VIEW_FLAG: i64 = 1 << 5;
+
+/// A widget appearance to draw on its page
+struct Placement {
+    appearance: ObjectId,
+    matrix: [f32; 6], // Maps the appearance's transformed BBox onto the widget's Rect
+}
+
+/// Draw each form widget's current appearance into its page and remove the interactive form
+/// Widgets without an appearance are dropped; field dictionaries become unreachable and are
+/// pruned with the rest of the document. Returns the number of widgets removed
+pub(crate) fn flatten_forms(doc: &mut Document) -> usize {
+    let mut removed = 0;
+    for page_id in doc.get_pages().into_values() {
+        match flatten_page(doc, page_id) {
+            Ok(count) => removed += count,
+            Err(e) => warn!("Could not flatten form fields on page {:?}: {}", page_id, e),
+        }
+    }
+
+    let catalog_id = doc.trailer.get(b"Root").and_then(Object::as_reference).ok();
+    if let Some(catalog) = catalog_id.and_then(|id| doc.get_dictionary_mut(id).ok()) {
+        if catalog.remove(b"AcroForm").is_some() {
+            info!("Flattened {} form widgets and removed the AcroForm", removed);
+        }
+    }
+    removed
+}
+
+/// Replace the widget annotations of one page with drawings of their appearances
+fn flatten_page(doc: &mut Document, page_id: ObjectId) -> Result<usize, String> {
+    let annots = match doc.get_dictionary(page_id).and_then(|page| page.get(b"Annots")) {
+        Ok(Object::Reference(id)) => doc.get_object(*id).and_then(Object::as_array).cloned().unwrap_or_default(),
+        Ok(Object::Array(items)) => items.clone(),
+        _ => return Ok(0),
+    };
+
+    let mut kept = Vec::with_capacity(annots.len());
+    let mut placements = Vec::new();
+    let mut removed = 0;
+    for annot in annots {
+        let dict = match &annot {
+            Object::Reference(id) => doc.get_dictionary(*id).ok(),
+            Object::Dictionary(dict) => Some(dict),
+            _ => None,
+        };
+        match dict {
+            Some(dict) if dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Widget") => {
+                placements.extend(widget_placement(doc, dict));
+                removed += 1;
+            }
+            _ => kept.push(annot),
+        }
+    }
+    if removed == 0 {
+        return Ok(0);
+    }
+
+    if !placements.is_empty() {
+        draw_appearances(doc, page_id, &placements)?;
+    }
+
+    let page = doc.get_dictionary_mut(page_id).map_err(|e| format!("Broken page: {}", e))?;
+    if kept.is_empty() {
+        page.remove(b"Annots");
+    } else {
+        page.set("Annots", Object::Array(kept));
+    }
+    debug!("Page {:?}: flattened {} widgets, drew {} appearances", page_id, removed, placements.len());
+    Ok(removed)
+}
+
+/// Where a visible widget's normal appearance goes on the page, if it has one
+fn widget_placement(doc: &Document, widget: &Dictionary) -> Option<Placement> {
+    let flags = widget.get(b"F").and_then(Object::as_i64).unwrap_or(0);
+    if flags & (HIDDEN_FLAG | NO_VIEW_FLAG) != 0 {
+        return None;
+    }
+
+    // /N is either the appearance itself or a map from states (/AS) to appearances
+    let normal = resolve(doc, widget.get(b"AP").ok()?).as_dict().ok()?.get(b"N").ok()?;
+    let appearance = match normal {
+        Object::Reference(id) if doc.get_object(*id).and_then(Object::as_stream).is_ok() => *id,
+        states => {
+            let state = widget.get(b"AS").and_then(Object::as_name).ok()?;
+            resolve(doc, states).as_dict().ok()?.get(state).and_then(Object::as_reference).ok()?
+        }
+    };
+    let stream = doc.get_object(appearance).and_then(Object::as_stream).ok()?;
+
+    let rect = rectangle(doc, widget.get(b"Rect").ok()?)?;
+    let bbox = rectangle(doc, stream.dict.get(b"BBox").ok()?)?;
+    let form_matrix = stream.dict.get(b"Matrix").ok()
+        .and_then(|matrix| numbers(doc, matrix))
+        .and_then(|values| <[f32; 6]>::try_from(values).ok())
+        .unwrap_or([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+
+    // PDF 32000-1, 12.5.5: the BBox, transformed by the form's Matrix, is fitted to the Rect
+    let corners = [(bbox[0], bbox[1]), (bbox[2], bbox[1]), (bbox[0], bbox[3]), (bbox[2], bbox[3])]
+        .map(|(x, y)| (
+            form_matrix[0] * x + form_matrix[2] * y + form_matrix[4],
+            form_matrix[1] * x + form_matrix[3] * y + form_matrix[5],
+        ));
+    let min_x = corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min);
+    let max_x = corners.iter().map(|c| c.0).fold(f32::NEG_INFINITY, f32::max);
+    let min_y = corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min);
+    let max_y = corners.iter().map(|c| c.1).fold(f32::NEG_INFINITY, f32::max);
+    if max_x - min_x <= 0.0 || max_y - min_y <= 0.0 {
+        return None;
+    }
+
+    let scale_x = (rect[2] - rect[0]) / (max_x - min_x);
+    let scale_y = (rect[3] - rect[1]) / (max_y - min_y);
+    Some(Placement {
+        appearance,
+        matrix: [scale_x, 0.0, 0.0, scale_y, rect[0] - min_x * scale_x, rect[1] - min_y * scale_y],
+    })
+}
+
+/// Add the appearances as form XObjects and draw them after the page's own content
+/// The existing content is wrapped in q/Q so whatever state it leaves doesn't move them
+fn draw_appearances(doc: &mut Document, page_id: ObjectId, placements: &[Placement]) -> Result<(), String> {
+    // Appearance streams are forms already, but some writers omit the XObject keys
+    for placement in placements {
+        if let Ok(Object::Stream(stream)) = doc.get_object_mut(placement.appearance) {
+            stream.dict.set("Type", Object::Name(b"XObject".to_vec()));
+            stream.dict.set("Subtype", Object::Name(b"Form".to_vec()));
+        }
+    }
+
+    ensure_own_resources(doc, page_id)?;
+    let names = add_page_xobjects(doc, page_id, placements.iter().map(|p| p.appearance).collect(), "FlatForm")?;
+
+    let mut drawing = b"Q\n".to_vec();
+    for (placement, name) in placements.iter().zip(&names) {
+        let [a, b, c, d, e, f] = placement.matrix;
+        drawing.extend(format!("q {} {} {} {} {} {} cm /", a, b, c, d, e, f).into_bytes());
+        drawing.extend_from_slice(name);
+        drawing.extend_from_slice(b" Do Q\n");
+    }
+    let open_id = doc.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec()));
+    let drawing_id = doc.add_object(Stream::new(Dictionary::new(), drawing));
+
+    let mut contents = vec![Object::Reference(open_id)];
+    contents.extend(doc.get_page_contents(page_id).into_iter().map(Object::Reference));
+    contents.push(Object::Reference(drawing_id));
+    doc.get_dictionary_mut(page_id)
+        .map_err(|e| format!("Broken page: {}", e))?
+        .set("Contents", Object::Array(contents));
+    Ok(())
+}
+
+fn resolve<'a>(doc: &'a Document, object: &'a Object) -> &'a Object {
+    match object {
+        Object::Reference(id) => doc.get_object(*id).unwrap_or(object),
+        other => other,
+    }
+}
+
+fn numbers(doc: &Document, object: &Object) -> Option<Vec<f32>> {
+    resolve(doc, object).as_array().ok()?
+        .iter()
+        .map(|item| match resolve(doc, item) {
+            Object::Integer(n) => Some(*n as f32),
+            Object::Real(n) => Some(*n),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A rectangle normalized so the first corner is the lower left
+fn rectangle(doc: &Document, object: &Object) -> Option<[f32; 4]> {
+    let [x1, y1, x2, y2] = <[f32; 4]>::try_from(numbers(doc, object)?).ok()?;
+    Some([x1.min(x2), y1.min(y2), x1.max(x2), y1.max(y2)])
+}
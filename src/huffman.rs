@@ -0,0 +1,461 @@
+// Lossless JPEG size reduction: re-coding baseline scans with optimal Huffman tables
+// The quantized coefficients are carried over bit for bit, so the pixels don't change
+use log::debug;
+
+/// Huffman table index: class (0 = DC, 1 = AC) times four plus the table id
+type TableSlot = usize;
+
+/// Canonical Huffman table as stored in a DHT segment
+#[derive(Clone)]
+struct HuffmanTable {
+    bits: [u8; 17], // bits[n] = number of codes of length n
+    values: Vec<u8>,
+}
+
+/// Decoding limits per code length, as in ITU T.81 F.2.2.3
+struct DecodeTable {
+    max_code: [i32; 18],
+    value_offset: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl DecodeTable {
+    fn new(table: &HuffmanTable) -> Self {
+        let mut decode = DecodeTable { max_code: [-1; 18], value_offset: [0; 17], values: table.values.clone() };
+        let mut code = 0i32;
+        let mut index = 0i32;
+        for length in 1..=16 {
+            let count = table.bits[length] as i32;
+            if count > 0 {
+                decode.value_offset[length] = index - code;
+                code += count;
+                index += count;
+                decode.max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        decode.max_code[17] = i32::MAX;
+        decode
+    }
+}
+
+/// One entropy-coded item of a scan
+enum Token {
+    Symbol { slot: TableSlot, symbol: u8, extra: u16, extra_len: u8 },
+    Restart(u8),
+}
+
+struct FrameComponent {
+    id: u8,
+    h: usize,
+    v: usize,
+}
+
+/// Re-code a baseline JPEG with Huffman tables built for its own symbol statistics, like
+/// `jpegtran -optimize`. Progressive, arithmetic-coded and lossless JPEGs are refused
+/// Every other segment is copied as is, so metadata and quantization tables are kept and
+/// the image decodes to exactly the same pixels
+pub fn optimize_jpeg_huffman(data: &[u8]) -> Result<Vec<u8>, String> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err("Not a JPEG".to_string());
+    }
+    let truncated = || "Truncated JPEG".to_string();
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&[0xFF, 0xD8]);
+    let mut tables: [Option<HuffmanTable>; 8] = Default::default();
+    let mut frame: Option<(usize, usize, Vec<FrameComponent>)> = None;
+    let mut restart_interval = 0usize;
+    let mut pos = 2;
+    loop {
+        if *data.get(pos).ok_or_else(truncated)? != 0xFF {
+            return Err("Expected a JPEG marker".to_string());
+        }
+        let marker = *data.get(pos + 1).ok_or_else(truncated)?;
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        // End of image; anything after it is kept too
+        if marker == 0xD9 {
+            output.extend_from_slice(&data[pos..]);
+            break;
+        }
+        if matches!(marker, 0x01 | 0xD0..=0xD7) {
+            output.extend_from_slice(&data[pos..pos + 2]);
+            pos += 2;
+            continue;
+        }
+        let length = u16::from_be_bytes([*data.get(pos + 2).ok_or_else(truncated)?, *data.get(pos + 3).ok_or_else(truncated)?]) as usize;
+        let segment_end = pos + 2 + length;
+        let segment = data.get(pos + 4..segment_end).ok_or_else(truncated)?;
+        match marker {
+            // Tables are rewritten in front of each scan instead
+            0xC4 => parse_tables(segment, &mut tables)?,
+            0xC0 | 0xC1 => {
+                frame = Some(parse_frame(segment)?);
+                output.extend_from_slice(&data[pos..segment_end]);
+            }
+            0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                return Err(format!("Only baseline Huffman JPEGs can be optimized (SOF marker {:02X})", marker));
+            }
+            0xDD => {
+                restart_interval = u16::from_be_bytes([*segment.first().ok_or_else(truncated)?, *segment.get(1).ok_or_else(truncated)?]) as usize;
+                output.extend_from_slice(&data[pos..segment_end]);
+            }
+            0xDA => {
+                let (width, height, components) = frame.as_ref().ok_or("Scan before frame header")?;
+                let scan = parse_scan(segment, components)?;
+                let decoders: Vec<Option<DecodeTable>> = tables.iter().map(|table| table.as_ref().map(DecodeTable::new)).collect();
+                let (tokens, scan_end) = decode_scan(data, segment_end, &scan, components, (*width, *height), restart_interval, &decoders)?;
+
+                let optimal = optimal_tables(&tokens)?;
+                write_tables(&mut output, &optimal);
+                output.extend_from_slice(&data[pos..segment_end]);
+                encode_scan(&mut output, &tokens, &optimal);
+                pos = scan_end;
+                continue;
+            }
+            _ => output.extend_from_slice(&data[pos..segment_end]),
+        }
+        pos = segment_end;
+    }
+
+    debug!("Huffman optimization: {} -> {} bytes", data.len(), output.len());
+    Ok(output)
+}
+
+fn parse_tables(mut segment: &[u8], tables: &mut [Option<HuffmanTable>; 8]) -> Result<(), String> {
+    while let Some(&class_id) = segment.first() {
+        let (class, id) = ((class_id >> 4) as usize, (class_id & 0x0F) as usize);
+        if class > 1 || id > 3 {
+            return Err("Invalid Huffman table".to_string());
+        }
+        let counts = segment.get(1..17).ok_or("Truncated Huffman table")?;
+        let mut bits = [0u8; 17];
+        bits[1..].copy_from_slice(counts);
+        let total: usize = counts.iter().map(|&count| count as usize).sum();
+        let values = segment.get(17..17 + total).ok_or("Truncated Huffman table")?.to_vec();
+        tables[class * 4 + id] = Some(HuffmanTable { bits, values });
+        segment = &segment[17 + total..];
+    }
+    Ok(())
+}
+
+fn parse_frame(segment: &[u8]) -> Result<(usize, usize, Vec<FrameComponent>), String> {
+    let header = segment.get(..6).ok_or("Truncated frame header")?;
+    let height = u16::from_be_bytes([header[1], header[2]]) as usize;
+    let width = u16::from_be_bytes([header[3], header[4]]) as usize;
+    // A zero height is only given later by a DNL marker
+    if width == 0 || height == 0 {
+        return Err("JPEG frame without dimensions".to_string());
+    }
+    let components = segment.get(6..6 + header[5] as usize * 3).ok_or("Truncated frame header")?
+        .chunks(3)
+        .map(|component| FrameComponent { id: component[0], h: (component[1] >> 4).max(1) as usize, v: (component[1] & 0x0F).max(1) as usize })
+        .collect();
+    Ok((width, height, components))
+}
+
+/// Frame component index with its DC and AC table slots, for every component in a scan
+fn parse_scan(segment: &[u8], components: &[FrameComponent]) -> Result<Vec<(usize, TableSlot, TableSlot)>, String> {
+    let count = *segment.first().ok_or("Truncated scan header")? as usize;
+    let selectors = segment.get(1..1 + count * 2).ok_or("Truncated scan header")?;
+    let progression = segment.get(1 + count * 2..4 + count * 2).ok_or("Truncated scan header")?;
+    if progression != [0, 63, 0] {
+        return Err("Not a baseline scan".to_string());
+    }
+    selectors.chunks(2)
+        .map(|selector| {
+            let index = components.iter().position(|component| component.id == selector[0]).ok_or("Scan names an unknown component")?;
+            let (dc, ac) = ((selector[1] >> 4) as usize, (selector[1] & 0x0F) as usize);
+            if dc > 3 || ac > 3 {
+                return Err("Invalid Huffman table selector".to_string());
+            }
+            Ok((index, dc, 4 + ac))
+        })
+        .collect()
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    available: u32,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Result<u32, String> {
+        if self.available == 0 {
+            let byte = *self.data.get(self.pos).ok_or("Truncated scan data")?;
+            if byte == 0xFF {
+                // A stuffed zero follows a literal 0xFF; anything else is a marker
+                if self.data.get(self.pos + 1) != Some(&0x00) {
+                    return Err("Scan data ended early".to_string());
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            self.buffer = byte as u32;
+            self.available = 8;
+        }
+        self.available -= 1;
+        Ok((self.buffer >> self.available) & 1)
+    }
+
+    fn bits(&mut self, count: u8) -> Result<u16, String> {
+        let mut value = 0u16;
+        for _ in 0..count {
+            value = (value << 1) | self.bit()? as u16;
+        }
+        Ok(value)
+    }
+
+    fn symbol(&mut self, table: &DecodeTable) -> Result<u8, String> {
+        let mut code = self.bit()? as i32;
+        let mut length = 1;
+        while code > table.max_code[length] {
+            code = (code << 1) | self.bit()? as i32;
+            length += 1;
+            if length > 16 {
+                return Err("Invalid Huffman code".to_string());
+            }
+        }
+        table.values.get((table.value_offset[length] + code) as usize).copied().ok_or_else(|| "Invalid Huffman code".to_string())
+    }
+
+    /// Drop the padding bits of the current byte, as before a marker
+    fn align(&mut self) {
+        self.available = 0;
+    }
+}
+
+/// Read the Huffman symbols and extra bits of one scan, returning them and where the scan ends
+fn decode_scan(
+    data: &[u8],
+    start: usize,
+    scan: &[(usize, TableSlot, TableSlot)],
+    components: &[FrameComponent],
+    (width, height): (usize, usize),
+    restart_interval: usize,
+    decoders: &[Option<DecodeTable>],
+) -> Result<(Vec<Token>, usize), String> {
+    let h_max = components.iter().map(|component| component.h).max().unwrap_or(1);
+    let v_max = components.iter().map(|component| component.v).max().unwrap_or(1);
+
+    // (component, DC slot, AC slot) for every block of one MCU, and the number of MCUs
+    let (mcu_blocks, mcu_count): (Vec<(usize, TableSlot, TableSlot)>, usize) = if let [single] = scan {
+        // A lone component is coded block by block over its own sampled size
+        let component = &components[single.0];
+        let columns = (width * component.h).div_ceil(h_max).div_ceil(8);
+        let rows = (height * component.v).div_ceil(v_max).div_ceil(8);
+        (vec![*single], columns * rows)
+    } else {
+        let blocks = scan.iter()
+            .flat_map(|selector| std::iter::repeat_n(*selector, components[selector.0].h * components[selector.0].v))
+            .collect();
+        (blocks, width.div_ceil(8 * h_max) * height.div_ceil(8 * v_max))
+    };
+    let decoder = |slot: TableSlot| decoders[slot].as_ref().ok_or_else(|| "Scan uses an undefined Huffman table".to_string());
+
+    let mut reader = BitReader { data, pos: start, buffer: 0, available: 0 };
+    let mut tokens = Vec::new();
+    for mcu in 0..mcu_count {
+        if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+            reader.align();
+            while data.get(reader.pos) == Some(&0xFF) && data.get(reader.pos + 1) == Some(&0xFF) {
+                reader.pos += 1;
+            }
+            match data.get(reader.pos..reader.pos + 2) {
+                Some([0xFF, marker @ 0xD0..=0xD7]) => tokens.push(Token::Restart(*marker)),
+                _ => return Err("Missing restart marker".to_string()),
+            }
+            reader.pos += 2;
+        }
+        for &(_, dc_slot, ac_slot) in &mcu_blocks {
+            let size = reader.symbol(decoder(dc_slot)?)?;
+            if size > 15 {
+                return Err("Invalid DC difference".to_string());
+            }
+            tokens.push(Token::Symbol { slot: dc_slot, symbol: size, extra: reader.bits(size)?, extra_len: size });
+
+            let ac = decoder(ac_slot)?;
+            let mut k = 1;
+            while k < 64 {
+                let symbol = reader.symbol(ac)?;
+                let (run, size) = ((symbol >> 4) as usize, symbol & 0x0F);
+                tokens.push(Token::Symbol { slot: ac_slot, symbol, extra: reader.bits(size)?, extra_len: size });
+                if size == 0 && run != 15 {
+                    break; // End of block
+                }
+                k += run + 1;
+            }
+            if k > 64 {
+                return Err("Coefficient run past the end of a block".to_string());
+            }
+        }
+    }
+    reader.align();
+    Ok((tokens, reader.pos))
+}
+
+/// Optimal length-limited tables for every slot the tokens use (ITU T.81 K.2)
+fn optimal_tables(tokens: &[Token]) -> Result<Vec<(TableSlot, HuffmanTable)>, String> {
+    let mut frequencies = [[0u64; 257]; 8];
+    for token in tokens {
+        if let Token::Symbol { slot, symbol, .. } = token {
+            frequencies[*slot][*symbol as usize] += 1;
+        }
+    }
+    frequencies.iter().enumerate()
+        .filter(|(_, counts)| counts.iter().any(|&count| count > 0))
+        .map(|(slot, counts)| Ok((slot, optimal_table(*counts)?)))
+        .collect()
+}
+
+fn optimal_table(mut frequency: [u64; 257]) -> Result<HuffmanTable, String> {
+    // A reserved symbol keeps any code from being all ones
+    frequency[256] = 1;
+    let mut code_size = [0usize; 257];
+    let mut others = [usize::MAX; 257];
+    loop {
+        // The two least frequent symbols, the later one winning ties
+        let mut c1 = None;
+        let mut c2 = None;
+        for symbol in 0..257 {
+            if frequency[symbol] == 0 {
+                continue;
+            }
+            if c1.is_none_or(|c: usize| frequency[symbol] <= frequency[c]) {
+                c2 = c1;
+                c1 = Some(symbol);
+            } else if c2.is_none_or(|c: usize| frequency[symbol] <= frequency[c]) {
+                c2 = Some(symbol);
+            }
+        }
+        let (Some(mut c1), Some(mut c2)) = (c1, c2) else { break };
+        frequency[c1] += frequency[c2];
+        frequency[c2] = 0;
+        code_size[c1] += 1;
+        while others[c1] != usize::MAX {
+            c1 = others[c1];
+            code_size[c1] += 1;
+        }
+        others[c1] = c2;
+        code_size[c2] += 1;
+        while others[c2] != usize::MAX {
+            c2 = others[c2];
+            code_size[c2] += 1;
+        }
+    }
+
+    let mut counts = [0usize; 33];
+    for &size in code_size.iter().filter(|&&size| size > 0) {
+        // Only pathological symbol statistics build a tree this deep
+        *counts.get_mut(size).ok_or("Huffman tree too deep")? += 1;
+    }
+    // Lengthen no code past 16 bits, borrowing from shorter prefixes (T.81 figure K.3)
+    for i in (17..=32).rev() {
+        while counts[i] > 0 {
+            let mut j = i - 2;
+            while counts[j] == 0 {
+                j -= 1;
+            }
+            counts[i] -= 2;
+            counts[i - 1] += 1;
+            counts[j + 1] += 2;
+            counts[j] -= 1;
+        }
+    }
+    // Drop the reserved symbol, which has the longest code
+    let mut longest = 16;
+    while counts[longest] == 0 {
+        longest -= 1;
+    }
+    counts[longest] -= 1;
+
+    let mut bits = [0u8; 17];
+    for length in 1..=16 {
+        bits[length] = counts[length] as u8;
+    }
+    let mut values = Vec::new();
+    for size in 1..=32 {
+        values.extend((0..256).filter(|&symbol| code_size[symbol] == size).map(|symbol| symbol as u8));
+    }
+    Ok(HuffmanTable { bits, values })
+}
+
+fn write_tables(output: &mut Vec<u8>, tables: &[(TableSlot, HuffmanTable)]) {
+    let length = 2 + tables.iter().map(|(_, table)| 17 + table.values.len()).sum::<usize>();
+    output.extend_from_slice(&[0xFF, 0xC4]);
+    output.extend_from_slice(&(length as u16).to_be_bytes());
+    for (slot, table) in tables {
+        output.push((((slot / 4) << 4) | (slot % 4)) as u8);
+        output.extend_from_slice(&table.bits[1..]);
+        output.extend_from_slice(&table.values);
+    }
+}
+
+struct BitWriter<'a> {
+    output: &'a mut Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter<'_> {
+    fn write(&mut self, value: u32, length: u32) {
+        for shift in (0..length).rev() {
+            self.buffer = (self.buffer << 1) | ((value >> shift) & 1);
+            self.count += 1;
+            if self.count == 8 {
+                let byte = self.buffer as u8;
+                self.output.push(byte);
+                if byte == 0xFF {
+                    self.output.push(0x00);
+                }
+                self.buffer = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    /// Pad the last byte with one bits
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.write(0xFF, 8 - self.count);
+        }
+    }
+}
+
+fn encode_scan(output: &mut Vec<u8>, tokens: &[Token], tables: &[(TableSlot, HuffmanTable)]) {
+    // (code, length) per symbol, for every slot
+    let mut codes = vec![[(0u32, 0u32); 256]; 8];
+    for (slot, table) in tables {
+        let mut code = 0u32;
+        let mut index = 0;
+        for length in 1..=16 {
+            for _ in 0..table.bits[length] {
+                codes[*slot][table.values[index] as usize] = (code, length as u32);
+                code += 1;
+                index += 1;
+            }
+            code <<= 1;
+        }
+    }
+
+    let mut writer = BitWriter { output, buffer: 0, count: 0 };
+    for token in tokens {
+        match token {
+            Token::Symbol { slot, symbol, extra, extra_len } => {
+                let (code, length) = codes[*slot][*symbol as usize];
+                writer.write(code, length);
+                writer.write(*extra as u32, *extra_len as u32);
+            }
+            Token::Restart(marker) => {
+                writer.flush();
+                writer.output.extend_from_slice(&[0xFF, *marker]);
+            }
+        }
+    }
+    writer.flush();
+}
@@ -0,0 +1,555 @@
+// Recompression of inline images (BI ... ID ... EI) embedded in page content streams
+use crate::decode::{decode_stream_bounded, StreamBudget};
+use crate::resources::{add_page_xobjects, has_own_resources};
+use crate::{compress_image_stream, CompressionSettings};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use log::{debug, info};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Arrays and dictionaries nested deeper than this in inline image parameters are rejected,
+/// so crafted content can't exhaust the stack
+const MAX_NESTING_DEPTH: usize = 256;
+
+/// Identical inline images drawn at least this often in one content stream become a shared XObject
+const PROMOTE_MIN_OCCURRENCES: usize = 2;
+
+/// An inline image found in a content stream
+struct InlineImage {
+    start: usize, // Offset of the BI operator
+    end: usize, // Offset just past the EI operator
+    dict: Dictionary, // Image parameters with abbreviations expanded
+    data: Vec<u8>,
+}
+
+/// How one inline image is rewritten
+enum Replacement {
+    Inline(Vec<u8>), // A new BI ... EI block
+    XObject(usize), // Index into the content stream's promoted images
+}
+
+/// Recompress inline images in every page content stream
+/// Returns the number of images rewritten and the content bytes saved
+pub(crate) fn compress_inline_images(doc: &mut Document, settings: &CompressionSettings) -> (usize, i64) {
+    let mut rewritten = 0;
+    let mut saved = 0i64;
+
+    let page_contents: Vec<(ObjectId, Vec<ObjectId>)> = doc.get_pages().into_values()
+        .map(|page_id| (page_id, doc.get_page_contents(page_id)))
+        .collect();
+    let mut pages_drawing: HashMap<ObjectId, usize> = HashMap::new();
+    for content_id in page_contents.iter().flat_map(|(_, contents)| contents) {
+        *pages_drawing.entry(*content_id).or_default() += 1;
+    }
+
+    let mut visited = HashSet::new();
+    for (page_id, contents) in page_contents {
+        for content_id in contents {
+            if settings.protected_objects.contains(&content_id) || !visited.insert(content_id) {
+                continue;
+            }
+            // Promoted images are named in one page's resources, which other pages drawing the stream lack
+            let shared = pages_drawing[&content_id] > 1;
+            match compress_content_stream(doc, page_id, content_id, shared, settings) {
+                Ok(Some((count, bytes))) => {
+                    rewritten += count;
+                    saved += bytes;
+                }
+                Ok(None) => {}
+                Err(e) => debug!("Skipping inline images in {:?}: {}", content_id, e),
+            }
+        }
+    }
+
+    if rewritten > 0 {
+        info!("Recompressed {} inline images, saving {} bytes", rewritten, saved);
+    }
+    (rewritten, saved)
+}
+
+/// Rewrite the inline images of one content stream; Ok(None) means nothing changed
+/// Images are only promoted to XObjects when `page_id` is the one page drawing the stream
+fn compress_content_stream(
+    doc: &mut Document,
+    page_id: ObjectId,
+    content_id: ObjectId,
+    shared: bool,
+    settings: &CompressionSettings,
+) -> Result<Option<(usize, i64)>, String> {
+    let budget = StreamBudget::new(
+        Duration::from_millis(settings.stream_timeout_ms),
+        settings.max_decompressed_bytes,
+    );
+
+    let stream = match doc.get_object(content_id) {
+        Ok(Object::Stream(stream)) => stream,
+        _ => return Ok(None),
+    };
+    let content = if stream.dict.has(b"Filter") {
+        match decode_stream_bounded(stream, budget.generic_limit(stream.content.len()))? {
+            Some(content) => content,
+            None => return Ok(None),
+        }
+    } else {
+        stream.content.clone()
+    };
+
+    let images = find_inline_images(&content)?;
+    if images.is_empty() {
+        return Ok(None);
+    }
+    debug!("Found {} inline images in {:?}", images.len(), content_id);
+
+    // Identical images are recompressed once
+    let mut occurrences: HashMap<&[u8], usize> = HashMap::new();
+    for image in &images {
+        *occurrences.entry(&content[image.start..image.end]).or_default() += 1;
+    }
+    let mut recompressed: HashMap<&[u8], Option<Stream>> = HashMap::new();
+    for image in &images {
+        let source = &content[image.start..image.end];
+        if !recompressed.contains_key(source) {
+            recompressed.insert(source, recompress_inline_image(image, settings, &budget));
+        }
+    }
+
+    let can_promote = !shared && has_own_resources(doc, page_id);
+    let mut promoted: Vec<Stream> = Vec::new();
+    let mut promoted_index: HashMap<&[u8], usize> = HashMap::new();
+    let mut replacements: Vec<(usize, Replacement)> = Vec::new();
+
+    for (index, image) in images.iter().enumerate() {
+        let source = &content[image.start..image.end];
+        let Some(compressed) = &recompressed[source] else {
+            continue;
+        };
+
+        // Repeats cost their full data every time inline, but only a name as an XObject
+        if can_promote && occurrences[source] >= PROMOTE_MIN_OCCURRENCES {
+            let slot = *promoted_index.entry(source).or_insert_with(|| {
+                promoted.push(compressed.clone());
+                promoted.len() - 1
+            });
+            replacements.push((index, Replacement::XObject(slot)));
+            continue;
+        }
+
+        match write_inline_image(compressed) {
+            Some(block) if block.len() < source.len() => replacements.push((index, Replacement::Inline(block))),
+            // JPEG data that looks like an EI operator can't be stored inline
+            None if can_promote => {
+                promoted.push(compressed.clone());
+                replacements.push((index, Replacement::XObject(promoted.len() - 1)));
+            }
+            _ => {}
+        }
+    }
+
+    if replacements.is_empty() {
+        return Ok(None);
+    }
+
+    let names = match promoted.is_empty() {
+        true => Vec::new(),
+        false => {
+            let ids = promoted.iter().map(|stream| doc.add_object(stream.clone())).collect();
+            add_page_xobjects(doc, page_id, ids, "InlineIm")?
+        }
+    };
+
+    let mut output = Vec::with_capacity(content.len());
+    let mut position = 0;
+    for (index, replacement) in &replacements {
+        let image = &images[*index];
+        output.extend_from_slice(&content[position..image.start]);
+        match replacement {
+            Replacement::Inline(block) => output.extend_from_slice(block),
+            Replacement::XObject(slot) => {
+                output.push(b'/');
+                output.extend_from_slice(&names[*slot]);
+                output.extend_from_slice(b" Do");
+            }
+        }
+        position = image.end;
+    }
+    output.extend_from_slice(&content[position..]);
+
+    let promoted_bytes: usize = promoted.iter().map(|stream| stream.content.len()).sum();
+    let saved = content.len() as i64 - output.len() as i64 - promoted_bytes as i64;
+    debug!("Rewrote {} inline images in {:?} ({} promoted to XObjects)", replacements.len(), content_id, promoted.len());
+
+    // Leave the stream unfiltered; the generic stream pass deflates it afterwards
+    if let Ok(Object::Stream(stream)) = doc.get_object_mut(content_id) {
+        stream.dict.remove(b"Filter");
+        stream.dict.remove(b"DecodeParms");
+        stream.set_content(output);
+    }
+
+    Ok(Some((replacements.len(), saved)))
+}
+
+/// Run an inline image through the XObject image pipeline if its format is one it handles
+fn recompress_inline_image(image: &InlineImage, settings: &CompressionSettings, budget: &StreamBudget) -> Option<Stream> {
+    let dict = &image.dict;
+    let supported = !dict.has(b"ImageMask")
+        && !dict.has(b"Decode")
+        && !dict.has(b"DecodeParms")
+        && matches!(dict.get(b"BitsPerComponent"), Ok(Object::Integer(8)))
+        && matches!(dict.get(b"ColorSpace"), Ok(Object::Name(name)) if name == b"DeviceRGB" || name == b"DeviceGray")
+        && match dict.get(b"Filter") {
+            Err(_) => true,
+            Ok(Object::Name(name)) => name == b"FlateDecode",
+            Ok(Object::Array(filters)) => matches!(filters.as_slice(), [Object::Name(name)] if name == b"FlateDecode"),
+            _ => false,
+        };
+    if !supported {
+        return None;
+    }
+
+    let mut xobject = Dictionary::new();
+    xobject.set("Type", Object::Name(b"XObject".to_vec()));
+    xobject.set("Subtype", Object::Name(b"Image".to_vec()));
+    for key in [b"Width".as_slice(), b"Height", b"BitsPerComponent", b"ColorSpace", b"Interpolate"] {
+        if let Ok(value) = dict.get(key) {
+            xobject.set(key.to_vec(), value.clone());
+        }
+    }
+    if dict.has(b"Filter") {
+        xobject.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+    }
+
+    let compressed = compress_image_stream(&Stream::new(xobject, image.data.clone()), settings, None, budget).ok()?;
+    (compressed.content.len() < image.data.len()).then_some(compressed)
+}
+
+/// Serialize a recompressed image as a BI ... EI block, or None if its data can't be delimited
+fn write_inline_image(stream: &Stream) -> Option<Vec<u8>> {
+    let integer = |key: &[u8]| match stream.dict.get(key) {
+        Ok(Object::Integer(value)) => Some(*value),
+        _ => None,
+    };
+    let color_space: &[u8] = match stream.dict.get(b"ColorSpace") {
+        Ok(Object::Name(name)) if name == b"DeviceGray" => b"G",
+        _ => b"RGB",
+    };
+    // Flat images may have come back flate-compressed rather than as JPEG
+    let filter: &[u8] = match stream.dict.get(b"Filter") {
+        Ok(Object::Name(name)) if name == b"DCTDecode" => b"DCT",
+        Ok(Object::Name(name)) if name == b"FlateDecode" => b"Fl",
+        _ => return None,
+    };
+
+    let mut block = format!("BI /W {} /H {} /BPC 8 /CS /", integer(b"Width")?, integer(b"Height")?).into_bytes();
+    block.extend_from_slice(color_space);
+    if let Ok(Object::Boolean(true)) = stream.dict.get(b"Interpolate") {
+        block.extend_from_slice(b" /I true");
+    }
+    block.extend_from_slice(b" /F /");
+    block.extend_from_slice(filter);
+    block.extend_from_slice(b" ID\n");
+    let data_start = block.len();
+    block.extend_from_slice(&stream.content);
+    block.extend_from_slice(b"\nEI");
+
+    // Readers find the end of filtered data by scanning for EI, so it must not appear earlier
+    (find_end_marker(&block, data_start) == Some(data_start + stream.content.len())).then_some(block)
+}
+
+/// Locate every inline image in decoded content, skipping strings and comments
+fn find_inline_images(content: &[u8]) -> Result<Vec<InlineImage>, String> {
+    let mut images = Vec::new();
+    let mut position = 0;
+
+    while position < content.len() {
+        match content[position] {
+            b'%' => position = skip_comment(content, position),
+            b'(' => position = skip_literal_string(content, position)?,
+            b'<' if content.get(position + 1) == Some(&b'<') => position += 2,
+            b'<' => position = skip_hex_string(content, position)?,
+            b'/' => position = token_end(content, position + 1),
+            byte if is_whitespace(byte) || is_delimiter(byte) => position += 1,
+            _ => {
+                let end = token_end(content, position);
+                if &content[position..end] == b"BI" {
+                    let image = parse_inline_image(content, position, end)?;
+                    position = image.end;
+                    images.push(image);
+                } else {
+                    position = end;
+                }
+            }
+        }
+    }
+
+    Ok(images)
+}
+
+/// Parse the parameters and data of an inline image whose BI operator spans `start..after_bi`
+fn parse_inline_image(content: &[u8], start: usize, after_bi: usize) -> Result<InlineImage, String> {
+    let mut dict = Dictionary::new();
+    let mut position = after_bi;
+
+    loop {
+        position = skip_whitespace(content, position);
+        if position >= content.len() {
+            return Err("Inline image without ID".to_string());
+        }
+        if content[position..].starts_with(b"ID") && is_token_boundary(content, position + 2) {
+            position += 2;
+            break;
+        }
+        let (key, next) = parse_object(content, position)?;
+        let (value, next) = parse_object(content, next)?;
+        let Object::Name(key) = key else {
+            return Err("Inline image key is not a name".to_string());
+        };
+        let key = expand_key(&key);
+        let value = if key == b"ColorSpace" || key == b"Filter" { expand_value(value) } else { value };
+        dict.set(key, value);
+        position = next;
+    }
+
+    // Exactly one whitespace byte separates ID from the data
+    let data_start = position + 1;
+    if data_start > content.len() {
+        return Err("Inline image data missing".to_string());
+    }
+
+    let (data_end, end) = match known_data_length(&dict) {
+        Some(length) => match end_after(content, data_start + length) {
+            Some(end) => (data_start + length, end),
+            None => scan_for_end(content, data_start)?,
+        },
+        None => scan_for_end(content, data_start)?,
+    };
+
+    Ok(InlineImage {
+        start,
+        end,
+        dict,
+        data: content[data_start..data_end].to_vec(),
+    })
+}
+
+/// Data length when it is stated (PDF 2.0 /L) or implied by unfiltered samples
+fn known_data_length(dict: &Dictionary) -> Option<usize> {
+    let integer = |key: &[u8]| match dict.get(key) {
+        Ok(Object::Integer(value)) if *value >= 0 => Some(*value as usize),
+        _ => None,
+    };
+    if let Some(length) = integer(b"Length") {
+        return Some(length);
+    }
+    if dict.has(b"Filter") {
+        return None;
+    }
+
+    let image_mask = matches!(dict.get(b"ImageMask"), Ok(Object::Boolean(true)));
+    let bits = if image_mask { 1 } else { integer(b"BitsPerComponent")? };
+    let components = match dict.get(b"ColorSpace") {
+        _ if image_mask => 1,
+        Ok(Object::Name(name)) if name == b"DeviceGray" => 1,
+        Ok(Object::Name(name)) if name == b"DeviceRGB" => 3,
+        Ok(Object::Name(name)) if name == b"DeviceCMYK" => 4,
+        Ok(Object::Array(items)) if matches!(items.first(), Some(Object::Name(name)) if name == b"Indexed") => 1,
+        _ => return None,
+    };
+    let row_bytes = integer(b"Width")?.checked_mul(components)?.checked_mul(bits)?.div_ceil(8);
+    row_bytes.checked_mul(integer(b"Height")?)
+}
+
+/// Offset just past EI if only whitespace lies between `data_end` and a delimited EI
+fn end_after(content: &[u8], data_end: usize) -> Option<usize> {
+    let position = skip_whitespace(content, data_end);
+    (content.get(position..position + 2) == Some(b"EI") && is_token_boundary(content, position + 2))
+        .then_some(position + 2)
+}
+
+/// Find the end of inline data by scanning for whitespace followed by a delimited EI
+fn scan_for_end(content: &[u8], data_start: usize) -> Result<(usize, usize), String> {
+    let data_end = find_end_marker(content, data_start).ok_or("Inline image without EI")?;
+    Ok((data_end, data_end + 3))
+}
+
+/// Offset of the whitespace byte before the first delimited EI at or after `from`
+fn find_end_marker(content: &[u8], from: usize) -> Option<usize> {
+    (from..content.len().saturating_sub(2)).find(|&i| {
+        is_whitespace(content[i]) && &content[i + 1..i + 3] == b"EI" && is_token_boundary(content, i + 3)
+    })
+}
+
+/// Parse one content-stream operand: a name, number, boolean, string, array or dictionary
+fn parse_object(content: &[u8], position: usize) -> Result<(Object, usize), String> {
+    parse_nested_object(content, position, 0)
+}
+
+/// `parse_object` for an operand inside `depth` enclosing arrays and dictionaries
+fn parse_nested_object(content: &[u8], position: usize, depth: usize) -> Result<(Object, usize), String> {
+    if depth > MAX_NESTING_DEPTH {
+        return Err(format!("Operands nested more than {} levels deep", MAX_NESTING_DEPTH));
+    }
+    let position = skip_whitespace(content, position);
+    let byte = *content.get(position).ok_or("Unexpected end of content stream")?;
+
+    match byte {
+        b'/' => {
+            let end = token_end(content, position + 1);
+            Ok((Object::Name(content[position + 1..end].to_vec()), end))
+        }
+        b'[' => {
+            let mut items = Vec::new();
+            let mut next = position + 1;
+            loop {
+                next = skip_whitespace(content, next);
+                match content.get(next) {
+                    Some(b']') => return Ok((Object::Array(items), next + 1)),
+                    Some(_) => {
+                        let (item, after) = parse_nested_object(content, next, depth + 1)?;
+                        items.push(item);
+                        next = after;
+                    }
+                    None => return Err("Unterminated array".to_string()),
+                }
+            }
+        }
+        b'<' if content.get(position + 1) == Some(&b'<') => {
+            let mut dict = Dictionary::new();
+            let mut next = position + 2;
+            loop {
+                next = skip_whitespace(content, next);
+                if content[next..].starts_with(b">>") {
+                    return Ok((Object::Dictionary(dict), next + 2));
+                }
+                let (key, after) = parse_nested_object(content, next, depth + 1)?;
+                let (value, after) = parse_nested_object(content, after, depth + 1)?;
+                let Object::Name(key) = key else {
+                    return Err("Dictionary key is not a name".to_string());
+                };
+                dict.set(key, value);
+                next = after;
+            }
+        }
+        b'<' => {
+            let end = skip_hex_string(content, position)?;
+            Ok((Object::String(content[position + 1..end - 1].to_vec(), lopdf::StringFormat::Hexadecimal), end))
+        }
+        b'(' => {
+            let end = skip_literal_string(content, position)?;
+            Ok((Object::String(content[position + 1..end - 1].to_vec(), lopdf::StringFormat::Literal), end))
+        }
+        _ => {
+            let end = token_end(content, position);
+            let token = std::str::from_utf8(&content[position..end]).map_err(|_| "Invalid token")?;
+            let object = match token {
+                "true" => Object::Boolean(true),
+                "false" => Object::Boolean(false),
+                "null" => Object::Null,
+                _ => match token.parse::<i64>() {
+                    Ok(value) => Object::Integer(value),
+                    Err(_) => Object::Real(token.parse::<f32>().map_err(|_| format!("Unexpected token {}", token))?),
+                },
+            };
+            Ok((object, end))
+        }
+    }
+}
+
+/// Full key name for an inline image abbreviation
+fn expand_key(key: &[u8]) -> Vec<u8> {
+    let full: &[u8] = match key {
+        b"BPC" => b"BitsPerComponent",
+        b"CS" => b"ColorSpace",
+        b"D" => b"Decode",
+        b"DP" => b"DecodeParms",
+        b"F" => b"Filter",
+        b"H" => b"Height",
+        b"IM" => b"ImageMask",
+        b"I" => b"Interpolate",
+        b"L" => b"Length",
+        b"W" => b"Width",
+        other => other,
+    };
+    full.to_vec()
+}
+
+/// Full colour space and filter names for inline image abbreviations
+fn expand_value(value: Object) -> Object {
+    match value {
+        Object::Name(name) => {
+            let full: &[u8] = match name.as_slice() {
+                b"G" => b"DeviceGray",
+                b"RGB" => b"DeviceRGB",
+                b"CMYK" => b"DeviceCMYK",
+                b"I" => b"Indexed",
+                b"AHx" => b"ASCIIHexDecode",
+                b"A85" => b"ASCII85Decode",
+                b"LZW" => b"LZWDecode",
+                b"Fl" => b"FlateDecode",
+                b"RL" => b"RunLengthDecode",
+                b"CCF" => b"CCITTFaxDecode",
+                b"DCT" => b"DCTDecode",
+                other => other,
+            };
+            Object::Name(full.to_vec())
+        }
+        Object::Array(items) => Object::Array(items.into_iter().map(expand_value).collect()),
+        other => other,
+    }
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\r' | b'\n' | b'\x0c' | b'\0')
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    matches!(byte, b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%')
+}
+
+fn is_token_boundary(content: &[u8], position: usize) -> bool {
+    content.get(position).is_none_or(|&byte| is_whitespace(byte) || is_delimiter(byte))
+}
+
+fn token_end(content: &[u8], position: usize) -> usize {
+    (position..content.len())
+        .find(|&i| is_whitespace(content[i]) || is_delimiter(content[i]))
+        .unwrap_or(content.len())
+}
+
+fn skip_whitespace(content: &[u8], position: usize) -> usize {
+    (position..content.len()).find(|&i| !is_whitespace(content[i])).unwrap_or(content.len())
+}
+
+fn skip_comment(content: &[u8], position: usize) -> usize {
+    (position..content.len())
+        .find(|&i| content[i] == b'\n' || content[i] == b'\r')
+        .unwrap_or(content.len())
+}
+
+/// Offset just past a literal string, honouring escapes and nested parentheses
+fn skip_literal_string(content: &[u8], position: usize) -> Result<usize, String> {
+    let mut depth = 0;
+    let mut i = position;
+    while i < content.len() {
+        match content[i] {
+            b'\\' => i += 1,
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Err("Unterminated string".to_string())
+}
+
+fn skip_hex_string(content: &[u8], position: usize) -> Result<usize, String> {
+    content[position..]
+        .iter()
+        .position(|&byte| byte == b'>')
+        .map(|offset| position + offset + 1)
+        .ok_or_else(|| "Unterminated hex string".to_string())
+}
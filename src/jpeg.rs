@@ -0,0 +1,134 @@
+// JPEG encoding, backed by the image crate or mozjpeg (selected via cargo features)
+use image::DynamicImage;
+
+/// Encode as JPEG, keeping a single channel for grayscale images
+#[cfg(not(feature = "mozjpeg"))]
+pub(crate) fn encode_jpeg(img: &DynamicImage, quality: u8, progressive: bool) -> Result<Vec<u8>, String> {
+    if progressive {
+        return encode_progressive_jpeg(img, quality);
+    }
+
+    let mut output = Vec::new();
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output, quality);
+
+    let result = if img.color().has_color() {
+        let rgb = img.to_rgb8();
+        encoder.encode(rgb.as_raw(), rgb.width(), rgb.height(), image::ColorType::Rgb8)
+    } else {
+        let gray = img.to_luma8();
+        encoder.encode(gray.as_raw(), gray.width(), gray.height(), image::ColorType::L8)
+    };
+    result.map_err(|e| format!("JPEG encoding failed: {}", e))?;
+
+    Ok(output)
+}
+
+/// Progressive JPEG via jpeg-encoder, since image 0.24 only writes baseline
+#[cfg(not(feature = "mozjpeg"))]
+fn encode_progressive_jpeg(img: &DynamicImage, quality: u8) -> Result<Vec<u8>, String> {
+    use jpeg_encoder::{ColorType, Encoder};
+
+    let too_large = |_| format!("Image too large for progressive JPEG: {}x{}", img.width(), img.height());
+    let width = u16::try_from(img.width()).map_err(too_large)?;
+    let height = u16::try_from(img.height()).map_err(too_large)?;
+
+    let mut output = Vec::new();
+    let mut encoder = Encoder::new(&mut output, quality);
+    encoder.set_progressive(true);
+
+    let result = if img.color().has_color() {
+        encoder.encode(img.to_rgb8().as_raw(), width, height, ColorType::Rgb)
+    } else {
+        encoder.encode(img.to_luma8().as_raw(), width, height, ColorType::Luma)
+    };
+    result.map_err(|e| format!("JPEG encoding failed: {}", e))?;
+
+    Ok(output)
+}
+
+/// Encode as JPEG with mozjpeg's trellis quantization, keeping a single channel for grayscale images
+/// mozjpeg's defaults already use optimized progressive scans, so `progressive` only forces them on
+#[cfg(feature = "mozjpeg")]
+pub(crate) fn encode_jpeg(img: &DynamicImage, quality: u8, progressive: bool) -> Result<Vec<u8>, String> {
+    use mozjpeg::{ColorSpace, Compress};
+
+    let (color_space, pixels) = if img.color().has_color() {
+        (ColorSpace::JCS_RGB, img.to_rgb8().into_raw())
+    } else {
+        (ColorSpace::JCS_GRAYSCALE, img.to_luma8().into_raw())
+    };
+    let (width, height) = (img.width() as usize, img.height() as usize);
+
+    // libjpeg reports fatal errors by unwinding
+    let result = std::panic::catch_unwind(move || -> std::io::Result<Vec<u8>> {
+        let mut compress = Compress::new(color_space);
+        compress.set_size(width, height);
+        compress.set_quality(quality as f32);
+        if progressive {
+            compress.set_progressive_mode();
+        }
+
+        let mut started = compress.start_compress(Vec::new())?;
+        started.write_scanlines(&pixels)?;
+        started.finish()
+    });
+
+    match result {
+        Ok(Ok(output)) => Ok(output),
+        Ok(Err(e)) => Err(format!("JPEG encoding failed: {}", e)),
+        Err(_) => Err("JPEG encoding failed: mozjpeg aborted".to_string()),
+    }
+}
+
+/// Colour layout of a JPEG, read from its frame header and Adobe APP14 marker
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct JpegLayout {
+    pub components: u8,
+    pub adobe_transform: Option<u8>, // 0 = none (RGB/CMYK), 1 = YCbCr, 2 = YCCK
+}
+
+impl JpegLayout {
+    /// CMYK or YCCK data, whose colours depend on the Adobe transform and inversion conventions
+    pub fn is_four_component(&self) -> bool {
+        self.components == 4
+    }
+}
+
+/// Scan JPEG marker segments up to the first scan for the frame's component count
+/// Returns None for data that doesn't parse as a JPEG
+pub(crate) fn jpeg_layout(data: &[u8]) -> Option<JpegLayout> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut adobe_transform = None;
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        // Fill bytes and standalone markers carry no length
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if matches!(marker, 0x01 | 0xD0..=0xD7) {
+            pos += 2;
+            continue;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + length)?;
+        match marker {
+            // APP14 "Adobe": version(2) flags0(2) flags1(2) transform(1)
+            0xEE if segment.len() >= 12 && segment.starts_with(b"Adobe") => adobe_transform = Some(segment[11]),
+            // SOFn (C4, C8 and CC are DHT, JPG and DAC): precision(1) height(2) width(2) components(1)
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                return Some(JpegLayout { components: *segment.get(5)?, adobe_transform });
+            }
+            0xDA => return None,
+            _ => {}
+        }
+        pos += 2 + length;
+    }
+    None
+}
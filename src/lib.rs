@@ -1,15 +1,499 @@
-use lopdf::{Document, Object, Stream};
-use log::{info, debug};
+#![allow(non_snake_case)]
+
+use lopdf::{Document, Object, ObjectId, Stream};
+use log::{info, debug, warn};
 use image::{DynamicImage, ImageFormat};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicUsize, AtomicI64, Ordering};
+use std::time::Duration;
+
+use decode::{decode_stream_bounded, StreamBudget};
+use jpeg::encode_jpeg;
 
 // Export API module for the api binary
+#[cfg(feature = "server")]
 pub mod api;
+mod batch;
+mod builder;
+mod chunk;
+mod color;
+mod convert;
+mod decode;
+mod deflate;
+mod detect;
+mod estimate;
+mod extract;
+mod forms;
+mod huffman;
+mod inline;
+mod jpeg;
+mod manifest;
+mod merge;
+mod pdfa;
+mod placement;
+mod preview;
+mod quantize;
+mod recover;
+mod remove_images;
+mod resources;
+mod split;
+mod validate;
+mod verify;
+mod xref;
 
-#[derive(Clone, Debug)]
+pub use batch::{compress_path, compress_paths_parallel};
+pub use builder::CompressionSettingsBuilder;
+pub use convert::images_to_pdf;
+#[cfg(feature = "pdf-render")]
+pub use convert::pdf_pages_to_images;
+pub use detect::{detect_input_type, detect_pdf_kind, InputType, PdfKind};
+pub use estimate::estimate_pdf_compression;
+pub use extract::{extract_images, ExtractedImage};
+pub use huffman::optimize_jpeg_huffman;
+pub use manifest::{sha256_hex, CompressionManifest};
+pub use merge::merge_and_compress_pdfs;
+pub use split::split_and_compress;
+pub use validate::{validate_input, InputKind};
+pub use verify::{is_better_compressed, verify_pdf_structure};
+pub use preview::{load_preview_image, PreviewImage};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct CompressionSettings {
     pub quality: u8, // 0-100, JPEG quality
+    pub flate_level: FlateLevel, // Deflate effort for generic streams
+    pub recompress_existing_flate: bool, // Re-deflate streams that are already FlateDecode (slow, usually small gains)
+    pub zopfli_min_bytes: Option<usize>, // Also try zopfli on generic streams this large, keeping the smaller (needs the zopfli feature)
+    pub protected_objects: HashSet<ObjectId>, // Streams that must be left byte-identical
+    pub raster_only: bool, // Also leave page content streams byte-identical, so only images and other streams change
+    pub remove_images: bool, // Delete image XObjects and the Do operators drawing them, for text-only output
+    pub aggressive_prune: bool, // Delete zero-length streams and objects unreachable from the trailer; off only drops objects nothing refers to
+    pub dedup_dictionaries: bool, // Also merge identical dictionary and array objects (ExtGStates, resource dicts), not just streams
+    pub prune_resources: bool, // After compression, drop resource entries (/XObject /Im1 ...) whose objects no longer exist
+    pub lossless_preference: f32, // Auto format keeps PNG while png_size <= jpeg_size * this
+    pub rounds: Option<u32>, // Compression rounds; None runs DEFAULT_ROUNDS (PDF_COMPRESSION_ROUNDS only reaches compress_pdf_bytes and the API server)
+    pub round_convergence_pct: f64, // Stop rounds early once one shrinks the document by less than this; 0 runs them all
+    pub max_dimension: Option<u32>, // Long-edge pixel cap for images; None uses the quality band
+    pub max_short_edge: Option<u32>, // Short-edge pixel cap, so tall scans can keep a larger max_dimension and still bound their width
+    pub strip_metadata: bool, // Drop XMP/Metadata objects
+    pub lossless: bool, // Never re-encode pixels lossily or resize them
+    pub no_resize: bool, // Re-encode images at the chosen quality but keep their exact pixel dimensions, whatever the caps or quality band say
+    pub force_grayscale: bool, // Convert re-encoded images to grayscale
+    pub flatten_background: [u8; 3], // RGB colour transparent pixels are composited over wherever the output has no alpha channel (JPEG)
+    pub image_policy: ImagePolicy, // What to do with image streams in PDFs
+    pub min_reduction_pct: Option<f64>, // Return the input unchanged if it shrinks by less than this
+    pub stream_timeout_ms: u64, // Soft per-stream time budget; slower streams keep their original bytes
+    pub max_decompressed_bytes: usize, // Decode cap per stream, stops decompression bombs
+    pub max_image_pixels: u64, // Standalone images declaring more pixels than this are rejected before decoding
+    pub load_timeout_ms: u64, // Give up on a PDF the parser can't load within this long
+    pub progressive: bool, // Emit progressive JPEGs instead of baseline
+    pub optimize_huffman: bool, // Losslessly re-pack baseline JPEGs (new and existing DCTDecode images) with optimal Huffman tables
+    pub jpeg_quality: Option<u8>, // Exact JPEG encoder quality (1-100); `quality` still drives downsampling and palettes
+    pub min_jpeg_quality: Option<u8>, // Floor for the JPEG encoder quality, whatever the level or jpeg_quality asks for
+    pub max_jpeg_quality: Option<u8>, // Ceiling for the JPEG encoder quality
+    pub resample_filter: ResampleFilter, // Filter used when downsampling images
+    pub verify: bool, // Reload the output and check page count and MediaBoxes against the input
+    pub verify_fallback: bool, // On a failed verification, return the input instead of an error
+    pub low_memory: bool, // Compress streams (and auto-format image trials, dedup hashing) one at a time instead of in parallel
+    pub max_threads: Option<usize>, // Compress streams on a private pool of this many threads instead of the global rayon pool
+    pub page_chunk_size: Option<usize>, // Compress the streams of this many pages at a time, so stream copies scale with the chunk; loading and output still hold the whole document
+    pub reduce_png: bool, // Losslessly write gray-only PNGs as grayscale and few-color PNGs as indexed
+    pub quantize_png: bool, // Below quality 90, reduce PNG output to an alpha-aware palette sized by quality; lossy, so off by default
+    pub target_dpi: Option<u32>, // Downsample PDF images to this resolution at their largest placed size, instead of the quality band
+    pub flatten_forms: bool, // Draw form field appearances into the page content and remove the interactive form
+    pub set_producer: bool, // Record the crate version and JPEG quality (not the compression level) as /Producer in the document information dictionary
+    pub convert_to_srgb: bool, // Convert standalone images with a non-sRGB ICC profile into sRGB pixels
+    pub auto_strategy: bool, // Detect scanned vs digital PDFs and adjust these settings to suit (see tuned_for)
+    pub preserve_pdfa: bool, // For inputs claiming PDF/A, skip what would break conformance: metadata stripping, /Producer, lossy re-encoding of ICC/calibrated images
+}
+
+impl Default for CompressionSettings {
+    fn default() -> Self {
+        Self {
+            quality: compression_level_to_quality(75),
+            flate_level: FlateLevel::Best,
+            recompress_existing_flate: false,
+            zopfli_min_bytes: None,
+            protected_objects: HashSet::new(),
+            raster_only: false,
+            remove_images: false,
+            aggressive_prune: true,
+            dedup_dictionaries: true,
+            prune_resources: true,
+            lossless_preference: 1.1,
+            rounds: None,
+            round_convergence_pct: 1.0,
+            max_dimension: None,
+            max_short_edge: None,
+            strip_metadata: true,
+            lossless: false,
+            no_resize: false,
+            force_grayscale: false,
+            flatten_background: [255, 255, 255],
+            image_policy: ImagePolicy::Recompress,
+            min_reduction_pct: None,
+            stream_timeout_ms: 5_000,
+            max_decompressed_bytes: 256 * 1024 * 1024,
+            max_image_pixels: MAX_IMAGE_PIXELS as u64,
+            load_timeout_ms: xref::LOAD_TIMEOUT_MS,
+            progressive: false,
+            optimize_huffman: false,
+            jpeg_quality: None,
+            min_jpeg_quality: None,
+            max_jpeg_quality: None,
+            resample_filter: ResampleFilter::default(),
+            verify: false,
+            verify_fallback: false,
+            low_memory: false,
+            max_threads: None,
+            page_chunk_size: None,
+            reduce_png: true,
+            quantize_png: false,
+            target_dpi: None,
+            flatten_forms: false,
+            set_producer: true,
+            convert_to_srgb: false,
+            auto_strategy: false,
+            preserve_pdfa: false,
+        }
+    }
+}
+
+impl CompressionSettings {
+    /// Parse settings from a JSON string, or from the JSON file at that path
+    /// Omitted keys keep their default values
+    pub fn from_json(path_or_str: &str) -> Result<CompressionSettings, String> {
+        if path_or_str.trim_start().starts_with('{') {
+            return Self::parse_json(path_or_str);
+        }
+        let json = std::fs::read_to_string(path_or_str)
+            .map_err(|e| format!("Failed to read config {}: {}", path_or_str, e))?;
+        Self::parse_json(&json)
+    }
+    
+    /// Parse settings from JSON text, never taking it for a path
+    /// Omitted keys keep their default values
+    pub fn parse_json(json: &str) -> Result<CompressionSettings, String> {
+        serde_json::from_str(json)
+            .map_err(|e| format!("Invalid compression settings: {}", e))
+    }
+    
+    /// These settings adjusted for a kind of PDF, used by auto_strategy
+    /// Scans are downsampled to SCANNED_TARGET_DPI; digital documents get their existing flate
+    /// streams re-deflated and a JPEG quality floor. Limits already set are kept
+    pub fn tuned_for(&self, kind: PdfKind) -> CompressionSettings {
+        match kind {
+            PdfKind::Scanned => CompressionSettings {
+                target_dpi: self.target_dpi.or(Some(SCANNED_TARGET_DPI)),
+                ..self.clone()
+            },
+            PdfKind::Digital => CompressionSettings {
+                recompress_existing_flate: true,
+                min_jpeg_quality: self.min_jpeg_quality.or(Some(DIGITAL_MIN_JPEG_QUALITY)),
+                ..self.clone()
+            },
+        }
+    }
+    
+    /// Quality handed to the JPEG encoder, kept within the configured floor and ceiling
+    fn jpeg_encoder_quality(&self) -> u8 {
+        let floor = self.min_jpeg_quality.unwrap_or(1).clamp(1, 100);
+        let ceiling = self.max_jpeg_quality.unwrap_or(100).clamp(floor, 100);
+        self.jpeg_quality.unwrap_or(self.quality).clamp(floor, ceiling)
+    }
+}
+
+/// Resolution auto_strategy downsamples scanned pages to
+const SCANNED_TARGET_DPI: u32 = 150;
+
+/// JPEG quality floor auto_strategy keeps for images in digital documents
+const DIGITAL_MIN_JPEG_QUALITY: u8 = 85;
+
+/// How image streams inside PDFs are treated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImagePolicy {
+    /// Re-encode images as JPEG (or flate-only in lossless mode)
+    #[default]
+    Recompress,
+    /// Leave image streams exactly as they are
+    Keep,
+}
+
+/// Deflate effort used when (re)compressing non-image streams
+/// Lower levels trade output size for speed on large streams
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlateLevel {
+    Fast,
+    Default,
+    #[default]
+    Best,
+}
+
+impl FlateLevel {
+    pub(crate) fn to_compression(self) -> flate2::Compression {
+        match self {
+            FlateLevel::Fast => flate2::Compression::fast(),
+            FlateLevel::Default => flate2::Compression::default(),
+            FlateLevel::Best => flate2::Compression::best(),
+        }
+    }
+}
+
+/// Resampling filter used when downsampling images
+/// Cheaper filters trade sharpness for speed on large batches
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResampleFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    #[default]
+    Lanczos3,
+}
+
+impl ResampleFilter {
+    fn to_filter_type(self) -> image::imageops::FilterType {
+        match self {
+            ResampleFilter::Nearest => image::imageops::FilterType::Nearest,
+            ResampleFilter::Triangle => image::imageops::FilterType::Triangle,
+            ResampleFilter::CatmullRom => image::imageops::FilterType::CatmullRom,
+            ResampleFilter::Lanczos3 => image::imageops::FilterType::Lanczos3,
+        }
+    }
+}
+
+impl std::str::FromStr for ResampleFilter {
+    type Err = String;
+    
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_lowercase().as_str() {
+            "nearest" => Ok(ResampleFilter::Nearest),
+            "triangle" => Ok(ResampleFilter::Triangle),
+            "catmullrom" => Ok(ResampleFilter::CatmullRom),
+            "lanczos3" => Ok(ResampleFilter::Lanczos3),
+            _ => Err(format!("Unknown resample filter: {} (expected nearest, triangle, catmullrom or lanczos3)", name)),
+        }
+    }
+}
+
+/// Ghostscript-style presets (`/screen`, `/ebook`, `/printer`, `/prepress`), each a compression
+/// level with an image resolution to match; all of them keep colour, like Ghostscript's
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityPreset {
+    /// 72 DPI and strong compression, for on-screen viewing
+    Screen,
+    /// 150 DPI, for e-readers and sharing
+    Ebook,
+    /// 300 DPI with little visible loss, for office printing
+    Printer,
+    /// 300 DPI at near-lossless quality, for print production
+    Prepress,
+}
+
+impl QualityPreset {
+    /// Compression level (10-95) the preset compresses at
+    pub fn level(self) -> u8 {
+        match self {
+            QualityPreset::Screen => 90,
+            QualityPreset::Ebook => 75,
+            QualityPreset::Printer => 40,
+            QualityPreset::Prepress => 20,
+        }
+    }
+    
+    /// Resolution PDF images are downsampled to at their largest placed size
+    pub fn target_dpi(self) -> u32 {
+        match self {
+            QualityPreset::Screen => 72,
+            QualityPreset::Ebook => 150,
+            QualityPreset::Printer | QualityPreset::Prepress => 300,
+        }
+    }
+    
+    /// Long-edge pixel cap, for standalone images and PDF images that are never drawn
+    pub fn max_dimension(self) -> Option<u32> {
+        match self {
+            QualityPreset::Screen => Some(1280),
+            QualityPreset::Ebook => Some(2048),
+            QualityPreset::Printer => Some(4096),
+            QualityPreset::Prepress => None,
+        }
+    }
+    
+    /// Default settings with this preset applied
+    pub fn settings(self) -> CompressionSettings {
+        CompressionSettings::builder().preset(self).build()
+    }
+}
+
+impl std::str::FromStr for QualityPreset {
+    type Err = String;
+    
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        // Ghostscript spells them with a leading slash
+        match name.trim().trim_start_matches('/').to_lowercase().as_str() {
+            "screen" => Ok(QualityPreset::Screen),
+            "ebook" => Ok(QualityPreset::Ebook),
+            "printer" => Ok(QualityPreset::Printer),
+            "prepress" => Ok(QualityPreset::Prepress),
+            _ => Err(format!("Unknown preset: {} (expected screen, ebook, printer or prepress)", name)),
+        }
+    }
+}
+
+/// Image formats `compress_image_with_settings` can write
+const IMAGE_OUTPUT_FORMATS: [&str; 3] = ["jpg", "png", "webp"];
+
+/// What this build can read and write, and which optional cargo features it was compiled with
+#[derive(Clone, Debug, Serialize)]
+pub struct SupportedFormats {
+    pub input: Vec<&'static str>, // File extensions accepted for compression
+    pub output: Vec<&'static str>, // File extensions that can be produced
+    pub features: Vec<&'static str>,
+}
+
+/// Formats and features of this build, for clients that shouldn't hardcode them
+pub fn supported_formats() -> SupportedFormats {
+    let mut input = vec!["pdf"];
+    input.extend(
+        ImageFormat::all()
+            .filter(|format| format.reading_enabled())
+            .filter_map(|format| format.extensions_str().first().copied()),
+    );
+    
+    let mut output = vec!["pdf"];
+    output.extend(IMAGE_OUTPUT_FORMATS);
+    
+    let features = [
+        ("gui", cfg!(feature = "gui")),
+        ("api", cfg!(feature = "api")),
+        ("server", cfg!(feature = "server")),
+        ("zlib-ng", cfg!(feature = "zlib-ng")),
+        ("mozjpeg", cfg!(feature = "mozjpeg")),
+        ("zopfli", cfg!(feature = "zopfli")),
+        ("pdf-render", cfg!(feature = "pdf-render")),
+    ];
+    
+    SupportedFormats {
+        input,
+        output,
+        features: features.into_iter().filter(|(_, enabled)| *enabled).map(|(name, _)| name).collect(),
+    }
+}
+
+/// Name of the zlib backend flate2 was built with (selected via cargo features)
+pub fn flate_backend() -> &'static str {
+    if cfg!(feature = "zlib-ng") {
+        "zlib-ng"
+    } else {
+        "miniz_oxide"
+    }
+}
+
+/// Name of the JPEG encoder selected via cargo features
+pub fn jpeg_backend() -> &'static str {
+    if cfg!(feature = "mozjpeg") {
+        "mozjpeg"
+    } else {
+        "image"
+    }
+}
+
+/// Breakdown of what each compression step saved
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CompressionReport {
+    pub original_size: u64,
+    pub final_size: u64,
+    pub duplicate_objects: usize, // Identical streams (and, with dedup_dictionaries, dictionaries and arrays) merged into a single object
+    pub duplicate_font_programs: usize, // Embedded font programs merged into a shared copy
+    pub image_bytes_saved: i64,
+    pub inline_images: usize, // Inline images (BI ... EI) recompressed or moved to XObjects
+    pub encoded_images: usize, // Image streams run through the encoder
+    pub reused_image_encodings: usize, // Image copies given an identical image's encoding instead of being re-encoded
+    pub flattened_form_fields: usize, // Form widgets drawn into page content (or dropped) by flatten_forms
+    pub removed_images: usize, // Image XObjects deleted by remove_images
+    pub dangling_resources_removed: usize, // Resource entries dropped by prune_resources because their object was gone
+    pub flate_bytes_saved: i64,
+    pub metadata_objects_removed: usize,
+    pub metadata_bytes_removed: u64,
+    pub skipped: bool, // Output is the untouched input: min_reduction_pct was not met or verification fell back
+    pub rounds_run: u32, // Compression rounds performed before converging or hitting the round limit
+    pub detected_kind: Option<PdfKind>, // What auto_strategy took the PDF for
+    pub pdfa_note: Option<String>, // What preserve_pdfa held back, for inputs claiming PDF/A
+    pub images: Vec<ImageReport>, // Every image XObject considered, in object order
+}
+
+/// Before and after figures for one image XObject
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ImageReport {
+    pub object_id: ObjectId,
+    pub original_width: u32,
+    pub original_height: u32,
+    pub output_width: u32,
+    pub output_height: u32,
+    pub original_filter: Option<String>, // Filter names separated by spaces; None if unfiltered
+    pub output_filter: Option<String>,
+    pub original_bytes: u64,
+    pub output_bytes: u64,
+    pub downsampled: bool,
+}
+
+impl ImageReport {
+    fn new(object_id: ObjectId, stream: &Stream) -> Self {
+        let (width, height) = image_dimensions(stream);
+        let filter = filter_names(stream);
+        Self {
+            object_id,
+            original_width: width,
+            original_height: height,
+            output_width: width,
+            output_height: height,
+            original_filter: filter.clone(),
+            output_filter: filter,
+            original_bytes: stream.content.len() as u64,
+            output_bytes: stream.content.len() as u64,
+            downsampled: false,
+        }
+    }
+    
+    /// Record the stream that ended up in the document
+    fn set_output(&mut self, stream: &Stream) {
+        (self.output_width, self.output_height) = image_dimensions(stream);
+        self.output_filter = filter_names(stream);
+        self.output_bytes = stream.content.len() as u64;
+        self.downsampled = self.output_width < self.original_width || self.output_height < self.original_height;
+    }
+}
+
+fn image_dimensions(stream: &Stream) -> (u32, u32) {
+    let dimension = |key: &[u8]| stream.dict.get(key).and_then(Object::as_i64).ok()
+        .and_then(|value| u32::try_from(value).ok())
+        .unwrap_or(0);
+    (dimension(b"Width"), dimension(b"Height"))
+}
+
+fn filter_names(stream: &Stream) -> Option<String> {
+    let names: Vec<String> = match stream.dict.get(b"Filter") {
+        Ok(Object::Name(name)) => vec![String::from_utf8_lossy(name).into_owned()],
+        Ok(Object::Array(filters)) => filters.iter()
+            .filter_map(|filter| filter.as_name().ok())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect(),
+        _ => Vec::new(),
+    };
+    (!names.is_empty()).then(|| names.join(" "))
+}
+
+impl CompressionReport {
+    /// Total size change of the document (negative if the output grew)
+    pub fn total_bytes_saved(&self) -> i64 {
+        self.original_size as i64 - self.final_size as i64
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -20,14 +504,13 @@ pub struct CompressionResult {
     pub error_message: Option<String>,
 }
 
-/// Compress PDF from bytes with specified quality percentage (0-100)
-/// Quality maps to compression: 75 = 75% compression = ~25% of original size
-pub fn compress_pdf_bytes(input_bytes: &[u8], compression_level: u8) -> Result<Vec<u8>, String> {
+/// Map a compression level (10-95) to JPEG quality
+/// Compression 10-25 = JPEG 90-100, 25-50 = 70-90, 50-75 = 50-70, 75-95 = 30-50
+pub fn compression_level_to_quality(compression_level: u8) -> u8 {
     // Clamp compression level
-    let compression_level = compression_level.min(95).max(10);
+    let compression_level = compression_level.clamp(10, 95);
     
-    // Convert compression level to JPEG quality
-    let jpeg_quality = if compression_level <= 25 {
+    if compression_level <= 25 {
         100 - (compression_level as f32 * 0.4) as u8 // 100 to 90
     } else if compression_level <= 50 {
         90 - ((compression_level - 25) as f32 * 0.8) as u8 // 90 to 70
@@ -35,235 +518,1119 @@ pub fn compress_pdf_bytes(input_bytes: &[u8], compression_level: u8) -> Result<V
         70 - ((compression_level - 50) as f32 * 0.8) as u8 // 70 to 50
     } else {
         50 - ((compression_level - 75) as f32) as u8 // 50 to 25
+    }
+}
+
+/// Size reduction from `original` to `compressed` bytes, in percent
+/// Negative when the output grew; 0 for an empty original
+pub fn reduction_percentage(original: u64, compressed: u64) -> f64 {
+    if original == 0 {
+        return 0.0;
+    }
+    (original as f64 - compressed as f64) / original as f64 * 100.0
+}
+
+/// Compression rounds run when the settings don't set any
+const DEFAULT_ROUNDS: u32 = 2;
+
+/// Most compression rounds ever run
+const MAX_ROUNDS: u32 = 5;
+
+/// Rounds from the PDF_COMPRESSION_ROUNDS environment variable, if set to a number
+/// Read by the level-based entry points and the API server; settings-based ones ignore it
+pub(crate) fn env_compression_rounds() -> Option<u32> {
+    std::env::var("PDF_COMPRESSION_ROUNDS").ok().and_then(|v| v.trim().parse::<u32>().ok())
+}
+
+/// Compress PDF from bytes with specified quality percentage (0-100)
+/// Quality maps to compression: 75 = 75% compression = ~25% of original size
+/// For backward compatibility the number of rounds still comes from PDF_COMPRESSION_ROUNDS if set
+pub fn compress_pdf_bytes(input_bytes: &[u8], compression_level: u8) -> Result<Vec<u8>, String> {
+    let settings = CompressionSettings {
+        quality: compression_level_to_quality(compression_level),
+        rounds: env_compression_rounds(),
+        ..Default::default()
     };
     
+    info!("Starting compression with quality {}% (compression level {}%)", settings.quality, compression_level.clamp(10, 95));
+    
+    compress_pdf_with_settings(input_bytes, &settings)
+}
+
+/// Compress PDF from bytes and return a report of per-step savings
+/// Like compress_pdf_bytes, honors PDF_COMPRESSION_ROUNDS
+pub fn compress_pdf_bytes_reported(input_bytes: &[u8], compression_level: u8) -> Result<(Vec<u8>, CompressionReport), String> {
     let settings = CompressionSettings {
-        quality: jpeg_quality,
+        quality: compression_level_to_quality(compression_level),
+        rounds: env_compression_rounds(),
+        ..Default::default()
     };
     
-    info!("Starting compression with quality {}% (compression level {}%)", jpeg_quality, compression_level);
-    
-    // Load PDF from bytes
-    let mut doc = Document::load_mem(input_bytes)
-        .map_err(|e| format!("Failed to load PDF: {}", e))?;
+    compress_pdf_with_settings_reported(input_bytes, &settings)
+}
+
+/// Compress PDF from bytes using explicit settings
+/// Nothing is read from the environment: `rounds` unset means DEFAULT_ROUNDS
+pub fn compress_pdf_with_settings(input_bytes: &[u8], settings: &CompressionSettings) -> Result<Vec<u8>, String> {
+    compress_pdf_with_settings_reported(input_bytes, settings).map(|(output, _)| output)
+}
+
+/// Compress PDF from bytes using explicit settings and return a report of per-step savings
+pub fn compress_pdf_with_settings_reported(
+    input_bytes: &[u8],
+    settings: &CompressionSettings,
+) -> Result<(Vec<u8>, CompressionReport), String> {
+    let doc = xref::load_document_within(input_bytes, Duration::from_millis(settings.load_timeout_ms))?;
+    compress_loaded_pdf(doc, input_bytes, settings)
+}
+
+/// Like [`compress_pdf_with_settings`], with the input shared with the loader thread instead of copied
+#[cfg(feature = "server")]
+pub(crate) fn compress_shared_pdf(input: &std::sync::Arc<Vec<u8>>, settings: &CompressionSettings) -> Result<Vec<u8>, String> {
+    let doc = xref::load_shared_document_within(std::sync::Arc::clone(input), Duration::from_millis(settings.load_timeout_ms))?;
+    compress_loaded_pdf(doc, input, settings).map(|(output, _)| output)
+}
+
+/// Compress a document loaded from `input_bytes`, which verification and the reduction check compare against
+fn compress_loaded_pdf(
+    mut doc: Document,
+    input_bytes: &[u8],
+    settings: &CompressionSettings,
+) -> Result<(Vec<u8>, CompressionReport), String> {
+    let mut report = CompressionReport {
+        original_size: input_bytes.len() as u64,
+        ..Default::default()
+    };
     
     let total_objects = doc.objects.len();
     info!("PDF loaded successfully. Total objects: {}", total_objects);
     
+    let expected_layout = settings.verify.then(|| verify::page_layout(&doc));
+    
+    compress_document(&mut doc, settings, &mut report)?;
+    
+    // Save to bytes
+    let mut output = Vec::new();
+    doc.save_to(&mut output)
+        .map_err(|e| format!("Failed to save: {}", e))?;
+    
+    info!("PDF compressed successfully: {} bytes -> {} bytes", input_bytes.len(), output.len());
+    
+    if let Some(expected_layout) = expected_layout {
+        if let Err(e) = verify::verify_output(&expected_layout, &output) {
+            if !settings.verify_fallback {
+                return Err(e);
+            }
+            warn!("{} - returning the original PDF", e);
+            report.skipped = true;
+            report.final_size = input_bytes.len() as u64;
+            return Ok((input_bytes.to_vec(), report));
+        }
+        debug!("Verified {} pages against the input", expected_layout.len());
+    }
+    
+    if below_min_reduction(input_bytes.len(), output.len(), settings.min_reduction_pct) {
+        report.skipped = true;
+        report.final_size = input_bytes.len() as u64;
+        return Ok((input_bytes.to_vec(), report));
+    }
+    
+    report.final_size = output.len() as u64;
+    Ok((output, report))
+}
+
+/// Compress PDF from bytes and also return a manifest with digests of the input and output
+pub fn compress_pdf_with_manifest(
+    input_bytes: &[u8],
+    settings: &CompressionSettings,
+) -> Result<(Vec<u8>, CompressionReport, CompressionManifest), String> {
+    let (output, report) = compress_pdf_with_settings_reported(input_bytes, settings)?;
+    let manifest = CompressionManifest::new(input_bytes, &output, settings);
+    Ok((output, report, manifest))
+}
+
+/// Compress a PDF read from `input` and write the result straight to `output`
+/// The document is still held in memory, but the output is not buffered into a second copy.
+/// With `verify` or `min_reduction_pct` set the output has to be checked (or discarded) before
+/// anything is written, so it is buffered as with [`compress_pdf_with_settings`]
+pub fn compress_pdf<R: std::io::Read, W: std::io::Write>(
+    mut input: R,
+    mut output: W,
+    settings: &CompressionSettings,
+) -> Result<(), String> {
+    let mut input_bytes = Vec::new();
+    input.read_to_end(&mut input_bytes)
+        .map_err(|e| format!("Failed to read input: {}", e))?;
+    
+    if settings.verify || settings.min_reduction_pct.is_some() {
+        let compressed = compress_pdf_with_settings(&input_bytes, settings)?;
+        return output.write_all(&compressed)
+            .and_then(|_| output.flush())
+            .map_err(|e| format!("Failed to save: {}", e));
+    }
+    
+    let mut report = CompressionReport {
+        original_size: input_bytes.len() as u64,
+        ..Default::default()
+    };
+    let mut doc = xref::load_document_within(&input_bytes, Duration::from_millis(settings.load_timeout_ms))?;
+    info!("PDF loaded successfully. Total objects: {}", doc.objects.len());
+    compress_document(&mut doc, settings, &mut report)?;
+    
+    doc.save_to(&mut output)
+        .and_then(|_| output.flush())
+        .map_err(|e| format!("Failed to save: {}", e))?;
+    info!("PDF compressed successfully from {} bytes", input_bytes.len());
+    Ok(())
+}
+
+/// Rebuild a PDF without compressing it: merge duplicate objects, drop unused ones, correct
+/// every stream's /Length and write a fresh xref table
+/// Images keep their bytes and filters; the only re-encoding is lopdf deflating streams that had no filter
+pub fn normalize_pdf(input_bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut doc = xref::load_document_within(input_bytes, Duration::from_millis(xref::LOAD_TIMEOUT_MS))?;
+    info!("PDF loaded for normalizing. Total objects: {}", doc.objects.len());
+    
+    let duplicates = remove_duplicate_objects(&mut doc, &HashSet::new(), true, true);
+    for object in doc.objects.values_mut() {
+        if let Object::Stream(stream) = object {
+            if is_image_stream(stream) {
+                stream.allows_compression = false;
+            }
+        }
+    }
+    
+    let navigation = navigation_snapshot(&doc);
+    let navigation_roots: HashSet<ObjectId> = navigation.keys().copied().collect();
+    // Before pruning, so objects that only held an indirect /Length go too
+    let lengths = fix_stream_lengths(&mut doc);
+    doc.compress();
+    let pruned = prune(&mut doc, &navigation_roots, true);
+    restore_navigation(&mut doc, navigation);
+    xref::reset_trailer_layout(&mut doc);
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output)
+        .map_err(|e| format!("Failed to save: {}", e))?;
+    info!(
+        "PDF normalized: {} bytes -> {} bytes ({} duplicates merged, {} unused objects removed, {} lengths corrected)",
+        input_bytes.len(), output.len(), duplicates, pruned, lengths
+    );
+    Ok(output)
+}
+
+/// Set every stream's /Length to the size of its content as a direct integer
+/// lopdf writes dictionaries as they are, so a stale or indirect /Length would otherwise be kept
+fn fix_stream_lengths(doc: &mut Document) -> usize {
+    let mut fixed = 0;
+    for object in doc.objects.values_mut() {
+        if let Object::Stream(stream) = object {
+            let length = stream.content.len() as i64;
+            if !matches!(stream.dict.get(b"Length"), Ok(Object::Integer(current)) if *current == length) {
+                stream.dict.set("Length", Object::Integer(length));
+                fixed += 1;
+            }
+        }
+    }
+    fixed
+}
+
+/// Run every compression pass over a loaded document, leaving it ready to save
+fn compress_document(doc: &mut Document, settings: &CompressionSettings, report: &mut CompressionReport) -> Result<(), String> {
+    // Decided before any pass runs, so every one of them sees the adjusted settings
+    let auto_settings;
+    let settings = if settings.auto_strategy {
+        let kind = detect::detect_pdf_kind(doc);
+        info!("Auto strategy: treating the PDF as {:?}", kind);
+        report.detected_kind = Some(kind);
+        auto_settings = settings.tuned_for(kind);
+        &auto_settings
+    } else {
+        settings
+    };
+    
+    // Also before any pass, since stripping metadata would remove the claim itself
+    let pdfa_settings;
+    let settings = if settings.preserve_pdfa {
+        pdfa_settings = preserving_pdfa(doc, settings, report);
+        &pdfa_settings
+    } else {
+        settings
+    };
+    
+    // Flattened widgets leave behind plain form XObjects, which the later passes compress like any other
+    if settings.flatten_forms {
+        report.flattened_form_fields = forms::flatten_forms(doc);
+    }
+    
+    // Page content streams join the protected set, so every later pass leaves them byte-identical
+    let raster_only_settings;
+    let settings = if settings.raster_only {
+        let mut protected_objects = settings.protected_objects.clone();
+        for page_id in doc.get_pages().into_values() {
+            protected_objects.extend(doc.get_page_contents(page_id));
+        }
+        info!("Raster only: keeping {} content streams as they are", protected_objects.len() - settings.protected_objects.len());
+        raster_only_settings = CompressionSettings { protected_objects, ..settings.clone() };
+        &raster_only_settings
+    } else {
+        settings
+    };
+    
+    // Before dedup and recompression, so no work is spent on images that are about to go
+    if settings.remove_images {
+        report.removed_images = remove_images::remove_images(doc, settings);
+    }
+    
     // Remove duplicate objects
     info!("Removing duplicate objects...");
-    let removed = remove_duplicate_objects(&mut doc);
-    info!("Removed {} duplicate objects", removed);
+    report.duplicate_objects = remove_duplicate_objects(doc, &settings.protected_objects, settings.dedup_dictionaries, !settings.low_memory);
+    info!("Removed {} duplicate objects", report.duplicate_objects);
+    
+    // Share identical embedded font programs between font descriptors
+    report.duplicate_font_programs = dedup_font_programs(doc, &settings.protected_objects);
+    info!("Merged {} duplicate font programs", report.duplicate_font_programs);
+    
+    // Inline images live inside content streams, so they are rewritten before those are deflated
+    if !settings.lossless && settings.image_policy != ImagePolicy::Keep {
+        let (inline_images, inline_saved) = inline::compress_inline_images(doc, settings);
+        report.inline_images = inline_images;
+        report.image_bytes_saved += inline_saved;
+    }
     
     // Compress images and streams
     info!("Compressing all streams with quality {}...", settings.quality);
-    compress_all_streams(&mut doc, &settings)?;
+    compress_all_streams(doc, settings, report)?;
     
     // Remove metadata to reduce size
     info!("Removing metadata objects...");
     let before_metadata = doc.objects.len();
-    doc.objects.retain(|_, obj| {
+    let mut metadata_bytes = 0u64;
+    doc.objects.retain(|obj_id, obj| {
+        if !settings.strip_metadata {
+            return true;
+        }
+        if settings.protected_objects.contains(obj_id) {
+            return true;
+        }
         if let Object::Dictionary(dict) = obj {
             if let Ok(Object::Name(name)) = dict.get(b"Type") {
                 return name != b"Metadata";
             }
         }
+        if let Object::Stream(stream) = obj {
+            if let Ok(Object::Name(name)) = stream.dict.get(b"Type") {
+                if name == b"Metadata" {
+                    metadata_bytes += stream.content.len() as u64;
+                    return false;
+                }
+            }
+        }
         true
     });
-    let metadata_removed = before_metadata - doc.objects.len();
-    info!("Removed {} metadata objects", metadata_removed);
+    report.metadata_objects_removed = before_metadata - doc.objects.len();
+    report.metadata_bytes_removed = metadata_bytes;
+    info!("Removed {} metadata objects", report.metadata_objects_removed);
+    
+    // Written after stripping, so the provenance survives it
+    if settings.set_producer {
+        set_producer(doc, settings);
+    }
     
-    // Perform compression rounds (configurable via env var for performance tuning)
-    let compression_rounds = std::env::var("PDF_COMPRESSION_ROUNDS")
-        .ok()
-        .and_then(|v| v.parse::<u32>().ok())
-        .unwrap_or(2) // Default to 2 rounds for better latency vs quality balance
-        .min(5); // Cap at 5 rounds max
+    // Keep lopdf's own compression passes away from protected streams
+    for obj_id in &settings.protected_objects {
+        if let Some(Object::Stream(stream)) = doc.objects.get_mut(obj_id) {
+            stream.allows_compression = false;
+        }
+    }
     
-    info!("Performing {} compression round(s)...", compression_rounds);
+    // Settings alone decide the rounds, so the environment can't change a caller's configuration
+    let compression_rounds = settings.rounds.unwrap_or(DEFAULT_ROUNDS).min(MAX_ROUNDS);
+    
+    // Outline and link objects are kept through pruning even if the tree structure hides them
+    let navigation = navigation_snapshot(doc);
+    let navigation_roots: HashSet<ObjectId> = navigation.keys().copied().collect();
+    
+    info!("Performing up to {} compression round(s)...", compression_rounds);
     for i in 0..compression_rounds {
         debug!("Compression round {}", i + 1);
+        let before = document_weight(doc);
         doc.compress();
-        doc.prune_objects();
-        doc.delete_zero_length_streams();
+        prune(doc, &navigation_roots, settings.aggressive_prune);
+        if settings.aggressive_prune {
+            doc.delete_zero_length_streams();
+        }
+        report.rounds_run += 1;
+        
+        // Later rounds rarely find anything, so stop once one barely changes the document
+        let after = document_weight(doc);
+        let shrunk_pct = |before: usize, after: usize| reduction_percentage(before as u64, after as u64).max(0.0);
+        if shrunk_pct(before.0, after.0) < settings.round_convergence_pct
+            && shrunk_pct(before.1, after.1) < settings.round_convergence_pct
+        {
+            debug!("Converged after {} round(s): {:?} -> {:?} (objects, stream bytes)", i + 1, before, after);
+            break;
+        }
     }
     
     // Final cleanup
     info!("Final cleanup...");
     doc.compress();
-    doc.prune_objects();
+    prune(doc, &navigation_roots, settings.aggressive_prune);
+    restore_navigation(doc, navigation);
+    
+    // Objects missing from the input or deleted by earlier passes may still be named in resources
+    if settings.prune_resources {
+        report.dangling_resources_removed = resources::prune_dangling_resources(doc, &settings.protected_objects);
+        info!("Removed {} resource entries pointing at missing objects", report.dangling_resources_removed);
+    }
     
     info!("Final object count: {}", doc.objects.len());
     
-    // Save to bytes
-    let mut output = Vec::new();
-    doc.save_to(&mut output)
-        .map_err(|e| format!("Failed to save: {}", e))?;
+    xref::reset_trailer_layout(doc);
+    Ok(())
+}
+
+/// Settings that keep a PDF/A input conforming, or `settings` with preserve_pdfa off if it makes no claim
+/// The XMP packet is kept byte-identical, /Info is left as the XMP describes it, and images in
+/// ICC-based or calibrated colour spaces only get lossless treatment so their colour space survives
+fn preserving_pdfa(doc: &Document, settings: &CompressionSettings, report: &mut CompressionReport) -> CompressionSettings {
+    let Some(conformance) = pdfa::pdfa_conformance(doc) else {
+        debug!("No PDF/A claim found, compressing as usual");
+        return CompressionSettings { preserve_pdfa: false, ..settings.clone() };
+    };
     
-    info!("PDF compressed successfully: {} bytes -> {} bytes", input_bytes.len(), output.len());
+    let note = format!(
+        "{} input: kept XMP metadata and /Producer, and re-encoded device-independent images losslessly only",
+        conformance
+    );
+    info!("{}", note);
+    report.pdfa_note = Some(note);
     
-    Ok(output)
+    let mut protected_objects = settings.protected_objects.clone();
+    protected_objects.extend(pdfa::metadata_stream(doc));
+    CompressionSettings {
+        strip_metadata: false,
+        set_producer: false,
+        protected_objects,
+        ..settings.clone()
+    }
 }
 
-fn remove_duplicate_objects(doc: &mut Document) -> usize {
+/// Record this crate, its version and the JPEG quality used as the document's /Producer
+/// The quality, not the 10-95 compression level, since settings only carry the quality it maps to
+/// (level 75 is quality 50). Every other /Info entry, /CreationDate included, is kept as it was
+fn set_producer(doc: &mut Document, settings: &CompressionSettings) {
+    let producer = format!("{} {} (JPEG quality {})", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), settings.quality);
+    debug!("Setting /Producer to {}", producer);
+    
+    let info_id = match doc.trailer.get(b"Info") {
+        Ok(Object::Reference(id)) if doc.get_dictionary(*id).is_ok() => *id,
+        // The trailer must reference /Info indirectly, so an inline dictionary is moved out
+        Ok(Object::Dictionary(info)) => {
+            let info = info.clone();
+            doc.add_object(info)
+        }
+        _ => doc.add_object(lopdf::Dictionary::new()),
+    };
+    if settings.protected_objects.contains(&info_id) {
+        return;
+    }
+    doc.trailer.set("Info", Object::Reference(info_id));
+    if let Ok(info) = doc.get_dictionary_mut(info_id) {
+        info.set("Producer", Object::string_literal(producer));
+    }
+}
+
+/// Object count and total stored stream bytes, a cheap stand-in for the saved size
+fn document_weight(doc: &Document) -> (usize, usize) {
+    let stream_bytes = doc.objects.values()
+        .filter_map(|object| object.as_stream().ok())
+        .map(|stream| stream.content.len())
+        .sum();
+    (doc.objects.len(), stream_bytes)
+}
+
+/// Merge byte-identical streams into one object and point every reference at it, and with
+/// `dictionaries` identical dictionary and array objects as well
+/// Hashes narrow the candidates; dictionaries and contents are compared before merging
+/// With `parallel` stream contents are hashed across the rayon pool; matching still runs in
+/// object order, so the same copies are kept either way
+fn remove_duplicate_objects(doc: &mut Document, protected: &HashSet<ObjectId>, dictionaries: bool, parallel: bool) -> usize {
     use ahash::AHashMap;
     use std::hash::{Hash, Hasher};
     use ahash::AHasher;
     
     // Use hash-based deduplication to avoid expensive content cloning
-    let mut unique_streams: AHashMap<u64, lopdf::ObjectId> = AHashMap::new();
-    let mut to_replace: Vec<(lopdf::ObjectId, lopdf::ObjectId)> = Vec::new();
+    let mut unique_streams: AHashMap<u64, Vec<ObjectId>> = AHashMap::new();
+    let mut remap: AHashMap<ObjectId, ObjectId> = AHashMap::new();
+    
+    // References held by protected objects must keep resolving, so those targets stay put
+    let mut pinned: HashSet<ObjectId> = protected.clone();
+    for obj_id in protected {
+        if let Some(object) = doc.objects.get(obj_id) {
+            collect_references(object, &mut pinned);
+        }
+    }
 
+    // Hash the content without cloning; with many large images this is most of the pass
+    let hash_stream = |(obj_id, object): (&ObjectId, &Object)| {
+        let stream = object.as_stream().ok()?;
+        let mut hasher = AHasher::default();
+        stream.content.hash(&mut hasher);
+        Some((*obj_id, hasher.finish()))
+    };
+    let content_hashes: Vec<(ObjectId, u64)> = if parallel {
+        doc.objects.par_iter().filter_map(hash_stream).collect()
+    } else {
+        doc.objects.iter().filter_map(hash_stream).collect()
+    };
+    
     // Find duplicate streams using content hash
-    for (obj_id, object) in doc.objects.iter() {
-        if let Object::Stream(stream) = object {
-            // Hash the content without cloning
+    for (obj_id, content_hash) in content_hashes {
+        let Some(Object::Stream(stream)) = doc.objects.get(&obj_id) else {
+            continue;
+        };
+        let candidates = unique_streams.entry(content_hash).or_default();
+        let existing = candidates.iter().copied().find(|candidate_id| {
+            matches!(doc.objects.get(candidate_id), Some(Object::Stream(candidate))
+                if candidate.dict == stream.dict && candidate.content == stream.content)
+        });
+        
+        match existing {
+            Some(existing_id) if !pinned.contains(&obj_id) => {
+                debug!("Found duplicate stream: {:?} is same as {:?}", obj_id, existing_id);
+                remap.insert(obj_id, existing_id);
+            }
+            _ => candidates.push(obj_id),
+        }
+    }
+
+    for obj_id in remap.keys() {
+        doc.objects.remove(obj_id);
+    }
+    remap_references(doc, &remap, protected);
+    let mut merged = remap.len();
+    if !dictionaries {
+        return merged;
+    }
+    
+    // Merging makes the dictionaries that referred to the copies identical in turn (a font
+    // pointing at a merged descriptor), so passes repeat until one finds nothing
+    loop {
+        let mut unique_dicts: AHashMap<u64, Vec<ObjectId>> = AHashMap::new();
+        let mut remap: AHashMap<ObjectId, ObjectId> = AHashMap::new();
+        for (obj_id, object) in doc.objects.iter() {
+            if !matches!(object, Object::Dictionary(_) | Object::Array(_)) || !is_shareable(object) {
+                continue;
+            }
             let mut hasher = AHasher::default();
-            stream.content.hash(&mut hasher);
-            let content_hash = hasher.finish();
+            hash_normalized(object, &mut hasher);
             
-            if let Some(&existing_id) = unique_streams.get(&content_hash) {
-                to_replace.push((*obj_id, existing_id));
-                debug!("Found duplicate stream: {:?} is same as {:?}", obj_id, existing_id);
-            } else {
-                unique_streams.insert(content_hash, *obj_id);
+            let candidates = unique_dicts.entry(hasher.finish()).or_default();
+            let existing = candidates.iter().copied().find(|candidate_id| {
+                doc.objects.get(candidate_id).is_some_and(|candidate| same_normalized(candidate, object))
+            });
+            match existing {
+                Some(existing_id) if !pinned.contains(obj_id) => {
+                    debug!("Found duplicate object: {:?} is same as {:?}", obj_id, existing_id);
+                    remap.insert(*obj_id, existing_id);
+                }
+                _ => candidates.push(*obj_id),
+            }
+        }
+        if remap.is_empty() {
+            break;
+        }
+        for obj_id in remap.keys() {
+            doc.objects.remove(obj_id);
+        }
+        remap_references(doc, &remap, protected);
+        merged += remap.len();
+    }
+
+    merged
+}
+
+/// Dictionary types whose objects must stay distinct even when their contents match:
+/// the page tree, annotations, outline items, form fields and structure elements are
+/// told apart by object identity
+fn is_shareable(object: &Object) -> bool {
+    let Object::Dictionary(dict) = object else {
+        return true;
+    };
+    let identity_type = matches!(
+        dict.get(b"Type").and_then(Object::as_name),
+        Ok(b"Catalog" | b"Pages" | b"Page" | b"Annot" | b"Outlines" | b"StructTreeRoot" | b"StructElem" | b"Sig")
+    );
+    // Untyped annotations still have a Rect, and fields and page tree nodes a Parent
+    !identity_type && !dict.has(b"Rect") && !dict.has(b"Parent") && !dict.has(b"FT")
+}
+
+/// Hash an object so that dictionaries with the same entries in another order hash alike
+fn hash_normalized<H: std::hash::Hasher>(object: &Object, hasher: &mut H) {
+    use std::hash::Hash;
+    
+    std::mem::discriminant(object).hash(hasher);
+    match object {
+        Object::Boolean(value) => value.hash(hasher),
+        Object::Integer(value) => value.hash(hasher),
+        Object::Real(value) => value.to_bits().hash(hasher),
+        Object::Name(name) => name.hash(hasher),
+        Object::String(bytes, _) => bytes.hash(hasher),
+        Object::Reference(id) => id.hash(hasher),
+        Object::Array(items) => items.iter().for_each(|item| hash_normalized(item, hasher)),
+        Object::Dictionary(dict) => {
+            let mut entries: Vec<_> = dict.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            for (key, value) in entries {
+                key.hash(hasher);
+                hash_normalized(value, hasher);
+            }
+        }
+        // Streams are only ever indirect objects, and dedup hashes those by content
+        Object::Stream(stream) => stream.content.hash(hasher),
+        Object::Null => {}
+    }
+}
+
+/// Whether two objects are equal up to the order of dictionary entries
+fn same_normalized(a: &Object, b: &Object) -> bool {
+    match (a, b) {
+        (Object::Array(a), Object::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_normalized(a, b))
+        }
+        (Object::Dictionary(a), Object::Dictionary(b)) => {
+            a.len() == b.len() && a.iter().all(|(key, value)| b.get(key).is_ok_and(|other| same_normalized(value, other)))
+        }
+        // The string format (literal or hex) is only how it is written, not what it holds
+        (Object::String(a, _), Object::String(b, _)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Rewrite references to remapped objects everywhere except inside protected objects
+fn remap_references(
+    doc: &mut Document,
+    remap: &ahash::AHashMap<ObjectId, ObjectId>,
+    protected: &HashSet<ObjectId>,
+) {
+    fn visit(object: &mut Object, remap: &ahash::AHashMap<ObjectId, ObjectId>) {
+        match object {
+            Object::Reference(id) => {
+                if let Some(new_id) = remap.get(id) {
+                    *id = *new_id;
+                }
+            }
+            Object::Array(items) => items.iter_mut().for_each(|item| visit(item, remap)),
+            Object::Dictionary(dict) => dict.iter_mut().for_each(|(_, value)| visit(value, remap)),
+            Object::Stream(stream) => stream.dict.iter_mut().for_each(|(_, value)| visit(value, remap)),
+            _ => {}
+        }
+    }
+    
+    if remap.is_empty() {
+        return;
+    }
+    for (obj_id, object) in doc.objects.iter_mut() {
+        if !protected.contains(obj_id) {
+            visit(object, remap);
+        }
+    }
+    doc.trailer.iter_mut().for_each(|(_, value)| visit(value, remap));
+}
+
+/// Add every object id referenced (directly or nested) by `object` to `references`
+pub(crate) fn collect_references(object: &Object, references: &mut HashSet<ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            references.insert(*id);
+        }
+        Object::Array(items) => items.iter().for_each(|item| collect_references(item, references)),
+        Object::Dictionary(dict) => dict.iter().for_each(|(_, value)| collect_references(value, references)),
+        Object::Stream(stream) => stream.dict.iter().for_each(|(_, value)| collect_references(value, references)),
+        _ => {}
+    }
+}
+
+/// Outline root and link annotations, by id, as they were before pruning
+fn navigation_snapshot(doc: &Document) -> ahash::AHashMap<ObjectId, Object> {
+    let mut roots = HashSet::new();
+    if let Ok(Object::Reference(outlines_id)) = doc.catalog().and_then(|catalog| catalog.get(b"Outlines")) {
+        roots.insert(*outlines_id);
+    }
+    for page_id in doc.get_pages().into_values() {
+        if let Ok(annots) = doc.get_dictionary(page_id).and_then(|page| page.get(b"Annots")) {
+            collect_references(annots, &mut roots);
+        }
+    }
+    
+    roots.into_iter()
+        .filter_map(|id| doc.objects.get(&id).map(|object| (id, object.clone())))
+        .collect()
+}
+
+/// Remove unused objects: everything unreachable from the trailer or `extra_roots` when
+/// `aggressive`, otherwise only objects that nothing refers to at all
+fn prune(doc: &mut Document, extra_roots: &HashSet<ObjectId>, aggressive: bool) -> usize {
+    if aggressive {
+        prune_unreachable(doc, extra_roots)
+    } else {
+        prune_unreferenced(doc, extra_roots)
+    }
+}
+
+/// Remove objects that no other object, the trailer or `extra_roots` refers to
+/// Unlike prune_unreachable this keeps whatever an orphaned object still points at
+fn prune_unreferenced(doc: &mut Document, extra_roots: &HashSet<ObjectId>) -> usize {
+    let mut referenced = extra_roots.clone();
+    doc.trailer.iter().for_each(|(_, value)| collect_references(value, &mut referenced));
+    for (id, object) in &doc.objects {
+        let mut references = HashSet::new();
+        collect_references(object, &mut references);
+        references.remove(id);
+        referenced.extend(references);
+    }
+    
+    let before = doc.objects.len();
+    doc.objects.retain(|id, _| referenced.contains(id));
+    before - doc.objects.len()
+}
+
+/// Remove objects unreachable from the trailer or from `extra_roots`
+fn prune_unreachable(doc: &mut Document, extra_roots: &HashSet<ObjectId>) -> usize {
+    let mut pending: Vec<ObjectId> = extra_roots.iter().copied().collect();
+    let mut trailer_refs = HashSet::new();
+    doc.trailer.iter().for_each(|(_, value)| collect_references(value, &mut trailer_refs));
+    pending.extend(trailer_refs);
+    
+    let mut reachable = HashSet::new();
+    while let Some(id) = pending.pop() {
+        if !reachable.insert(id) {
+            continue;
+        }
+        if let Some(object) = doc.objects.get(&id) {
+            let mut references = HashSet::new();
+            collect_references(object, &mut references);
+            pending.extend(references.into_iter().filter(|r| !reachable.contains(r)));
+        }
+    }
+    
+    let before = doc.objects.len();
+    doc.objects.retain(|id, _| reachable.contains(id));
+    before - doc.objects.len()
+}
+
+/// Put back any outline or link object that a cleanup pass dropped
+fn restore_navigation(doc: &mut Document, navigation: ahash::AHashMap<ObjectId, Object>) {
+    for (id, object) in navigation {
+        if let std::collections::btree_map::Entry::Vacant(entry) = doc.objects.entry(id) {
+            warn!("Navigation object {:?} was dropped during cleanup, restoring it", id);
+            entry.insert(object);
+        }
+    }
+}
+
+/// Point font descriptors with identical FontFile/FontFile2/FontFile3 programs at one shared stream
+/// Programs are compared after decoding, so differently filtered copies still match
+/// The orphaned copies are removed by the later prune pass
+fn dedup_font_programs(doc: &mut Document, protected: &HashSet<ObjectId>) -> usize {
+    use ahash::AHashMap;
+    use std::hash::{Hash, Hasher};
+    use ahash::AHasher;
+    
+    const FONT_FILE_KEYS: [&[u8]; 3] = [b"FontFile", b"FontFile2", b"FontFile3"];
+    
+    // Canonical program per content hash, with its decoded bytes to rule out collisions
+    let mut canonical: AHashMap<u64, Vec<(ObjectId, Vec<u8>)>> = AHashMap::new();
+    let mut remap: AHashMap<ObjectId, ObjectId> = AHashMap::new();
+    
+    let mut program_ids: Vec<(&[u8], ObjectId)> = Vec::new();
+    for (obj_id, object) in doc.objects.iter() {
+        if protected.contains(obj_id) {
+            continue;
+        }
+        if let Object::Dictionary(dict) = object {
+            for key in FONT_FILE_KEYS {
+                if let Ok(Object::Reference(program_id)) = dict.get(key) {
+                    program_ids.push((key, *program_id));
+                }
+            }
+        }
+    }
+    program_ids.sort();
+    program_ids.dedup();
+    
+    for (key, program_id) in program_ids {
+        if protected.contains(&program_id) || remap.contains_key(&program_id) {
+            continue;
+        }
+        let Some(Object::Stream(stream)) = doc.objects.get(&program_id) else {
+            continue;
+        };
+        let decoded = if stream.dict.has(b"Filter") {
+            match stream.decompressed_content() {
+                Ok(content) => content,
+                Err(_) => continue,
+            }
+        } else {
+            stream.content.clone()
+        };
+        
+        // Font kind and segment lengths must match as well as the program bytes
+        let mut hasher = AHasher::default();
+        key.hash(&mut hasher);
+        for entry in [&b"Subtype"[..], b"Length1", b"Length2", b"Length3"] {
+            format!("{:?}", stream.dict.get(entry).ok()).hash(&mut hasher);
+        }
+        decoded.hash(&mut hasher);
+        
+        let candidates = canonical.entry(hasher.finish()).or_default();
+        if let Some((existing_id, _)) = candidates.iter().find(|(_, bytes)| *bytes == decoded) {
+            debug!("Font program {:?} duplicates {:?}", program_id, existing_id);
+            remap.insert(program_id, *existing_id);
+        } else {
+            candidates.push((program_id, decoded));
+        }
+    }
+    
+    if remap.is_empty() {
+        return 0;
+    }
+    
+    for (obj_id, object) in doc.objects.iter_mut() {
+        if protected.contains(obj_id) {
+            continue;
+        }
+        if let Object::Dictionary(dict) = object {
+            for key in FONT_FILE_KEYS {
+                if let Ok(Object::Reference(program_id)) = dict.get_mut(key) {
+                    if let Some(shared_id) = remap.get(program_id) {
+                        *program_id = *shared_id;
+                    }
+                }
             }
         }
     }
-
-    to_replace.len()
+    
+    remap.len()
 }
 
-fn compress_all_streams(doc: &mut Document, settings: &CompressionSettings) -> Result<(), String> {
-    let mut objects_to_update = Vec::new();
-
-    // Find all stream objects and clone the streams we need to process
+fn compress_all_streams(doc: &mut Document, settings: &CompressionSettings, report: &mut CompressionReport) -> Result<(), String> {
+    // Find all stream objects we need to process
+    let mut candidates = Vec::new();
     for (obj_id, object) in doc.objects.iter() {
+        if settings.protected_objects.contains(obj_id) {
+            debug!("Skipping protected object {:?}", obj_id);
+            continue;
+        }
         if let Object::Stream(ref stream) = object {
             let is_image = is_image_stream(stream);
-            let original_size = stream.content.len();
-            objects_to_update.push((*obj_id, stream.clone(), is_image, original_size));
+            if is_image && settings.image_policy == ImagePolicy::Keep {
+                continue;
+            }
+            // Samples that aren't plain gray/RGB values only get lossless flate treatment, as do
+            // profiled colours a PDF/A file relies on
+            let is_image = is_image
+                && !has_special_color_space(doc, stream)
+                && !(settings.preserve_pdfa && pdfa::is_device_independent(doc, stream));
+            candidates.push((*obj_id, is_image));
         }
     }
 
-    let total_streams = objects_to_update.len();
-    info!("Processing {} streams in parallel", total_streams);
+    let total_streams = candidates.len();
+    
+    let mut image_reports: Vec<ImageReport> = candidates.iter()
+        .filter_map(|(obj_id, _)| match doc.objects.get(obj_id) {
+            Some(Object::Stream(stream)) if is_image_stream(stream) => Some(ImageReport::new(*obj_id, stream)),
+            _ => None,
+        })
+        .collect();
+    
+    // Drawn sizes are only needed to downsample by resolution
+    let placed_sizes = match settings.target_dpi {
+        Some(dpi) if !settings.lossless => {
+            info!("Downsampling images to {} DPI at their placed size", dpi);
+            placement::image_display_sizes(doc, settings)
+        }
+        _ => HashMap::new(),
+    };
+    
+    // Copies of an image that dedup couldn't merge (their dictionaries differ elsewhere) are
+    // encoded once; each (copy, source) pair takes the source's result afterwards
+    let mut image_copies: Vec<(ObjectId, ObjectId)> = Vec::new();
+    if !settings.lossless {
+        let mut sources: ahash::AHashMap<u64, Vec<ObjectId>> = ahash::AHashMap::new();
+        candidates.retain(|(obj_id, is_image)| {
+            let Some(Object::Stream(stream)) = doc.objects.get(obj_id).filter(|_| *is_image) else {
+                return true;
+            };
+            let placed = placed_sizes.get(obj_id);
+            let group = sources.entry(image_encoding_hash(stream, placed)).or_default();
+            let source = group.iter().copied().find(|source_id| {
+                matches!(doc.objects.get(source_id), Some(Object::Stream(source))
+                    if same_image_encoding(source, stream) && placed_sizes.get(source_id) == placed)
+            });
+            match source {
+                Some(source_id) => {
+                    image_copies.push((*obj_id, source_id));
+                    false
+                }
+                None => {
+                    group.push(*obj_id);
+                    true
+                }
+            }
+        });
+    }
     
     // Use atomic operations instead of Mutex to avoid lock contention in parallel iterator
     let compressed_count = AtomicUsize::new(0);
     let image_count = AtomicUsize::new(0);
-    let total_saved = AtomicI64::new(0);
+    let image_saved = AtomicI64::new(0);
+    let flate_saved = AtomicI64::new(0);
 
-    // Compress streams in parallel using rayon
     // Note: Returning None means "don't update this stream" - the original remains in the document
-    let compressed_streams: Vec<_> = objects_to_update
-        .par_iter()
-        .filter_map(|(obj_id, stream, is_image, original_size)| {
-            if *is_image {
-                image_count.fetch_add(1, Ordering::Relaxed);
-                debug!("Processing image stream {:?}, original size: {} bytes", obj_id, original_size);
+    let mut replaced: HashSet<ObjectId> = HashSet::new();
+    let process = |obj_id: &ObjectId, stream: &Stream, is_image: bool| -> Option<Stream> {
+        let original_size = stream.content.len();
+        if is_image {
+            image_count.fetch_add(1, Ordering::Relaxed);
+            debug!("Processing image stream {:?}, original size: {} bytes", obj_id, original_size);
+        }
+        
+        let budget = StreamBudget::new(
+            Duration::from_millis(settings.stream_timeout_ms),
+            settings.max_decompressed_bytes,
+        );
+        let compressed = if is_image && !settings.lossless {
+            compress_image_stream(stream, settings, placed_sizes.get(obj_id).copied(), &budget)
+        } else {
+            compress_generic_stream(stream, settings, &budget)
+        };
+        let compressed = match compressed {
+            Ok(s) => s,
+            Err(e) => {
+                debug!("Stream compression failed for {:?}: {}, keeping original", obj_id, e);
+                // Return None to skip updating - original stream preserved in document
+                return None;
             }
-            
-            let compressed = if *is_image {
-                match compress_image_stream(stream, settings.quality) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        debug!("Image compression failed for {:?}: {}, keeping original", obj_id, e);
-                        // Return None to skip updating - original stream preserved in document
-                        return None;
-                    }
-                }
+        };
+        
+        let new_size = compressed.content.len();
+        
+        // Only update if compressed version is smaller
+        if new_size < original_size {
+            let saved = original_size as i64 - new_size as i64;
+            if is_image {
+                image_saved.fetch_add(saved, Ordering::Relaxed);
             } else {
-                compress_generic_stream(stream)
+                flate_saved.fetch_add(saved, Ordering::Relaxed);
+            }
+            compressed_count.fetch_add(1, Ordering::Relaxed);
+            debug!("Compressed {:?}: {} -> {} bytes (saved {} bytes)", 
+                   obj_id, original_size, new_size, saved);
+            Some(compressed)
+        } else {
+            debug!("Keeping original {:?}: compressed would be {} bytes (original {})", 
+                   obj_id, new_size, original_size);
+            // Return None to skip updating - original stream preserved in document
+            None
+        }
+    };
+
+    if settings.low_memory {
+        // One stream at a time, written back before the next, so only one extra copy is ever alive
+        info!("Processing {} streams sequentially (low memory)", total_streams);
+        for (obj_id, is_image) in candidates {
+            let compressed = match doc.objects.get(&obj_id) {
+                Some(Object::Stream(stream)) => process(&obj_id, stream, is_image),
+                _ => None,
             };
-            
-            let new_size = compressed.content.len();
-            
-            // Only update if compressed version is smaller
-            if new_size < *original_size {
-                let saved = *original_size as i64 - new_size as i64;
-                total_saved.fetch_add(saved, Ordering::Relaxed);
-                compressed_count.fetch_add(1, Ordering::Relaxed);
-                debug!("Compressed {:?}: {} -> {} bytes (saved {} bytes)", 
-                       obj_id, original_size, new_size, saved);
-                Some((*obj_id, compressed))
-            } else {
-                debug!("Keeping original {:?}: compressed would be {} bytes (original {})", 
-                       obj_id, new_size, original_size);
-                // Return None to skip updating - original stream preserved in document
-                None
+            if let Some(compressed_stream) = compressed {
+                doc.objects.insert(obj_id, Object::Stream(compressed_stream));
+                replaced.insert(obj_id);
             }
-        })
-        .collect();
+        }
+    } else {
+        // Each chunk is cloned, compressed and written back before the next one is cloned
+        let chunks = match settings.page_chunk_size {
+            Some(pages) => {
+                let chunks = chunk::group_by_pages(doc, candidates, pages);
+                info!("Processing {} streams in parallel, in {} chunks of up to {} pages", total_streams, chunks.len(), pages.max(1));
+                chunks
+            }
+            None => {
+                info!("Processing {} streams in parallel", total_streams);
+                vec![candidates]
+            }
+        };
+        // A private pool keeps one large document from taking every thread of the global pool
+        let pool = match settings.max_threads {
+            Some(threads) => {
+                debug!("Using a private pool of {} threads", threads.max(1));
+                Some(rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads.max(1))
+                    .build()
+                    .map_err(|e| format!("Failed to start thread pool: {}", e))?)
+            }
+            None => None,
+        };
+
+        for chunk in chunks {
+            // Clone the streams so rayon can work on them while the document stays untouched
+            let objects_to_update: Vec<(ObjectId, Stream, bool)> = chunk
+                .into_iter()
+                .filter_map(|(obj_id, is_image)| match doc.objects.get(&obj_id) {
+                    Some(Object::Stream(stream)) => Some((obj_id, stream.clone(), is_image)),
+                    _ => None,
+                })
+                .collect();
+
+            // Compress streams in parallel using rayon
+            let compress_streams = || -> Vec<_> {
+                objects_to_update
+                    .par_iter()
+                    .filter_map(|(obj_id, stream, is_image)| process(obj_id, stream, *is_image).map(|compressed| (*obj_id, compressed)))
+                    .collect()
+            };
+            let compressed_streams = match &pool {
+                Some(pool) => pool.install(compress_streams),
+                None => compress_streams(),
+            };
+
+            // Update document with successfully compressed streams only
+            // Streams not in this list remain unchanged in the document
+            for (obj_id, compressed_stream) in compressed_streams {
+                doc.objects.insert(obj_id, Object::Stream(compressed_stream));
+                replaced.insert(obj_id);
+            }
+        }
+    }
     
-    // Update document with successfully compressed streams only
-    // Streams not in this list remain unchanged in the document
-    for (obj_id, compressed_stream) in compressed_streams {
-        doc.objects.insert(obj_id, Object::Stream(compressed_stream));
+    for (copy_id, source_id) in image_copies {
+        let (Some(Object::Stream(source)), Some(Object::Stream(copy))) = (doc.objects.get(&source_id), doc.objects.get(&copy_id)) else {
+            continue;
+        };
+        if !replaced.contains(&source_id) {
+            continue;
+        }
+        // The copy keeps its own dictionary apart from the entries the encoder rewrites
+        let mut dict = copy.dict.clone();
+        for key in IMAGE_ENCODING_KEYS.iter().chain([&b"Length".as_slice()]) {
+            match source.dict.get(key) {
+                Ok(value) => dict.set(key.to_vec(), value.clone()),
+                Err(_) => {
+                    dict.remove(key);
+                }
+            }
+        }
+        let reused = Stream::new(dict, source.content.clone());
+        image_saved.fetch_add(copy.content.len() as i64 - reused.content.len() as i64, Ordering::Relaxed);
+        compressed_count.fetch_add(1, Ordering::Relaxed);
+        report.reused_image_encodings += 1;
+        debug!("Reused encoding of {:?} for identical image {:?}", source_id, copy_id);
+        doc.objects.insert(copy_id, Object::Stream(reused));
     }
 
+    for image in &mut image_reports {
+        if let Some(Object::Stream(stream)) = doc.objects.get(&image.object_id) {
+            image.set_output(stream);
+        }
+    }
+    report.images.extend(image_reports);
+    
     let final_compressed = compressed_count.load(Ordering::Relaxed);
     let final_image_count = image_count.load(Ordering::Relaxed);
-    let final_saved = total_saved.load(Ordering::Relaxed);
+    report.encoded_images += final_image_count;
+    report.image_bytes_saved += image_saved.load(Ordering::Relaxed);
+    report.flate_bytes_saved = flate_saved.load(Ordering::Relaxed);
 
     info!("Compressed {}/{} streams", final_compressed, total_streams);
     info!("Found {} image streams", final_image_count);
-    info!("Total bytes saved from stream compression: {}", final_saved);
+    info!("Total bytes saved from stream compression: {}", report.image_bytes_saved + report.flate_bytes_saved);
 
     Ok(())
 }
 
-fn compress_generic_stream(stream: &Stream) -> Stream {
-    use flate2::write::ZlibEncoder;
-    use flate2::Compression;
-    use std::io::Write;
-    
+fn compress_generic_stream(
+    stream: &Stream,
+    settings: &CompressionSettings,
+    budget: &StreamBudget,
+) -> Result<Stream, String> {
     let original_content_size = stream.content.len();
     
     // Already compressed? Try to recompress the decompressed content
     if let Ok(filter) = stream.dict.get(b"Filter") {
+        let already_flate = match filter {
+            Object::Name(name) => name == b"FlateDecode",
+            Object::Array(filters) => matches!(filters.as_slice(), [Object::Name(name)] if name == b"FlateDecode"),
+            _ => false,
+        };
+        if already_flate && !settings.recompress_existing_flate {
+            return Ok(stream.clone());
+        }
         debug!("Stream has filter: {:?}, attempting recompression", filter);
         
         // Try to decompress and recompress with better settings
-        if let Ok(decompressed) = stream.decompressed_content() {
+        if let Some(decompressed) = decode_stream_bounded(stream, budget.generic_limit(original_content_size))? {
             debug!("Decompressed content: {} bytes, recompressing...", decompressed.len());
+            budget.check("decompression")?;
             
-            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
-            if encoder.write_all(&decompressed).is_ok() {
-                if let Ok(recompressed) = encoder.finish() {
-                    debug!("Recompressed: {} -> {} bytes (was {} bytes)", 
-                           decompressed.len(), recompressed.len(), original_content_size);
-                    
-                    if recompressed.len() < original_content_size {
-                        let mut new_dict = stream.dict.clone();
-                        new_dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
-                        new_dict.set("Length", Object::Integer(recompressed.len() as i64));
-                        return Stream::new(new_dict, recompressed);
-                    }
+            if let Some(recompressed) = deflate::zlib_compress(&decompressed, settings.flate_level, settings.zopfli_min_bytes) {
+                debug!("Recompressed: {} -> {} bytes (was {} bytes)", 
+                       decompressed.len(), recompressed.len(), original_content_size);
+                
+                if recompressed.len() < original_content_size {
+                    let mut new_dict = stream.dict.clone();
+                    // Decoding undid any predictor, and the old filter's parameters don't apply to flate
+                    new_dict.remove(b"DecodeParms");
+                    new_dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+                    new_dict.set("Length", Object::Integer(recompressed.len() as i64));
+                    return Ok(Stream::new(new_dict, recompressed));
                 }
             }
         }
         
-        return stream.clone();
+        return Ok(stream.clone());
     }
     
     debug!("Applying Flate compression to uncompressed stream ({} bytes)", original_content_size);
     
     // Apply flate compression to uncompressed stream
-    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
-    if encoder.write_all(&stream.content).is_ok() {
-        if let Ok(compressed) = encoder.finish() {
-            debug!("Flate compressed: {} -> {} bytes", original_content_size, compressed.len());
-            if compressed.len() < original_content_size {
-                let mut new_dict = stream.dict.clone();
-                new_dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
-                new_dict.set("Length", Object::Integer(compressed.len() as i64));
-                return Stream::new(new_dict, compressed);
-            }
+    if let Some(compressed) = deflate::zlib_compress(&stream.content, settings.flate_level, settings.zopfli_min_bytes) {
+        debug!("Flate compressed: {} -> {} bytes", original_content_size, compressed.len());
+        if compressed.len() < original_content_size {
+            let mut new_dict = stream.dict.clone();
+            new_dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+            new_dict.set("Length", Object::Integer(compressed.len() as i64));
+            return Ok(Stream::new(new_dict, compressed));
         }
     }
     
-    stream.clone()
+    Ok(stream.clone())
+}
+
+/// Image dictionary entries that decoding reads, encoding rewrites or that decide the encoding
+/// Images agreeing on these and on their samples always encode to the same result; the masks
+/// are here because a colour-key /Mask rules out JPEG
+const IMAGE_ENCODING_KEYS: [&[u8]; 10] = [
+    b"Filter", b"DecodeParms", b"Width", b"Height", b"BitsPerComponent", b"ColorSpace", b"Decode", b"ImageMask",
+    b"Mask", b"SMask",
+];
+
+/// Hash of an image's samples and placed size; dictionaries are compared on a hash match
+fn image_encoding_hash(stream: &Stream, placed: Option<&(f32, f32)>) -> u64 {
+    use std::hash::{Hash, Hasher};
+    
+    let mut hasher = ahash::AHasher::default();
+    stream.content.hash(&mut hasher);
+    placed.map(|(w, h)| (w.to_bits(), h.to_bits())).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn same_image_encoding(a: &Stream, b: &Stream) -> bool {
+    IMAGE_ENCODING_KEYS.iter().all(|key| a.dict.get(key).ok() == b.dict.get(key).ok()) && a.content == b.content
 }
 
 fn is_image_stream(stream: &Stream) -> bool {
@@ -273,27 +1640,147 @@ fn is_image_stream(stream: &Stream) -> bool {
     false
 }
 
-fn compress_image_stream(stream: &Stream, quality: u8) -> Result<Stream, String> {
-    use image::imageops::FilterType;
+/// Whether an image uses a colour space whose samples are not device gray/RGB values
+/// Separation and DeviceN samples are tint values for a transform function, and Indexed
+/// samples are palette indices, so reading them as pixels would corrupt colours
+fn has_special_color_space(doc: &Document, stream: &Stream) -> bool {
+    let color_space = match stream.dict.get(b"ColorSpace") {
+        Ok(Object::Reference(id)) => doc.get_object(*id).ok(),
+        Ok(object) => Some(object),
+        Err(_) => None,
+    };
+    let family = match color_space {
+        Some(Object::Name(name)) => Some(name.as_slice()),
+        Some(Object::Array(items)) => match items.first() {
+            Some(Object::Name(name)) => Some(name.as_slice()),
+            _ => None,
+        },
+        _ => None,
+    };
     
-    // Check filter type - skip if already JPEG
-    if let Ok(Object::Name(filter)) = stream.dict.get(b"Filter") {
-        if filter == b"DCTDecode" {
-            return Err("Already JPEG (DCTDecode)".to_string());
+    matches!(family, Some(b"Separation" | b"DeviceN" | b"Indexed"))
+}
+
+/// Whether an image stream holds JPEG data, alone or as the last filter of a chain
+fn is_dct_image(stream: &Stream) -> bool {
+    match stream.dict.get(b"Filter") {
+        Ok(Object::Name(name)) => name == b"DCTDecode",
+        Ok(Object::Array(filters)) => matches!(filters.last(), Some(Object::Name(name)) if name == b"DCTDecode"),
+        _ => false,
+    }
+}
+
+/// JPEG data of a DCTDecode image, with any filters applied before DCTDecode undone,
+/// and the DCTDecode parameters (ColorTransform) to keep
+fn dct_payload(stream: &Stream, budget: &StreamBudget) -> Result<(Vec<u8>, Option<lopdf::Dictionary>), String> {
+    let chain = decode::filter_chain(&stream.dict).ok_or("Malformed filter chain")?;
+    let Some(((_, dct_params), wrappers)) = chain.split_last() else {
+        return Err("No filter".to_string());
+    };
+    let jpeg_data = if wrappers.is_empty() {
+        stream.content.clone()
+    } else {
+        decode::decode_filters(&stream.content, wrappers, budget.generic_limit(stream.content.len()))?
+            .ok_or_else(|| format!("Unsupported filters around JPEG data: {}", filter_names(stream).unwrap_or_default()))?
+    };
+    budget.check("JPEG unwrapping")?;
+    Ok((jpeg_data, dct_params.cloned()))
+}
+
+/// With optimize_huffman, the JPEG re-coded with optimal Huffman tables if that is smaller
+/// Progressive and other non-baseline JPEGs are returned as they are
+fn optimize_jpeg(jpeg: Vec<u8>, settings: &CompressionSettings) -> Vec<u8> {
+    if !settings.optimize_huffman {
+        return jpeg;
+    }
+    match optimize_jpeg_huffman(&jpeg) {
+        Ok(optimized) if optimized.len() < jpeg.len() => {
+            debug!("Optimized Huffman tables: {} -> {} bytes", jpeg.len(), optimized.len());
+            optimized
+        }
+        Ok(_) => jpeg,
+        Err(e) => {
+            debug!("Keeping JPEG Huffman tables: {}", e);
+            jpeg
         }
     }
+}
+
+/// Whether an image is masked by a colour-key /Mask array rather than a stencil image
+/// Color keys match exact sample values, which lossy JPEG output would no longer hit
+fn has_color_key_mask(stream: &Stream) -> bool {
+    matches!(stream.dict.get(b"Mask"), Ok(Object::Array(_)))
+}
+
+/// Whether an image stream holds JPEG 2000 data, by its filter or by a JP2/J2K signature
+/// The image crate has no JPEG 2000 decoder, so these streams are kept as they are
+fn is_jpx_image(stream: &Stream) -> bool {
+    const JP2_SIGNATURE: &[u8] = b"\x00\x00\x00\x0cjP  \r\n\x87\n";
+    const J2K_CODESTREAM: &[u8] = b"\xff\x4f\xff\x51";
+    
+    let jpx_filter = match stream.dict.get(b"Filter") {
+        Ok(Object::Name(name)) => name == b"JPXDecode",
+        Ok(Object::Array(filters)) => filters.iter().any(|f| matches!(f, Object::Name(name) if name == b"JPXDecode")),
+        _ => false,
+    };
+    jpx_filter || stream.content.starts_with(JP2_SIGNATURE) || stream.content.starts_with(J2K_CODESTREAM)
+}
+
+/// Container format of image data embedded whole in a stream instead of as PDF samples
+/// Some pipelines store WebP/AVIF files under non-standard filters (or none at all)
+fn embedded_image_format(data: &[u8]) -> Option<ImageFormat> {
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some(ImageFormat::WebP);
+    }
+    if data.len() >= 12 && &data[4..8] == b"ftyp" && matches!(&data[8..12], b"avif" | b"avis") {
+        return Some(ImageFormat::Avif);
+    }
+    None
+}
+
+/// Decode a WebP/AVIF file embedded in an image stream, sized from the file rather than the dictionary
+/// Transparency is composited over `background`
+fn decode_embedded_image(data: &[u8], format: ImageFormat, background: [u8; 3]) -> Result<DynamicImage, String> {
+    let reader = image::io::Reader::with_format(std::io::Cursor::new(data), format);
+    let (width, height) = reader.into_dimensions()
+        .map_err(|e| format!("Embedded {:?} not decodable: {}", format, e))?;
+    checked_image_dimensions(width as i64, height as i64)?;
+    
+    let img = image::load_from_memory_with_format(data, format)
+        .map_err(|e| format!("Embedded {:?} not decodable: {}", format, e))?;
+    debug!("Decoded embedded {:?} image: {}x{}", format, width, height);
+    let img = color::flatten_alpha(img, background);
+    Ok(if img.color().has_color() { img.to_rgb8().into() } else { img.to_luma8().into() })
+}
+
+/// Decode an 8-bit gray/RGB/RGBA image XObject into a grayscale or RGB image
+/// RGBA samples are composited over `background`
+fn decode_image_stream(stream: &Stream, budget: &StreamBudget, background: [u8; 3]) -> Result<DynamicImage, String> {
+    // Codestream bytes would otherwise be guessed at as raw samples by their length
+    if is_jpx_image(stream) {
+        return Err("JPEG 2000 (JPXDecode) images are not supported".to_string());
+    }
+    
+    if let Some(format) = embedded_image_format(&stream.content) {
+        let img = decode_embedded_image(&stream.content, format, background)?;
+        budget.check("image decoding")?;
+        return Ok(img);
+    }
     
     // Get image properties
     let width = match stream.dict.get(b"Width") {
-        Ok(Object::Integer(w)) => *w as u32,
+        Ok(Object::Integer(w)) => *w,
         _ => return Err("No width".to_string()),
     };
     
     let height = match stream.dict.get(b"Height") {
-        Ok(Object::Integer(h)) => *h as u32,
+        Ok(Object::Integer(h)) => *h,
         _ => return Err("No height".to_string()),
     };
     
+    // Reject absurd declared sizes before decompressing or allocating anything
+    let (width, height) = checked_image_dimensions(width, height)?;
+    
     let bpc = match stream.dict.get(b"BitsPerComponent") {
         Ok(Object::Integer(b)) => *b as u32,
         _ => 8,
@@ -304,41 +1791,33 @@ fn compress_image_stream(stream: &Stream, quality: u8) -> Result<Stream, String>
         return Err(format!("Not 8-bit (bpc={})", bpc));
     }
     
-    // Manual decompression for FlateDecode
-    let content = if let Ok(Object::Name(filter)) = stream.dict.get(b"Filter") {
-        if filter == b"FlateDecode" {
-            use flate2::read::ZlibDecoder;
-            use std::io::Read;
-            
-            let mut decoder = ZlibDecoder::new(&stream.content[..]);
-            let mut decompressed = Vec::new();
-            decoder.read_to_end(&mut decompressed)
-                .map_err(|e| format!("Manual decompression failed: {}", e))?;
-            
-            decompressed
-        } else {
-            stream.decompressed_content()
-                .map_err(|e| format!("Decompress failed: {:?}", e))?
-        }
+    // An 8-bit image never needs more than 4 bytes per pixel, so anything larger is a bomb
+    let decode_limit = (width as usize * height as usize * 4).min(budget.max_decompressed());
+    
+    // Every filter of a chain is undone, so ASCII85-wrapped samples are never taken for pixels
+    let content = if stream.dict.has(b"Filter") {
+        decode_stream_bounded(stream, decode_limit)?
+            .ok_or_else(|| format!("Unsupported filter chain: {}", filter_names(stream).unwrap_or_default()))?
     } else {
         stream.content.clone()
     };
     
-    let original_content_size = content.len();
+    budget.check("image decoding")?;
     
     // Determine number of components
-    let pixel_count = (width * height) as usize;
-    let components = if original_content_size == pixel_count * 3 {
+    let pixel_count = width as usize * height as usize;
+    let components = if content.len() == pixel_count * 3 {
         3
-    } else if original_content_size == pixel_count * 4 {
+    } else if content.len() == pixel_count * 4 {
         4
-    } else if original_content_size == pixel_count {
+    } else if content.len() == pixel_count {
         1
     } else {
-        return Err(format!("Unexpected size: {} bytes for {}x{} image", original_content_size, width, height));
+        return Err(format!("Unexpected size: {} bytes for {}x{} image", content.len(), width, height));
     };
+    let mut content = content;
+    apply_decode_array(&stream.dict, &mut content, components);
     
-    // Convert to RGB and encode as JPEG
     let dyn_img = match components {
         3 => {
             if let Some(img) = image::RgbImage::from_raw(width, height, content) {
@@ -349,14 +1828,15 @@ fn compress_image_stream(stream: &Stream, quality: u8) -> Result<Stream, String>
         },
         4 => {
             if let Some(img) = image::RgbaImage::from_raw(width, height, content) {
-                image::DynamicImage::ImageRgba8(img).to_rgb8().into()
+                color::flatten_alpha(image::DynamicImage::ImageRgba8(img), background)
             } else {
                 return Err("Failed to create RGBA image".to_string());
             }
         },
+        // Kept as one channel, so the JPEG and its ColorSpace stay gray
         1 => {
             if let Some(img) = image::GrayImage::from_raw(width, height, content) {
-                image::DynamicImage::ImageLuma8(img).to_rgb8().into()
+                image::DynamicImage::ImageLuma8(img)
             } else {
                 return Err("Failed to create grayscale image".to_string());
             }
@@ -364,46 +1844,175 @@ fn compress_image_stream(stream: &Stream, quality: u8) -> Result<Stream, String>
         _ => return Err(format!("Unsupported component count: {}", components))
     };
     
-    // Downsample based on quality setting
-    let (target_width, target_height) = if quality < 90 && (width > 1500 || height > 1500) {
-        let max_dimension = if quality >= 70 {
-            1500.0
-        } else if quality >= 50 {
-            1200.0
-        } else {
-            1000.0
-        };
-        
-        let scale = max_dimension / width.max(height) as f32;
-        let new_w = (width as f32 * scale) as u32;
-        let new_h = (height as f32 * scale) as u32;
-        info!("Downsampling large image (quality {}): {}x{} -> {}x{}", quality, width, height, new_w, new_h);
-        (new_w, new_h)
+    Ok(dyn_img)
+}
+
+/// Remap samples through the image's /Decode array, so pixels hold the colours as displayed
+/// Re-encoded images drop /Decode, since their colour space and component count can change
+fn apply_decode_array(dict: &lopdf::Dictionary, samples: &mut [u8], components: usize) {
+    let Ok(Object::Array(items)) = dict.get(b"Decode") else {
+        return;
+    };
+    let ranges: Option<Vec<f32>> = items.iter()
+        .map(|item| match item {
+            Object::Integer(n) => Some(*n as f32),
+            Object::Real(n) => Some(*n),
+            _ => None,
+        })
+        .collect();
+    let Some(ranges) = ranges.filter(|ranges| ranges.len() == components * 2) else {
+        warn!("Ignoring malformed /Decode array for {}-component image", components);
+        return;
+    };
+    if ranges.chunks_exact(2).all(|range| range == [0.0, 1.0]) {
+        return;
+    }
+    
+    let tables: Vec<[u8; 256]> = ranges.chunks_exact(2)
+        .map(|range| std::array::from_fn(|sample| {
+            let value = range[0] + sample as f32 / 255.0 * (range[1] - range[0]);
+            (value * 255.0).round().clamp(0.0, 255.0) as u8
+        }))
+        .collect();
+    for pixel in samples.chunks_exact_mut(components) {
+        for (sample, table) in pixel.iter_mut().zip(&tables) {
+            *sample = table[*sample as usize];
+        }
+    }
+}
+
+/// Re-encode an image stream as JPEG, or flate-compress its original samples if that is smaller
+/// Flat artwork and screenshots often deflate far better than they JPEG at low quality
+/// Downsampled images always stay JPEG, since the original samples would exceed the size cap
+/// `placed` is the largest size in points the image is drawn at, if known, for `target_dpi`
+fn compress_image_stream(
+    stream: &Stream,
+    settings: &CompressionSettings,
+    placed: Option<(f32, f32)>,
+    budget: &StreamBudget,
+) -> Result<Stream, String> {
+    // Check filter type - skip if already JPEG
+    if is_dct_image(stream) {
+        // Encodings wrapped around the JPEG data (ASCII85, flate) are peeled off, leaving plain DCTDecode
+        let (jpeg_data, dct_params) = dct_payload(stream, budget)?;
+        // Decoding these as RGB drops the CMYK data and any /Decode inversion, so they are never re-encoded
+        if let Some(layout) = jpeg::jpeg_layout(&jpeg_data).filter(|layout| layout.is_four_component()) {
+            info!("Keeping CMYK/YCCK JPEG untouched (Adobe transform {:?})", layout.adobe_transform);
+            return Err("CMYK/YCCK JPEG (DCTDecode) kept as is".to_string());
+        }
+        // Re-packing the Huffman tables leaves the pixels alone, so it is safe for any JPEG data
+        let unwrapped = jpeg_data.len() < stream.content.len();
+        let optimized = optimize_jpeg(jpeg_data, settings);
+        if optimized.len() < stream.content.len() {
+            if unwrapped {
+                debug!("Unwrapped JPEG from filter chain: {} -> {} bytes", stream.content.len(), optimized.len());
+            }
+            let mut dict = stream.dict.clone();
+            dict.set("Filter", Object::Name(b"DCTDecode".to_vec()));
+            match dct_params {
+                Some(params) => dict.set("DecodeParms", Object::Dictionary(params)),
+                None => {
+                    dict.remove(b"DecodeParms");
+                }
+            }
+            dict.set("Length", Object::Integer(optimized.len() as i64));
+            return Ok(Stream::new(dict, optimized));
+        }
+        return Err("Already JPEG (DCTDecode)".to_string());
+    }
+    if is_jpx_image(stream) {
+        info!("Skipping JPEG 2000 (JPXDecode) image: decoding is not supported, keeping original");
+        return Err("JPEG 2000 (JPXDecode) images are not supported".to_string());
+    }
+    
+    // A stencil /Mask is a separate image referenced from the dictionary, which JPEG output keeps
+    let jpeg = if has_color_key_mask(stream) {
+        debug!("Color-key masked image, trying only lossless flate");
+        Err("Color-key masked images are not transcoded to JPEG".to_string())
     } else {
-        (width, height)
+        encode_image_stream_as_jpeg(stream, settings, placed, budget)
+    };
+    let downsampled = jpeg.as_ref().is_ok_and(|jpeg| {
+        jpeg.dict.get(b"Width").ok() != stream.dict.get(b"Width").ok()
+            || jpeg.dict.get(b"Height").ok() != stream.dict.get(b"Height").ok()
+    });
+    if downsampled {
+        return jpeg;
+    }
+    budget.check("image encoding")?;
+    let flate = compress_generic_stream(stream, settings, budget);
+    
+    match (jpeg, flate) {
+        (Ok(jpeg), Ok(flate)) if flate.content.len() <= jpeg.content.len() => {
+            debug!("Flate beats JPEG for image: {} vs {} bytes", flate.content.len(), jpeg.content.len());
+            Ok(flate)
+        }
+        (Ok(jpeg), _) => Ok(jpeg),
+        (Err(e), Ok(flate)) if flate.content.len() < stream.content.len() => {
+            debug!("JPEG encoding unavailable ({}), using flate", e);
+            Ok(flate)
+        }
+        (Err(e), _) => Err(e),
+    }
+}
+
+fn encode_image_stream_as_jpeg(
+    stream: &Stream,
+    settings: &CompressionSettings,
+    placed: Option<(f32, f32)>,
+    budget: &StreamBudget,
+) -> Result<Stream, String> {
+    let quality = settings.quality;
+    
+    let dyn_img = decode_image_stream(stream, budget, settings.flatten_background)?;
+    let (width, height) = (dyn_img.width(), dyn_img.height());
+    let original_content_size = dyn_img.as_bytes().len();
+    
+    // Downsample based on quality setting
+    let (target_width, target_height) = match downsample_limit(settings, width, height, placed) {
+        Some(max_dimension) => {
+            let scale = max_dimension / width.max(height) as f32;
+            let new_w = ((width as f32 * scale) as u32).max(1);
+            let new_h = ((height as f32 * scale) as u32).max(1);
+            info!("Downsampling large image (quality {}): {}x{} -> {}x{}", quality, width, height, new_w, new_h);
+            (new_w, new_h)
+        }
+        None => (width, height),
     };
     
     let final_img = if target_width != width || target_height != height {
-        dyn_img.resize_exact(target_width, target_height, FilterType::Lanczos3)
+        dyn_img.resize_exact(target_width, target_height, settings.resample_filter.to_filter_type())
     } else {
         dyn_img
     };
+    budget.check("resampling")?;
+    
+    let final_img = if settings.force_grayscale {
+        image::DynamicImage::ImageLuma8(final_img.to_luma8())
+    } else {
+        final_img
+    };
     
     // Encode as JPEG with specified quality
-    let mut compressed = Vec::new();
-    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut compressed, quality);
-    if encoder.encode_image(&final_img).is_ok() {
-        info!("JPEG encoding successful: {} bytes -> {} bytes (quality {})", original_content_size, compressed.len(), quality);
+    let jpeg_quality = settings.jpeg_encoder_quality();
+    if let Ok(compressed) = encode_jpeg(&final_img, jpeg_quality, settings.progressive) {
+        let compressed = optimize_jpeg(compressed, settings);
+        info!("JPEG encoding successful: {} bytes -> {} bytes (quality {})", original_content_size, compressed.len(), jpeg_quality);
         
         let mut new_dict = stream.dict.clone();
         new_dict.set("Filter", Object::Name(b"DCTDecode".to_vec()));
         new_dict.set("Length", Object::Integer(compressed.len() as i64));
-        new_dict.set("ColorSpace", Object::Name(b"DeviceRGB".to_vec()));
+        let color_space: &[u8] = if final_img.color().has_color() { b"DeviceRGB" } else { b"DeviceGray" };
+        new_dict.set("ColorSpace", Object::Name(color_space.to_vec()));
+        new_dict.set("BitsPerComponent", Object::Integer(8));
+        // Parameters of the old filter don't apply to DCTDecode
+        new_dict.remove(b"DecodeParms");
+        // The pixels already went through /Decode; /Intent and /Interpolate carry over unchanged
+        new_dict.remove(b"Decode");
         
-        if target_width != width || target_height != height {
-            new_dict.set("Width", Object::Integer(target_width as i64));
-            new_dict.set("Height", Object::Integer(target_height as i64));
-        }
+        // Embedded WebP/AVIF data carries its own size, which the dictionary may not match
+        new_dict.set("Width", Object::Integer(target_width as i64));
+        new_dict.set("Height", Object::Integer(target_height as i64));
         
         return Ok(Stream::new(new_dict, compressed));
     }
@@ -411,6 +2020,88 @@ fn compress_image_stream(stream: &Stream, quality: u8) -> Result<Stream, String>
     Err("Image encoding failed".to_string())
 }
 
+/// Largest width or height accepted for an image XObject (the JPEG format limit)
+const MAX_IMAGE_DIMENSION: i64 = 65_535;
+/// Largest pixel count accepted for an image XObject (~100 megapixels)
+const MAX_IMAGE_PIXELS: usize = 100_000_000;
+
+/// Validate declared image dimensions, returning them as u32
+fn checked_image_dimensions(width: i64, height: i64) -> Result<(u32, u32), String> {
+    if !(1..=MAX_IMAGE_DIMENSION).contains(&width) || !(1..=MAX_IMAGE_DIMENSION).contains(&height) {
+        return Err(format!("Invalid image dimensions: {}x{}", width, height));
+    }
+    
+    let pixel_count = width as usize * height as usize;
+    if pixel_count > MAX_IMAGE_PIXELS {
+        return Err(format!("Image too large: {}x{} exceeds {} pixels", width, height, MAX_IMAGE_PIXELS));
+    }
+    
+    Ok((width as u32, height as u32))
+}
+
+/// Reject an image file whose header declares more than `max_pixels` pixels
+/// Only the header is read, so a small file claiming a huge canvas is never decoded
+pub(crate) fn check_image_file_size(data: &[u8], format: Option<ImageFormat>, max_pixels: u64) -> Result<(), String> {
+    let mut reader = image::io::Reader::new(std::io::Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| format!("Failed to load image: {}", e))?;
+    if let Some(format) = format {
+        reader.set_format(format);
+    }
+    let (width, height) = reader
+        .into_dimensions()
+        .map_err(|e| format!("Failed to load image: {}", e))?;
+    
+    let pixel_count = width as u64 * height as u64;
+    if pixel_count > max_pixels {
+        warn!("Rejecting {}x{} image before decoding: over {} pixels", width, height, max_pixels);
+        return Err(format!("Image too large: {}x{} exceeds {} pixels", width, height, max_pixels));
+    }
+    Ok(())
+}
+
+/// Long-edge size an image should be downsampled to, or None to keep its size
+/// An explicit max_dimension or max_short_edge wins over the quality-based bands
+fn downsample_limit(settings: &CompressionSettings, width: u32, height: u32, placed: Option<(f32, f32)>) -> Option<f32> {
+    let long_edge = width.max(height);
+    
+    if settings.lossless || settings.no_resize {
+        return None;
+    }
+    
+    // Pixels the largest placement needs at the target DPI, keeping the aspect ratio
+    let dpi_limit = settings.target_dpi.zip(placed).and_then(|(dpi, (placed_w, placed_h))| {
+        let pixels_per_point = dpi as f32 / 72.0;
+        let scale = (placed_w * pixels_per_point / width as f32).max(placed_h * pixels_per_point / height as f32);
+        let limit = (long_edge as f32 * scale).ceil().max(1.0);
+        limit.is_finite().then_some(limit)
+    });
+    // The short-edge cap, expressed as the long edge it allows at this aspect ratio
+    let short_edge_limit = settings.max_short_edge.map(|max_short_edge| {
+        max_short_edge as f32 * long_edge as f32 / width.min(height).max(1) as f32
+    });
+    let explicit_limit = [settings.max_dimension.map(|max_dimension| max_dimension as f32), short_edge_limit, dpi_limit]
+        .into_iter()
+        .flatten()
+        .reduce(f32::min);
+    if let Some(limit) = explicit_limit {
+        return (limit < long_edge as f32).then_some(limit);
+    }
+    
+    let quality = settings.quality;
+    if quality < 90 && long_edge > 1500 {
+        Some(if quality >= 70 {
+            1500.0
+        } else if quality >= 50 {
+            1200.0
+        } else {
+            1000.0
+        })
+    } else {
+        None
+    }
+}
+
 /// Compress standalone image from bytes
 /// Returns (compressed_bytes, output_format_extension)
 pub fn compress_image_bytes(
@@ -419,30 +2110,84 @@ pub fn compress_image_bytes(
     output_format: Option<&str>,
 ) -> Result<(Vec<u8>, String), String> {
     // Clamp compression level
-    let compression_level = compression_level.min(95).max(10);
+    let compression_level = compression_level.clamp(10, 95);
     
     // Convert compression level to quality (same mapping as PDF)
-    let quality = if compression_level <= 25 {
-        100 - (compression_level as f32 * 0.4) as u8
-    } else if compression_level <= 50 {
-        90 - ((compression_level - 25) as f32 * 0.8) as u8
-    } else if compression_level <= 75 {
-        70 - ((compression_level - 50) as f32 * 0.8) as u8
-    } else {
-        50 - ((compression_level - 75) as f32) as u8
+    let settings = CompressionSettings {
+        quality: compression_level_to_quality(compression_level),
+        ..Default::default()
     };
     
-    info!("Compressing image with quality {}% (compression level {}%)", quality, compression_level);
+    info!("Compressing image with quality {}% (compression level {}%)", settings.quality, compression_level);
     
+    compress_image_with_settings(input_bytes, output_format, &settings)
+}
+
+/// Compress standalone image from bytes using explicit settings
+/// Returns (compressed_bytes, output_format_extension)
+pub fn compress_image_with_settings(
+    input_bytes: &[u8],
+    output_format: Option<&str>,
+    settings: &CompressionSettings,
+) -> Result<(Vec<u8>, String), String> {
     // Detect input format
     let input_format = image::guess_format(input_bytes)
         .map_err(|e| format!("Failed to detect image format: {}", e))?;
     
     info!("Detected input format: {:?}", input_format);
+    compress_image_with_format(input_bytes, input_format, output_format, settings)
+}
+
+/// Compress a standalone image whose format is already known, e.g. from detect_input_type
+/// Returns (compressed_bytes, output_format_extension)
+pub fn compress_image_with_format(
+    input_bytes: &[u8],
+    input_format: ImageFormat,
+    output_format: Option<&str>,
+    settings: &CompressionSettings,
+) -> Result<(Vec<u8>, String), String> {
+    let (compressed, extension) = recompress_image(input_bytes, input_format, output_format, settings)?;
+    
+    if below_min_reduction(input_bytes.len(), compressed.len(), settings.min_reduction_pct) {
+        let original_extension = input_format.extensions_str().first().copied().unwrap_or("img");
+        return Ok((input_bytes.to_vec(), original_extension.to_string()));
+    }
+    
+    Ok((compressed, extension))
+}
+
+/// Whether a result shrank by less than the requested minimum percentage
+fn below_min_reduction(original_size: usize, compressed_size: usize, min_reduction_pct: Option<f64>) -> bool {
+    let Some(min_pct) = min_reduction_pct else {
+        return false;
+    };
+    if original_size == 0 {
+        return false;
+    }
+    
+    let reduction = reduction_percentage(original_size as u64, compressed_size as u64);
+    if reduction < min_pct {
+        info!("Reduction {:.2}% is below the {:.2}% minimum, keeping original", reduction, min_pct);
+        return true;
+    }
+    false
+}
+
+/// Decode and re-encode a standalone image in the requested (or auto-selected) format
+fn recompress_image(
+    input_bytes: &[u8],
+    input_format: ImageFormat,
+    output_format: Option<&str>,
+    settings: &CompressionSettings,
+) -> Result<(Vec<u8>, String), String> {
     
     // Load image
-    let img = image::load_from_memory(input_bytes)
+    check_image_file_size(input_bytes, Some(input_format), settings.max_image_pixels)?;
+    let img = image::load_from_memory_with_format(input_bytes, input_format)
         .map_err(|e| format!("Failed to load image: {}", e))?;
+    // Outputs carry no ICC profile, so without conversion wide-gamut colors are read as sRGB
+    let img = if settings.convert_to_srgb { color::convert_to_srgb(img, input_bytes, input_format) } else { img };
+    let img = if settings.force_grayscale { img.grayscale() } else { img };
     
     let original_size = input_bytes.len();
     info!("Image loaded: {}x{}, {} bytes", img.width(), img.height(), original_size);
@@ -450,11 +2195,16 @@ pub fn compress_image_bytes(
     // Determine output format
     let target_format = if let Some(fmt) = output_format {
         match fmt.to_lowercase().as_str() {
+            "jpg" | "jpeg" if settings.lossless => {
+                return Err("JPEG output is not available in lossless mode".to_string());
+            }
             "jpg" | "jpeg" => ImageFormat::Jpeg,
             "png" => ImageFormat::Png,
             "webp" => ImageFormat::WebP,
             _ => return Err(format!("Unsupported output format: {}", fmt)),
         }
+    } else if settings.lossless {
+        ImageFormat::Png
     } else {
         // Auto-select: JPEG for lossy sources, PNG for lossless
         // But try both and pick the best
@@ -463,8 +2213,13 @@ pub fn compress_image_bytes(
             _ => {
                 // For lossless sources, try both and pick smaller
                 info!("Trying both JPEG and PNG to find best compression...");
-                let jpeg_result = encode_image_with_quality(&img, quality, ImageFormat::Jpeg);
-                let png_result = encode_image_with_quality(&img, quality, ImageFormat::Png);
+                // The two encodes are independent, so they run side by side unless memory is tight
+                let encode = |format| encode_image_with_quality(&img, settings, format);
+                let (jpeg_result, png_result) = if settings.low_memory {
+                    (encode(ImageFormat::Jpeg), encode(ImageFormat::Png))
+                } else {
+                    rayon::join(|| encode(ImageFormat::Jpeg), || encode(ImageFormat::Png))
+                };
                 
                 match (jpeg_result, png_result) {
                     (Ok(jpeg_bytes), Ok(png_bytes)) => {
@@ -472,8 +2227,8 @@ pub fn compress_image_bytes(
                         let png_size = png_bytes.len();
                         info!("JPEG: {} bytes, PNG: {} bytes", jpeg_size, png_size);
                         
-                        // If sizes are within 10%, prefer PNG for lossless
-                        if png_size as f64 <= jpeg_size as f64 * 1.1 {
+                        // Prefer PNG for lossless while it stays within the configured margin (default 10%)
+                        if png_size as f64 <= jpeg_size as f64 * settings.lossless_preference as f64 {
                             info!("Choosing PNG (lossless and similar size)");
                             return Ok((png_bytes, "png".to_string()));
                         } else {
@@ -498,7 +2253,19 @@ pub fn compress_image_bytes(
     };
     
     // Encode with target format
-    let compressed = encode_image_with_quality(&img, quality, target_format)?;
+    let compressed = encode_image_with_quality(&img, settings, target_format)?;
+    // A JPEG input re-packed as it is loses nothing, so it wins whenever it is smaller
+    let compressed = if settings.optimize_huffman && input_format == ImageFormat::Jpeg && target_format == ImageFormat::Jpeg {
+        let repacked = optimize_jpeg(input_bytes.to_vec(), settings);
+        if repacked.len() < compressed.len() {
+            info!("Keeping the original JPEG with optimized Huffman tables");
+            repacked
+        } else {
+            compressed
+        }
+    } else {
+        compressed
+    };
     let extension = match target_format {
         ImageFormat::Jpeg => "jpg",
         ImageFormat::Png => "png",
@@ -508,7 +2275,7 @@ pub fn compress_image_bytes(
     
     info!("Image compressed: {} bytes -> {} bytes ({:.2}% reduction)",
           original_size, compressed.len(),
-          (original_size as f64 - compressed.len() as f64) / original_size as f64 * 100.0);
+          reduction_percentage(original_size as u64, compressed.len() as u64));
     
     Ok((compressed, extension.to_string()))
 }
@@ -516,42 +2283,37 @@ pub fn compress_image_bytes(
 /// Encode image with specified quality and format
 fn encode_image_with_quality(
     img: &DynamicImage,
-    quality: u8,
+    settings: &CompressionSettings,
     format: ImageFormat,
 ) -> Result<Vec<u8>, String> {
-    use image::imageops::FilterType;
+    let quality = settings.quality;
     
     // Downsample large images based on quality
     let (width, height) = (img.width(), img.height());
-    let downsampled = if quality < 90 && (width > 1500 || height > 1500) {
-        let max_dimension = if quality >= 70 {
-            1500.0
-        } else if quality >= 50 {
-            1200.0
-        } else {
-            1000.0
-        };
-        
-        let scale = max_dimension / width.max(height) as f32;
-        if scale < 1.0 {
-            let new_w = (width as f32 * scale) as u32;
-            let new_h = (height as f32 * scale) as u32;
+    let downsampled = match downsample_limit(settings, width, height, None) {
+        Some(max_dimension) => {
+            let scale = max_dimension / width.max(height) as f32;
+            let new_w = ((width as f32 * scale) as u32).max(1);
+            let new_h = ((height as f32 * scale) as u32).max(1);
             debug!("Downsampling: {}x{} -> {}x{}", width, height, new_w, new_h);
-            img.resize_exact(new_w, new_h, FilterType::Lanczos3)
-        } else {
-            img.clone()
+            img.resize_exact(new_w, new_h, settings.resample_filter.to_filter_type())
         }
-    } else {
-        img.clone()
+        None => img.clone(),
     };
     
     let mut output = Vec::new();
     
     match format {
         ImageFormat::Jpeg => {
-            let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output, quality);
-            encoder.encode_image(&downsampled)
-                .map_err(|e| format!("JPEG encoding failed: {}", e))?;
+            // JPEG has no alpha channel, so transparent pixels take the background colour
+            let flattened = color::flatten_alpha(downsampled, settings.flatten_background);
+            output = optimize_jpeg(encode_jpeg(&flattened, settings.jpeg_encoder_quality(), settings.progressive)?, settings);
+        }
+        ImageFormat::Png if settings.quantize_png && quality < 90 && !settings.lossless => {
+            // Below lossless quality, reduce to an indexed palette sized by quality
+            let colors = quantize::palette_size_for_quality(quality);
+            debug!("Quantizing PNG to {} colors", colors);
+            output = quantize::encode_indexed_png(&downsampled, colors)?;
         }
         ImageFormat::Png => {
             // Map quality to PNG compression level (inverse: higher quality = less compression)
@@ -562,22 +2324,12 @@ fn encode_image_with_quality(
             } else {
                 image::codecs::png::CompressionType::Fast
             };
-            
-            let encoder = image::codecs::png::PngEncoder::new_with_quality(
-                &mut output,
-                compression_level,
-                image::codecs::png::FilterType::Adaptive,
-            );
-            
-            // Use write_image method for image 0.24
-            use image::ImageEncoder;
-            encoder.write_image(
-                downsampled.as_bytes(),
-                downsampled.width(),
-                downsampled.height(),
-                downsampled.color(),
-            )
-            .map_err(|e| format!("PNG encoding failed: {}", e))?;
+            output = if settings.reduce_png {
+                // Lossless, but drop to grayscale or a palette when the pixels allow it
+                quantize::encode_lossless_png(&downsampled, compression_level)?
+            } else {
+                quantize::write_png(&downsampled, compression_level)?
+            };
         }
         ImageFormat::WebP => {
             // WebP support is limited in image 0.24, use lossless encoding
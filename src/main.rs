@@ -6,6 +6,7 @@ use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self, Receiver};
 use std::thread;
 use log::{info, warn};
+use rayon::prelude::*;
 
 struct PdfCompressor {
     selected_files: Vec<PathBuf>,
@@ -15,7 +16,9 @@ struct PdfCompressor {
     receiver: Option<Receiver<CompressionResult>>,
     compression_level: u8, // 0-100, how much to compress (0=minimal, 100=maximum)
     estimated_size: Option<u64>,
+    estimate_receiver: Option<Receiver<u64>>, // Pending background estimate, replaced on every re-estimate
     is_dragging: bool, // Track if files are being dragged over
+    preview: Option<PreviewPair>, // Open before/after comparison, if any
 }
 
 impl Default for PdfCompressor {
@@ -28,12 +31,14 @@ impl Default for PdfCompressor {
             receiver: None,
             compression_level: 75, // Default: 75% compression (good balance)
             estimated_size: None,
+            estimate_receiver: None,
             is_dragging: false,
+            preview: None,
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 struct CompressionResult {
     file_name: String,
     original_size: u64,
@@ -41,25 +46,24 @@ struct CompressionResult {
     success: bool,
     error_message: Option<String>,
     compressed_path: Option<PathBuf>,
+    original_path: Option<PathBuf>,
     downloaded: bool,
 }
 
-// Settings moved to lib.rs - using library function now
+/// Longest texture edge used for the before/after preview
+const PREVIEW_MAX_EDGE: u32 = 1024;
 
-impl Default for CompressionResult {
-    fn default() -> Self {
-        Self {
-            file_name: String::new(),
-            original_size: 0,
-            compressed_size: 0,
-            success: false,
-            error_message: None,
-            compressed_path: None,
-            downloaded: false,
-        }
-    }
+/// Original and compressed textures shown side by side
+struct PreviewPair {
+    title: String,
+    original: egui::TextureHandle,
+    compressed: egui::TextureHandle,
+    original_size: u64,
+    compressed_size: u64,
 }
 
+// Settings moved to lib.rs - using library function now
+
 impl PdfCompressor {
     fn new() -> Self {
         Self::default()
@@ -102,22 +106,30 @@ impl PdfCompressor {
     fn estimate_compressed_size(&mut self) {
         if self.selected_files.is_empty() {
             self.estimated_size = None;
+            self.estimate_receiver = None;
             return;
         }
         
-        let total_size: u64 = self.selected_files.iter()
-            .filter_map(|path| std::fs::metadata(path).ok())
-            .map(|m| m.len())
-            .sum();
-        
-        // Compression level 0-100 directly maps to reduction %
-        // Level 25 = ~25% reduction (high quality, minimal compression)
-        // Level 50 = ~50% reduction (balanced)
-        // Level 75 = ~75% reduction (good compression) - DEFAULT
-        // Level 90 = ~90% reduction (aggressive)
-        let reduction_factor = (self.compression_level as f64) / 100.0;
+        // PDFs are estimated by the library, which decodes images, so keep it off the UI thread
+        let files = self.selected_files.clone();
+        let level = self.compression_level;
+        let (tx, rx) = mpsc::channel();
+        self.estimate_receiver = Some(rx);
         
-        self.estimated_size = Some((total_size as f64 * (1.0 - reduction_factor)) as u64);
+        thread::spawn(move || {
+            let total: u64 = files.par_iter().map(|path| estimate_file_size(path, level)).sum();
+            // The receiver is gone if the level changed again meanwhile
+            let _ = tx.send(total);
+        });
+    }
+    
+    fn check_estimate(&mut self) {
+        if let Some(ref rx) = self.estimate_receiver {
+            if let Ok(estimate) = rx.try_recv() {
+                self.estimated_size = Some(estimate);
+                self.estimate_receiver = None;
+            }
+        }
     }
     
     fn download_all(&mut self) {
@@ -186,14 +198,42 @@ impl PdfCompressor {
         let (tx, rx) = mpsc::channel();
         self.receiver = Some(rx);
 
+        // Files compress concurrently on the rayon pool; results arrive in completion order,
+        // which check_processing_results handles since it only counts them
         thread::spawn(move || {
-            for file_path in files {
-                let result = compress_single_file(&file_path, jpeg_quality);
+            files.par_iter().enumerate().for_each_with(tx, |tx, (index, file_path)| {
+                let result = compress_single_file(file_path, index, jpeg_quality);
                 let _ = tx.send(result);
-            }
+            });
         });
     }
 
+    fn open_preview(&mut self, ctx: &Context, idx: usize) {
+        let result = &self.compression_results[idx];
+        let (Some(original_path), Some(compressed_path)) = (&result.original_path, &result.compressed_path) else {
+            return;
+        };
+        
+        let textures = load_preview_texture(ctx, "preview_original", original_path)
+            .and_then(|original| Ok((original, load_preview_texture(ctx, "preview_compressed", compressed_path)?)));
+        match textures {
+            Ok((original, compressed)) => {
+                self.preview = Some(PreviewPair {
+                    title: result.file_name.clone(),
+                    original,
+                    compressed,
+                    original_size: result.original_size,
+                    compressed_size: result.compressed_size,
+                });
+            }
+            Err(e) => {
+                // Fall back to the external viewer
+                warn!("In-app preview failed: {}", e);
+                open_file(compressed_path);
+            }
+        }
+    }
+
     fn check_processing_results(&mut self) {
         if let Some(ref rx) = self.receiver {
             // Non-blocking check for new results
@@ -217,7 +257,25 @@ impl PdfCompressor {
     }
 }
 
-fn compress_single_file(input_path: &Path, compression_level: u8) -> CompressionResult {
+/// Estimated compressed size of one file
+fn estimate_file_size(path: &Path, compression_level: u8) -> u64 {
+    let Ok(bytes) = std::fs::read(path) else {
+        return 0;
+    };
+    
+    if bytes.starts_with(b"%PDF") {
+        match PDFcompressor::estimate_pdf_compression(&bytes, compression_level) {
+            Ok(estimate) => return estimate,
+            Err(e) => warn!("Estimate failed for {:?}: {}", path, e),
+        }
+    }
+    
+    // Images (and unreadable PDFs) fall back to the linear guess: level N is ~N% reduction
+    let reduction_factor = compression_level as f64 / 100.0;
+    (bytes.len() as f64 * (1.0 - reduction_factor)) as u64
+}
+
+fn compress_single_file(input_path: &Path, batch_index: usize, compression_level: u8) -> CompressionResult {
     let file_name = input_path.file_name()
         .and_then(|n| n.to_str())
         .unwrap_or("Unknown")
@@ -233,50 +291,9 @@ fn compress_single_file(input_path: &Path, compression_level: u8) -> Compression
     };
     info!("Original file size: {} bytes", original_size);
 
-    // Detect file type by extension
-    let extension = input_path.extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("")
-        .to_lowercase();
-    
-    let is_pdf = extension == "pdf";
-    info!("File type: {}", if is_pdf { "PDF" } else { "Image" });
-
     // Read input file and compress using library
-    match std::fs::read(input_path) {
-        Ok(input_bytes) => {
-            let (compressed_bytes, output_ext) = if is_pdf {
-                match PDFcompressor::compress_pdf_bytes(&input_bytes, compression_level) {
-                    Ok(bytes) => (bytes, "pdf".to_string()),
-                    Err(error) => {
-                        return CompressionResult {
-                            file_name,
-                            original_size,
-                            compressed_size: 0,
-                            success: false,
-                            error_message: Some(error),
-                            compressed_path: None,
-                            downloaded: false,
-                        };
-                    }
-                }
-            } else {
-                match PDFcompressor::compress_image_bytes(&input_bytes, compression_level, None) {
-                    Ok((bytes, ext)) => (bytes, ext),
-                    Err(error) => {
-                        return CompressionResult {
-                            file_name,
-                            original_size,
-                            compressed_size: 0,
-                            success: false,
-                            error_message: Some(error),
-                            compressed_path: None,
-                            downloaded: false,
-                        };
-                    }
-                }
-            };
-            
+    match PDFcompressor::compress_path(input_path, compression_level) {
+        Ok((compressed_bytes, output_ext)) => {
             // Generate temp output path in system temp directory
             let temp_dir = std::env::temp_dir();
             let file_stem = input_path.file_stem()
@@ -286,7 +303,8 @@ fn compress_single_file(input_path: &Path, compression_level: u8) -> Compression
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs();
-            let output_filename = format!("{}_compressed_{}.{}", file_stem, timestamp, output_ext);
+            // Batch index keeps same-named files from different folders apart
+            let output_filename = format!("{}_compressed_{}_{}.{}", file_stem, timestamp, batch_index, output_ext);
             let output_path = temp_dir.join(&output_filename);
             info!("Temp output path: {:?}", output_path);
             
@@ -299,16 +317,13 @@ fn compress_single_file(input_path: &Path, compression_level: u8) -> Compression
                     success: false,
                     error_message: Some(format!("Failed to write output: {}", e)),
                     compressed_path: None,
+                    original_path: None,
                     downloaded: false,
                 };
             }
             
             let compressed_size = compressed_bytes.len() as u64;
-            let reduction = if original_size > 0 {
-                (original_size as i64 - compressed_size as i64) as f64 / original_size as f64 * 100.0
-            } else {
-                0.0
-            };
+            let reduction = PDFcompressor::reduction_percentage(original_size, compressed_size);
             
             info!("Compressed file size: {} bytes", compressed_size);
             info!("Reduction: {:.2}%", reduction);
@@ -321,16 +336,18 @@ fn compress_single_file(input_path: &Path, compression_level: u8) -> Compression
                 success: true,
                 error_message: None,
                 compressed_path: Some(output_path),
+                original_path: Some(input_path.to_path_buf()),
                 downloaded: false,
             }
         }
-        Err(e) => CompressionResult {
+        Err(error) => CompressionResult {
             file_name,
             original_size,
             compressed_size: 0,
             success: false,
-            error_message: Some(format!("Failed to read input file: {}", e)),
+            error_message: Some(error),
             compressed_path: None,
+            original_path: None,
             downloaded: false,
         }
     }
@@ -346,6 +363,11 @@ impl eframe::App for PdfCompressor {
             ctx.request_repaint(); // Keep UI responsive
         }
         
+        if self.estimate_receiver.is_some() {
+            self.check_estimate();
+            ctx.request_repaint();
+        }
+        
         // Handle file drops
         ctx.input(|i| {
             if !i.raw.hovered_files.is_empty() {
@@ -487,11 +509,8 @@ impl eframe::App for PdfCompressor {
                         .map(|m| m.len())
                         .sum();
                     
-                    let reduction = if total_original > 0 {
-                        ((total_original - estimated) as f64 / total_original as f64 * 100.0) as u64
-                    } else {
-                        0
-                    };
+                    // Shown as a whole percentage; a growth reads as 0%
+                    let reduction = PDFcompressor::reduction_percentage(total_original, estimated) as u64;
                     
                     ui.add_space(5.0);
                     ui.horizontal(|ui| {
@@ -561,6 +580,7 @@ impl eframe::App for PdfCompressor {
                 });
                 ui.add_space(10.0);
 
+                let mut compare_idx = None;
                 ScrollArea::vertical()
                     .id_salt("results_scroll")
                     .show(ui, |ui| {
@@ -580,11 +600,7 @@ impl eframe::App for PdfCompressor {
 
                             if result.success {
                                 ui.add_space(5.0);
-                                let reduction = if result.original_size > 0 && result.compressed_size < result.original_size {
-                                    ((result.original_size - result.compressed_size) as f64 / result.original_size as f64 * 100.0) as u64
-                                } else {
-                                    0
-                                };
+                                let reduction = PDFcompressor::reduction_percentage(result.original_size, result.compressed_size) as u64;
                                 
                                 let color = if reduction > 10 { Color32::from_rgb(40, 200, 40) } 
                                            else if reduction > 0 { Color32::from_rgb(200, 200, 40) }
@@ -606,6 +622,12 @@ impl eframe::App for PdfCompressor {
                                         if ui.button(RichText::new("👁 Preview").size(14.0)).clicked() {
                                             open_file(compressed_path);
                                         }
+                                        
+                                        // PDFs aren't rasterized, so only images get the in-app comparison
+                                        let is_pdf = compressed_path.extension().is_some_and(|ext| ext == "pdf");
+                                        if !is_pdf && ui.button(RichText::new("🔍 Compare").size(14.0)).clicked() {
+                                            compare_idx = Some(idx);
+                                        }
                                     }
                                     
                                     if result.downloaded {
@@ -622,11 +644,65 @@ impl eframe::App for PdfCompressor {
                         }
                     }
                 });
+                
+                if let Some(idx) = compare_idx {
+                    self.open_preview(ctx, idx);
+                }
             }
         });
+        
+        self.show_preview(ctx);
     }
 }
 
+impl PdfCompressor {
+    fn show_preview(&mut self, ctx: &Context) {
+        let Some(preview) = &self.preview else {
+            return;
+        };
+        
+        let mut open = true;
+        egui::Window::new(format!("Before / After: {}", preview.title))
+            .open(&mut open)
+            .default_width(760.0)
+            .show(ctx, |ui| {
+                let saved = preview.original_size as i64 - preview.compressed_size as i64;
+                ui.label(RichText::new(format!(
+                    "{}  →  {}  ({} saved)",
+                    format_file_size(preview.original_size),
+                    format_file_size(preview.compressed_size),
+                    if saved >= 0 { format_file_size(saved as u64) } else { format!("-{}", format_file_size(saved.unsigned_abs())) }
+                )).size(14.0).strong());
+                ui.add_space(5.0);
+                
+                let half_width = (ui.available_width() - 10.0) / 2.0;
+                ui.horizontal_top(|ui| {
+                    for (label, texture) in [("Original", &preview.original), ("Compressed", &preview.compressed)] {
+                        ui.vertical(|ui| {
+                            ui.label(RichText::new(label).size(14.0));
+                            ui.add(egui::Image::new(texture).max_width(half_width).max_height(500.0));
+                        });
+                    }
+                });
+            });
+        
+        if !open {
+            self.preview = None;
+        }
+    }
+}
+
+/// Read an image file and upload a downscaled copy as a texture
+fn load_preview_texture(ctx: &Context, name: &str, path: &Path) -> Result<egui::TextureHandle, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let preview = PDFcompressor::load_preview_image(&bytes, PREVIEW_MAX_EDGE)?;
+    let image = egui::ColorImage::from_rgba_unmultiplied(
+        [preview.width as usize, preview.height as usize],
+        &preview.rgba,
+    );
+    Ok(ctx.load_texture(name, image, egui::TextureOptions::LINEAR))
+}
+
 fn format_file_size(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
     let mut size = bytes as f64;
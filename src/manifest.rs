@@ -0,0 +1,35 @@
+// Audit manifests: digests of a compression's input and output, and what produced it
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::CompressionSettings;
+
+/// Record of one compression for audit trails and integrity checks
+#[derive(Clone, Debug, Serialize)]
+pub struct CompressionManifest {
+    pub crate_version: &'static str,
+    pub input_sha256: String, // Lowercase hex
+    pub output_sha256: String, // Lowercase hex
+    pub input_size: u64,
+    pub output_size: u64,
+    pub settings: CompressionSettings,
+}
+
+impl CompressionManifest {
+    /// Hash `input` and `output` and record the settings that turned one into the other
+    pub fn new(input: &[u8], output: &[u8], settings: &CompressionSettings) -> Self {
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION"),
+            input_sha256: sha256_hex(input),
+            output_sha256: sha256_hex(output),
+            input_size: input.len() as u64,
+            output_size: output.len() as u64,
+            settings: settings.clone(),
+        }
+    }
+}
+
+/// SHA-256 digest of `data` as lowercase hex
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
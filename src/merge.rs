@@ -0,0 +1,120 @@
+// Combining several PDF documents into one
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use log::info;
+
+use crate::compress_pdf_bytes;
+use crate::xref::load_document;
+
+/// Page attributes a page may inherit from its ancestors in the page tree
+const INHERITABLE_KEYS: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Concatenate the pages of every input document, then compress the merged PDF
+/// Identical streams across inputs (shared logos, fonts) are merged by the compression pass
+pub fn merge_and_compress_pdfs(inputs: &[Vec<u8>], compression_level: u8) -> Result<Vec<u8>, String> {
+    let merged = merge_pdfs(inputs)?;
+    compress_pdf_bytes(&merged, compression_level)
+}
+
+/// Concatenate the pages of every input document into a single uncompressed PDF
+fn merge_pdfs(inputs: &[Vec<u8>]) -> Result<Vec<u8>, String> {
+    if inputs.is_empty() {
+        return Err("No PDFs provided".to_string());
+    }
+
+    let mut merged = Document::with_version("1.5");
+    let mut page_ids: Vec<ObjectId> = Vec::new();
+    let mut next_id = 1;
+
+    for (index, input) in inputs.iter().enumerate() {
+        let mut doc = load_document(input)
+            .map_err(|e| format!("Document {}: {}", index + 1, e))?;
+
+        // Give every document its own id range so objects never collide
+        doc.renumber_objects_with(next_id);
+        next_id = doc.objects.keys().map(|id| id.0).max().unwrap_or(next_id) + 1;
+
+        if doc.version > merged.version {
+            merged.version = doc.version.clone();
+        }
+
+        let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+        if pages.is_empty() {
+            return Err(format!("Document {}: No pages found", index + 1));
+        }
+
+        // Pages are re-parented under the new tree, so copy down anything they inherited
+        for page_id in &pages {
+            let inherited = inherited_attributes(&doc, *page_id);
+            if let Ok(Object::Dictionary(page)) = doc.get_object_mut(*page_id) {
+                for (key, value) in inherited {
+                    page.set(key, value);
+                }
+            }
+        }
+
+        page_ids.extend(&pages);
+        merged.objects.extend(doc.objects);
+    }
+
+    let pages_id = (next_id, 0);
+    for page_id in &page_ids {
+        if let Some(Object::Dictionary(page)) = merged.objects.get_mut(page_id) {
+            page.set("Parent", Object::Reference(pages_id));
+        }
+    }
+
+    let page_count = page_ids.len();
+    merged.objects.insert(
+        pages_id,
+        Object::Dictionary(Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(page_ids.into_iter().map(Object::Reference).collect())),
+            ("Count", Object::Integer(page_count as i64)),
+        ])),
+    );
+    merged.max_id = next_id;
+
+    // The old catalogs and page tree roots become unreferenced and are pruned
+    let catalog_id = merged.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Catalog".to_vec())),
+        ("Pages", Object::Reference(pages_id)),
+    ]));
+    merged.trailer.set("Root", Object::Reference(catalog_id));
+    merged.prune_objects();
+
+    let mut output = Vec::new();
+    merged.save_to(&mut output)
+        .map_err(|e| format!("Failed to save merged PDF: {}", e))?;
+
+    info!("Merged {} documents into {} pages: {} bytes", inputs.len(), page_count, output.len());
+
+    Ok(output)
+}
+
+/// Inheritable attributes a page lacks itself but gets from an ancestor Pages node
+pub(crate) fn inherited_attributes(doc: &Document, page_id: ObjectId) -> Vec<(Vec<u8>, Object)> {
+    let mut found = Vec::new();
+    let Ok(page) = doc.get_dictionary(page_id) else {
+        return found;
+    };
+
+    for key in INHERITABLE_KEYS {
+        if page.has(key) {
+            continue;
+        }
+        let mut parent = page.get(b"Parent").and_then(Object::as_reference).ok();
+        // Depth bound guards against cyclic Parent links in damaged files
+        for _ in 0..32 {
+            let Some(node) = parent.and_then(|id| doc.get_dictionary(id).ok()) else {
+                break;
+            };
+            if let Ok(value) = node.get(key) {
+                found.push((key.to_vec(), value.clone()));
+                break;
+            }
+            parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+        }
+    }
+
+    found
+}
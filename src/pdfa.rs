@@ -0,0 +1,67 @@
+// Recognizing PDF/A files, so compression can leave alone what their conformance depends on
+use lopdf::{Document, Object, ObjectId, Stream};
+
+/// Colour space families whose samples are defined without reference to an output device
+const DEVICE_INDEPENDENT_SPACES: [&[u8]; 4] = [b"ICCBased", b"CalRGB", b"CalGray", b"Lab"];
+
+/// The PDF/A level a document claims, e.g. "PDF/A-2b", or just "PDF/A" when only a PDF/A
+/// OutputIntent says so; None for documents that make no PDF/A claim
+pub(crate) fn pdfa_conformance(doc: &Document) -> Option<String> {
+    let catalog = doc.catalog().ok()?;
+
+    let xmp = metadata_stream(doc).and_then(|id| doc.get_object(id).ok()).and_then(|object| object.as_stream().ok());
+    if let Some(xmp) = xmp {
+        let packet = xmp.decompressed_content().unwrap_or_else(|_| xmp.content.clone());
+        if let Some(part) = xmp_property(&packet, b"pdfaid:part") {
+            let conformance = xmp_property(&packet, b"pdfaid:conformance").unwrap_or_default();
+            return Some(format!("PDF/A-{}{}", part, conformance.to_lowercase()));
+        }
+    }
+
+    let intents = catalog.get(b"OutputIntents").ok()
+        .and_then(|intents| resolve(doc, intents).as_array().ok())?;
+    intents.iter()
+        .filter_map(|intent| resolve(doc, intent).as_dict().ok())
+        .any(|intent| matches!(intent.get(b"S").and_then(Object::as_name), Ok(b"GTS_PDFA1")))
+        .then(|| "PDF/A".to_string())
+}
+
+/// The catalog's XMP metadata stream
+pub(crate) fn metadata_stream(doc: &Document) -> Option<ObjectId> {
+    doc.catalog().ok()?.get(b"Metadata").and_then(Object::as_reference).ok()
+}
+
+/// Whether an image's colours are defined by a profile or calibration rather than the device,
+/// which re-encoding to DeviceGray/DeviceRGB would throw away
+pub(crate) fn is_device_independent(doc: &Document, stream: &Stream) -> bool {
+    let Ok(color_space) = stream.dict.get(b"ColorSpace") else {
+        return false;
+    };
+    let family = match resolve(doc, color_space) {
+        Object::Name(name) => name.as_slice(),
+        Object::Array(items) => match items.first() {
+            Some(Object::Name(name)) => name.as_slice(),
+            _ => return false,
+        },
+        _ => return false,
+    };
+    DEVICE_INDEPENDENT_SPACES.contains(&family)
+}
+
+fn resolve<'a>(doc: &'a Document, object: &'a Object) -> &'a Object {
+    match object {
+        Object::Reference(id) => doc.get_object(*id).unwrap_or(object),
+        _ => object,
+    }
+}
+
+/// Value of a simple XMP property, written either as an attribute or as an element
+fn xmp_property(packet: &[u8], name: &[u8]) -> Option<String> {
+    let start = packet.windows(name.len()).position(|window| window == name)? + name.len();
+    let value: Vec<u8> = packet[start..].iter()
+        .skip_while(|byte| byte.is_ascii_whitespace() || matches!(byte, b'=' | b'"' | b'\'' | b'>'))
+        .take_while(|byte| byte.is_ascii_alphanumeric())
+        .copied()
+        .collect();
+    (!value.is_empty()).then(|| String::from_utf8_lossy(&value).into_owned())
+}
@@ -0,0 +1,245 @@
+// Placed sizes of image XObjects, read from the transformation matrices in page content
+use crate::decode::{decode_stream_bounded, StreamBudget};
+use crate::CompressionSettings;
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use log::debug;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Form XObjects nested deeper than this are not followed
+const MAX_FORM_DEPTH: usize = 8;
+
+/// Transformation matrix [a b c d e f], as in the `cm` operator
+type Matrix = [f32; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// Largest width and height, in points, at which each image XObject is drawn on a page
+/// Images referenced from content that can't be parsed get an infinite size, so their
+/// placement never limits them
+pub(crate) fn image_display_sizes(doc: &Document, settings: &CompressionSettings) -> HashMap<ObjectId, (f32, f32)> {
+    let mut sizes = HashMap::new();
+    for page_id in doc.get_pages().into_values() {
+        let xobjects = page_xobjects(doc, page_id);
+        let content = page_content(doc, page_id, settings);
+        walk_content(doc, content.as_deref(), &xobjects, IDENTITY, 0, settings, &mut sizes);
+    }
+    debug!("Found placements for {} image XObjects", sizes.len());
+    sizes
+}
+
+/// Follow `q`/`Q`/`cm` through a content stream and record the size of every image it draws
+fn walk_content(
+    doc: &Document,
+    content: Option<&[u8]>,
+    xobjects: &HashMap<Vec<u8>, ObjectId>,
+    ctm: Matrix,
+    depth: usize,
+    settings: &CompressionSettings,
+    sizes: &mut HashMap<ObjectId, (f32, f32)>,
+) {
+    let Some(content) = content.and_then(|content| Content::decode(content).ok()) else {
+        debug!("Content not parseable, images it may draw have unknown placement");
+        mark_unplaced(doc, xobjects, depth, sizes);
+        return;
+    };
+
+    let mut ctm = ctm;
+    let mut saved = Vec::new();
+    for operation in &content.operations {
+        match operation.operator.as_str() {
+            "q" => saved.push(ctm),
+            "Q" => ctm = saved.pop().unwrap_or(ctm),
+            "cm" => {
+                if let Some(matrix) = matrix(&operation.operands) {
+                    ctm = multiply(&matrix, &ctm);
+                }
+            }
+            "Do" => {
+                let Some(id) = operation.operands.first()
+                    .and_then(|name| name.as_name().ok())
+                    .and_then(|name| xobjects.get(name)) else {
+                    continue;
+                };
+                draw_xobject(doc, *id, xobjects, ctm, depth, settings, sizes);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Record an image drawn with `ctm`, or walk a form XObject's content under it
+fn draw_xobject(
+    doc: &Document,
+    id: ObjectId,
+    parent_xobjects: &HashMap<Vec<u8>, ObjectId>,
+    ctm: Matrix,
+    depth: usize,
+    settings: &CompressionSettings,
+    sizes: &mut HashMap<ObjectId, (f32, f32)>,
+) {
+    let Ok(stream) = doc.get_object(id).and_then(Object::as_stream) else {
+        return;
+    };
+    match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+        Ok(b"Image") => {
+            // The image fills the unit square, so the matrix's column lengths are its drawn size
+            let width = ctm[0].hypot(ctm[1]);
+            let height = ctm[2].hypot(ctm[3]);
+            let size = sizes.entry(id).or_insert((0.0, 0.0));
+            *size = (size.0.max(width), size.1.max(height));
+        }
+        Ok(b"Form") if depth < MAX_FORM_DEPTH => {
+            let form_matrix = stream.dict.get(b"Matrix").ok()
+                .and_then(|object| resolve(doc, object).as_array().ok())
+                .and_then(|items| matrix(items))
+                .unwrap_or(IDENTITY);
+            // Forms without their own Resources use the ones they are drawn with
+            let xobjects = match stream.dict.get(b"Resources").map(|resources| resolve(doc, resources).as_dict()) {
+                Ok(Ok(resources)) => xobject_names(doc, &[resources]),
+                _ => parent_xobjects.clone(),
+            };
+            let content = decode_content(doc, id, settings);
+            walk_content(doc, content.as_deref(), &xobjects, multiply(&form_matrix, &ctm), depth + 1, settings, sizes);
+        }
+        _ => {}
+    }
+}
+
+/// Give every image reachable through `xobjects` an unknown (infinite) placement
+fn mark_unplaced(doc: &Document, xobjects: &HashMap<Vec<u8>, ObjectId>, depth: usize, sizes: &mut HashMap<ObjectId, (f32, f32)>) {
+    for id in xobjects.values() {
+        let Ok(stream) = doc.get_object(*id).and_then(Object::as_stream) else {
+            continue;
+        };
+        match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+            Ok(b"Image") => {
+                sizes.insert(*id, (f32::INFINITY, f32::INFINITY));
+            }
+            Ok(b"Form") if depth < MAX_FORM_DEPTH => {
+                if let Ok(resources) = stream.dict.get(b"Resources").and_then(|resources| resolve(doc, resources).as_dict()) {
+                    mark_unplaced(doc, &xobject_names(doc, &[resources]), depth + 1, sizes);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Form XObjects a page can draw, at any nesting level, each with the XObject names its
+/// content draws from: the form's own Resources, or its caller's for forms without any
+/// Page-scoped passes use this to reach images that the page's XObject dictionary only
+/// names indirectly, through the forms there
+pub(crate) fn page_forms(doc: &Document, page_id: ObjectId) -> Vec<(ObjectId, HashMap<Vec<u8>, ObjectId>)> {
+    let mut forms = Vec::new();
+    collect_forms(doc, &page_xobjects(doc, page_id), 0, &mut forms);
+    forms
+}
+
+fn collect_forms(
+    doc: &Document,
+    xobjects: &HashMap<Vec<u8>, ObjectId>,
+    depth: usize,
+    forms: &mut Vec<(ObjectId, HashMap<Vec<u8>, ObjectId>)>,
+) {
+    if depth >= MAX_FORM_DEPTH {
+        return;
+    }
+    for id in xobjects.values() {
+        // A form naming itself (or an ancestor) in its Resources must not loop
+        if forms.iter().any(|(form_id, _)| form_id == id) {
+            continue;
+        }
+        let Ok(stream) = doc.get_object(*id).and_then(Object::as_stream) else {
+            continue;
+        };
+        if !matches!(stream.dict.get(b"Subtype").and_then(Object::as_name), Ok(b"Form")) {
+            continue;
+        }
+        let names = match stream.dict.get(b"Resources").map(|resources| resolve(doc, resources).as_dict()) {
+            Ok(Ok(resources)) => xobject_names(doc, &[resources]),
+            _ => xobjects.clone(),
+        };
+        forms.push((*id, names.clone()));
+        collect_forms(doc, &names, depth + 1, forms);
+    }
+}
+
+/// XObject names available to a page, including inherited resources
+pub(crate) fn page_xobjects(doc: &Document, page_id: ObjectId) -> HashMap<Vec<u8>, ObjectId> {
+    let (own, inherited) = doc.get_page_resources(page_id);
+    let resources: Vec<&Dictionary> = own.into_iter()
+        .chain(inherited.into_iter().filter_map(|id| doc.get_dictionary(id).ok()))
+        .collect();
+    xobject_names(doc, &resources)
+}
+
+/// Map XObject names to object IDs, earlier resource dictionaries taking precedence
+pub(crate) fn xobject_names(doc: &Document, resources: &[&Dictionary]) -> HashMap<Vec<u8>, ObjectId> {
+    let mut names = HashMap::new();
+    for resources in resources {
+        let Some(xobjects) = resources.get(b"XObject").ok()
+            .and_then(|xobjects| resolve(doc, xobjects).as_dict().ok()) else {
+            continue;
+        };
+        for (name, object) in xobjects.iter() {
+            if let Object::Reference(id) = object {
+                names.entry(name.clone()).or_insert(*id);
+            }
+        }
+    }
+    names
+}
+
+/// Concatenated content of a page, or None if any part of it can't be decoded
+fn page_content(doc: &Document, page_id: ObjectId, settings: &CompressionSettings) -> Option<Vec<u8>> {
+    let mut content = Vec::new();
+    for content_id in doc.get_page_contents(page_id) {
+        content.extend(decode_content(doc, content_id, settings)?);
+        content.push(b'\n');
+    }
+    Some(content)
+}
+
+pub(crate) fn decode_content(doc: &Document, id: ObjectId, settings: &CompressionSettings) -> Option<Vec<u8>> {
+    let stream = doc.get_object(id).and_then(Object::as_stream).ok()?;
+    if !stream.dict.has(b"Filter") {
+        return Some(stream.content.clone());
+    }
+    let budget = StreamBudget::new(
+        Duration::from_millis(settings.stream_timeout_ms),
+        settings.max_decompressed_bytes,
+    );
+    decode_stream_bounded(stream, budget.generic_limit(stream.content.len())).ok().flatten()
+}
+
+pub(crate) fn resolve<'a>(doc: &'a Document, object: &'a Object) -> &'a Object {
+    match object {
+        Object::Reference(id) => doc.get_object(*id).unwrap_or(object),
+        other => other,
+    }
+}
+
+fn matrix(operands: &[Object]) -> Option<Matrix> {
+    let values: Vec<f32> = operands.iter()
+        .map(|operand| match operand {
+            Object::Integer(n) => Some(*n as f32),
+            Object::Real(n) => Some(*n),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    values.try_into().ok()
+}
+
+/// `m` applied before `n`, in PDF's row-vector convention
+fn multiply(m: &Matrix, n: &Matrix) -> Matrix {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
@@ -0,0 +1,35 @@
+// Decoding for the GUI's before/after preview
+use image::imageops::FilterType;
+
+/// Decoded RGBA pixels ready to upload as a texture
+#[derive(Clone, Debug)]
+pub struct PreviewImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>, // Unmultiplied RGBA, row-major
+}
+
+/// Decode an image file into RGBA, shrunk so its long edge is at most `max_edge`
+/// PDFs are not rasterized, so they return an error
+pub fn load_preview_image(bytes: &[u8], max_edge: u32) -> Result<PreviewImage, String> {
+    if bytes.starts_with(b"%PDF") {
+        return Err("Preview is only available for images".to_string());
+    }
+
+    let img = image::load_from_memory(bytes)
+        .map_err(|e| format!("Failed to decode image: {}", e))?;
+
+    // Thumbnails only need to fill a panel, so a cheap filter is enough
+    let img = if img.width().max(img.height()) > max_edge {
+        img.resize(max_edge, max_edge, FilterType::Triangle)
+    } else {
+        img
+    };
+
+    let rgba = img.to_rgba8();
+    Ok(PreviewImage {
+        width: rgba.width(),
+        height: rgba.height(),
+        rgba: rgba.into_raw(),
+    })
+}
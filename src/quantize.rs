@@ -0,0 +1,183 @@
+// Palette quantization for smaller lossless-looking PNG output
+use ahash::AHashMap;
+use image::codecs::png::CompressionType;
+use image::{ColorType, DynamicImage};
+use log::debug;
+
+/// Largest palette that still packs below 8 bits per pixel
+const SUB_BYTE_PALETTE: usize = 16;
+
+/// Map image quality to a palette size (higher quality = more colors)
+pub(crate) fn palette_size_for_quality(quality: u8) -> usize {
+    (quality as usize * 3).clamp(16, 256)
+}
+
+/// Encode an image as an 8-bit indexed PNG with an alpha-aware palette
+/// Images that already fit in the palette keep their exact colors
+pub(crate) fn encode_indexed_png(
+    img: &DynamicImage,
+    max_colors: usize,
+) -> Result<Vec<u8>, String> {
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let pixels = rgba.as_raw();
+
+    let (palette, indices) = match exact_palette(pixels, max_colors) {
+        Some(exact) => {
+            debug!("Exact palette: {} colors", exact.0.len() / 4);
+            exact
+        }
+        None => {
+            let quantizer = color_quant::NeuQuant::new(10, max_colors, pixels);
+            let indices = pixels
+                .chunks_exact(4)
+                .map(|px| quantizer.index_of(px) as u8)
+                .collect();
+            debug!("Quantized palette: {} colors", max_colors);
+            (quantizer.color_map_rgba(), indices)
+        }
+    };
+
+    write_indexed_png(width, height, &palette, &indices)
+}
+
+/// Encode an 8-bit image losslessly in the smallest PNG layout that holds every pixel exactly
+/// Fully opaque alpha is dropped, gray-only colour becomes grayscale, and images with few
+/// distinct colours become indexed; other sample depths are written unchanged
+/// `compression` applies to the non-indexed layouts; palette data always gets the best deflate
+pub(crate) fn encode_lossless_png(img: &DynamicImage, compression: CompressionType) -> Result<Vec<u8>, String> {
+    if !matches!(img.color(), ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8) {
+        return write_png(img, compression);
+    }
+
+    let rgba = img.to_rgba8();
+    let pixels = rgba.as_raw();
+    let opaque = pixels.chunks_exact(4).all(|px| px[3] == 255);
+    let gray = pixels.chunks_exact(4).all(|px| px[0] == px[1] && px[1] == px[2]);
+
+    // Gray images only gain from a palette once it packs below 8 bits per pixel
+    let max_colors = if gray { SUB_BYTE_PALETTE } else { 256 };
+    if let Some((palette, indices)) = exact_palette(pixels, max_colors) {
+        debug!("Lossless PNG: indexed, {} colors", palette.len() / 4);
+        return write_indexed_png(rgba.width(), rgba.height(), &palette, &indices);
+    }
+
+    let reduced = match (gray, opaque) {
+        (true, true) => DynamicImage::ImageLuma8(img.to_luma8()),
+        (true, false) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        (false, true) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        (false, false) => DynamicImage::ImageRgba8(rgba),
+    };
+    debug!("Lossless PNG: {:?} -> {:?}", img.color(), reduced.color());
+    write_png(&reduced, compression)
+}
+
+/// Full-colour PNG, keeping the image's own layout
+pub(crate) fn write_png(img: &DynamicImage, compression: CompressionType) -> Result<Vec<u8>, String> {
+    use image::ImageEncoder;
+
+    let mut output = Vec::new();
+    image::codecs::png::PngEncoder::new_with_quality(
+        &mut output,
+        compression,
+        image::codecs::png::FilterType::Adaptive,
+    )
+    .write_image(img.as_bytes(), img.width(), img.height(), img.color())
+    .map_err(|e| format!("PNG encoding failed: {}", e))?;
+    Ok(output)
+}
+
+/// Write palette indices as an indexed PNG, packing them below 8 bits when the palette allows
+fn write_indexed_png(width: u32, height: u32, palette: &[u8], indices: &[u8]) -> Result<Vec<u8>, String> {
+    let rgb_palette: Vec<u8> = palette
+        .chunks_exact(4)
+        .flat_map(|c| [c[0], c[1], c[2]])
+        .collect();
+    let mut alpha: Vec<u8> = palette.chunks_exact(4).map(|c| c[3]).collect();
+    // tRNS may omit trailing opaque entries
+    while alpha.last() == Some(&255) {
+        alpha.pop();
+    }
+
+    let colors = palette.len() / 4;
+    let (depth, bits) = match colors {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+    let data = if bits == 8 {
+        indices.to_vec()
+    } else {
+        pack_rows(indices, width as usize, bits)
+    };
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    // Filters rarely help palette data, so rely on the strongest deflate instead
+    encoder.set_compression(png::Compression::Best);
+    encoder.set_filter(png::FilterType::NoFilter);
+    encoder.set_adaptive_filter(png::AdaptiveFilterType::NonAdaptive);
+    encoder.set_palette(rgb_palette);
+    if !alpha.is_empty() {
+        encoder.set_trns(alpha);
+    }
+
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| format!("PNG encoding failed: {}", e))?;
+    writer
+        .write_image_data(&data)
+        .map_err(|e| format!("PNG encoding failed: {}", e))?;
+    writer
+        .finish()
+        .map_err(|e| format!("PNG encoding failed: {}", e))?;
+
+    Ok(output)
+}
+
+/// Pack 8-bit indices into `bits`-wide samples, most significant first, each row padded to a byte
+fn pack_rows(indices: &[u8], width: usize, bits: usize) -> Vec<u8> {
+    let per_byte = 8 / bits;
+    let row_bytes = width.div_ceil(per_byte);
+    let mut packed = Vec::with_capacity(row_bytes * indices.len() / width.max(1));
+
+    for row in indices.chunks(width) {
+        for group in row.chunks(per_byte) {
+            let mut byte = 0u8;
+            for (position, &index) in group.iter().enumerate() {
+                byte |= index << (8 - bits * (position + 1));
+            }
+            packed.push(byte);
+        }
+    }
+    packed
+}
+
+/// Build a palette from the image's own colors if there are at most `max_colors`
+fn exact_palette(pixels: &[u8], max_colors: usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut lookup: AHashMap<[u8; 4], u8> = AHashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(pixels.len() / 4);
+
+    for px in pixels.chunks_exact(4) {
+        let color = [px[0], px[1], px[2], px[3]];
+        let index = match lookup.get(&color) {
+            Some(&index) => index,
+            None => {
+                if lookup.len() >= max_colors {
+                    return None;
+                }
+                let index = lookup.len() as u8;
+                lookup.insert(color, index);
+                palette.extend_from_slice(&color);
+                index
+            }
+        };
+        indices.push(index);
+    }
+
+    Some((palette, indices))
+}
@@ -0,0 +1,97 @@
+// Rebuilding a page tree that loads but leads to no pages
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use log::warn;
+
+/// Page attributes a page inherits from its ancestors when it doesn't set them itself
+const INHERITABLE_KEYS: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Make `doc.get_pages()` find the document's pages when the /Root or /Pages tree is broken
+/// Every /Type /Page object becomes a direct kid of one /Pages node, in object order, keeping
+/// what it inherited from its old parents. Documents whose tree already leads to pages are left
+/// alone; an error means there are no page objects to rebuild from
+pub(crate) fn recover_page_tree(doc: &mut Document) -> Result<(), String> {
+    if !doc.get_pages().is_empty() {
+        return Ok(());
+    }
+
+    let page_ids: Vec<ObjectId> = doc.objects.iter()
+        .filter(|(_, object)| object.as_dict().is_ok_and(|dict| dict.type_is(b"Page")))
+        .map(|(id, _)| *id)
+        .collect();
+    if page_ids.is_empty() {
+        return Err("Failed to load PDF: the page tree leads to no pages and the file has no page objects to rebuild it from".to_string());
+    }
+
+    let catalog_id = match doc.trailer.get(b"Root").and_then(Object::as_reference) {
+        Ok(id) if doc.get_dictionary(id).is_ok() => id,
+        _ => {
+            let id = find_by_type(doc, b"Catalog")
+                .unwrap_or_else(|| doc.add_object(Dictionary::from_iter(vec![("Type", Object::Name(b"Catalog".to_vec()))])));
+            doc.trailer.set("Root", Object::Reference(id));
+            id
+        }
+    };
+    let pages_id = match doc.get_dictionary(catalog_id).and_then(|catalog| catalog.get(b"Pages")).and_then(Object::as_reference) {
+        Ok(id) if doc.get_dictionary(id).is_ok_and(|pages| pages.type_is(b"Pages")) => id,
+        _ => doc.new_object_id(),
+    };
+
+    // Read before reparenting, while the old parent chains are still there
+    let inherited: Vec<(ObjectId, Dictionary)> = page_ids.iter()
+        .map(|page_id| (*page_id, inherited_attributes(doc, *page_id)))
+        .collect();
+    for (page_id, attributes) in inherited {
+        if let Ok(Object::Dictionary(page)) = doc.get_object_mut(page_id) {
+            for (key, value) in attributes.iter() {
+                page.set(key.clone(), value.clone());
+            }
+            page.set("Parent", Object::Reference(pages_id));
+        }
+    }
+
+    let mut pages = match doc.objects.remove(&pages_id) {
+        Some(Object::Dictionary(pages)) => pages,
+        _ => Dictionary::from_iter(vec![("Type", Object::Name(b"Pages".to_vec()))]),
+    };
+    pages.remove(b"Parent");
+    pages.set("Kids", Object::Array(page_ids.iter().map(|id| Object::Reference(*id)).collect()));
+    pages.set("Count", Object::Integer(page_ids.len() as i64));
+    doc.objects.insert(pages_id, Object::Dictionary(pages));
+    if let Ok(Object::Dictionary(catalog)) = doc.get_object_mut(catalog_id) {
+        catalog.set("Pages", Object::Reference(pages_id));
+    }
+
+    warn!("PDF page tree led to no pages, rebuilt it from {} page objects", page_ids.len());
+    Ok(())
+}
+
+fn find_by_type(doc: &Document, type_name: &[u8]) -> Option<ObjectId> {
+    doc.objects.iter()
+        .find(|(_, object)| object.as_dict().is_ok_and(|dict| dict.type_is(type_name)))
+        .map(|(id, _)| *id)
+}
+
+/// Inheritable attributes the page misses but one of its old ancestors sets
+fn inherited_attributes(doc: &Document, page_id: ObjectId) -> Dictionary {
+    let mut attributes = Dictionary::new();
+    let Ok(page) = doc.get_dictionary(page_id) else {
+        return attributes;
+    };
+    let mut missing: Vec<&'static [u8]> = INHERITABLE_KEYS.into_iter().filter(|key| !page.has(key)).collect();
+    let mut parent = page.get(b"Parent").and_then(Object::as_reference).ok();
+    // Bounded by the object count, so a Parent cycle can't loop forever
+    for _ in 0..doc.objects.len() {
+        let Some(Ok(node)) = parent.map(|id| doc.get_dictionary(id)) else {
+            break;
+        };
+        missing.retain(|key| match node.get(key) {
+            Ok(value) => {
+                attributes.set(key.to_vec(), value.clone());
+                false
+            }
+            Err(_) => true,
+        });
+        parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    attributes
+}
@@ -0,0 +1,151 @@
+// Dropping image XObjects, and the Do operators that draw them, for text-only output
+use crate::placement::{decode_content, page_forms, page_xobjects, resolve, xobject_names};
+use crate::CompressionSettings;
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use log::{debug, info};
+use std::collections::{HashMap, HashSet};
+
+/// Delete every image XObject, its entries in XObject resource dictionaries and the `Do`
+/// operators that draw it. Returns the number of images removed
+/// Images drawn by content that can't be parsed (or is protected) are kept, so every remaining
+/// `Do` still finds its image. Inline images are not touched
+pub(crate) fn remove_images(doc: &mut Document, settings: &CompressionSettings) -> usize {
+    let images: HashSet<ObjectId> = doc.objects.iter()
+        .filter(|(id, object)| !settings.protected_objects.contains(id) && is_image(object))
+        .map(|(id, _)| *id)
+        .collect();
+    if images.is_empty() {
+        return 0;
+    }
+
+    // Every content stream, with the XObject names it draws from
+    let mut holders: Vec<(ObjectId, HashMap<Vec<u8>, ObjectId>)> = Vec::new();
+    let mut forms_held = HashSet::new();
+    for page_id in doc.get_pages().into_values() {
+        let xobjects = page_xobjects(doc, page_id);
+        for content_id in doc.get_page_contents(page_id) {
+            holders.push((content_id, xobjects.clone()));
+        }
+        // Nested forms too, including those drawing from their caller's resources
+        for (form_id, names) in page_forms(doc, page_id) {
+            if forms_held.insert(form_id) {
+                holders.push((form_id, names));
+            }
+        }
+    }
+    // Forms no page draws (annotation appearances, say) can only use their own Resources
+    for (id, object) in &doc.objects {
+        let Object::Stream(stream) = object else {
+            continue;
+        };
+        if !matches!(stream.dict.get(b"Subtype"), Ok(Object::Name(name)) if name == b"Form") {
+            continue;
+        }
+        if forms_held.contains(id) {
+            continue;
+        }
+        if let Ok(resources) = stream.dict.get(b"Resources").and_then(|resources| resolve(doc, resources).as_dict()) {
+            holders.push((*id, xobject_names(doc, &[resources])));
+        }
+    }
+
+    let mut kept = HashSet::new();
+    let mut rewrites = Vec::new();
+    for (content_id, xobjects) in holders {
+        let drawn: HashSet<&[u8]> = xobjects.iter()
+            .filter(|(_, id)| images.contains(id))
+            .map(|(name, _)| name.as_slice())
+            .collect();
+        if drawn.is_empty() {
+            continue;
+        }
+        if settings.protected_objects.contains(&content_id) {
+            kept.extend(xobjects.values().filter(|id| images.contains(id)));
+            continue;
+        }
+        match strip_image_draws(doc, content_id, &drawn, settings) {
+            Ok(Some(content)) => rewrites.push((content_id, content)),
+            Ok(None) => {}
+            Err(e) => {
+                debug!("Keeping images drawn by {:?}: {}", content_id, e);
+                kept.extend(xobjects.values().filter(|id| images.contains(id)));
+            }
+        }
+    }
+    // A kept image still needs its soft mask or stencil mask
+    let masks: Vec<ObjectId> = kept.iter()
+        .filter_map(|id| doc.get_object(*id).and_then(Object::as_stream).ok())
+        .flat_map(|stream| [b"SMask".as_slice(), b"Mask"].map(|key| stream.dict.get(key).and_then(Object::as_reference).ok()))
+        .flatten()
+        .collect();
+    kept.extend(masks);
+
+    // Left unfiltered; the generic stream pass deflates the rewritten content afterwards
+    for (content_id, content) in rewrites {
+        if let Ok(Object::Stream(stream)) = doc.get_object_mut(content_id) {
+            stream.dict.remove(b"Filter");
+            stream.dict.remove(b"DecodeParms");
+            stream.set_content(content);
+        }
+    }
+
+    let removed: HashSet<ObjectId> = images.difference(&kept).copied().collect();
+    // XObject resource dictionaries may be inline or objects of their own
+    let xobject_dicts: HashSet<ObjectId> = doc.objects.values()
+        .filter_map(|object| match object {
+            Object::Dictionary(dict) => Some(dict),
+            Object::Stream(stream) => Some(&stream.dict),
+            _ => None,
+        })
+        .filter_map(|dict| dict.get(b"XObject").and_then(Object::as_reference).ok())
+        .collect();
+    for (id, object) in doc.objects.iter_mut() {
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &mut stream.dict,
+            _ => continue,
+        };
+        if xobject_dicts.contains(id) {
+            remove_references(dict, &removed);
+        } else if let Ok(Object::Dictionary(xobjects)) = dict.get_mut(b"XObject") {
+            remove_references(xobjects, &removed);
+        }
+    }
+    doc.objects.retain(|id, _| !removed.contains(id));
+
+    info!("Removed {} images ({} kept for unparseable content)", removed.len(), kept.len());
+    removed.len()
+}
+
+fn is_image(object: &Object) -> bool {
+    matches!(object, Object::Stream(stream) if matches!(stream.dict.get(b"Subtype"), Ok(Object::Name(name)) if name == b"Image"))
+}
+
+/// Drop the entries of `dict` that point at removed objects
+fn remove_references(dict: &mut Dictionary, removed: &HashSet<ObjectId>) {
+    let names: Vec<Vec<u8>> = dict.iter()
+        .filter(|(_, value)| matches!(value, Object::Reference(id) if removed.contains(id)))
+        .map(|(name, _)| name.clone())
+        .collect();
+    for name in names {
+        dict.remove(&name);
+    }
+}
+
+/// Content without the `Do` operators for `drawn`; Ok(None) means it draws none of them
+fn strip_image_draws(doc: &Document, content_id: ObjectId, drawn: &HashSet<&[u8]>, settings: &CompressionSettings) -> Result<Option<Vec<u8>>, String> {
+    let decoded = decode_content(doc, content_id, settings).ok_or("content can't be decoded")?;
+    let mut content = Content::decode(&decoded).map_err(|e| format!("content can't be parsed: {}", e))?;
+    let before = content.operations.len();
+    content.operations.retain(|operation| {
+        operation.operator != "Do"
+            || !operation.operands.first()
+                .and_then(|name| name.as_name().ok())
+                .is_some_and(|name| drawn.contains(name))
+    });
+    if content.operations.len() == before {
+        return Ok(None);
+    }
+    content.encode().map(Some).map_err(|e| format!("content can't be written: {}", e))
+}
@@ -0,0 +1,178 @@
+// Page resource dictionary helpers for passes that add XObjects to pages or leave stale entries
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use log::debug;
+use std::collections::HashSet;
+
+/// Page tree levels followed when looking up inherited resources
+const MAX_INHERIT_DEPTH: usize = 32;
+
+/// Whether a page has a resources dictionary of its own that XObjects can be added to
+/// Inherited resources may be shared with other pages, so they must not be extended for one page
+pub(crate) fn has_own_resources(doc: &Document, page_id: ObjectId) -> bool {
+    match doc.get_dictionary(page_id).and_then(|page| page.get(b"Resources")) {
+        Ok(Object::Reference(id)) => doc.get_dictionary(*id).is_ok(),
+        Ok(Object::Dictionary(_)) => true,
+        _ => false,
+    }
+}
+
+/// The page's resources dictionary, whether stored inline or as its own object
+fn page_resources_mut(doc: &mut Document, page_id: ObjectId) -> Result<&mut Dictionary, String> {
+    let broken = |e: lopdf::Error| format!("Broken resources: {}", e);
+    let resources_ref = match doc.get_dictionary(page_id).and_then(|page| page.get(b"Resources")).map_err(broken)? {
+        Object::Reference(id) => Some(*id),
+        _ => None,
+    };
+    match resources_ref {
+        Some(id) => doc.get_dictionary_mut(id).map_err(broken),
+        None => match doc.get_dictionary_mut(page_id).map_err(broken)?.get_mut(b"Resources") {
+            Ok(Object::Dictionary(resources)) => Ok(resources),
+            _ => Err("Page has no resources dictionary".to_string()),
+        },
+    }
+}
+
+/// Add XObjects to a page's resources under fresh `prefix`-numbered names, returning the names
+pub(crate) fn add_page_xobjects(doc: &mut Document, page_id: ObjectId, ids: Vec<ObjectId>, prefix: &str) -> Result<Vec<Vec<u8>>, String> {
+    let xobjects_ref = {
+        let resources = page_resources_mut(doc, page_id)?;
+        match resources.get(b"XObject") {
+            Ok(Object::Reference(id)) => Some(*id),
+            Ok(Object::Dictionary(_)) => None,
+            _ => {
+                resources.set("XObject", Object::Dictionary(Dictionary::new()));
+                None
+            }
+        }
+    };
+    let xobjects = match xobjects_ref {
+        Some(id) => doc.get_dictionary_mut(id).map_err(|e| format!("Broken XObject dictionary: {}", e))?,
+        None => match page_resources_mut(doc, page_id)?.get_mut(b"XObject") {
+            Ok(Object::Dictionary(dict)) => dict,
+            _ => return Err("Broken XObject dictionary".to_string()),
+        },
+    };
+
+    let mut names = Vec::with_capacity(ids.len());
+    let mut counter = 0;
+    for id in ids {
+        let name = loop {
+            counter += 1;
+            let name = format!("{}{}", prefix, counter).into_bytes();
+            if !xobjects.has(&name) {
+                break name;
+            }
+        };
+        xobjects.set(name.clone(), Object::Reference(id));
+        names.push(name);
+    }
+    Ok(names)
+}
+
+/// Give a page that inherits its resources its own copy, so XObjects can be added to it
+pub(crate) fn ensure_own_resources(doc: &mut Document, page_id: ObjectId) -> Result<(), String> {
+    if has_own_resources(doc, page_id) {
+        return Ok(());
+    }
+    // The nearest ancestor with Resources is the one the page inherits
+    let mut resources = Dictionary::new();
+    let mut node = doc.get_dictionary(page_id).ok();
+    for _ in 0..MAX_INHERIT_DEPTH {
+        let Some(dict) = node else { break };
+        match dict.get(b"Resources") {
+            Ok(Object::Reference(id)) => {
+                resources = doc.get_dictionary(*id).cloned().unwrap_or_default();
+                break;
+            }
+            Ok(Object::Dictionary(inherited)) => {
+                resources = inherited.clone();
+                break;
+            }
+            _ => node = dict.get(b"Parent").and_then(Object::as_reference).and_then(|id| doc.get_dictionary(id)).ok(),
+        }
+    }
+    doc.get_dictionary_mut(page_id)
+        .map_err(|e| format!("Broken page: {}", e))?
+        .set("Resources", Object::Dictionary(resources));
+    Ok(())
+}
+
+/// Resource categories whose entries name other objects
+const RESOURCE_CATEGORIES: [&[u8]; 7] = [b"XObject", b"Font", b"ExtGState", b"ColorSpace", b"Pattern", b"Shading", b"Properties"];
+
+/// Drop resource entries that reference objects no longer in the document, in page, page tree
+/// and form XObject resources alike, protected objects excepted. Returns the number of entries removed
+/// Broken inputs and passes that delete objects directly can leave such names behind, and
+/// strict readers flag them even when no content uses the name
+pub(crate) fn prune_dangling_resources(doc: &mut Document, protected: &HashSet<ObjectId>) -> usize {
+    let existing: HashSet<ObjectId> = doc.objects.keys().copied().collect();
+
+    // Resources and their category dictionaries may each be inline or objects of their own
+    let resources_ids: HashSet<ObjectId> = doc.objects.values()
+        .filter_map(object_dict)
+        .filter_map(|dict| dict.get(b"Resources").and_then(Object::as_reference).ok())
+        .collect();
+    let category_ids: HashSet<ObjectId> = doc.objects.iter()
+        .filter_map(|(id, object)| {
+            let dict = object_dict(object)?;
+            match dict.get(b"Resources") {
+                Ok(Object::Dictionary(resources)) => Some(resources),
+                _ if resources_ids.contains(id) => Some(dict),
+                _ => None,
+            }
+        })
+        .flat_map(|resources| RESOURCE_CATEGORIES.iter().filter_map(|key| resources.get(key).and_then(Object::as_reference).ok()))
+        .collect();
+
+    let mut removed = 0;
+    for (id, object) in doc.objects.iter_mut() {
+        if protected.contains(id) {
+            continue;
+        }
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &mut stream.dict,
+            _ => continue,
+        };
+        if category_ids.contains(id) {
+            removed += remove_dangling(dict, &existing);
+        }
+        if resources_ids.contains(id) {
+            removed += prune_categories(dict, &existing);
+        }
+        if let Ok(Object::Dictionary(resources)) = dict.get_mut(b"Resources") {
+            removed += prune_categories(resources, &existing);
+        }
+    }
+    removed
+}
+
+fn object_dict(object: &Object) -> Option<&Dictionary> {
+    match object {
+        Object::Dictionary(dict) => Some(dict),
+        Object::Stream(stream) => Some(&stream.dict),
+        _ => None,
+    }
+}
+
+/// Drop dangling entries from the inline category dictionaries of a resources dictionary
+fn prune_categories(resources: &mut Dictionary, existing: &HashSet<ObjectId>) -> usize {
+    RESOURCE_CATEGORIES.iter()
+        .map(|key| match resources.get_mut(key) {
+            Ok(Object::Dictionary(category)) => remove_dangling(category, existing),
+            _ => 0,
+        })
+        .sum()
+}
+
+fn remove_dangling(dict: &mut Dictionary, existing: &HashSet<ObjectId>) -> usize {
+    let names: Vec<Vec<u8>> = dict.iter()
+        .filter(|(_, value)| matches!(value, Object::Reference(id) if !existing.contains(id)))
+        .map(|(name, _)| name.clone())
+        .collect();
+    for name in &names {
+        debug!("Dropping resource /{} pointing at a missing object", String::from_utf8_lossy(name));
+        dict.remove(name);
+    }
+    names.len()
+}
@@ -0,0 +1,101 @@
+// Splitting a PDF into one document per page
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use log::{debug, info};
+use std::collections::HashSet;
+
+use crate::compress_pdf_bytes;
+use crate::merge::inherited_attributes;
+use crate::xref::load_document;
+
+/// Extract every page into its own single-page PDF and compress each one independently
+/// Each document carries only the objects its page references, so shared resources are
+/// repeated in every page that uses them
+pub fn split_and_compress(input: &[u8], compression_level: u8) -> Result<Vec<Vec<u8>>, String> {
+    let doc = load_document(input)?;
+    let pages = doc.get_pages();
+    if pages.is_empty() {
+        return Err("No pages found".to_string());
+    }
+
+    // Other pages and the page tree are never copied; references to them (links, /P) end up dangling
+    let page_tree: HashSet<ObjectId> = doc.objects.iter()
+        .filter(|(_, object)| matches!(
+            object.as_dict().and_then(|dict| dict.get(b"Type")).and_then(Object::as_name),
+            Ok(b"Page" | b"Pages")
+        ))
+        .map(|(id, _)| *id)
+        .chain(pages.values().copied())
+        .collect();
+
+    let outputs = pages.iter()
+        .map(|(number, page_id)| {
+            let page = extract_page(&doc, *page_id, &page_tree)
+                .map_err(|e| format!("Page {}: {}", number, e))?;
+            compress_pdf_bytes(&page, compression_level)
+                .map_err(|e| format!("Page {}: {}", number, e))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    info!("Split {} pages into separate documents: {} bytes total", outputs.len(), outputs.iter().map(Vec::len).sum::<usize>());
+    Ok(outputs)
+}
+
+/// Save a page as a single-page PDF holding the page and everything reachable from it
+fn extract_page(doc: &Document, page_id: ObjectId, page_tree: &HashSet<ObjectId>) -> Result<Vec<u8>, String> {
+    let mut page = doc.get_dictionary(page_id)
+        .map_err(|e| format!("Broken page: {}", e))?
+        .clone();
+    // The page leaves its tree, so copy down anything it inherited
+    for (key, value) in inherited_attributes(doc, page_id) {
+        page.set(key, value);
+    }
+    page.remove(b"Parent");
+
+    let mut single = Document::with_version(&doc.version);
+    let mut pending = Vec::new();
+    page.iter().for_each(|(_, value)| collect_references(value, &mut pending));
+    while let Some(id) = pending.pop() {
+        if page_tree.contains(&id) || single.objects.contains_key(&id) {
+            continue;
+        }
+        let Ok(object) = doc.get_object(id) else {
+            continue;
+        };
+        collect_references(object, &mut pending);
+        single.objects.insert(id, object.clone());
+    }
+    debug!("Page {:?} references {} objects", page_id, single.objects.len());
+
+    single.max_id = doc.max_id;
+    let pages_id = single.new_object_id();
+    page.set("Parent", Object::Reference(pages_id));
+    single.objects.insert(page_id, Object::Dictionary(page));
+    single.objects.insert(
+        pages_id,
+        Object::Dictionary(Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(vec![Object::Reference(page_id)])),
+            ("Count", Object::Integer(1)),
+        ])),
+    );
+    let catalog_id = single.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Catalog".to_vec())),
+        ("Pages", Object::Reference(pages_id)),
+    ]));
+    single.trailer.set("Root", Object::Reference(catalog_id));
+
+    let mut output = Vec::new();
+    single.save_to(&mut output)
+        .map_err(|e| format!("Failed to save page: {}", e))?;
+    Ok(output)
+}
+
+fn collect_references(object: &Object, references: &mut Vec<ObjectId>) {
+    match object {
+        Object::Reference(id) => references.push(*id),
+        Object::Array(items) => items.iter().for_each(|item| collect_references(item, references)),
+        Object::Dictionary(dict) => dict.iter().for_each(|(_, value)| collect_references(value, references)),
+        Object::Stream(stream) => stream.dict.iter().for_each(|(_, value)| collect_references(value, references)),
+        _ => {}
+    }
+}
@@ -0,0 +1,141 @@
+// Cheap checks that an upload is worth sending, without loading the whole file
+use image::io::Reader as ImageReader;
+use lopdf::xref::{Xref, XrefEntry, XrefType};
+use lopdf::{Document, Object, ObjectId, Reader};
+use serde::Serialize;
+use std::io::Cursor;
+
+use crate::detect::{detect_input_type, pdf_header_offset, InputType};
+
+/// How far from the end of a PDF `%%EOF` and `startxref` are searched for
+const TRAILER_SEARCH_BYTES: usize = 1024;
+
+/// What a valid input holds, with the metadata that was cheap to read
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum InputKind {
+    Pdf {
+        version: String,
+        pages: Option<u32>, // None when the page tree sits in a compressed object stream
+    },
+    Image {
+        format: &'static str, // File extension, as in `supported_formats`
+        width: u32,
+        height: u32,
+    },
+}
+
+/// Check that `bytes` is a PDF or image this crate can compress, without a full parse or decode
+/// PDFs need a header, a `%%EOF`-terminated trailer whose `startxref` points at a cross-reference
+/// section, and a /Root; images need a header their decoder can read dimensions from
+pub fn validate_input(bytes: &[u8]) -> Result<InputKind, String> {
+    if bytes.is_empty() {
+        return Err("Input is empty".to_string());
+    }
+    match detect_input_type(bytes, None) {
+        InputType::Pdf => validate_pdf(bytes),
+        InputType::Image(format) => {
+            let (width, height) = ImageReader::with_format(Cursor::new(bytes), format)
+                .into_dimensions()
+                .map_err(|e| format!("Corrupt {:?} image: {}", format, e))?;
+            Ok(InputKind::Image {
+                format: format.extensions_str().first().copied().unwrap_or("image"),
+                width,
+                height,
+            })
+        }
+        InputType::Unknown => Err("Unsupported input: neither a PDF nor a readable image".to_string()),
+    }
+}
+
+fn validate_pdf(bytes: &[u8]) -> Result<InputKind, String> {
+    let header = pdf_header_offset(bytes).ok_or("Not a PDF: no %PDF- header in the first 1 KB")?;
+    let version: String = bytes[header + 5..].iter()
+        .take_while(|byte| byte.is_ascii_digit() || **byte == b'.')
+        .map(|byte| *byte as char)
+        .collect();
+    if version.is_empty() {
+        return Err("Corrupt PDF: the header has no version".to_string());
+    }
+
+    let tail_start = bytes.len().saturating_sub(TRAILER_SEARCH_BYTES);
+    let tail = &bytes[tail_start..];
+    let eof = rfind(tail, b"%%EOF").ok_or("Corrupt PDF: no %%EOF marker at the end, the file may be truncated")?;
+    let startxref = rfind(&tail[..eof], b"startxref").ok_or("Corrupt PDF: no startxref before %%EOF")?;
+    let xref_offset = leading_integer(&tail[startxref + b"startxref".len()..eof])
+        .ok_or("Corrupt PDF: startxref is not followed by an offset")?;
+
+    // Some writers count offsets from the header rather than the start of the file
+    let xref_start = [Some(xref_offset), xref_offset.checked_add(header)].into_iter().flatten()
+        .find(|offset| bytes.get(*offset..).is_some_and(|rest| rest.starts_with(b"xref") || object_header(rest).is_some()))
+        .ok_or_else(|| format!("Corrupt PDF: startxref points at byte {}, which holds no cross-reference section", xref_offset))?;
+
+    // The trailer follows a classic table; a cross-reference stream carries the same keys in its dictionary
+    let trailer_end = tail_start + startxref;
+    let root = bytes.get(xref_start..trailer_end)
+        .and_then(|trailer| find(trailer, b"/Root"))
+        .and_then(|position| reference_at(&bytes[xref_start + position + b"/Root".len()..trailer_end]))
+        .ok_or("Corrupt PDF: the trailer has no /Root")?;
+
+    Ok(InputKind::Pdf { version, pages: page_count(bytes, root) })
+}
+
+/// /Count of the page tree, read from the catalog and root Pages objects only
+fn page_count(bytes: &[u8], root: ObjectId) -> Option<u32> {
+    let mut reader = Reader { buffer: bytes, document: Document::new() };
+    reader.document.reference_table = Xref::new(0, XrefType::CrossReferenceTable);
+    let mut load = |id: ObjectId| -> Option<Object> {
+        let offset = find_object(bytes, id)?;
+        reader.document.reference_table.insert(id.0, XrefEntry::Normal { offset: offset as u32, generation: id.1 });
+        reader.get_object(id).ok()
+    };
+
+    let catalog = load(root)?;
+    let pages = catalog.as_dict().ok()?.get(b"Pages").and_then(Object::as_reference).ok()?;
+    let count = load(pages)?.as_dict().ok()?.get(b"Count").and_then(Object::as_i64).ok()?;
+    u32::try_from(count).ok()
+}
+
+/// Offset of the last `id gen obj` header, so incremental updates win over the original
+fn find_object(bytes: &[u8], id: ObjectId) -> Option<usize> {
+    let pattern = format!("{} {} obj", id.0, id.1);
+    let mut end = bytes.len();
+    while let Some(position) = rfind(&bytes[..end], pattern.as_bytes()) {
+        // "14 0 obj" also contains "4 0 obj"
+        if position == 0 || bytes[position - 1].is_ascii_whitespace() {
+            return Some(position);
+        }
+        end = position + pattern.len() - 1;
+    }
+    None
+}
+
+/// `id gen obj` at the start of `bytes`, as a cross-reference stream begins
+fn object_header(bytes: &[u8]) -> Option<ObjectId> {
+    let mut tokens = bytes.split(u8::is_ascii_whitespace).filter(|token| !token.is_empty());
+    let id = std::str::from_utf8(tokens.next()?).ok()?.parse().ok()?;
+    let generation = std::str::from_utf8(tokens.next()?).ok()?.parse().ok()?;
+    tokens.next()?.starts_with(b"obj").then_some((id, generation))
+}
+
+/// `id gen R` at the start of `bytes`
+fn reference_at(bytes: &[u8]) -> Option<ObjectId> {
+    let mut tokens = bytes.split(|byte| byte.is_ascii_whitespace() || *byte == b'/' || *byte == b'>')
+        .filter(|token| !token.is_empty());
+    let id = std::str::from_utf8(tokens.next()?).ok()?.parse().ok()?;
+    let generation = std::str::from_utf8(tokens.next()?).ok()?.parse().ok()?;
+    (tokens.next()? == b"R").then_some((id, generation))
+}
+
+fn leading_integer(bytes: &[u8]) -> Option<usize> {
+    let digits = bytes.split(u8::is_ascii_whitespace).find(|token| !token.is_empty())?;
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|window| window == needle)
+}
@@ -0,0 +1,92 @@
+// Structural check that a compressed PDF still has the pages of its input
+use crate::xref::load_document;
+use lopdf::{Document, Object, ObjectId};
+use std::cmp::Ordering;
+
+/// Page tree levels followed when looking up an inherited MediaBox
+const MAX_INHERIT_DEPTH: usize = 32;
+/// MediaBox coordinates closer than this are treated as equal (reals are rewritten on save)
+const MEDIA_BOX_TOLERANCE: f32 = 0.01;
+
+/// Effective MediaBox of every page, in page order
+pub(crate) fn page_layout(doc: &Document) -> Vec<Option<[f32; 4]>> {
+    doc.get_pages().into_values().map(|page_id| media_box(doc, page_id)).collect()
+}
+
+/// Reload compressed output and compare its pages against the input's layout
+pub(crate) fn verify_output(expected: &[Option<[f32; 4]>], output: &[u8]) -> Result<(), String> {
+    let doc = Document::load_mem(output)
+        .map_err(|e| format!("Verification failed: output does not parse: {}", e))?;
+    compare_layouts(expected, &page_layout(&doc)).map_err(|e| format!("Verification failed: {}", e))
+}
+
+/// Check that two page layouts agree in page count and MediaBoxes
+fn compare_layouts(expected: &[Option<[f32; 4]>], actual: &[Option<[f32; 4]>]) -> Result<(), String> {
+    if actual.len() != expected.len() {
+        return Err(format!("page count changed from {} to {}", expected.len(), actual.len()));
+    }
+    for (index, (before, after)) in expected.iter().zip(actual).enumerate() {
+        let same = match (before, after) {
+            (Some(before), Some(after)) => before.iter().zip(after).all(|(a, b)| (a - b).abs() <= MEDIA_BOX_TOLERANCE),
+            (None, None) => true,
+            _ => false,
+        };
+        if !same {
+            return Err(format!("page {} MediaBox changed from {:?} to {:?}", index + 1, before, after));
+        }
+    }
+    Ok(())
+}
+
+/// Check that `compressed` has the same page count and MediaBoxes as `original`
+pub fn verify_pdf_structure(original: &[u8], compressed: &[u8]) -> Result<(), String> {
+    let doc = load_document(original)?;
+    verify_output(&page_layout(&doc), compressed)
+}
+
+/// Compare two copies of the same document by size, once both are known to be intact
+/// Greater means `a` is the better-compressed (smaller) copy, Less means `b` is. Fails if either
+/// doesn't load or their page counts or MediaBoxes differ, so a smaller broken file never wins
+pub fn is_better_compressed(a: &[u8], b: &[u8]) -> Result<Ordering, String> {
+    let layout_a = page_layout(&load_document(a).map_err(|e| format!("First PDF: {}", e))?);
+    let layout_b = page_layout(&load_document(b).map_err(|e| format!("Second PDF: {}", e))?);
+    compare_layouts(&layout_a, &layout_b)
+        .map_err(|e| format!("PDFs are not equivalent: {}", e))?;
+    Ok(b.len().cmp(&a.len()))
+}
+
+/// MediaBox of a page, inherited from its ancestors if the page doesn't set one
+pub(crate) fn media_box(doc: &Document, page_id: ObjectId) -> Option<[f32; 4]> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    for _ in 0..MAX_INHERIT_DEPTH {
+        if let Ok(media_box) = node.get(b"MediaBox") {
+            return rectangle(doc, media_box);
+        }
+        let parent = node.get(b"Parent").ok()?.as_reference().ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
+    None
+}
+
+fn rectangle(doc: &Document, object: &Object) -> Option<[f32; 4]> {
+    let resolve = |object: &Object| match object {
+        Object::Reference(id) => doc.get_object(*id).ok().cloned(),
+        other => Some(other.clone()),
+    };
+    let Object::Array(items) = resolve(object)? else {
+        return None;
+    };
+    if items.len() != 4 {
+        return None;
+    }
+
+    let mut rect = [0.0; 4];
+    for (value, item) in rect.iter_mut().zip(&items) {
+        *value = match resolve(item)? {
+            Object::Integer(n) => n as f32,
+            Object::Real(n) => n,
+            _ => return None,
+        };
+    }
+    Some(rect)
+}
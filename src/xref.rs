@@ -0,0 +1,164 @@
+// Loading and saving across classic, stream and hybrid cross-reference layouts
+use crate::detect::pdf_header_offset;
+use lopdf::xref::XrefType;
+use lopdf::{Document, Object};
+use log::{debug, warn};
+use std::any::Any;
+use std::cell::RefCell;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// Trailer keys that describe the input's byte layout, which no longer applies once the document is rewritten
+const LAYOUT_TRAILER_KEYS: [&[u8]; 7] = [b"XRefStm", b"Prev", b"Filter", b"DecodeParms", b"Length", b"W", b"Index"];
+
+/// How long loading may take when the caller has no settings of its own
+pub(crate) const LOAD_TIMEOUT_MS: u64 = 10_000;
+
+/// Load a PDF, including objects a hybrid-reference file lists only in its cross-reference stream
+/// Object and cross-reference streams are dropped once loaded: their objects are already expanded
+/// and the writer regenerates the layout
+pub(crate) fn load_document(input: &[u8]) -> Result<Document, String> {
+    load_document_within(input, Duration::from_millis(LOAD_TIMEOUT_MS))
+}
+
+thread_local! {
+    static LOAD_GUARD: RefCell<Option<Arc<dyn Any + Send + Sync>>> = const { RefCell::new(None) };
+}
+
+/// Run `work` with `guard` kept alive by every loader thread it starts until that thread exits
+/// A load that times out leaves its parser running, so the API hands its job permit over here
+/// to keep the slot taken until the parser has really stopped
+#[cfg(feature = "server")]
+pub(crate) fn with_load_guard<T>(guard: Arc<dyn Any + Send + Sync>, work: impl FnOnce() -> T) -> T {
+    let previous = LOAD_GUARD.with(|current| current.replace(Some(guard)));
+    let result = work();
+    LOAD_GUARD.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Load a borrowed PDF on a worker thread, giving up after `timeout`
+/// The worker may outlive the borrow, so it gets a copy; see [`load_shared_document_within`]
+pub(crate) fn load_document_within(input: &[u8], timeout: Duration) -> Result<Document, String> {
+    load_shared_document_within(Arc::new(input.to_vec()), timeout)
+}
+
+/// Load a PDF on a worker thread that shares `input` instead of copying it, giving up after `timeout`
+/// Malformed files can make the parser spin; it can't be interrupted, so a load that times out
+/// keeps its thread, the input and any guard from [`with_load_guard`] until the parser returns
+/// A parser panic is an error too
+pub(crate) fn load_shared_document_within(input: Arc<Vec<u8>>, timeout: Duration) -> Result<Document, String> {
+    let guard = LOAD_GUARD.with(|current| current.borrow().clone());
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name("pdf-load".to_string())
+        .spawn(move || {
+            let _guard = guard;
+            let result = match load_pool() {
+                Some(pool) => pool.install(|| parse_document(&input)),
+                None => parse_document(&input),
+            };
+            // The receiver is gone once the caller gave up, nobody needs the result then
+            let _ = sender.send(result);
+        })
+        .map_err(|e| format!("Failed to load PDF: could not start loader thread: {}", e))?;
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            warn!("Gave up loading PDF after {} ms", timeout.as_millis());
+            Err(format!("Failed to load PDF: gave up after {} ms", timeout.as_millis()))
+        }
+        Err(RecvTimeoutError::Disconnected) => Err("Failed to load PDF: parser crashed".to_string()),
+    }
+}
+
+/// Pool for lopdf's parallel object parsing
+/// Callers are often global-pool workers blocked on the result, which would leave the parser no threads there
+fn load_pool() -> Option<&'static rayon::ThreadPool> {
+    static POOL: OnceLock<Option<rayon::ThreadPool>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        rayon::ThreadPoolBuilder::new()
+            .thread_name(|index| format!("pdf-load-{}", index))
+            .build()
+            .map_err(|e| warn!("Failed to build PDF load pool, parsing on the global pool: {}", e))
+            .ok()
+    })
+    .as_ref()
+}
+
+fn parse_document(input: &[u8]) -> Result<Document, String> {
+    // Offsets count from the header, so anything in front of it is cut off first
+    let input = match pdf_header_offset(input) {
+        Some(offset) if offset > 0 => {
+            debug!("Skipping {} bytes before the PDF header", offset);
+            &input[offset..]
+        }
+        _ => input,
+    };
+    let mut doc = Document::load_mem(input).map_err(|e| format!("Failed to load PDF: {}", e))?;
+
+    // lopdf only follows XRefStm while walking /Prev, so a single-section hybrid file keeps it here
+    if let Ok(offset) = doc.trailer.get(b"XRefStm").and_then(Object::as_i64) {
+        merge_xref_stream_objects(&mut doc, input, offset);
+    }
+
+    doc.objects.retain(|_, object| match object {
+        Object::Stream(stream) => !stream.dict.type_is(b"ObjStm") && !stream.dict.type_is(b"XRef"),
+        _ => true,
+    });
+    crate::recover::recover_page_tree(&mut doc)?;
+    Ok(doc)
+}
+
+/// Add the objects listed in a hybrid file's cross-reference stream that the classic table omits
+fn merge_xref_stream_objects(doc: &mut Document, input: &[u8], offset: i64) {
+    let Some(redirected) = with_startxref(input, offset) else {
+        warn!("Hybrid-reference PDF has no startxref to redirect, objects only in its XRefStm are skipped");
+        return;
+    };
+    let stream_doc = match Document::load_mem(&redirected) {
+        Ok(stream_doc) => stream_doc,
+        Err(e) => {
+            warn!("Failed to read XRefStm of hybrid-reference PDF: {}", e);
+            return;
+        }
+    };
+
+    let mut added = 0;
+    for (id, object) in stream_doc.objects {
+        if let std::collections::btree_map::Entry::Vacant(entry) = doc.objects.entry(id) {
+            entry.insert(object);
+            added += 1;
+        }
+    }
+    doc.max_id = doc.max_id.max(stream_doc.max_id);
+    debug!("Hybrid-reference PDF: added {} objects from its XRefStm", added);
+}
+
+/// A copy of the file whose final startxref points at `offset`
+/// startxref is the last thing in the file, so no other offsets move
+fn with_startxref(input: &[u8], offset: i64) -> Option<Vec<u8>> {
+    const KEYWORD: &[u8] = b"startxref";
+    let keyword = input.windows(KEYWORD.len()).rposition(|window| window == KEYWORD)?;
+    let digits_start = keyword + KEYWORD.len() + input[keyword + KEYWORD.len()..].iter().position(u8::is_ascii_digit)?;
+    let digits_end = digits_start + input[digits_start..].iter().take_while(|byte| byte.is_ascii_digit()).count();
+
+    let mut output = Vec::with_capacity(input.len());
+    output.extend_from_slice(&input[..digits_start]);
+    output.extend_from_slice(offset.to_string().as_bytes());
+    output.extend_from_slice(&input[digits_end..]);
+    Some(output)
+}
+
+/// Remove trailer entries left over from the input's cross-reference layout before saving
+/// A stale XRefStm or Prev offset would send readers into the middle of the rewritten file
+pub(crate) fn reset_trailer_layout(doc: &mut Document) {
+    for key in LAYOUT_TRAILER_KEYS {
+        doc.trailer.remove(key);
+    }
+    if matches!(doc.reference_table.cross_reference_type, XrefType::CrossReferenceTable) {
+        doc.trailer.remove(b"Type");
+    }
+}
@@ -1,7 +1,14 @@
+#![cfg(feature = "server")]
+
 mod common;
 
 use common::*;
-use PDFcompressor::{compress_pdf_bytes, compress_image_bytes};
+use PDFcompressor::{compress_pdf_bytes, compress_image_bytes, reduction_percentage};
+use PDFcompressor::api::{create_router_with_state, serve_with_shutdown, ApiKeys, AppState};
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::response::Response;
+use tower::ServiceExt;
 
 // Note: These tests verify the compression functionality used by the API.
 // For full end-to-end API testing with HTTP, authentication, etc., use:
@@ -27,19 +34,12 @@ fn test_api_pdf_compression_workflow() {
     
     // Verify output is valid PDF
     assert!(compressed.starts_with(b"%PDF"), "Output should be valid PDF");
-    assert!(compressed.len() > 0, "Compressed data should not be empty");
+    assert!(!compressed.is_empty(), "Compressed data should not be empty");
     
     // API would return this data with appropriate headers
     let original_size = pdf_data.len();
     let compressed_size = compressed.len();
-    let reduction = if original_size > 0 && compressed_size <= original_size {
-        ((original_size - compressed_size) as f64 / original_size as f64) * 100.0
-    } else if original_size > 0 {
-        // Negative reduction (file got bigger)
-        -((compressed_size - original_size) as f64 / original_size as f64) * 100.0
-    } else {
-        0.0
-    };
+    let reduction = reduction_percentage(original_size as u64, compressed_size as u64);
     
     println!("API PDF workflow: {} -> {} bytes ({:.1}% reduction)", 
              original_size, compressed_size, reduction);
@@ -58,7 +58,7 @@ fn test_api_image_compression_workflow() {
     let (compressed, format) = result.unwrap();
     
     // Verify output
-    assert!(compressed.len() > 0, "Compressed data should not be empty");
+    assert!(!compressed.is_empty(), "Compressed data should not be empty");
     assert!(format == "jpg" || format == "png", "Should return valid format");
     
     let original_size = image_data.len();
@@ -184,7 +184,7 @@ fn test_api_image_format_conversion_jpeg() {
     
     let (compressed, format) = result.unwrap();
     assert_eq!(format, "jpg", "Should convert to requested format");
-    assert!(compressed.len() > 0);
+    assert!(!compressed.is_empty());
 }
 
 #[test]
@@ -196,7 +196,7 @@ fn test_api_image_format_conversion_png() {
     
     let (compressed, format) = result.unwrap();
     assert_eq!(format, "png");
-    assert!(compressed.len() > 0);
+    assert!(!compressed.is_empty());
 }
 
 #[test]
@@ -227,19 +227,13 @@ fn test_api_response_metadata() {
     let compressed_size = compressed.len();
     
     // API would include these in headers
-    let reduction_percentage = if original_size > 0 && compressed_size <= original_size {
-        ((original_size - compressed_size) as f64 / original_size as f64) * 100.0
-    } else if original_size > 0 {
-        // Can be negative if compression makes file bigger
-        -((compressed_size - original_size) as f64 / original_size as f64) * 100.0
-    } else {
-        0.0
-    };
+    // Can be negative if compression makes file bigger
+    let reduction = reduction_percentage(original_size as u64, compressed_size as u64);
     
     assert!(original_size > 0);
     assert!(compressed_size > 0);
     // Reduction can be negative (file got bigger)
-    println!("Reduction: {:.1}%", reduction_percentage);
+    println!("Reduction: {:.1}%", reduction);
 }
 
 // ============================================================================
@@ -298,11 +292,9 @@ fn test_api_large_image_handling() {
 #[test]
 fn test_api_batch_processing() {
     // Simulate API handling multiple files in sequence
-    let files = vec![
-        generate_minimal_pdf(),
+    let files = [generate_minimal_pdf(),
         generate_pdf_with_image(),
-        generate_jpeg_image(300, 200),
-    ];
+        generate_jpeg_image(300, 200)];
     
     let mut results = Vec::new();
     
@@ -377,3 +369,889 @@ fn test_api_default_compression_rounds() {
     
     assert!(result.is_ok(), "Should work with default compression rounds");
 }
+
+// ============================================================================
+// HTTP Router Tests
+// ============================================================================
+
+async fn post_multipart(state: AppState, uri: &str, fields: &[(&str, Option<&str>, &[u8])]) -> Response {
+    let (content_type, body) = multipart_body(fields);
+    let request = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("Content-Type", content_type)
+        .body(Body::from(body))
+        .unwrap();
+    
+    create_router_with_state(state).oneshot(request).await.unwrap()
+}
+
+async fn body_bytes(response: Response) -> Vec<u8> {
+    axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()
+}
+
+#[tokio::test]
+async fn test_http_compress_pdf() {
+    let pdf = generate_pdf_with_large_content(2000);
+    let response = post_multipart(AppState::new(2), "/api/compress", &[("file", Some("doc.pdf"), &pdf)]).await;
+    
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "application/pdf");
+    assert!(body_bytes(response).await.starts_with(b"%PDF"));
+}
+
+#[tokio::test]
+async fn test_http_busy_returns_503_when_permits_exhausted() {
+    let state = AppState::new(2);
+    let _held = state.job_permits.clone().try_acquire_many_owned(2).unwrap();
+    
+    let pdf = generate_pdf_with_large_content(2000);
+    let response = post_multipart(state.clone(), "/api/compress", &[("file", Some("doc.pdf"), &pdf)]).await;
+    
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.headers().contains_key("Retry-After"));
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert!(body["error"].as_str().unwrap().contains("busy"));
+    
+    // Releasing the permits makes the server accept work again
+    drop(_held);
+    let response = post_multipart(state, "/api/compress", &[("file", Some("doc.pdf"), &pdf)]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_http_images_to_pdf() {
+    let first = generate_jpeg_image(200, 100);
+    let second = generate_png_image(100, 100);
+    let response = post_multipart(AppState::new(2), "/api/images-to-pdf", &[
+        ("file", Some("a.jpg"), &first),
+        ("file", Some("b.png"), &second),
+        ("level", None, b"60"),
+    ]).await;
+    
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Page-Count"], "2");
+    let doc = lopdf::Document::load_mem(&body_bytes(response).await).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+}
+
+// The borderline fixture is tuned to the image crate's JPEG sizes; mozjpeg output is smaller
+#[cfg(not(feature = "mozjpeg"))]
+#[tokio::test]
+async fn test_http_prefer_lossless_controls_format() {
+    let png = generate_borderline_png_image();
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("img.png"), &png),
+        ("prefer_lossless", None, b"1.0"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "image/jpeg");
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("img.png"), &png),
+        ("prefer_lossless", None, b"2.0"),
+    ]).await;
+    assert_eq!(response.headers()["Content-Type"], "image/png");
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("img.png"), &png),
+        ("prefer_lossless", None, b"lots"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_http_quantize_png_is_opt_in() {
+    let png = generate_borderline_png_image();
+    let original = image::load_from_memory(&png).unwrap().to_rgb8();
+    let post = |quantize: &'static [u8]| {
+        let png = png.clone();
+        async move {
+            post_multipart(AppState::new(2), "/api/compress", &[
+                ("file", Some("img.png"), &png),
+                ("format", None, b"png"),
+                ("quantize_png", None, quantize),
+            ]).await
+        }
+    };
+    
+    let response = post(b"false").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let lossless = image::load_from_memory(&body_bytes(response).await).unwrap().to_rgb8();
+    assert_eq!(lossless, original, "PNG output should stay lossless by default");
+    
+    let response = post(b"true").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let quantized = image::load_from_memory(&body_bytes(response).await).unwrap().to_rgb8();
+    assert_eq!(quantized.dimensions(), original.dimensions());
+    assert_ne!(quantized, original, "quantize_png should reduce the image to a smaller palette");
+    
+    assert_eq!(post(b"maybe").await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_http_jpeg_quality_overrides_level_mapping() {
+    let jpeg = generate_jpeg_image(400, 300);
+    let post = |jpeg_quality: Option<&'static [u8]>| {
+        let jpeg = jpeg.clone();
+        async move {
+            let mut fields: Vec<(&str, Option<&str>, &[u8])> = vec![
+                ("file", Some("photo.jpg"), &jpeg),
+                ("level", None, b"75"),
+                ("format", None, b"jpg"),
+            ];
+            if let Some(quality) = jpeg_quality {
+                fields.push(("jpeg_quality", None, quality));
+            }
+            let response = post_multipart(AppState::new(2), "/api/compress", &fields).await;
+            (response.status(), body_bytes(response).await)
+        }
+    };
+    
+    let (status, by_level) = post(None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, exact) = post(Some(b"85")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(exact.len() > by_level.len(),
+            "Quality 85 ({} bytes) should be larger than level 75 ({} bytes)", exact.len(), by_level.len());
+    
+    // Level 75 maps to quality 50, so asking for 50 directly gives the same bytes
+    let (_, same) = post(Some(b"50")).await;
+    assert_eq!(same, by_level);
+    
+    let (status, _) = post(Some(b"0")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_http_min_jpeg_quality_floors_level_mapping() {
+    let jpeg = generate_jpeg_image(400, 300);
+    let post = |fields: &'static [(&'static str, &'static [u8])]| {
+        let jpeg = jpeg.clone();
+        async move {
+            let mut all: Vec<(&str, Option<&str>, &[u8])> = vec![
+                ("file", Some("photo.jpg"), &jpeg),
+                ("level", None, b"95"),
+                ("format", None, b"jpg"),
+            ];
+            all.extend(fields.iter().map(|(name, value)| (*name, None, *value)));
+            let response = post_multipart(AppState::new(2), "/api/compress", &all).await;
+            (response.status(), body_bytes(response).await)
+        }
+    };
+    
+    // Level 95 maps to quality 25, well below the floor
+    let (status, floored) = post(&[("min_jpeg_quality", b"40")]).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, exact) = post(&[("jpeg_quality", b"40")]).await;
+    assert_eq!(floored, exact);
+    let (_, by_level) = post(&[]).await;
+    assert!(floored.len() > by_level.len(),
+            "Floored output ({} bytes) should be larger than level 95 ({} bytes)", floored.len(), by_level.len());
+    
+    let (status, _) = post(&[("min_jpeg_quality", b"60"), ("max_jpeg_quality", b"50")]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_http_oversized_text_field_is_rejected() {
+    let jpeg = generate_jpeg_image(100, 100);
+    let format = vec![b'j'; 64 * 1024];
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("photo.jpg"), &jpeg),
+        ("format", None, &format),
+    ]).await;
+    
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert!(body["error"].as_str().unwrap().contains("too long"), "Unexpected error: {}", body);
+}
+
+#[tokio::test]
+async fn test_http_unsupported_upload_type_is_415() {
+    // Local file header of an empty ZIP entry
+    let mut zip = b"PK\x03\x04\x14\x00\x00\x00\x00\x00".to_vec();
+    zip.resize(64, 0);
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("archive.zip"), &zip),
+    ]).await;
+    
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    let error = body["error"].as_str().unwrap();
+    assert!(error.contains("application/zip"), "Unexpected error: {}", error);
+    for accepted in ["pdf", "jpg", "png"] {
+        assert!(error.contains(accepted), "Accepted types should be listed: {}", error);
+    }
+}
+
+#[tokio::test]
+async fn test_http_filename_template_renders_placeholders() {
+    let pdf = generate_pdf_with_large_content(500);
+    let disposition = |response: &Response| response.headers()["Content-Disposition"].to_str().unwrap().to_string();
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("report.pdf"), &pdf),
+        ("compression", None, b"60"),
+        ("filename_template", None, b"{stem}_q{level}_{date}.{ext}"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let disposition = disposition(&response);
+    let name = disposition.strip_prefix("attachment; filename=\"report_q60_").unwrap()
+        .strip_suffix(".pdf\"").unwrap();
+    assert_eq!(name.len(), 10, "Expected a YYYY-MM-DD date, got {}", name);
+    assert!(name.chars().enumerate().all(|(i, c)| if i == 4 || i == 7 { c == '-' } else { c.is_ascii_digit() }));
+    
+    // Without {ext} the real extension is still appended, and stray characters are sanitized away
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("my.report.pdf"), &pdf),
+        ("filename_template", None, b"archive/{stem}-{level}"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Disposition"], "attachment; filename=\"archivemyreport-75.pdf\"");
+}
+
+#[tokio::test]
+async fn test_http_filename_template_rejects_unknown_placeholders() {
+    let pdf = generate_pdf_with_large_content(500);
+    for (template, message) in [
+        (&b"{stem}_{user}"[..], "unknown placeholder {user}"),
+        (b"{stem", "unbalanced braces"),
+        (b"stem}", "unbalanced braces"),
+    ] {
+        let response = post_multipart(AppState::new(2), "/api/compress", &[
+            ("file", Some("report.pdf"), &pdf),
+            ("filename_template", None, template),
+        ]).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = String::from_utf8(body_bytes(response).await).unwrap();
+        assert!(body.contains(message), "Unexpected error: {}", body);
+    }
+}
+
+#[tokio::test]
+async fn test_http_inline_disposition() {
+    let pdf = generate_pdf_with_large_content(500);
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("report.pdf"), &pdf),
+        ("disposition", None, b"inline"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Disposition"], "inline; filename=\"report-compressed.pdf\"");
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[("file", Some("report.pdf"), &pdf)]).await;
+    assert_eq!(response.headers()["Content-Disposition"], "attachment; filename=\"report-compressed.pdf\"");
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("report.pdf"), &pdf),
+        ("disposition", None, b"download"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_http_request_id_is_echoed() {
+    let (content_type, body) = multipart_body(&[("file", Some("doc.pdf"), &generate_pdf_with_large_content(500))]);
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/compress")
+        .header("Content-Type", content_type)
+        .header("X-Request-Id", "trace-42")
+        .body(Body::from(body))
+        .unwrap();
+    let response = create_router_with_state(AppState::new(2)).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Request-Id"], "trace-42");
+    
+    // Without one (and on rejected requests too) a fresh UUID is sent back
+    let request = Request::builder().uri("/no-such-route").body(Body::empty()).unwrap();
+    let response = create_router_with_state(AppState::new(2)).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let generated = response.headers()["X-Request-Id"].to_str().unwrap();
+    assert_eq!(generated.len(), 36, "Expected a UUID, got {}", generated);
+    assert_eq!(generated.matches('-').count(), 4);
+}
+
+#[tokio::test]
+async fn test_http_resample_filter_field() {
+    let png = generate_png_image(2000, 1600);
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("big.png"), &png),
+        ("format", None, b"jpg"),
+        ("resample_filter", None, b"triangle"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let decoded = image::load_from_memory(&body_bytes(response).await).unwrap();
+    assert_eq!(decoded.width().max(decoded.height()), 1200, "Level 75 should still pick the downsampling band");
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("big.png"), &png),
+        ("resample_filter", None, b"bicubic"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_http_no_resize_keeps_dimensions() {
+    let jpeg = generate_jpeg_image(2000, 2000);
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("scan.jpg"), &jpeg),
+        ("compression", None, b"90"),
+        ("no_resize", None, b"true"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_bytes(response).await;
+    let decoded = image::load_from_memory(&body).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (2000, 2000));
+    assert_ne!(body, jpeg, "The image should still be re-encoded");
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("scan.jpg"), &jpeg),
+        ("no_resize", None, b"maybe"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_http_flatten_background_field() {
+    let png = generate_flat_color_rgba_png(200, 200);
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("logo.png"), &png),
+        ("format", None, b"jpg"),
+        ("background", None, b"#000000"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let decoded = image::load_from_memory(&body_bytes(response).await).unwrap().to_rgb8();
+    // Fully black, half-transparent stripes stay black over a black background
+    assert!(decoded.pixels().filter(|p| p.0.iter().all(|c| *c < 16)).count() > 200 * 200 / 10);
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("logo.png"), &png),
+        ("flatten_background", None, b"navy"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_http_preset_field() {
+    let png = generate_png_image(2000, 1600);
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("big.png"), &png),
+        ("format", None, b"jpg"),
+        ("preset", None, b"/screen"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let decoded = image::load_from_memory(&body_bytes(response).await).unwrap();
+    assert_eq!(decoded.width().max(decoded.height()), 1280, "Screen should cap the long edge");
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("big.png"), &png),
+        ("preset", None, b"poster"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+/// Names and contents of a zip response, in archive order
+fn zip_entries(archive: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+    use std::io::Read;
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(archive)).expect("Response should be a zip");
+    (0..archive.len())
+        .map(|index| {
+            let mut entry = archive.by_index(index).unwrap();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            (entry.name().to_string(), data)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_http_compress_batch() {
+    let first = generate_pdf_with_large_content(2000);
+    let second = generate_pdf_with_image();
+    let third = generate_minimal_pdf();
+    let photo = generate_png_image(400, 300);
+    
+    let response = post_multipart(AppState::new(2), "/api/compress-batch", &[
+        ("file", Some("report.pdf"), &first),
+        ("file", Some("scan.pdf"), &second),
+        ("file", Some("report.pdf"), &third),
+        ("file", Some("photo.png"), &photo),
+        ("compression", None, b"60"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "application/zip");
+    assert_eq!(response.headers()["X-File-Count"], "4");
+    assert_eq!(response.headers()["X-Failed-Count"], "0");
+    
+    let entries = zip_entries(body_bytes(response).await);
+    let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["report-compressed.pdf", "scan-compressed.pdf", "report-compressed-2.pdf", "photo-compressed.png"]);
+    for (name, data) in &entries[..3] {
+        let doc = lopdf::Document::load_mem(data).unwrap_or_else(|e| panic!("{} should be a PDF: {}", name, e));
+        assert!(!doc.get_pages().is_empty());
+    }
+    assert!(entries[0].1.len() < first.len(), "The batch should really compress");
+    assert_eq!(image::load_from_memory(&entries[3].1).unwrap().width(), 400);
+}
+
+#[tokio::test]
+async fn test_http_compress_batch_isolates_failures() {
+    let pdf = generate_minimal_pdf();
+    let corrupted = generate_corrupted_pdf();
+    
+    let response = post_multipart(AppState::new(1), "/api/compress-batch", &[
+        ("file", Some("good.pdf"), &pdf),
+        ("file", Some("broken.pdf"), &corrupted),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Failed-Count"], "1");
+    let entries = zip_entries(body_bytes(response).await);
+    assert_eq!(entries[0].0, "good-compressed.pdf");
+    assert_eq!(entries[1].0, "errors.txt");
+    assert!(String::from_utf8_lossy(&entries[1].1).starts_with("broken.pdf: "));
+    
+    let response = post_multipart(AppState::new(1), "/api/compress-batch", &[
+        ("file", Some("broken.pdf"), &corrupted),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_http_compress_batch_leaves_job_slots_for_other_requests() {
+    assert_eq!(AppState::new(1).batch_concurrency(), 1);
+    assert_eq!(AppState::new(5).batch_concurrency(), 2);
+    
+    let state = AppState::new(4);
+    let pdf = generate_pdf_with_large_content(2000);
+    let fields: Vec<(&str, Option<&str>, &[u8])> = (0..8).map(|_| ("file", Some("doc.pdf"), pdf.as_slice())).collect();
+    let (content_type, body) = multipart_body(&fields);
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/compress-batch")
+        .header("Content-Type", content_type)
+        .body(Body::from(body))
+        .unwrap();
+    let batch = tokio::spawn(create_router_with_state(state.clone()).oneshot(request));
+    
+    // However long the batch runs, half the slots stay free for single-file requests
+    while !batch.is_finished() {
+        assert!(state.job_permits.available_permits() >= 2, "The batch took more than half the job slots");
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    }
+    let response = batch.await.unwrap().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-File-Count"], "8");
+}
+
+#[tokio::test]
+async fn test_http_extract_images() {
+    let pdf = generate_pdf_with_image();
+    let response = post_multipart(AppState::new(2), "/api/extract-images", &[("file", Some("doc.pdf"), &pdf)]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Image-Count"], "1");
+    
+    let entries = zip_entries(body_bytes(response).await);
+    assert_eq!(entries.len(), 2);
+    let (index_name, index) = &entries[0];
+    assert_eq!(index_name, "images.json");
+    let index: serde_json::Value = serde_json::from_slice(index).unwrap();
+    let (file, data) = &entries[1];
+    assert_eq!(index[0]["file"], file.as_str());
+    assert_eq!((index[0]["width"].as_u64(), index[0]["height"].as_u64()), (Some(10), Some(10)));
+    assert_eq!(index[0]["pages"], serde_json::json!([1]));
+    assert_eq!(image::load_from_memory(data).unwrap().width(), 10);
+    
+    let response = post_multipart(AppState::new(2), "/api/extract-images", &[("file", Some("bad.pdf"), &generate_corrupted_pdf())]).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_http_formats_endpoint() {
+    // Public even when keys are configured
+    let state = AppState::new(2).with_api_keys(ApiKeys::parse("alpha-key-0001").unwrap());
+    let request = Request::builder().uri("/api/formats").body(Body::empty()).unwrap();
+    let response = create_router_with_state(state).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let formats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let list = |key: &str| -> Vec<String> {
+        formats[key].as_array().unwrap().iter().map(|v| v.as_str().unwrap().to_string()).collect()
+    };
+    
+    for format in ["jpg", "png", "webp", "pdf"] {
+        assert!(list("output").iter().any(|f| f == format), "Missing output format {}", format);
+    }
+    for format in ["pdf", "jpg", "png", "webp", "tiff"] {
+        assert!(list("input").iter().any(|f| f == format), "Missing input format {}", format);
+    }
+    assert_eq!(list("features").iter().any(|f| f == "mozjpeg"), cfg!(feature = "mozjpeg"));
+}
+
+#[tokio::test]
+async fn test_http_json_responses_are_compressed() {
+    use std::io::Read;
+    
+    let request = Request::builder()
+        .uri("/api/formats")
+        .header("Accept-Encoding", "gzip")
+        .body(Body::empty())
+        .unwrap();
+    let response = create_router_with_state(AppState::new(2)).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-encoding"], "gzip");
+    
+    let body = body_bytes(response).await;
+    let mut json = Vec::new();
+    flate2::read::GzDecoder::new(&body[..]).read_to_end(&mut json).unwrap();
+    let formats: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert!(formats["output"].is_array());
+    
+    // Compressed PDFs go out as they are
+    let (content_type, body) = multipart_body(&[("file", Some("doc.pdf"), &generate_pdf_with_large_content(500))]);
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/compress")
+        .header("Content-Type", content_type)
+        .header("Accept-Encoding", "gzip, br")
+        .body(Body::from(body))
+        .unwrap();
+    let response = create_router_with_state(AppState::new(2)).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("content-encoding").is_none());
+    assert!(body_bytes(response).await.starts_with(b"%PDF"));
+}
+
+#[tokio::test]
+async fn test_http_scoped_api_keys() {
+    let state = AppState::new(2).with_api_keys(ApiKeys::parse("alpha-key-0001, beta-key-0002:2").unwrap());
+    let pdf = generate_minimal_pdf();
+    let send = |header: Option<(&'static str, &'static str)>| {
+        let (content_type, body) = multipart_body(&[("file", Some("doc.pdf"), &pdf)]);
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/api/compress")
+            .header("Content-Type", content_type);
+        if let Some((name, value)) = header {
+            request = request.header(name, value);
+        }
+        create_router_with_state(state.clone()).oneshot(request.body(Body::from(body)).unwrap())
+    };
+    
+    assert_eq!(send(Some(("X-API-Key", "alpha-key-0001"))).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(send(Some(("Authorization", "Bearer beta-key-0002"))).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(send(Some(("X-API-Key", "gamma-key-0003"))).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(send(None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    
+    // The beta key allows two requests per minute; alpha is unlimited
+    assert_eq!(send(Some(("X-API-Key", "beta-key-0002"))).await.unwrap().status(), StatusCode::OK);
+    let limited = send(Some(("X-API-Key", "beta-key-0002"))).await.unwrap();
+    assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(limited.headers().contains_key("Retry-After"));
+    assert_eq!(send(Some(("X-API-Key", "alpha-key-0001"))).await.unwrap().status(), StatusCode::OK);
+    
+    // Public endpoints stay open
+    let health = Request::builder().uri("/health").body(Body::empty()).unwrap();
+    assert_eq!(create_router_with_state(state.clone()).oneshot(health).await.unwrap().status(), StatusCode::OK);
+    
+    let json = ApiKeys::parse(r#"{"alpha-key-0001": 10, "beta-key-0002": null}"#).unwrap();
+    assert_eq!(json.len(), 2);
+    assert!(ApiKeys::parse("alpha-key-0001:lots").is_err());
+}
+
+#[tokio::test]
+async fn test_http_min_reduction_returns_original() {
+    let pdf = generate_minimal_pdf();
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("doc.pdf"), &pdf),
+        ("min_reduction_pct", None, b"99"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Compression-Skipped"], "true");
+    assert_eq!(response.headers()["Content-Type"], "application/pdf");
+    assert!(response.headers().contains_key("X-Measured-Reduction-Percentage"));
+    assert_eq!(body_bytes(response).await, pdf, "Original bytes should be returned untouched");
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("doc.pdf"), &pdf),
+        ("min_reduction_pct", None, b"250"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_http_fallback_original_on_compression_error() {
+    let pdf = generate_corrupted_pdf();
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[("file", Some("doc.pdf"), &pdf)]).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("doc.pdf"), &pdf),
+        ("fallback_original", None, b"true"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Compression-Skipped"], "true");
+    assert_eq!(response.headers()["Content-Type"], "application/pdf");
+    let error = response.headers()["X-Compression-Error"].to_str().unwrap().to_string();
+    assert!(error.starts_with("PDF compression failed"), "Unexpected error header: {}", error);
+    assert!(!response.headers().contains_key("X-Measured-Reduction-Percentage"));
+    assert_eq!(body_bytes(response).await, pdf, "Original bytes should be returned untouched");
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("doc.pdf"), &pdf),
+        ("fallback_original", None, b"maybe"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_http_cache_hit_on_repeat_upload() {
+    let state = AppState::new(2).with_cache(10 * 1024 * 1024);
+    let pdf = generate_pdf_with_large_content(2000);
+    
+    let first = post_multipart(state.clone(), "/api/compress", &[("file", Some("doc.pdf"), &pdf)]).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    assert_eq!(first.headers()["X-Cache"], "MISS");
+    let first_body = body_bytes(first).await;
+    
+    let second = post_multipart(state.clone(), "/api/compress", &[("file", Some("doc.pdf"), &pdf)]).await;
+    assert_eq!(second.headers()["X-Cache"], "HIT");
+    assert_eq!(body_bytes(second).await, first_body, "Cached response should be byte-identical");
+    
+    // A different level, or any other compression option, is a different cache entry
+    let options: [(&str, &[u8]); 7] = [
+        ("level", b"30"),
+        ("preset", b"ebook"),
+        ("jpeg_quality", b"60"),
+        ("resample_filter", b"nearest"),
+        ("flatten_forms", b"true"),
+        ("no_resize", b"true"),
+        ("quantize_png", b"true"),
+    ];
+    for (name, value) in options {
+        let response = post_multipart(state.clone(), "/api/compress", &[
+            ("file", Some("doc.pdf"), &pdf),
+            (name, None, value),
+        ]).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", name);
+        assert_eq!(response.headers()["X-Cache"], "MISS", "{} should be part of the cache key", name);
+    }
+}
+
+#[tokio::test]
+async fn test_http_compression_time_header() {
+    let state = AppState::new(2).with_cache(10 * 1024 * 1024);
+    let pdf = generate_pdf_with_image();
+    let time_ms = |response: &Response| -> u64 {
+        response.headers()["X-Compression-Time-Ms"].to_str().unwrap().parse()
+            .expect("X-Compression-Time-Ms should be a non-negative integer")
+    };
+    
+    let first = post_multipart(state.clone(), "/api/compress", &[("file", Some("doc.pdf"), &pdf)]).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    time_ms(&first);
+    
+    // Nothing is compressed for a cache hit
+    let second = post_multipart(state, "/api/compress", &[("file", Some("doc.pdf"), &pdf)]).await;
+    assert_eq!(second.headers()["X-Cache"], "HIT");
+    assert_eq!(time_ms(&second), 0);
+}
+
+#[tokio::test]
+async fn test_http_merge_pdfs() {
+    let pdf = generate_minimal_pdf();
+    
+    let response = post_multipart(AppState::new(2), "/api/merge", &[
+        ("file", Some("a.pdf"), &pdf),
+        ("file", Some("b.pdf"), &pdf),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Document-Count"], "2");
+    
+    let doc = lopdf::Document::load_mem(&body_bytes(response).await).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+    
+    let response = post_multipart(AppState::new(2), "/api/merge", &[
+        ("file", Some("a.pdf"), &pdf),
+        ("file", Some("b.pdf"), b"not a pdf"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_http_validate() {
+    let pdf = generate_minimal_pdf();
+    let response = post_multipart(AppState::new(2), "/api/validate", &[("file", Some("doc.pdf"), &pdf)]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert_eq!(body["kind"], "pdf");
+    assert_eq!(body["pages"], 1);
+    
+    let png = generate_png_image(64, 48);
+    let response = post_multipart(AppState::new(2), "/api/validate", &[("file", Some("photo.png"), &png)]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert_eq!(body, serde_json::json!({"kind": "image", "format": "png", "width": 64, "height": 48}));
+    
+    let response = post_multipart(AppState::new(2), "/api/validate", &[("file", Some("doc.pdf"), &pdf[..pdf.len() / 2])]).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert!(body["error"].as_str().is_some_and(|error| error.contains("%%EOF")), "{}", body);
+    
+    let response = post_multipart(AppState::new(2), "/api/validate", &[("level", None, b"75")]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_http_errors_have_json_bodies() {
+    let pdf = generate_pdf_with_large_content(200);
+    let response = post_multipart(AppState::new(2).with_body_limit(1024), "/api/compress", &[
+        ("file", Some("big.pdf"), &pdf),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert!(body["error"].as_str().is_some_and(|error| !error.is_empty()), "{}", body);
+    
+    // Router rejections with an empty body get the status reason
+    let request = Request::builder().uri("/api/missing").body(Body::empty()).unwrap();
+    let response = create_router_with_state(AppState::new(2)).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert_eq!(body["error"], "Not Found");
+    
+    let request = Request::builder().uri("/api/compress").body(Body::empty()).unwrap();
+    let response = create_router_with_state(AppState::new(2)).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert!(response.headers().contains_key("Allow"), "Other headers are kept");
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert_eq!(body["error"], "Method Not Allowed");
+}
+
+#[tokio::test]
+async fn test_http_max_input_bytes_rejects_before_compression() {
+    let pdf = generate_pdf_with_large_content(200);
+    // Every job slot is taken, so a request that got as far as compressing would get 503
+    let state = AppState::new(1).with_max_input_bytes(pdf.len() as u64 - 1);
+    let _held = state.job_permits.clone().try_acquire_owned().unwrap();
+    let response = post_multipart(state, "/api/compress", &[("file", Some("big.pdf"), &pdf)]).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert!(body["error"].as_str().unwrap().contains(&format!("up to {} bytes", pdf.len() - 1)), "{}", body);
+    
+    let response = post_multipart(AppState::new(1).with_max_input_bytes(pdf.len() as u64), "/api/compress", &[
+        ("file", Some("big.pdf"), &pdf),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK, "A file at the limit is compressed");
+}
+
+#[tokio::test]
+async fn test_http_max_input_bytes_applies_to_every_upload_endpoint() {
+    let pdf = generate_pdf_with_large_content(200);
+    let jpeg = generate_jpeg_image(300, 200);
+    let mut uploads: Vec<(&str, &str, &[u8])> = vec![
+        ("/api/images-to-pdf", "photo.jpg", &jpeg),
+        ("/api/merge", "doc.pdf", &pdf),
+        ("/api/compress-batch", "doc.pdf", &pdf),
+        ("/api/extract-images", "doc.pdf", &pdf),
+        ("/api/validate", "doc.pdf", &pdf),
+    ];
+    if cfg!(feature = "pdf-render") {
+        uploads.push(("/api/pdf-to-images", "doc.pdf", &pdf));
+    }
+    
+    for (uri, filename, data) in uploads {
+        let state = AppState::new(1).with_max_input_bytes(data.len() as u64 - 1);
+        let response = post_multipart(state, uri, &[("file", Some(filename), data)]).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{} should enforce the limit", uri);
+    }
+}
+
+#[tokio::test]
+async fn test_http_manifest_digest_headers() {
+    let pdf = generate_pdf_with_large_content(300);
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("doc.pdf"), &pdf),
+    ]).await;
+    assert!(!response.headers().contains_key("X-Input-SHA256"), "Digests are opt-in");
+    
+    let response = post_multipart(AppState::new(2), "/api/compress", &[
+        ("file", Some("doc.pdf"), &pdf),
+        ("manifest", None, b"true"),
+    ]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let input_sha256 = response.headers()["X-Input-SHA256"].to_str().unwrap().to_string();
+    let output_sha256 = response.headers()["X-Output-SHA256"].to_str().unwrap().to_string();
+    let body = body_bytes(response).await;
+    assert_eq!(input_sha256, PDFcompressor::sha256_hex(&pdf));
+    assert_eq!(output_sha256, PDFcompressor::sha256_hex(&body));
+    assert_eq!(input_sha256.len(), 64);
+}
+
+/// Minimal HTTP/1.1 GET over a fresh connection, returning the status line
+async fn http_get_status(addr: std::net::SocketAddr, path: &str) -> std::io::Result<String> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    
+    let mut stream = tokio::net::TcpStream::connect(addr).await?;
+    stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    Ok(response.lines().next().unwrap_or_default().to_string())
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown_drains_jobs() {
+    use std::time::Duration;
+    
+    let state = AppState::new(2);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (trigger, signal) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(serve_with_shutdown(
+        listener,
+        state.clone(),
+        async { signal.await.ok(); },
+        Duration::from_secs(30),
+    ));
+    
+    assert_eq!(http_get_status(addr, "/health").await.unwrap(), "HTTP/1.1 200 OK");
+    
+    // A compression job still running after its client went away
+    let job = state.job_permits.clone().acquire_owned().await.unwrap();
+    trigger.send(()).unwrap();
+    
+    let mut refused = false;
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(addr).await.is_err() {
+            refused = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(refused, "New connections should be refused after shutdown starts");
+    assert!(state.is_shutting_down());
+    
+    // Health reports the shutdown to anything still connected
+    let request = Request::builder().uri("/health").body(Body::empty()).unwrap();
+    let response = create_router_with_state(state.clone()).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!server.is_finished(), "Server should wait for the running job");
+    
+    drop(job);
+    tokio::time::timeout(Duration::from_secs(5), server).await
+        .expect("Server should exit once the job finishes")
+        .unwrap()
+        .unwrap();
+}
@@ -0,0 +1,76 @@
+// Chunked compression tests - kept in their own binary because they measure
+// heap use through the global allocator, which other tests would disturb
+
+mod common;
+
+use common::*;
+use lopdf::Document;
+use PDFcompressor::{compress_pdf_with_settings, CompressionSettings};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Counts live heap bytes so a test can compare peak memory between runs
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let now = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK_ALLOCATED.fetch_max(now, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            let now = ALLOCATED.fetch_add(new_size, Ordering::Relaxed) + new_size;
+            PEAK_ALLOCATED.fetch_max(now, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Compress and return the output with the most heap the call held above what was live before it
+fn compress_measuring_peak(input: &[u8], settings: &CompressionSettings) -> (Vec<u8>, usize) {
+    let baseline = ALLOCATED.load(Ordering::Relaxed);
+    PEAK_ALLOCATED.store(baseline, Ordering::Relaxed);
+    let output = compress_pdf_with_settings(input, settings).expect("Compression failed");
+    (output, PEAK_ALLOCATED.load(Ordering::Relaxed) - baseline)
+}
+
+#[test]
+fn test_page_chunks_match_whole_document_with_lower_peak_memory() {
+    let input = generate_scanned_pdf(40, 300, 300);
+    let whole_settings = CompressionSettings { quality: 90, ..Default::default() };
+    let chunked_settings = CompressionSettings { page_chunk_size: Some(4), ..whole_settings.clone() };
+
+    let (whole, whole_peak) = compress_measuring_peak(&input, &whole_settings);
+    let (chunked, chunked_peak) = compress_measuring_peak(&input, &chunked_settings);
+
+    assert_eq!(chunked, whole, "Chunking should only change when streams are compressed, not how");
+    let doc = Document::load_mem(&chunked).expect("Chunked output should load");
+    assert_eq!(doc.get_pages().len(), 40);
+    assert!(chunked.len() < input.len() / 4, "Scanned pages should still be recompressed");
+
+    // Loading still holds the file and the document at once, which bounds both runs from below;
+    // past that, the whole-document pass holds a copy of every image and its encoding together
+    assert!(
+        chunked_peak * 10 < whole_peak * 9,
+        "Chunked peak {} bytes should be well below whole-document peak {} bytes",
+        chunked_peak, whole_peak
+    );
+}
@@ -0,0 +1,177 @@
+mod common;
+
+use common::*;
+use std::process::Command;
+
+fn cli() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_pdfcompressor-cli"))
+}
+
+#[test]
+fn test_cli_dry_run_prints_report_without_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    let input_path = dir.path().join("input.pdf");
+    std::fs::write(&input_path, generate_pdf_with_large_content(2000)).unwrap();
+    
+    let output = cli()
+        .args(["--dry-run", "--level", "60"])
+        .arg(&input_path)
+        .output()
+        .expect("Failed to run CLI");
+    
+    assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+    
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .expect("Dry run should print a JSON report");
+    assert!(report["original_size"].as_u64().unwrap() > 0);
+    assert!(report["flate_bytes_saved"].as_i64().unwrap() > 0);
+    
+    assert!(!dir.path().join("input-compressed.pdf").exists(), "Dry run must not write output");
+    
+    // Images have no per-step breakdown, only their sizes
+    let image_path = dir.path().join("photo.png");
+    std::fs::write(&image_path, generate_png_image(300, 200)).unwrap();
+    let output = cli().arg("--dry-run").arg(&image_path).output().expect("Failed to run CLI");
+    assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .expect("Dry run should print a JSON report for images too");
+    assert!(report["original_size"].as_u64().unwrap() > 0);
+    assert!(report["final_size"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn test_cli_writes_output_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let input_path = dir.path().join("input.pdf");
+    let output_path = dir.path().join("out.pdf");
+    std::fs::write(&input_path, generate_minimal_pdf()).unwrap();
+    
+    let status = cli()
+        .arg(&input_path)
+        .args(["-o"])
+        .arg(&output_path)
+        .status()
+        .expect("Failed to run CLI");
+    
+    assert!(status.success());
+    let written = std::fs::read(&output_path).unwrap();
+    assert!(written.starts_with(b"%PDF"));
+}
+
+#[test]
+fn test_cli_rejects_unknown_option() {
+    let output = cli().arg("--bogus").output().expect("Failed to run CLI");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown option"));
+}
+
+#[test]
+fn test_cli_help_prints_usage_to_stdout() {
+    for flag in ["-h", "--help"] {
+        let output = cli().arg(flag).output().expect("Failed to run CLI");
+        assert!(output.status.success(), "{} should exit 0", flag);
+        assert!(String::from_utf8_lossy(&output.stdout).starts_with("Usage:"));
+        assert!(output.stderr.is_empty());
+    }
+}
+
+#[test]
+fn test_cli_reads_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let input_path = dir.path().join("input.pdf");
+    let config_path = dir.path().join("settings.json");
+    std::fs::write(&input_path, generate_pdf_with_large_content(2000)).unwrap();
+    std::fs::write(&config_path, r#"{"quality": 60, "rounds": 1, "flate_level": "fast"}"#).unwrap();
+    
+    let output = cli()
+        .arg("--dry-run")
+        .arg("--config")
+        .arg(&config_path)
+        .arg(&input_path)
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+    
+    // Typos in the config are reported rather than silently ignored
+    std::fs::write(&config_path, r#"{"qualty": 60}"#).unwrap();
+    let output = cli()
+        .arg("--config")
+        .arg(&config_path)
+        .arg(&input_path)
+        .output()
+        .expect("Failed to run CLI");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid compression settings"));
+    
+    // A config path that isn't valid UTF-8 is read as is, not mangled
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        
+        let config_path = dir.path().join(std::ffi::OsStr::from_bytes(b"settings-\xff.json"));
+        std::fs::write(&config_path, r#"{"quality": 60}"#).unwrap();
+        let output = cli()
+            .arg("--dry-run")
+            .arg("--config")
+            .arg(&config_path)
+            .arg(&input_path)
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+}
+
+#[test]
+fn test_cli_pipes_stdin_to_stdout() {
+    use std::io::Write;
+    use std::process::Stdio;
+    
+    let input = generate_pdf_with_large_content(2000);
+    let mut child = cli()
+        .args(["--level", "70", "--report"])
+        .env("RUST_LOG", "info")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run CLI");
+    
+    // Write from another thread so a full stdout pipe can't deadlock the test
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().expect("Failed to run CLI");
+    writer.join().unwrap().unwrap();
+    
+    assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.starts_with(b"%PDF"), "Stdout should hold only the compressed PDF");
+    assert!(output.stdout.len() < generate_pdf_with_large_content(2000).len());
+    let doc = lopdf::Document::load_mem(&output.stdout).expect("Stdout should be a valid PDF");
+    assert_eq!(doc.get_pages().len(), 1);
+    
+    // Logs and the report stay on stderr
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("\"original_size\""), "Report should go to stderr: {}", stderr);
+    assert!(stderr.contains("Wrote stdout"));
+}
+
+#[test]
+fn test_cli_dash_reads_stdin() {
+    use std::io::Write;
+    use std::process::Stdio;
+    
+    let dir = tempfile::tempdir().unwrap();
+    let output_path = dir.path().join("out.pdf");
+    let mut child = cli()
+        .args(["-", "-o"])
+        .arg(&output_path)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run CLI");
+    
+    child.stdin.take().unwrap().write_all(&generate_minimal_pdf()).unwrap();
+    let output = child.wait_with_output().expect("Failed to run CLI");
+    
+    assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(std::fs::read(&output_path).unwrap().starts_with(b"%PDF"));
+}
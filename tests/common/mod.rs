@@ -1,3 +1,4 @@
+// Helpers shared by the test binaries; the ones not every binary calls carry #[allow(dead_code)]
 use lopdf::{Document, Object, Stream, Dictionary};
 use image::{RgbImage, DynamicImage, ImageFormat};
 use std::io::Write;
@@ -86,6 +87,43 @@ pub fn generate_minimal_pdf() -> Vec<u8> {
     output
 }
 
+/// Generate a one-page PDF with an outline entry and a link annotation, both pointing at page 1
+#[allow(dead_code)]
+pub fn generate_pdf_with_outline() -> Vec<u8> {
+    let mut doc = Document::load_mem(&generate_minimal_pdf()).expect("Minimal PDF should load");
+    let page_id = *doc.get_pages().get(&1).expect("Page 1 should exist");
+    let destination = || Object::Array(vec![Object::Reference(page_id), Object::Name(b"Fit".to_vec())]);
+    
+    let outlines_id = doc.new_object_id();
+    let item_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Title", Object::string_literal("Chapter 1")),
+        ("Parent", Object::Reference(outlines_id)),
+        ("Dest", destination()),
+    ]));
+    doc.objects.insert(outlines_id, Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Outlines".to_vec())),
+        ("First", Object::Reference(item_id)),
+        ("Last", Object::Reference(item_id)),
+        ("Count", Object::Integer(1)),
+    ]).into());
+    
+    let link_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Annot".to_vec())),
+        ("Subtype", Object::Name(b"Link".to_vec())),
+        ("Rect", Object::Array(vec![100.into(), 690.into(), 200.into(), 720.into()])),
+        ("Dest", destination()),
+    ]));
+    doc.get_dictionary_mut(page_id).unwrap()
+        .set("Annots", Object::Array(vec![Object::Reference(link_id)]));
+    
+    let catalog_id = doc.trailer.get(b"Root").and_then(|o| o.as_reference()).unwrap();
+    doc.get_dictionary_mut(catalog_id).unwrap().set("Outlines", Object::Reference(outlines_id));
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).expect("Failed to save PDF");
+    output
+}
+
 /// Generate a PDF with an embedded image
 pub fn generate_pdf_with_image() -> Vec<u8> {
     let mut doc = Document::with_version("1.5");
@@ -183,6 +221,780 @@ pub fn generate_pdf_with_image() -> Vec<u8> {
     output
 }
 
+/// Generate a one-page PDF drawing the given image XObject as /Im1
+/// Lets tests craft arbitrary image dictionaries (sizes, filters, colour spaces)
+#[allow(dead_code)]
+pub fn generate_pdf_with_image_xobject(image: Stream) -> Vec<u8> {
+    generate_pdf_with_placed_image(image, b"q 100 0 0 100 50 650 cm /Im1 Do Q")
+}
+
+/// Generate a one-page PDF whose content stream `content` draws the image XObject as /Im1
+#[allow(dead_code)]
+pub fn generate_pdf_with_placed_image(image: Stream, content: &[u8]) -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    
+    let pages_id = doc.new_object_id();
+    
+    let catalog_id = doc.add_object(
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Catalog".to_vec())),
+            ("Pages", Object::Reference(pages_id)),
+        ])
+    );
+    
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    let image_id = doc.add_object(image);
+    let mut xobject_dict = Dictionary::new();
+    xobject_dict.set("Im1", Object::Reference(image_id));
+    
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), content.to_vec()));
+    
+    let page_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Page".to_vec())),
+        ("Parent", Object::Reference(pages_id)),
+        ("Resources", Dictionary::from_iter(vec![("XObject", Object::Dictionary(xobject_dict))]).into()),
+        ("MediaBox", Object::Array(vec![
+            Object::Integer(0),
+            Object::Integer(0),
+            Object::Integer(612),
+            Object::Integer(792),
+        ])),
+        ("Contents", Object::Reference(content_id)),
+    ]));
+    
+    doc.objects.insert(
+        pages_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(vec![Object::Reference(page_id)])),
+            ("Count", Object::Integer(1)),
+        ]).into()
+    );
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).expect("Failed to save PDF with image XObject");
+    output
+}
+
+/// Generate a one-page PDF/A-2b: an XMP packet naming the conformance, a GTS_PDFA1 OutputIntent,
+/// and a noisy RGB image in an ICCBased colour space sharing the intent's profile
+/// The profile bytes are a stand-in, nothing here reads them as ICC data
+#[allow(dead_code)]
+pub fn generate_pdfa_pdf() -> Vec<u8> {
+    let (width, height) = (200u32, 200u32);
+    let mut seed = 12345u32;
+    let pixels: Vec<u8> = (0..width * height * 3).map(|_| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (seed >> 24) as u8
+    }).collect();
+    
+    let mut image = Stream::new(image_xobject_dict(width as i64, height as i64, "DeviceRGB"), pixels);
+    let placeholder = generate_pdf_with_image_xobject(image.clone());
+    let mut doc = Document::load_mem(&placeholder).expect("Failed to load PDF/A base");
+    
+    let profile_id = doc.add_object(Stream::new(
+        Dictionary::from_iter(vec![("N", Object::Integer(3))]),
+        b"stand-in sRGB ICC profile".to_vec(),
+    ));
+    let image_id = doc.objects.iter()
+        .find(|(_, object)| object.as_stream().is_ok_and(|stream| stream.dict.has(b"Width")))
+        .map(|(id, _)| *id)
+        .unwrap();
+    image.dict.set("ColorSpace", Object::Array(vec![Object::Name(b"ICCBased".to_vec()), Object::Reference(profile_id)]));
+    doc.objects.insert(image_id, Object::Stream(image));
+    
+    let xmp = b"<?xpacket begin=\"\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+<rdf:Description rdf:about=\"\" xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\" pdfaid:part=\"2\" pdfaid:conformance=\"B\"/>\n\
+</rdf:RDF></x:xmpmeta>\n<?xpacket end=\"w\"?>".to_vec();
+    let mut metadata = Stream::new(
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Metadata".to_vec())),
+            ("Subtype", Object::Name(b"XML".to_vec())),
+        ]),
+        xmp,
+    );
+    metadata.allows_compression = false;
+    let metadata_id = doc.add_object(metadata);
+    let intent_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"OutputIntent".to_vec())),
+        ("S", Object::Name(b"GTS_PDFA1".to_vec())),
+        ("OutputConditionIdentifier", Object::string_literal("sRGB IEC61966-2.1")),
+        ("DestOutputProfile", Object::Reference(profile_id)),
+    ]));
+    
+    let catalog = doc.catalog_mut().expect("PDF/A base has a catalog");
+    catalog.set("Metadata", Object::Reference(metadata_id));
+    catalog.set("OutputIntents", Object::Array(vec![Object::Reference(intent_id)]));
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).expect("Failed to save PDF/A");
+    output
+}
+
+/// Generate a one-page PDF whose page draws form XObject /Fm1, which draws the image as /Im1
+/// With `form_resources` the image is named in the form's own Resources; without, the form
+/// draws it from the page's, as forms lacking Resources do
+#[allow(dead_code)]
+pub fn generate_pdf_with_image_in_form(image: Stream, form_matrix: [i64; 6], form_resources: bool) -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    
+    let image_id = doc.add_object(image);
+    let image_names = Dictionary::from_iter(vec![("Im1", Object::Reference(image_id))]);
+    let mut form_dict = Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"XObject".to_vec())),
+        ("Subtype", Object::Name(b"Form".to_vec())),
+        ("BBox", Object::Array(vec![0.into(), 0.into(), 1.into(), 1.into()])),
+        ("Matrix", Object::Array(form_matrix.iter().map(|&n| n.into()).collect())),
+    ]);
+    let mut page_xobjects = Dictionary::new();
+    if form_resources {
+        form_dict.set("Resources", Dictionary::from_iter(vec![("XObject", Object::Dictionary(image_names))]));
+    } else {
+        page_xobjects = image_names;
+    }
+    let form_id = doc.add_object(Stream::new(form_dict, b"q /Im1 Do Q".to_vec()));
+    page_xobjects.set("Fm1", Object::Reference(form_id));
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), b"q 1 0 0 1 50 600 cm /Fm1 Do Q".to_vec()));
+    
+    let page_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Page".to_vec())),
+        ("Parent", Object::Reference(pages_id)),
+        ("Resources", Dictionary::from_iter(vec![("XObject", Object::Dictionary(page_xobjects))]).into()),
+        ("MediaBox", Object::Array(vec![0.into(), 0.into(), 612.into(), 792.into()])),
+        ("Contents", Object::Reference(content_id)),
+    ]));
+    doc.objects.insert(
+        pages_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(vec![Object::Reference(page_id)])),
+            ("Count", Object::Integer(1)),
+        ]).into()
+    );
+    let catalog_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Catalog".to_vec())),
+        ("Pages", Object::Reference(pages_id)),
+    ]));
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).expect("Failed to save PDF with image in a form XObject");
+    output
+}
+
+/// Generate a one-page PDF drawing `copies` separate objects holding the same image
+/// Each copy gets its own /Name, so the dictionaries differ and dedup leaves them apart
+#[allow(dead_code)]
+pub fn generate_pdf_with_image_copies(image: Stream, copies: usize) -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    
+    let mut xobject_dict = Dictionary::new();
+    let mut content = Vec::new();
+    for index in 0..copies {
+        let name = format!("Im{}", index + 1);
+        let mut copy = image.clone();
+        copy.dict.set("Name", Object::Name(name.as_bytes().to_vec()));
+        xobject_dict.set(name.as_str(), Object::Reference(doc.add_object(copy)));
+        content.extend(format!("q 100 0 0 100 {} 650 cm /{} Do Q\n", 50 + index * 120, name).into_bytes());
+    }
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), content));
+    
+    let page_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Page".to_vec())),
+        ("Parent", Object::Reference(pages_id)),
+        ("Resources", Dictionary::from_iter(vec![("XObject", Object::Dictionary(xobject_dict))]).into()),
+        ("MediaBox", Object::Array(vec![0.into(), 0.into(), 612.into(), 792.into()])),
+        ("Contents", Object::Reference(content_id)),
+    ]));
+    doc.objects.insert(
+        pages_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(vec![Object::Reference(page_id)])),
+            ("Count", Object::Integer(1)),
+        ]).into()
+    );
+    let catalog_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Catalog".to_vec())),
+        ("Pages", Object::Reference(pages_id)),
+    ]));
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).expect("Failed to save PDF with image copies");
+    output
+}
+
+/// Generate a scan-like PDF: every page draws its own noisy, unfiltered RGB image over the whole MediaBox
+#[allow(dead_code)]
+pub fn generate_scanned_pdf(pages: usize, width: u32, height: u32) -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    
+    let mut kids = Vec::new();
+    for page in 0..pages {
+        // Scanner noise over a gradient, so every page is distinct and none of it compresses to nothing
+        let mut seed = (page as u32).wrapping_mul(2_654_435_761) | 1;
+        let pixels: Vec<u8> = (0..width * height)
+            .flat_map(|i| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let noise = (seed >> 24) as u8 / 4;
+                let (x, y) = (i % width, i / width);
+                [(x * 191 / width) as u8 + noise, (y * 191 / height) as u8 + noise, (page * 37 % 192) as u8 + noise]
+            })
+            .collect();
+        let image_id = doc.add_object(Stream::new(image_xobject_dict(width as i64, height as i64, "DeviceRGB"), pixels));
+        let content_id = doc.add_object(Stream::new(Dictionary::new(), b"q 612 0 0 792 0 0 cm /Im1 Do Q".to_vec()));
+        let mut xobject_dict = Dictionary::new();
+        xobject_dict.set("Im1", Object::Reference(image_id));
+        kids.push(Object::Reference(doc.add_object(Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Page".to_vec())),
+            ("Parent", Object::Reference(pages_id)),
+            ("Resources", Dictionary::from_iter(vec![("XObject", Object::Dictionary(xobject_dict))]).into()),
+            ("MediaBox", Object::Array(vec![0.into(), 0.into(), 612.into(), 792.into()])),
+            ("Contents", Object::Reference(content_id)),
+        ]))));
+    }
+    doc.objects.insert(
+        pages_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Count", Object::Integer(kids.len() as i64)),
+            ("Kids", Object::Array(kids)),
+        ]).into()
+    );
+    let catalog_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Catalog".to_vec())),
+        ("Pages", Object::Reference(pages_id)),
+    ]));
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).expect("Failed to save scanned PDF");
+    output
+}
+
+/// Generate a one-page PDF with a filled-in text field (AcroForm, widget annotation with a
+/// normal appearance stream) next to an ordinary link annotation
+#[allow(dead_code)]
+pub fn generate_pdf_with_form_field() -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let page_id = doc.new_object_id();
+    
+    let appearance_id = doc.add_object(Stream::new(
+        Dictionary::from_iter(vec![
+            ("BBox", Object::Array(vec![0.into(), 0.into(), 200.into(), 20.into()])),
+            ("Resources", Dictionary::new().into()),
+        ]),
+        b"/Tx BMC q 0 0 1 rg 2 2 196 16 re f Q EMC".to_vec(),
+    ));
+    let widget_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Annot".to_vec())),
+        ("Subtype", Object::Name(b"Widget".to_vec())),
+        ("FT", Object::Name(b"Tx".to_vec())),
+        ("T", Object::string_literal("name")),
+        ("V", Object::string_literal("Filled in")),
+        ("Rect", Object::Array(vec![100.into(), 600.into(), 300.into(), 620.into()])),
+        ("P", Object::Reference(page_id)),
+        ("AP", Dictionary::from_iter(vec![("N", Object::Reference(appearance_id))]).into()),
+    ]));
+    let link_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Annot".to_vec())),
+        ("Subtype", Object::Name(b"Link".to_vec())),
+        ("Rect", Object::Array(vec![100.into(), 500.into(), 200.into(), 520.into()])),
+        ("A", Dictionary::from_iter(vec![
+            ("S", Object::Name(b"URI".to_vec())),
+            ("URI", Object::string_literal("https://example.com")),
+        ]).into()),
+    ]));
+    
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), b"BT /F1 12 Tf 100 700 Td (Form) Tj ET".to_vec()));
+    let font_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Font".to_vec())),
+        ("Subtype", Object::Name(b"Type1".to_vec())),
+        ("BaseFont", Object::Name(b"Helvetica".to_vec())),
+    ]));
+    doc.objects.insert(page_id, Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Page".to_vec())),
+        ("Parent", Object::Reference(pages_id)),
+        ("MediaBox", Object::Array(vec![0.into(), 0.into(), 612.into(), 792.into()])),
+        ("Resources", Dictionary::from_iter(vec![
+            ("Font", Dictionary::from_iter(vec![("F1", Object::Reference(font_id))]).into()),
+        ]).into()),
+        ("Contents", Object::Reference(content_id)),
+        ("Annots", Object::Array(vec![Object::Reference(widget_id), Object::Reference(link_id)])),
+    ]).into());
+    doc.objects.insert(pages_id, Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Pages".to_vec())),
+        ("Kids", Object::Array(vec![Object::Reference(page_id)])),
+        ("Count", Object::Integer(1)),
+    ]).into());
+    
+    let catalog_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Catalog".to_vec())),
+        ("Pages", Object::Reference(pages_id)),
+        ("AcroForm", Dictionary::from_iter(vec![
+            ("Fields", Object::Array(vec![Object::Reference(widget_id)])),
+        ]).into()),
+    ]));
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).expect("Failed to save PDF with form field");
+    output
+}
+
+/// Generate a one-page PDF whose content stream draws a noisy RGB inline image `copies` times
+/// A literal string containing "BI" comes first, so parsers must skip strings
+#[allow(dead_code)]
+pub fn generate_pdf_with_inline_image(width: u32, height: u32, copies: usize) -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    
+    let pages_id = doc.new_object_id();
+    
+    let catalog_id = doc.add_object(
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Catalog".to_vec())),
+            ("Pages", Object::Reference(pages_id)),
+        ])
+    );
+    
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    let mut seed = 0x2545_f491u32;
+    let pixels: Vec<u8> = (0..width * height * 3)
+        .map(|i| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            ((i % 251) as u8).wrapping_add((seed % 48) as u8)
+        })
+        .collect();
+    
+    let mut content = b"BT /F1 12 Tf 72 720 Td (BI not an image EI) Tj ET\n".to_vec();
+    for copy in 0..copies {
+        content.extend_from_slice(format!("q 200 0 0 200 {} 400 cm\nBI /W {} /H {} /BPC 8 /CS /RGB ID ", 50 + copy * 210, width, height).as_bytes());
+        content.extend_from_slice(&pixels);
+        content.extend_from_slice(b"\nEI Q\n");
+    }
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), content));
+    
+    let page_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Page".to_vec())),
+        ("Parent", Object::Reference(pages_id)),
+        ("Resources", Object::Dictionary(Dictionary::new())),
+        ("MediaBox", Object::Array(vec![
+            Object::Integer(0),
+            Object::Integer(0),
+            Object::Integer(612),
+            Object::Integer(792),
+        ])),
+        ("Contents", Object::Reference(content_id)),
+    ]));
+    
+    doc.objects.insert(
+        pages_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(vec![Object::Reference(page_id)])),
+            ("Count", Object::Integer(1)),
+        ]).into()
+    );
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).expect("Failed to save PDF with inline image");
+    output
+}
+
+/// Cross-reference layout of a hand-written fixture PDF
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+pub enum XrefLayout {
+    Table, // Classic xref table and trailer
+    Stream, // Predictor-encoded xref stream, with the page tree inside an object stream
+    Hybrid, // Classic table for pre-1.5 readers plus an XRefStm stream for the compressed objects
+}
+
+/// Write a one-page PDF by hand in the given cross-reference layout
+/// lopdf always saves xref streams without object streams, so it can't produce these itself
+#[allow(dead_code)]
+pub fn generate_pdf_with_xref_layout(layout: XrefLayout) -> Vec<u8> {
+    let tree: [&[u8]; 3] = [
+        b"<< /Type /Catalog /Pages 2 0 R >>",
+        b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+        b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Contents 4 0 R /Resources << >> >>",
+    ];
+    let content = large_content_stream(200);
+    
+    let mut pdf = b"%PDF-1.5\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = [0usize; 7];
+    fn write_object(pdf: &mut Vec<u8>, offsets: &mut [usize; 7], id: usize, body: &[u8]) {
+        offsets[id] = pdf.len();
+        pdf.extend_from_slice(format!("{} 0 obj\n", id).as_bytes());
+        pdf.extend_from_slice(body);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+    let stream_body = |dict: &str, data: &[u8]| {
+        let mut body = format!("<< {} /Length {} >>\nstream\n", dict, data.len()).into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\nendstream");
+        body
+    };
+    
+    write_object(&mut pdf, &mut offsets, 4, &stream_body("", &content));
+    
+    if let XrefLayout::Table = layout {
+        for (index, dict) in tree.iter().enumerate() {
+            write_object(&mut pdf, &mut offsets, index + 1, dict);
+        }
+        let xref_start = pdf.len();
+        pdf.extend_from_slice(b"xref\n0 5\n0000000000 65535 f\r\n");
+        for offset in &offsets[1..=4] {
+            pdf.extend_from_slice(format!("{:010} 00000 n\r\n", offset).as_bytes());
+        }
+        pdf.extend_from_slice(format!("trailer\n<< /Size 5 /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", xref_start).as_bytes());
+        return pdf;
+    }
+    
+    // Objects 1-3 live in object stream 5
+    let mut header = String::new();
+    let mut objects = Vec::new();
+    for (index, dict) in tree.iter().enumerate() {
+        header.push_str(&format!("{} {} ", index + 1, objects.len()));
+        objects.extend_from_slice(dict);
+        objects.push(b'\n');
+    }
+    let mut object_stream = header.clone().into_bytes();
+    object_stream.extend_from_slice(&objects);
+    write_object(&mut pdf, &mut offsets, 5, &stream_body(&format!("/Type /ObjStm /N 3 /First {}", header.len()), &object_stream));
+    
+    // Hybrid files keep a classic table for objects outside the object stream
+    let table_start = pdf.len();
+    if let XrefLayout::Hybrid = layout {
+        pdf.extend_from_slice(b"xref\n0 5\n0000000000 65535 f\r\n");
+        for _ in 1..=3 {
+            pdf.extend_from_slice(b"0000000000 00000 f\r\n");
+        }
+        pdf.extend_from_slice(format!("{:010} 00000 n\r\n", offsets[4]).as_bytes());
+    }
+    
+    // Xref stream 6 with /W [1 2 2], rows PNG-predicted (filter type 0) and deflated like most writers do
+    let xref_offset = pdf.len() + if let XrefLayout::Hybrid = layout { 64 } else { 0 };
+    let (first_id, entries): (usize, Vec<(u8, usize, usize)>) = match layout {
+        XrefLayout::Hybrid => (1, vec![(2, 5, 0), (2, 5, 1), (2, 5, 2), (1, offsets[4], 0), (1, offsets[5], 0), (1, 0, 0)]),
+        _ => (0, vec![(0, 0, 0), (2, 5, 0), (2, 5, 1), (2, 5, 2), (1, offsets[4], 0), (1, offsets[5], 0), (1, 0, 0)]),
+    };
+    let mut rows = Vec::new();
+    for (index, (kind, field2, field3)) in entries.iter().enumerate() {
+        let field2 = if index == entries.len() - 1 { xref_offset } else { *field2 };
+        rows.extend_from_slice(&[0, *kind, (field2 >> 8) as u8, field2 as u8, (*field3 >> 8) as u8, *field3 as u8]);
+    }
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&rows).unwrap();
+    let rows = encoder.finish().unwrap();
+    let xref_dict = format!(
+        "/Type /XRef /Size 7 /Index [{} {}] /W [1 2 2] /Root 1 0 R /Filter /FlateDecode /DecodeParms << /Columns 5 /Predictor 12 >>",
+        first_id, entries.len(),
+    );
+    
+    if let XrefLayout::Hybrid = layout {
+        pdf.extend_from_slice(format!("trailer\n<< /Size 7 /Root 1 0 R /XRefStm {} >>\n", xref_offset).as_bytes());
+        // Pad so the stream starts at the offset announced above
+        assert!(pdf.len() <= xref_offset, "Hybrid trailer overran its padding");
+        pdf.resize(xref_offset, b' ');
+        write_object(&mut pdf, &mut offsets, 6, &stream_body(&xref_dict, &rows));
+        pdf.extend_from_slice(format!("startxref\n{}\n%%EOF\n", table_start).as_bytes());
+    } else {
+        write_object(&mut pdf, &mut offsets, 6, &stream_body(&xref_dict, &rows));
+        pdf.extend_from_slice(format!("startxref\n{}\n%%EOF\n", xref_offset).as_bytes());
+    }
+    pdf
+}
+
+/// Image XObject dictionary for raw 8-bit samples
+#[allow(dead_code)]
+pub fn image_xobject_dict(width: i64, height: i64, color_space: &str) -> Dictionary {
+    Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"XObject".to_vec())),
+        ("Subtype", Object::Name(b"Image".to_vec())),
+        ("Width", Object::Integer(width)),
+        ("Height", Object::Integer(height)),
+        ("ColorSpace", Object::Name(color_space.as_bytes().to_vec())),
+        ("BitsPerComponent", Object::Integer(8)),
+    ])
+}
+
+/// Generate a PDF whose single page has a large, uncompressed content stream
+#[allow(dead_code)]
+pub fn generate_pdf_with_large_content(repeats: usize) -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    
+    let pages_id = doc.new_object_id();
+    let content_id = doc.new_object_id();
+    let page_id = doc.new_object_id();
+    
+    let catalog_id = doc.add_object(
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Catalog".to_vec())),
+            ("Pages", Object::Reference(pages_id)),
+        ])
+    );
+    
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    let content = large_content_stream(repeats);
+    let mut content_dict = Dictionary::new();
+    content_dict.set("Length", Object::Integer(content.len() as i64));
+    
+    doc.objects.insert(
+        content_id,
+        Object::Stream(Stream::new(content_dict, content))
+    );
+    
+    doc.objects.insert(
+        page_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Page".to_vec())),
+            ("Parent", Object::Reference(pages_id)),
+            ("MediaBox", Object::Array(vec![
+                Object::Integer(0),
+                Object::Integer(0),
+                Object::Integer(612),
+                Object::Integer(792),
+            ])),
+            ("Contents", Object::Reference(content_id)),
+        ]).into()
+    );
+    
+    doc.objects.insert(
+        pages_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(vec![Object::Reference(page_id)])),
+            ("Count", Object::Integer(1)),
+        ]).into()
+    );
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).expect("Failed to save PDF with large content");
+    output
+}
+
+/// Generate a PDF with two fonts embedding the same font program, one copy flate-compressed
+/// Mimics documents produced by merging PDFs that each carried their own font subset
+#[allow(dead_code)]
+pub fn generate_pdf_with_duplicate_fonts() -> Vec<u8> {
+    let mut doc = Document::with_version("1.5");
+    
+    let pages_id = doc.new_object_id();
+    
+    let catalog_id = doc.add_object(
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Catalog".to_vec())),
+            ("Pages", Object::Reference(pages_id)),
+        ])
+    );
+    
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    
+    // Stand-in TrueType program: deterministic pseudo-random bytes, compressible enough to deflate
+    let mut seed: u32 = 7;
+    let program: Vec<u8> = (0..8192)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            ((seed >> 16) % 16) as u8
+        })
+        .collect();
+    
+    let mut font_dict = Dictionary::new();
+    for (index, base_font) in [&b"AAAAAA+DejaVuSans"[..], b"BBBBBB+DejaVuSans"].iter().enumerate() {
+        let mut program_dict = Dictionary::new();
+        program_dict.set("Length1", Object::Integer(program.len() as i64));
+        let mut program_stream = Stream::new(program_dict, program.clone());
+        if index == 1 {
+            program_stream.compress().expect("Failed to compress font program");
+        }
+        let program_id = doc.add_object(program_stream);
+        
+        let descriptor_id = doc.add_object(Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"FontDescriptor".to_vec())),
+            ("FontName", Object::Name(base_font.to_vec())),
+            ("Flags", Object::Integer(32)),
+            ("FontFile2", Object::Reference(program_id)),
+        ]));
+        
+        let font_id = doc.add_object(Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Font".to_vec())),
+            ("Subtype", Object::Name(b"TrueType".to_vec())),
+            ("BaseFont", Object::Name(base_font.to_vec())),
+            ("FontDescriptor", Object::Reference(descriptor_id)),
+        ]));
+        font_dict.set(format!("F{}", index + 1), Object::Reference(font_id));
+    }
+    
+    let content = b"BT /F1 24 Tf 100 700 Td (First) Tj /F2 24 Tf 0 -40 Td (Second) Tj ET".to_vec();
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), content));
+    
+    let page_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Page".to_vec())),
+        ("Parent", Object::Reference(pages_id)),
+        ("Resources", Dictionary::from_iter(vec![("Font", Object::Dictionary(font_dict))]).into()),
+        ("MediaBox", Object::Array(vec![
+            Object::Integer(0),
+            Object::Integer(0),
+            Object::Integer(612),
+            Object::Integer(792),
+        ])),
+        ("Contents", Object::Reference(content_id)),
+    ]));
+    
+    doc.objects.insert(
+        pages_id,
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Pages".to_vec())),
+            ("Kids", Object::Array(vec![Object::Reference(page_id)])),
+            ("Count", Object::Integer(1)),
+        ]).into()
+    );
+    
+    let mut output = Vec::new();
+    doc.save_to(&mut output).expect("Failed to save PDF with duplicate fonts");
+    output
+}
+
+/// Content stream of vector drawing operators used by `generate_pdf_with_large_content`
+#[allow(dead_code)]
+pub fn large_content_stream(repeats: usize) -> Vec<u8> {
+    let mut content = Vec::new();
+    for i in 0..repeats {
+        content.extend_from_slice(
+            format!("{} {} m {} {} l S\n", i % 612, i % 792, (i * 7) % 612, (i * 13) % 792).as_bytes()
+        );
+    }
+    content
+}
+
+/// ASCII85Decode data with `z` for zero groups and the `~>` end marker, wrapped at 75 columns
+#[allow(dead_code)]
+pub fn ascii85_encode(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    for chunk in data.chunks(4) {
+        let mut group = [0u8; 4];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let mut value = u32::from_be_bytes(group);
+        if value == 0 && chunk.len() == 4 {
+            output.push(b'z');
+            continue;
+        }
+        let mut digits = [0u8; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = (value % 85) as u8 + b'!';
+            value /= 85;
+        }
+        output.extend_from_slice(&digits[..chunk.len() + 1]);
+    }
+    let mut wrapped = Vec::new();
+    for line in output.chunks(75) {
+        wrapped.extend_from_slice(line);
+        wrapped.push(b'\n');
+    }
+    wrapped.extend_from_slice(b"~>");
+    wrapped
+}
+
+/// LZWDecode data with the default EarlyChange of 1, as legacy PDF writers produced it
+/// The table is cleared before it fills, so long inputs exercise clear codes too
+#[allow(dead_code)]
+pub fn lzw_encode(data: &[u8]) -> Vec<u8> {
+    const CLEAR: u32 = 256;
+    const END: u32 = 257;
+    
+    let mut output = Vec::new();
+    let (mut bits, mut bit_count) = (0u32, 0u32);
+    let mut write_code = |code: u32, next_code: u32, output: &mut Vec<u8>| {
+        // The decoder lags one entry behind, and widens one code early
+        let width = match next_code {
+            0..=511 => 9,
+            512..=1023 => 10,
+            1024..=2047 => 11,
+            _ => 12,
+        };
+        bits = (bits << width) | code;
+        bit_count += width;
+        while bit_count >= 8 {
+            bit_count -= 8;
+            output.push((bits >> bit_count) as u8);
+        }
+        bits &= (1 << bit_count) - 1;
+    };
+    
+    let mut table = std::collections::HashMap::new();
+    let mut next_code = 258;
+    write_code(CLEAR, next_code, &mut output);
+    let mut prefix: Option<u32> = None;
+    for &byte in data {
+        let Some(code) = prefix else {
+            prefix = Some(byte as u32);
+            continue;
+        };
+        if let Some(&longer) = table.get(&(code, byte)) {
+            prefix = Some(longer);
+            continue;
+        }
+        write_code(code, next_code, &mut output);
+        table.insert((code, byte), next_code);
+        next_code += 1;
+        if next_code == 4094 {
+            write_code(CLEAR, next_code, &mut output);
+            table.clear();
+            next_code = 258;
+        }
+        prefix = Some(byte as u32);
+    }
+    if let Some(code) = prefix {
+        write_code(code, next_code, &mut output);
+        next_code += 1;
+    }
+    write_code(END, next_code, &mut output);
+    if bit_count > 0 {
+        output.push((bits << (8 - bit_count)) as u8);
+    }
+    output
+}
+
+/// A tiny PNG declaring a `width` x `height` canvas, with pixel data for its first row only
+#[allow(dead_code)]
+pub fn generate_png_bomb(width: u32, height: u32) -> Vec<u8> {
+    let chunk = |kind: &[u8], data: &[u8]| {
+        let mut crc = flate2::Crc::new();
+        crc.update(kind);
+        crc.update(data);
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(&crc.sum().to_be_bytes());
+        chunk
+    };
+    
+    let mut header = width.to_be_bytes().to_vec();
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit RGB, no interlacing
+    
+    let mut first_row = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+    first_row.write_all(&vec![0; 1 + width as usize * 3]).unwrap();
+    
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png.extend(chunk(b"IHDR", &header));
+    png.extend(chunk(b"IDAT", &first_row.finish().unwrap()));
+    png.extend(chunk(b"IEND", &[]));
+    png
+}
+
 /// Generate a test JPEG image
 pub fn generate_jpeg_image(width: u32, height: u32) -> Vec<u8> {
     // Create a gradient image
@@ -224,17 +1036,91 @@ pub fn generate_png_image(width: u32, height: u32) -> Vec<u8> {
     output
 }
 
+/// Generate a blocky colour PNG, with too many colours for a palette, whose PNG encoding is ~1.4x its JPEG encoding
+/// Useful for exercising the JPEG-vs-PNG auto-selection threshold
+#[allow(dead_code)]
+pub fn generate_borderline_png_image() -> Vec<u8> {
+    let mut img = RgbImage::new(200, 200);
+    
+    for y in 0..200 {
+        for x in 0..200 {
+            let v = (((x / 8) * 37 + (y / 8) * 91) % 256) as u8;
+            img.put_pixel(x, y, image::Rgb([v, (y / 8 * 10) as u8, 255 - v]));
+        }
+    }
+    
+    let mut output = Vec::new();
+    DynamicImage::ImageRgb8(img).write_to(&mut std::io::Cursor::new(&mut output), ImageFormat::Png)
+        .expect("Failed to write PNG");
+    output
+}
+
+/// Generate a UI-mockup style RGBA PNG: a few flat colours, one semi-transparent
+#[allow(dead_code)]
+pub fn generate_flat_color_rgba_png(width: u32, height: u32) -> Vec<u8> {
+    let colors = [
+        [255, 255, 255, 255],
+        [30, 120, 220, 255],
+        [240, 80, 60, 255],
+        [40, 40, 40, 255],
+        [0, 0, 0, 128],
+    ];
+    let mut img = image::RgbaImage::new(width, height);
+    
+    for y in 0..height {
+        for x in 0..width {
+            let index = ((x / 3 + y / 5) as usize) % colors.len();
+            img.put_pixel(x, y, image::Rgba(colors[index]));
+        }
+    }
+    
+    let mut output = Vec::new();
+    DynamicImage::ImageRgba8(img).write_to(&mut std::io::Cursor::new(&mut output), ImageFormat::Png)
+        .expect("Failed to write PNG");
+    output
+}
+
 /// Generate invalid/corrupted data for error testing
+#[allow(dead_code)]
 pub fn generate_corrupted_pdf() -> Vec<u8> {
     b"%PDF-1.5\n%\xE2\xE3\xCF\xD3\nThis is not a valid PDF\n%%EOF".to_vec()
 }
 
 /// Generate corrupted image data
+#[allow(dead_code)]
 pub fn generate_corrupted_image() -> Vec<u8> {
     b"\xFF\xD8\xFF\xE0\x00\x10JFIF\x00\x01\x01\x00CORRUPTED".to_vec()
 }
 
+/// Build a multipart/form-data body from (field name, optional filename, data)
+/// Returns the Content-Type header value and the encoded body
+#[allow(dead_code)]
+pub fn multipart_body(fields: &[(&str, Option<&str>, &[u8])]) -> (String, Vec<u8>) {
+    let boundary = "----pdfcompressor-test-boundary";
+    let mut body = Vec::new();
+    
+    for (name, filename, data) in fields {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        match filename {
+            Some(filename) => body.extend_from_slice(format!(
+                "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+                name, filename
+            ).as_bytes()),
+            None => body.extend_from_slice(format!(
+                "Content-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                name
+            ).as_bytes()),
+        }
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    
+    (format!("multipart/form-data; boundary={}", boundary), body)
+}
+
 /// Helper to save test fixtures to disk
+#[allow(dead_code)]
 pub fn save_fixture(name: &str, data: &[u8]) -> std::path::PathBuf {
     let fixture_dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
         .join("tests")
@@ -249,13 +1135,14 @@ pub fn save_fixture(name: &str, data: &[u8]) -> std::path::PathBuf {
 }
 
 /// Load a test fixture from disk
+#[allow(dead_code)]
 pub fn load_fixture(name: &str) -> Vec<u8> {
     let fixture_path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
         .join("tests")
         .join("fixtures")
         .join(name);
     
-    std::fs::read(&fixture_path).expect(&format!("Failed to load fixture: {}", name))
+    std::fs::read(&fixture_path).unwrap_or_else(|_| panic!("Failed to load fixture: {}", name))
 }
 
 #[cfg(test)]
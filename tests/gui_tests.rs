@@ -2,6 +2,8 @@
 // We can't easily test the actual GUI components without egui runtime,
 // but we can test the pure functions
 
+use PDFcompressor::reduction_percentage;
+
 // Helper function from main.rs for testing
 fn format_file_size(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
@@ -197,7 +199,7 @@ fn test_estimate_compressed_size_realistic() {
 // Compression Result Tests
 // ============================================================================
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 struct CompressionResult {
     file_name: String,
     original_size: u64,
@@ -206,25 +208,13 @@ struct CompressionResult {
     error_message: Option<String>,
 }
 
-impl Default for CompressionResult {
-    fn default() -> Self {
-        Self {
-            file_name: String::new(),
-            original_size: 0,
-            compressed_size: 0,
-            success: false,
-            error_message: None,
-        }
-    }
-}
-
 #[test]
 fn test_compression_result_default() {
     let result = CompressionResult::default();
     assert_eq!(result.file_name, "");
     assert_eq!(result.original_size, 0);
     assert_eq!(result.compressed_size, 0);
-    assert_eq!(result.success, false);
+    assert!(!result.success);
     assert_eq!(result.error_message, None);
 }
 
@@ -280,37 +270,32 @@ fn test_compression_result_clone() {
 // Reduction Percentage Calculation Tests
 // ============================================================================
 
-fn calculate_reduction_percentage(original: u64, compressed: u64) -> f64 {
-    if original == 0 {
-        return 0.0;
-    }
-    (original as i64 - compressed as i64) as f64 / original as f64 * 100.0
-}
-
 #[test]
 fn test_calculate_reduction_percentage_basic() {
-    assert_eq!(calculate_reduction_percentage(1000, 500), 50.0);
-    assert_eq!(calculate_reduction_percentage(1000, 750), 25.0);
-    assert_eq!(calculate_reduction_percentage(1000, 250), 75.0);
+    assert_eq!(reduction_percentage(1000, 500), 50.0);
+    assert_eq!(reduction_percentage(1000, 750), 25.0);
+    assert_eq!(reduction_percentage(1000, 250), 75.0);
 }
 
 #[test]
 fn test_calculate_reduction_percentage_edge_cases() {
-    assert_eq!(calculate_reduction_percentage(1000, 1000), 0.0);
-    assert_eq!(calculate_reduction_percentage(1000, 0), 100.0);
-    assert_eq!(calculate_reduction_percentage(0, 0), 0.0);
+    assert_eq!(reduction_percentage(1000, 1000), 0.0);
+    assert_eq!(reduction_percentage(1000, 0), 100.0);
+    assert_eq!(reduction_percentage(0, 0), 0.0);
 }
 
 #[test]
 fn test_calculate_reduction_percentage_expansion() {
     // If compressed is larger (shouldn't happen normally)
-    let result = calculate_reduction_percentage(1000, 1100);
+    let result = reduction_percentage(1000, 1100);
     assert!(result < 0.0); // Negative reduction (expansion)
+    assert_eq!(result, -10.0);
+    assert_eq!(reduction_percentage(0, 500), 0.0);
 }
 
 #[test]
 fn test_calculate_reduction_percentage_precision() {
-    let reduction = calculate_reduction_percentage(1234, 567);
+    let reduction = reduction_percentage(1234, 567);
     let expected = (1234 - 567) as f64 / 1234.0 * 100.0;
     assert!((reduction - expected).abs() < 0.01);
 }
@@ -392,7 +377,7 @@ fn test_file_extension_detection() {
 #[test]
 fn test_validate_compression_level() {
     let is_valid_level = |level: u8| -> bool {
-        level >= 10 && level <= 95
+        (10..=95).contains(&level)
     };
     
     assert!(is_valid_level(10));
@@ -407,7 +392,7 @@ fn test_validate_compression_level() {
 #[test]
 fn test_clamp_compression_level() {
     let clamp_level = |level: u8| -> u8 {
-        level.max(10).min(95)
+        level.clamp(10, 95)
     };
     
     assert_eq!(clamp_level(0), 10);
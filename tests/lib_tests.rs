@@ -1,6 +1,6 @@
 mod common;
 
-use PDFcompressor::{compress_pdf_bytes, compress_image_bytes, CompressionSettings};
+use PDFcompressor::{images_to_pdf, optimize_jpeg_huffman, compress_pdf, compress_pdf_with_settings_reported, merge_and_compress_pdfs, compress_image_with_settings, compress_pdf_bytes, compress_pdf_bytes_reported, compress_pdf_with_settings, compress_image_bytes, split_and_compress, compress_pdf_with_manifest, is_better_compressed, reduction_percentage, compression_level_to_quality, detect_input_type, detect_pdf_kind, validate_input, InputKind, InputType, PdfKind, QualityPreset, CompressionSettings, extract_images, FlateLevel, ImagePolicy, ResampleFilter, verify_pdf_structure};
 use common::*;
 
 // ============================================================================
@@ -16,7 +16,7 @@ fn test_compress_pdf_bytes_valid_minimal() {
     assert!(result.is_ok(), "PDF compression should succeed");
     
     let compressed = result.unwrap();
-    assert!(compressed.len() > 0, "Compressed PDF should not be empty");
+    assert!(!compressed.is_empty(), "Compressed PDF should not be empty");
     assert!(compressed.starts_with(b"%PDF"), "Output should be valid PDF");
     
     println!("Minimal PDF: {} -> {} bytes", original_size, compressed.len());
@@ -31,7 +31,7 @@ fn test_compress_pdf_bytes_with_image() {
     assert!(result.is_ok(), "PDF with image compression should succeed");
     
     let compressed = result.unwrap();
-    assert!(compressed.len() > 0, "Compressed PDF should not be empty");
+    assert!(!compressed.is_empty(), "Compressed PDF should not be empty");
     assert!(compressed.starts_with(b"%PDF"), "Output should be valid PDF");
     
     println!("PDF with image: {} -> {} bytes", original_size, compressed.len());
@@ -114,6 +114,25 @@ fn test_compress_pdf_bytes_idempotent() {
     println!("Second compression: {} bytes", second_compression.len());
 }
 
+#[test]
+fn test_compress_pdf_bytes_reported_consistent() {
+    let input = generate_pdf_with_large_content(5000);
+    
+    let (compressed, report) = compress_pdf_bytes_reported(&input, 75).unwrap();
+    assert_eq!(report.original_size, input.len() as u64);
+    assert_eq!(report.final_size, compressed.len() as u64);
+    assert_eq!(compressed, compress_pdf_bytes(&input, 75).unwrap(), "Report must not change output");
+    
+    // Stream savings should account for most of the size delta; the rest is
+    // serialization overhead from rewriting the document structure
+    let step_savings = report.image_bytes_saved + report.flate_bytes_saved + report.metadata_bytes_removed as i64;
+    let total = report.total_bytes_saved();
+    assert!(total > 0, "Large content stream should shrink");
+    let slack = (report.original_size as i64) / 10;
+    assert!((step_savings - total).abs() <= slack,
+            "Step savings {} should roughly match total {} ({:?})", step_savings, total, report);
+}
+
 // ============================================================================
 // Image Compression Tests
 // ============================================================================
@@ -127,7 +146,7 @@ fn test_compress_image_bytes_jpeg() {
     assert!(result.is_ok(), "JPEG compression should succeed");
     
     let (compressed, ext) = result.unwrap();
-    assert!(compressed.len() > 0, "Compressed image should not be empty");
+    assert!(!compressed.is_empty(), "Compressed image should not be empty");
     assert!(ext == "jpg" || ext == "png", "Should output jpg or png, got: {}", ext);
     
     println!("JPEG: {} -> {} bytes ({})", original_size, compressed.len(), ext);
@@ -142,7 +161,7 @@ fn test_compress_image_bytes_png() {
     assert!(result.is_ok(), "PNG compression should succeed");
     
     let (compressed, ext) = result.unwrap();
-    assert!(compressed.len() > 0, "Compressed image should not be empty");
+    assert!(!compressed.is_empty(), "Compressed image should not be empty");
     assert!(ext == "jpg" || ext == "png", "Should output jpg or png, got: {}", ext);
     
     println!("PNG: {} -> {} bytes ({})", original_size, compressed.len(), ext);
@@ -160,7 +179,7 @@ fn test_compress_image_bytes_large_image() {
     // Large images should see significant size reduction due to downsampling
     println!("Large image: {} -> {} bytes ({:.1}% reduction)", 
              original_size, compressed.len(),
-             (1.0 - compressed.len() as f64 / original_size as f64) * 100.0);
+             reduction_percentage(original_size as u64, compressed.len() as u64));
 }
 
 #[test]
@@ -192,7 +211,7 @@ fn test_compress_image_bytes_format_conversion_jpeg() {
     
     let (compressed, ext) = result.unwrap();
     assert_eq!(ext, "jpg", "Should output JPEG");
-    assert!(compressed.len() > 0);
+    assert!(!compressed.is_empty());
 }
 
 #[test]
@@ -204,7 +223,7 @@ fn test_compress_image_bytes_format_conversion_png() {
     
     let (compressed, ext) = result.unwrap();
     assert_eq!(ext, "png", "Should output PNG");
-    assert!(compressed.len() > 0);
+    assert!(!compressed.is_empty());
 }
 
 #[test]
@@ -247,175 +266,2446 @@ fn test_compress_image_bytes_level_clamping() {
     assert!(result_max.is_ok(), "Should clamp maximum level");
 }
 
-// ============================================================================
-// Compression Settings Tests
-// ============================================================================
+// The borderline fixture is tuned to the image crate's JPEG sizes; mozjpeg output is smaller
+#[cfg(not(feature = "mozjpeg"))]
+#[test]
+fn test_lossless_preference_flips_auto_format() {
+    let input = generate_borderline_png_image();
+    
+    let favor_small = CompressionSettings { lossless_preference: 1.0, ..Default::default() };
+    let (_, ext) = compress_image_with_settings(&input, None, &favor_small).unwrap();
+    assert_eq!(ext, "jpg", "Multiplier 1.0 should pick the smaller JPEG");
+    
+    let favor_lossless = CompressionSettings { lossless_preference: 2.0, ..Default::default() };
+    let (_, ext) = compress_image_with_settings(&input, None, &favor_lossless).unwrap();
+    assert_eq!(ext, "png", "Multiplier 2.0 should keep the lossless PNG");
+}
 
+#[cfg(not(feature = "mozjpeg"))]
 #[test]
-fn test_compression_settings_creation() {
-    let settings = CompressionSettings { quality: 75 };
-    assert_eq!(settings.quality, 75);
+fn test_parallel_auto_format_matches_serial_choice() {
+    let input = generate_borderline_png_image();
+    for lossless_preference in [1.0, 2.0] {
+        let parallel = CompressionSettings { lossless_preference, ..Default::default() };
+        let serial = CompressionSettings { low_memory: true, ..parallel.clone() };
+        let (parallel_bytes, parallel_ext) = compress_image_with_settings(&input, None, &parallel).unwrap();
+        let (serial_bytes, serial_ext) = compress_image_with_settings(&input, None, &serial).unwrap();
+        assert_eq!(parallel_ext, serial_ext, "Both trial orders should pick the same format");
+        assert_eq!(parallel_bytes, serial_bytes);
+    }
 }
 
 #[test]
-fn test_compression_settings_clone() {
-    let settings = CompressionSettings { quality: 80 };
-    let cloned = settings.clone();
-    assert_eq!(cloned.quality, 80);
+fn test_png_quantization_shrinks_flat_color_image() {
+    let input = generate_flat_color_rgba_png(400, 300);
+    
+    let full = CompressionSettings { quality: 95, reduce_png: false, ..Default::default() };
+    let (full_png, _) = compress_image_with_settings(&input, Some("png"), &full).unwrap();
+    
+    let quantized = CompressionSettings { quality: 50, quantize_png: true, ..Default::default() };
+    let (indexed_png, ext) = compress_image_with_settings(&input, Some("png"), &quantized).unwrap();
+    assert_eq!(ext, "png");
+    
+    println!("Full-colour PNG: {} bytes, indexed PNG: {} bytes", full_png.len(), indexed_png.len());
+    assert!(indexed_png.len() * 2 < full_png.len(),
+            "Indexed PNG ({}) should be under half the full-colour PNG ({})",
+            indexed_png.len(), full_png.len());
+    
+    // Still decodes, with the alpha channel preserved
+    let decoded = image::load_from_memory(&indexed_png).expect("Indexed PNG should load").to_rgba8();
+    assert_eq!(decoded.dimensions(), (400, 300));
+    assert!(decoded.pixels().any(|p| p[3] == 128), "Semi-transparent pixels should survive");
+    
+    // Without the opt-in, PNG output stays lossless at any quality
+    let (default_png, _) = compress_image_with_settings(&input, Some("png"), &CompressionSettings { quality: 50, ..Default::default() }).unwrap();
+    assert_eq!(image::load_from_memory(&default_png).unwrap().to_rgba8(), image::load_from_memory(&input).unwrap().to_rgba8());
 }
 
-// ============================================================================
-// Integration Tests - Real-world Scenarios
-// ============================================================================
+#[test]
+fn test_jpeg_output_flattens_transparency_over_chosen_background() {
+    let rgba = image::RgbaImage::from_pixel(64, 64, image::Rgba([200, 0, 0, 128]));
+    let mut input = Vec::new();
+    image::DynamicImage::ImageRgba8(rgba)
+        .write_to(&mut std::io::Cursor::new(&mut input), image::ImageFormat::Png)
+        .unwrap();
+
+    let flattened_pixel = |background: [u8; 3]| {
+        let settings = CompressionSettings { quality: 95, flatten_background: background, ..Default::default() };
+        let (jpeg, ext) = compress_image_with_settings(&input, Some("jpg"), &settings).unwrap();
+        assert_eq!(ext, "jpg");
+        image::load_from_memory(&jpeg).expect("JPEG should load").to_rgb8().get_pixel(32, 32).0
+    };
+    let near = |actual: [u8; 3], expected: [u8; 3]| {
+        actual.iter().zip(expected).all(|(a, e)| (*a as i32 - e as i32).abs() <= 6)
+    };
+
+    // Half of (200, 0, 0) over the background
+    let over_white = flattened_pixel([255, 255, 255]);
+    assert!(near(over_white, [227, 127, 127]), "Default should flatten over white, got {:?}", over_white);
+    let over_blue = flattened_pixel([0, 0, 255]);
+    assert!(near(over_blue, [100, 0, 127]), "Should flatten over the chosen colour, got {:?}", over_blue);
+}
 
 #[test]
-fn test_batch_pdf_compression() {
-    let pdfs = vec![
-        generate_minimal_pdf(),
-        generate_pdf_with_image(),
-        generate_minimal_pdf(),
-    ];
+fn test_png_bit_depth_reduction() {
+    let png_info = |bytes: &[u8]| {
+        let reader = png::Decoder::new(bytes).read_info().expect("Output should be a PNG");
+        let info = reader.info();
+        (info.color_type, info.bit_depth)
+    };
+    let lossless = CompressionSettings { quality: 95, ..Default::default() };
+    let full = CompressionSettings { reduce_png: false, ..lossless.clone() };
     
-    for (i, pdf) in pdfs.iter().enumerate() {
-        let result = compress_pdf_bytes(pdf, 75);
-        assert!(result.is_ok(), "Batch PDF {} should compress successfully", i);
-        println!("Batch PDF {}: {} -> {} bytes", i, pdf.len(), result.unwrap().len());
+    // RGB pixels with R == G == B and too many levels for a small palette
+    let gray_rgb = image::RgbImage::from_fn(300, 200, |x, y| {
+        let level = ((x + y * 3) % 256) as u8;
+        image::Rgb([level, level, level])
+    });
+    let mut input = Vec::new();
+    image::DynamicImage::ImageRgb8(gray_rgb.clone())
+        .write_to(&mut std::io::Cursor::new(&mut input), image::ImageFormat::Png)
+        .unwrap();
+    
+    let (reduced, ext) = compress_image_with_settings(&input, Some("png"), &lossless).unwrap();
+    let (unreduced, _) = compress_image_with_settings(&input, Some("png"), &full).unwrap();
+    assert_eq!(ext, "png");
+    assert_eq!(png_info(&reduced), (png::ColorType::Grayscale, png::BitDepth::Eight));
+    assert_eq!(png_info(&unreduced).0, png::ColorType::Rgb);
+    println!("Gray-content PNG: RGB {} bytes, grayscale {} bytes", unreduced.len(), reduced.len());
+    assert!(reduced.len() < unreduced.len(), "Grayscale PNG ({}) should beat RGB ({})", reduced.len(), unreduced.len());
+    let decoded = image::load_from_memory(&reduced).unwrap().to_rgb8();
+    assert_eq!(decoded, gray_rgb, "Grayscale reduction must be lossless");
+    
+    // A black and white checkerboard fits a 1-bit palette
+    let (few_colors, _) = compress_image_with_settings(&generate_png_image(200, 100), Some("png"), &lossless).unwrap();
+    assert_eq!(png_info(&few_colors), (png::ColorType::Indexed, png::BitDepth::One));
+    let decoded = image::load_from_memory(&few_colors).unwrap().to_rgb8();
+    assert_eq!(decoded, image::load_from_memory(&generate_png_image(200, 100)).unwrap().to_rgb8());
+}
+
+#[test]
+fn test_duplicate_font_programs_are_shared() {
+    let input = generate_pdf_with_duplicate_fonts();
+    
+    let (output, report) = compress_pdf_bytes_reported(&input, 75).unwrap();
+    assert_eq!(report.duplicate_font_programs, 1);
+    
+    let doc = lopdf::Document::load_mem(&output).expect("Output should be a valid PDF");
+    let programs: std::collections::HashSet<lopdf::ObjectId> = doc.objects.values()
+        .filter_map(|obj| obj.as_dict().ok())
+        .filter_map(|dict| dict.get(b"FontFile2").and_then(|o| o.as_reference()).ok())
+        .collect();
+    assert_eq!(programs.len(), 1, "Both descriptors should share one font program");
+    
+    // Every font the page uses still resolves to a descriptor with a valid program
+    let page_id = *doc.get_pages().values().next().unwrap();
+    let fonts = doc.get_page_fonts(page_id);
+    assert_eq!(fonts.len(), 2);
+    for font in fonts.values() {
+        let descriptor_id = font.get(b"FontDescriptor").and_then(|o| o.as_reference()).unwrap();
+        let descriptor = doc.get_dictionary(descriptor_id).unwrap();
+        let program_id = descriptor.get(b"FontFile2").and_then(|o| o.as_reference()).unwrap();
+        let program = doc.get_object(program_id).and_then(|o| o.as_stream()).unwrap();
+        assert_eq!(program.decompressed_content().map(|c| c.len()).unwrap_or(program.content.len()), 8192);
     }
 }
 
 #[test]
-fn test_batch_image_compression() {
-    let images = vec![
-        generate_jpeg_image(200, 200),
-        generate_png_image(300, 200),
-        generate_jpeg_image(150, 150),
-    ];
+fn test_parallel_stream_hashing_merges_the_same_duplicates() {
+    use lopdf::{Dictionary, Object, Stream};
     
-    for (i, img) in images.iter().enumerate() {
-        let result = compress_image_bytes(img, 75, None);
-        assert!(result.is_ok(), "Batch image {} should compress successfully", i);
-        
-        let (compressed, ext) = result.unwrap();
-        println!("Batch image {}: {} -> {} bytes ({})", i, img.len(), compressed.len(), ext);
+    // 20 distinct streams, each repeated three times and interleaved with the others
+    let mut doc = lopdf::Document::load_mem(&generate_minimal_pdf()).unwrap();
+    let mut xobjects = Dictionary::new();
+    for copy in 0..3 {
+        for index in 0..20u8 {
+            let data: Vec<u8> = (0..4096u32).map(|i| (i as u8).wrapping_mul(index | 1).wrapping_add(index)).collect();
+            let id = doc.add_object(Stream::new(image_xobject_dict(64, 64, "DeviceGray"), data));
+            xobjects.set(format!("Im{}_{}", index, copy), Object::Reference(id));
+        }
     }
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    doc.get_dictionary_mut(page_id).unwrap()
+        .set("Resources", Dictionary::from_iter(vec![("XObject", Object::Dictionary(xobjects))]));
+    let mut input = Vec::new();
+    doc.save_to(&mut input).unwrap();
+    
+    let serial_settings = CompressionSettings { image_policy: ImagePolicy::Keep, low_memory: true, ..Default::default() };
+    let parallel_settings = CompressionSettings { low_memory: false, ..serial_settings.clone() };
+    let (serial, serial_report) = compress_pdf_with_settings_reported(&input, &serial_settings).unwrap();
+    let (parallel, parallel_report) = compress_pdf_with_settings_reported(&input, &parallel_settings).unwrap();
+    
+    assert_eq!(serial_report.duplicate_objects, 40);
+    assert_eq!(parallel_report.duplicate_objects, serial_report.duplicate_objects);
+    assert_eq!(parallel, serial, "Parallel hashing should keep exactly the copies serial hashing keeps");
 }
 
 #[test]
-fn test_mixed_quality_compression() {
-    let input = generate_pdf_with_image();
+fn test_duplicate_ext_gstates_are_merged() {
+    use lopdf::{Dictionary, Object};
     
-    // Compress at different quality levels and ensure all succeed
-    for quality in [10, 30, 50, 70, 90].iter() {
-        let result = compress_pdf_bytes(&input, *quality);
-        assert!(result.is_ok(), "Quality {} should work", quality);
+    // Merged documents repeat the same graphics state, here with its entries in another order
+    let mut doc = lopdf::Document::load_mem(&generate_minimal_pdf()).unwrap();
+    let gs1 = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"ExtGState".to_vec())),
+        ("CA", Object::Real(0.5)),
+        ("ca", Object::Real(0.5)),
+    ]));
+    let gs2 = doc.add_object(Dictionary::from_iter(vec![
+        ("ca", Object::Real(0.5)),
+        ("Type", Object::Name(b"ExtGState".to_vec())),
+        ("CA", Object::Real(0.5)),
+    ]));
+    let gs3 = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"ExtGState".to_vec())),
+        ("CA", Object::Real(0.25)),
+    ]));
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let ext_gstates = Dictionary::from_iter(vec![
+        ("GS1", Object::Reference(gs1)),
+        ("GS2", Object::Reference(gs2)),
+        ("GS3", Object::Reference(gs3)),
+    ]);
+    doc.get_dictionary_mut(page_id).unwrap()
+        .set("Resources", Dictionary::from_iter(vec![("ExtGState", Object::Dictionary(ext_gstates))]));
+    let mut input = Vec::new();
+    doc.save_to(&mut input).unwrap();
+    
+    let count_ext_gstates = |output: &[u8]| {
+        let doc = lopdf::Document::load_mem(output).unwrap();
+        let page_id = *doc.get_pages().get(&1).unwrap();
+        let resources = doc.get_dictionary(page_id).unwrap().get(b"Resources").unwrap().as_dict().unwrap();
+        let names = resources.get(b"ExtGState").unwrap().as_dict().unwrap();
+        assert_eq!(names.len(), 3, "Every resource name should still resolve");
+        assert!(names.iter().all(|(_, value)| doc.get_dictionary(value.as_reference().unwrap()).is_ok()));
+        doc.objects.values()
+            .filter_map(|obj| obj.as_dict().ok())
+            .filter(|dict| dict.get(b"Type").and_then(|t| t.as_name()).ok() == Some(b"ExtGState"))
+            .count()
+    };
+    
+    let (output, report) = compress_pdf_with_settings_reported(&input, &CompressionSettings::default()).unwrap();
+    assert_eq!(count_ext_gstates(&output), 2, "The two identical states should collapse to one");
+    assert!(report.duplicate_objects >= 1);
+    
+    let settings = CompressionSettings::builder().dedup_dictionaries(false).build();
+    let output = compress_pdf_with_settings(&input, &settings).unwrap();
+    assert_eq!(count_ext_gstates(&output), 3);
+}
+
+#[test]
+fn test_settings_from_json() {
+    let settings = CompressionSettings::from_json(r#"{
+        "quality": 65,
+        "rounds": 3,
+        "max_dimension": 1024,
+        "strip_metadata": false,
+        "force_grayscale": true,
+        "image_policy": "keep",
+        "flate_level": "fast",
+        "verify": true
+    }"#).expect("Sample config should parse");
+    
+    assert_eq!(settings.quality, 65);
+    assert_eq!(settings.rounds, Some(3));
+    assert_eq!(settings.max_dimension, Some(1024));
+    assert!(!settings.strip_metadata);
+    assert!(settings.force_grayscale);
+    assert_eq!(settings.image_policy, ImagePolicy::Keep);
+    assert_eq!(settings.flate_level, FlateLevel::Fast);
+    assert!(settings.verify && !settings.verify_fallback);
+    
+    // Omitted keys keep their defaults
+    let defaults = CompressionSettings::default();
+    assert!(!settings.lossless);
+    assert_eq!(settings.lossless_preference, defaults.lossless_preference);
+    assert!(settings.protected_objects.is_empty());
+    
+    assert!(CompressionSettings::from_json(r#"{"qualty": 65}"#).is_err(), "Unknown keys should be rejected");
+    assert!(CompressionSettings::from_json("/nonexistent/settings.json").is_err());
+    
+    // parse_json only ever reads its argument as JSON
+    assert_eq!(CompressionSettings::parse_json(r#"{"quality": 65}"#).unwrap().quality, 65);
+    let error = CompressionSettings::parse_json("settings.json").unwrap_err();
+    assert!(error.starts_with("Invalid compression settings"), "Unexpected error: {}", error);
+}
+
+#[test]
+fn test_lossless_and_grayscale_image_settings() {
+    let input = generate_png_image(300, 200);
+    
+    let lossless = CompressionSettings { lossless: true, ..Default::default() };
+    let (output, ext) = compress_image_with_settings(&input, None, &lossless).unwrap();
+    assert_eq!(ext, "png");
+    let original = image::load_from_memory(&input).unwrap().to_rgb8();
+    let decoded = image::load_from_memory(&output).unwrap().to_rgb8();
+    assert_eq!(original, decoded, "Lossless output should keep every pixel");
+    assert!(compress_image_with_settings(&input, Some("jpg"), &lossless).is_err());
+    
+    let gray = CompressionSettings { force_grayscale: true, ..Default::default() };
+    let (output, _) = compress_image_with_settings(&input, Some("jpg"), &gray).unwrap();
+    let decoded = image::load_from_memory(&output).unwrap();
+    assert_eq!(decoded.color().channel_count(), 1, "Grayscale output should be single-channel");
+}
+
+#[test]
+fn test_enormous_declared_image_is_skipped() {
+    // 100000x100000 overflows a u32 pixel count; the few real bytes must not be reinterpreted
+    for (width, height) in [(100_000, 100_000), (65_536, 65_536), (-10, 10), (20_000, 20_000)] {
+        let data = vec![128u8; 300];
+        let image = lopdf::Stream::new(image_xobject_dict(width, height, "DeviceRGB"), data.clone());
+        let input = generate_pdf_with_image_xobject(image);
+        
+        let output = compress_pdf_bytes(&input, 75)
+            .unwrap_or_else(|e| panic!("{}x{} should not fail the document: {}", width, height, e));
+        
+        let doc = lopdf::Document::load_mem(&output).unwrap();
+        let image = doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").and_then(|o| o.as_name()).ok() == Some(b"Image"))
+            .expect("Image XObject should survive");
+        assert_ne!(image.dict.get(b"Filter").and_then(|o| o.as_name()).ok(), Some(&b"DCTDecode"[..]),
+                   "{}x{} image should not be re-encoded", width, height);
     }
 }
 
 #[test]
-fn test_compression_preserves_pdf_structure() {
+fn test_min_reduction_keeps_original() {
     let input = generate_minimal_pdf();
-    let result = compress_pdf_bytes(&input, 75).unwrap();
     
-    // Verify the compressed PDF can be loaded again
-    let doc = lopdf::Document::load_mem(&result);
-    assert!(doc.is_ok(), "Compressed PDF should be loadable");
+    let settings = CompressionSettings { min_reduction_pct: Some(99.0), ..Default::default() };
+    let (output, report) = PDFcompressor::compress_pdf_with_settings_reported(&input, &settings).unwrap();
+    assert!(report.skipped);
+    assert_eq!(output, input, "Original should be returned when the threshold is not met");
     
-    let doc = doc.unwrap();
-    assert!(doc.get_pages().len() > 0, "Should preserve pages");
+    let image = generate_jpeg_image(200, 150);
+    let (output, ext) = compress_image_with_settings(&image, Some("png"), &settings).unwrap();
+    assert_eq!(output, image);
+    assert_eq!(ext, "jpg", "Skipped images keep their original format");
 }
 
-// ============================================================================
-// Edge Case Tests
-// ============================================================================
+#[test]
+fn test_compress_paths_parallel_keeps_input_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let pdf_path = dir.path().join("doc.pdf");
+    let png_path = dir.path().join("image.png");
+    let missing_path = dir.path().join("missing.pdf");
+    std::fs::write(&pdf_path, generate_pdf_with_large_content(2000)).unwrap();
+    std::fs::write(&png_path, generate_png_image(300, 200)).unwrap();
+    
+    let paths = vec![pdf_path, missing_path, png_path];
+    let results = PDFcompressor::compress_paths_parallel(&paths, 75);
+    
+    assert_eq!(results.len(), 3);
+    let (pdf, ext) = results[0].as_ref().expect("PDF should compress");
+    assert_eq!(ext, "pdf");
+    assert!(pdf.starts_with(b"%PDF"));
+    assert!(results[1].as_ref().unwrap_err().contains("Failed to read input file"));
+    let (_, ext) = results[2].as_ref().expect("Image should compress");
+    assert!(ext == "jpg" || ext == "png");
+}
 
 #[test]
-fn test_very_small_pdf() {
-    // Already minimal PDF
-    let input = generate_minimal_pdf();
-    let result = compress_pdf_bytes(&input, 90);
-    assert!(result.is_ok(), "Small PDF should compress");
+fn test_embedded_webp_image_is_reencoded() {
+    use lopdf::Object;
     
-    let compressed = result.unwrap();
-    // Even with high compression, structure overhead means it might not shrink much
-    assert!(compressed.len() > 0);
+    // A noisy image so lossless WebP stays larger than a JPEG of it
+    let mut seed = 7u32;
+    let pixels = image::RgbImage::from_fn(160, 120, |x, y| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        image::Rgb([(x + (seed >> 24)) as u8, (y * 2) as u8, (seed >> 16) as u8])
+    });
+    let mut webp = Vec::new();
+    image::codecs::webp::WebPEncoder::new_lossless(&mut webp)
+        .encode(pixels.as_raw(), 160, 120, image::ColorType::Rgb8)
+        .unwrap();
+    
+    // Stored without a filter, and under a filter name no PDF reader knows; the dictionary's size is stale
+    for filter in [None, Some("WebPDecode")] {
+        let mut dict = image_xobject_dict(80, 60, "DeviceRGB");
+        if let Some(filter) = filter {
+            dict.set("Filter", Object::Name(filter.as_bytes().to_vec()));
+        }
+        let input = generate_pdf_with_image_xobject(lopdf::Stream::new(dict, webp.clone()));
+        let settings = CompressionSettings { quality: 60, ..Default::default() };
+        let output = compress_pdf_with_settings(&input, &settings).unwrap();
+        
+        let doc = lopdf::Document::load_mem(&output).unwrap();
+        let image = doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec())))
+            .expect("Image XObject should remain");
+        assert_eq!(image.dict.get(b"Filter").unwrap().as_name().unwrap(), b"DCTDecode", "{:?} should be re-encoded", filter);
+        assert_eq!(image.dict.get(b"Width").unwrap().as_i64().unwrap(), 160);
+        assert_eq!(image.dict.get(b"Height").unwrap().as_i64().unwrap(), 120);
+        let decoded = image::load_from_memory_with_format(&image.content, image::ImageFormat::Jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (160, 120));
+    }
 }
 
 #[test]
-fn test_very_small_image() {
-    let input = generate_jpeg_image(10, 10);
-    let result = compress_image_bytes(&input, 90, None);
-    assert!(result.is_ok(), "Tiny image should compress");
+fn test_jpx_image_is_kept_unchanged() {
+    use lopdf::Object;
+    
+    // A JP2 signature and header followed by filler; 40x30 RGB makes its length match raw samples
+    let mut jp2 = b"\x00\x00\x00\x0cjP  \r\n\x87\n\x00\x00\x00\x14ftypjp2 \x00\x00\x00\x00jp2 ".to_vec();
+    jp2.resize(40 * 30 * 3, 0x80);
+    
+    for content in [jp2, vec![0x80; 40 * 30 * 3]] {
+        let mut dict = image_xobject_dict(40, 30, "DeviceRGB");
+        dict.set("Filter", Object::Name(b"JPXDecode".to_vec()));
+        // JPXDecode images may omit BitsPerComponent and take it from the codestream
+        dict.remove(b"BitsPerComponent");
+        let input = generate_pdf_with_image_xobject(lopdf::Stream::new(dict, content.clone()));
+        let settings = CompressionSettings { quality: 40, ..Default::default() };
+        let (output, report) = compress_pdf_with_settings_reported(&input, &settings).unwrap();
+        
+        let doc = lopdf::Document::load_mem(&output).unwrap();
+        let image = doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec())))
+            .expect("Image XObject should remain");
+        assert_eq!(image.dict.get(b"Filter").unwrap().as_name().unwrap(), b"JPXDecode");
+        assert_eq!(image.content, content, "JPEG 2000 data should be byte-identical");
+        assert_eq!(report.image_bytes_saved, 0);
+    }
 }
 
 #[test]
-fn test_extreme_compression_levels() {
-    let input = generate_jpeg_image(200, 200);
+fn test_target_dpi_downsamples_to_placed_size() {
+    use lopdf::Object;
     
-    // Test boundary values
-    let min_result = compress_image_bytes(&input, 10, None);
-    assert!(min_result.is_ok(), "Min compression level should work");
+    let mut seed = 11u32;
+    let pixels: Vec<u8> = (0..600 * 400 * 3).map(|_| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 24) as u8
+    }).collect();
+    // Drawn at 2in x 1.33in (144 x 96pt) through a translate then a scale, and smaller elsewhere
+    let content = b"q 1 0 0 1 50 600 cm q 144 0 0 96 0 0 cm /Im1 Do Q Q q 72 0 0 48 300 300 cm /Im1 Do Q";
+    let input = generate_pdf_with_placed_image(
+        lopdf::Stream::new(image_xobject_dict(600, 400, "DeviceRGB"), pixels),
+        content,
+    );
     
-    let max_result = compress_image_bytes(&input, 95, None);
-    assert!(max_result.is_ok(), "Max compression level should work");
+    let image_size = |settings: &CompressionSettings| {
+        let output = compress_pdf_with_settings(&input, settings).unwrap();
+        let doc = lopdf::Document::load_mem(&output).unwrap();
+        let image = doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec())))
+            .expect("Image XObject should remain")
+            .clone();
+        (image.dict.get(b"Width").unwrap().as_i64().unwrap(), image.dict.get(b"Height").unwrap().as_i64().unwrap())
+    };
+    
+    // 600px over 2in is 300 DPI
+    assert_eq!(image_size(&CompressionSettings::default()), (600, 400));
+    assert_eq!(image_size(&CompressionSettings { target_dpi: Some(150), ..Default::default() }), (300, 200));
+    assert_eq!(image_size(&CompressionSettings { target_dpi: Some(300), ..Default::default() }), (600, 400));
+    assert_eq!(image_size(&CompressionSettings { target_dpi: Some(150), lossless: true, ..Default::default() }), (600, 400));
 }
 
 #[test]
-fn test_compression_with_env_var() {
-    // Test that PDF_COMPRESSION_ROUNDS env var is respected
-    std::env::set_var("PDF_COMPRESSION_ROUNDS", "1");
+fn test_max_short_edge_limits_tall_scans() {
+    // A tall page scan: text lines as dark stripes on white
+    let (width, height) = (1000u32, 4000u32);
+    let pixels: Vec<u8> = (0..width * height).map(|i| if (i / width) % 40 < 6 { 20 } else { 240 }).collect();
+    let image_size = |output: &[u8]| {
+        let doc = lopdf::Document::load_mem(output).unwrap();
+        let image = doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").and_then(|o| o.as_name()).ok() == Some(b"Image"))
+            .unwrap();
+        (image.dict.get(b"Width").unwrap().as_i64().unwrap(), image.dict.get(b"Height").unwrap().as_i64().unwrap())
+    };
+    let input = generate_pdf_with_image_xobject(lopdf::Stream::new(image_xobject_dict(width as i64, height as i64, "DeviceGray"), pixels.clone()));
     
-    let input = generate_minimal_pdf();
-    let result = compress_pdf_bytes(&input, 50);
-    assert!(result.is_ok(), "Should work with custom compression rounds");
+    // A 4-megapixel scan can outlast the default per-stream budget on a loaded machine
+    let relaxed = CompressionSettings { stream_timeout_ms: 60_000, ..Default::default() };
     
-    std::env::remove_var("PDF_COMPRESSION_ROUNDS");
+    // The long edge already fits, so only the short-edge cap shrinks the scan
+    let settings = CompressionSettings { max_dimension: Some(4000), max_short_edge: Some(600), ..relaxed.clone() };
+    let output = compress_pdf_with_settings(&input, &settings).unwrap();
+    assert_eq!(image_size(&output), (600, 2400));
+    
+    let settings = CompressionSettings { max_dimension: Some(4000), ..relaxed.clone() };
+    let output = compress_pdf_with_settings(&input, &settings).unwrap();
+    assert_eq!(image_size(&output), (1000, 4000), "Without max_short_edge the scan keeps its size");
+    
+    // The tighter of the two caps governs
+    let settings = CompressionSettings { max_dimension: Some(1200), max_short_edge: Some(600), ..relaxed.clone() };
+    let output = compress_pdf_with_settings(&input, &settings).unwrap();
+    assert_eq!(image_size(&output), (300, 1200));
+    
+    let png = {
+        let mut png = Vec::new();
+        image::GrayImage::from_raw(width, height, pixels).unwrap()
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        png
+    };
+    let settings = CompressionSettings { max_dimension: Some(4000), max_short_edge: Some(600), ..relaxed };
+    let (output, _) = compress_image_with_settings(&png, Some("jpg"), &settings).unwrap();
+    let decoded = image::load_from_memory(&output).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (600, 2400));
 }
 
 #[test]
-fn test_concurrent_compression() {
-    use std::thread;
+fn test_identical_images_are_encoded_once() {
+    use lopdf::Object;
     
-    let handles: Vec<_> = (0..4).map(|i| {
-        thread::spawn(move || {
-            let input = if i % 2 == 0 {
-                generate_minimal_pdf()
-            } else {
-                generate_jpeg_image(200, 200)
-            };
-            
-            if i % 2 == 0 {
-                compress_pdf_bytes(&input, 75)
-            } else {
-                compress_image_bytes(&input, 75, None).map(|(data, _)| data)
-            }
-        })
+    let mut seed = 5u32;
+    let pixels: Vec<u8> = (0..200 * 150 * 3).map(|_| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 24) as u8
     }).collect();
+    let input = generate_pdf_with_image_copies(lopdf::Stream::new(image_xobject_dict(200, 150, "DeviceRGB"), pixels), 3);
     
-    for (i, handle) in handles.into_iter().enumerate() {
-        let result = handle.join();
-        assert!(result.is_ok(), "Thread {} should not panic", i);
-        assert!(result.unwrap().is_ok(), "Thread {} compression should succeed", i);
+    for low_memory in [false, true] {
+        let settings = CompressionSettings { quality: 50, low_memory, ..Default::default() };
+        let (output, report) = compress_pdf_with_settings_reported(&input, &settings).unwrap();
+        assert_eq!(report.duplicate_objects, 0, "Differing dictionaries should keep the copies apart");
+        assert_eq!(report.encoded_images, 1, "Only the first copy should be encoded");
+        assert_eq!(report.reused_image_encodings, 2);
+        
+        let doc = lopdf::Document::load_mem(&output).unwrap();
+        let images: Vec<&lopdf::Stream> = doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .filter(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec())))
+            .collect();
+        assert_eq!(images.len(), 3);
+        let mut names: Vec<&[u8]> = images.iter().map(|image| image.dict.get(b"Name").unwrap().as_name().unwrap()).collect();
+        names.sort();
+        assert_eq!(names, [b"Im1", b"Im2", b"Im3"], "Each copy keeps its own dictionary");
+        for image in &images {
+            assert_eq!(image.dict.get(b"Filter").unwrap().as_name().unwrap(), b"DCTDecode");
+            assert_eq!(image.dict.get(b"Length").unwrap().as_i64().unwrap(), image.content.len() as i64);
+            assert_eq!(image.content, images[0].content);
+        }
     }
 }
 
 #[test]
-fn test_parallel_stream_compression_no_lock_contention() {
-    // Test that parallel stream compression uses atomics instead of mutexes
-    // This test compresses a PDF with multiple streams to verify no lock contention
-    let pdf_with_image = generate_pdf_with_image();
+fn test_decode_array_is_applied_when_reencoding() {
+    use lopdf::Object;
     
-    // Run multiple times to stress test the parallel processing
-    for _ in 0..3 {
-        let result = compress_pdf_bytes(&pdf_with_image, 75);
-        assert!(result.is_ok(), "Parallel stream compression should succeed");
+    // Dark noisy samples, displayed light through an inverting /Decode
+    let mut seed = 3u32;
+    let pixels: Vec<u8> = (0..160 * 120 * 3).map(|_| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        20 + (seed >> 28) as u8
+    }).collect();
+    let mut dict = image_xobject_dict(160, 120, "DeviceRGB");
+    dict.set("Decode", Object::Array([1, 0, 1, 0, 1, 0].into_iter().map(Object::Integer).collect()));
+    dict.set("Intent", Object::Name(b"Perceptual".to_vec()));
+    dict.set("Interpolate", Object::Boolean(true));
+    let input = generate_pdf_with_image_xobject(lopdf::Stream::new(dict, pixels));
+    
+    let settings = CompressionSettings { quality: 50, ..Default::default() };
+    let output = compress_pdf_with_settings(&input, &settings).unwrap();
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    let image = doc.objects.values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .find(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec())))
+        .expect("Image XObject should remain");
+    
+    assert_eq!(image.dict.get(b"Filter").unwrap().as_name().unwrap(), b"DCTDecode");
+    assert!(image.dict.get(b"Decode").is_err(), "Decode is baked into the pixels");
+    assert_eq!(image.dict.get(b"Intent").unwrap().as_name().unwrap(), b"Perceptual");
+    assert_eq!(image.dict.get(b"Interpolate").unwrap(), &Object::Boolean(true));
+    
+    let decoded = image::load_from_memory_with_format(&image.content, image::ImageFormat::Jpeg).unwrap().to_rgb8();
+    let mean = decoded.as_raw().iter().map(|&v| v as u64).sum::<u64>() / decoded.as_raw().len() as u64;
+    assert!(mean > 200, "Colours should stay as displayed (light), got mean {}", mean);
+}
+
+#[test]
+fn test_color_key_masked_image_is_not_jpeg_transcoded() {
+    use lopdf::Object;
+    
+    // Noise that would normally be re-encoded as JPEG, with pure white keyed out
+    let mut seed = 11u32;
+    let pixels: Vec<u8> = (0..200 * 150 * 3).map(|_| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 24) as u8
+    }).collect();
+    let find_image = |doc: &lopdf::Document| doc.objects.values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .find(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec())))
+        .cloned()
+        .unwrap();
+    
+    let color_key = Object::Array(vec![Object::Integer(255); 6]);
+    let mut dict = image_xobject_dict(200, 150, "DeviceRGB");
+    dict.set("Mask", color_key.clone());
+    let input = generate_pdf_with_image_xobject(lopdf::Stream::new(dict, pixels.clone()));
+    
+    let output = compress_pdf_with_settings(&input, &CompressionSettings { quality: 50, ..Default::default() }).unwrap();
+    let image = find_image(&lopdf::Document::load_mem(&output).unwrap());
+    assert_ne!(image.dict.get(b"Filter").ok(), Some(&Object::Name(b"DCTDecode".to_vec())));
+    assert_eq!(image.dict.get(b"Mask").unwrap(), &color_key);
+    assert_eq!(image.decompressed_content().unwrap_or(image.content.clone()), pixels, "Samples must stay exact for the color key");
+    
+    // A stencil mask is its own object, so the JPEG keeps referring to it
+    let mut doc = lopdf::Document::load_mem(&generate_pdf_with_image_xobject(
+        lopdf::Stream::new(image_xobject_dict(200, 150, "DeviceRGB"), pixels),
+    )).unwrap();
+    let stencil_id = doc.add_object(lopdf::Stream::new(lopdf::Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"XObject".to_vec())),
+        ("Subtype", Object::Name(b"Image".to_vec())),
+        ("Width", Object::Integer(8)),
+        ("Height", Object::Integer(8)),
+        ("ImageMask", Object::Boolean(true)),
+        ("BitsPerComponent", Object::Integer(1)),
+    ]), vec![0xF0; 8]));
+    let image_id = doc.objects.iter()
+        .find(|(_, obj)| obj.as_stream().is_ok_and(|s| s.dict.get(b"Width").ok() == Some(&Object::Integer(200))))
+        .map(|(id, _)| *id)
+        .unwrap();
+    doc.get_object_mut(image_id).unwrap().as_stream_mut().unwrap().dict.set("Mask", Object::Reference(stencil_id));
+    let mut input = Vec::new();
+    doc.save_to(&mut input).unwrap();
+    
+    let output = compress_pdf_with_settings(&input, &CompressionSettings { quality: 50, ..Default::default() }).unwrap();
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    let image = doc.objects.values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .find(|stream| stream.dict.has(b"Mask"))
+        .unwrap();
+    assert_eq!(image.dict.get(b"Filter").unwrap().as_name().unwrap(), b"DCTDecode");
+    let stencil = doc.get_object(image.dict.get(b"Mask").unwrap().as_reference().unwrap()).unwrap().as_stream().unwrap();
+    assert_eq!(stencil.dict.get(b"ImageMask").unwrap(), &Object::Boolean(true));
+}
+
+#[test]
+fn test_image_copies_with_different_masks_are_encoded_apart() {
+    use lopdf::Object;
+    
+    let mut seed = 5u32;
+    let pixels: Vec<u8> = (0..200 * 150 * 3).map(|_| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 24) as u8
+    }).collect();
+    let input = generate_pdf_with_image_copies(lopdf::Stream::new(image_xobject_dict(200, 150, "DeviceRGB"), pixels), 3);
+    
+    // The third copy gets a colour-key mask, which must keep it out of JPEG
+    let mut doc = lopdf::Document::load_mem(&input).unwrap();
+    let masked_id = doc.objects.iter()
+        .find(|(_, obj)| obj.as_stream().is_ok_and(|stream| stream.dict.get(b"Name").ok() == Some(&Object::Name(b"Im3".to_vec()))))
+        .map(|(id, _)| *id)
+        .unwrap();
+    let mask = Object::Array([0, 10, 0, 10, 0, 10].map(Object::Integer).to_vec());
+    doc.get_object_mut(masked_id).unwrap().as_stream_mut().unwrap().dict.set("Mask", mask);
+    let mut input = Vec::new();
+    doc.save_to(&mut input).unwrap();
+    
+    let settings = CompressionSettings { quality: 50, ..Default::default() };
+    let (output, report) = compress_pdf_with_settings_reported(&input, &settings).unwrap();
+    assert_eq!(report.encoded_images, 2, "The masked copy should be encoded on its own");
+    assert_eq!(report.reused_image_encodings, 1);
+    
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    for (_, obj) in doc.objects.iter() {
+        let Ok(stream) = obj.as_stream() else { continue };
+        let Ok(name) = stream.dict.get(b"Name").and_then(Object::as_name) else { continue };
+        let filter = stream.dict.get(b"Filter").and_then(Object::as_name).ok();
+        if name == b"Im3" {
+            assert_ne!(filter, Some(b"DCTDecode".as_slice()), "A colour-key masked image must not become JPEG");
+        } else {
+            assert_eq!(filter, Some(b"DCTDecode".as_slice()));
+        }
+    }
+}
+
+#[test]
+fn test_flat_raw_image_falls_back_to_flate() {
+    use lopdf::Object;
+    
+    // Hard-edged colour bands: deflate collapses them, JPEG pays for every edge
+    let raw = image::RgbImage::from_fn(400, 300, |x, y| {
+        let band = (x / 7 + y / 5) % 3;
+        image::Rgb([[255, 0, 0], [0, 0, 255], [255, 255, 255]][band as usize])
+    }).into_raw();
+    let input = generate_pdf_with_image_xobject(lopdf::Stream::new(image_xobject_dict(400, 300, "DeviceRGB"), raw.clone()));
+    
+    let (output, report) = compress_pdf_bytes_reported(&input, 90).unwrap();
+    assert!(report.image_bytes_saved > 0);
+    
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    let image = doc.objects.values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .find(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec())))
+        .expect("Image XObject should remain");
+    assert_eq!(image.dict.get(b"Filter").unwrap().as_name().unwrap(), b"FlateDecode");
+    let mut pixels = Vec::new();
+    std::io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(image.content.as_slice()), &mut pixels).unwrap();
+    assert_eq!(pixels, raw, "Flate keeps the pixels exact");
+}
+
+#[test]
+fn test_separation_image_is_not_jpeg_transcoded() {
+    use lopdf::{Dictionary, Object};
+    use std::io::Read;
+    
+    // Spot-colour tint ramp: one tint byte per pixel, mapped to CMYK by a Type 2 function
+    let tint_transform = Dictionary::from_iter(vec![
+        ("FunctionType", Object::Integer(2)),
+        ("Domain", Object::Array(vec![Object::Integer(0), Object::Integer(1)])),
+        ("C0", Object::Array(vec![Object::Real(0.0); 4])),
+        ("C1", Object::Array(vec![Object::Real(0.0), Object::Real(0.5), Object::Real(1.0), Object::Real(0.0)])),
+        ("N", Object::Integer(1)),
+    ]);
+    let mut dict = image_xobject_dict(200, 200, "DeviceGray");
+    dict.set("ColorSpace", Object::Array(vec![
+        Object::Name(b"Separation".to_vec()),
+        Object::Name(b"PANTONE 123 C".to_vec()),
+        Object::Name(b"DeviceCMYK".to_vec()),
+        Object::Dictionary(tint_transform),
+    ]));
+    let tints: Vec<u8> = (0..200 * 200).map(|i| ((i % 200) + (i / 200)) as u8).collect();
+    let input = generate_pdf_with_image_xobject(lopdf::Stream::new(dict, tints.clone()));
+    
+    let output = compress_pdf_bytes(&input, 90).unwrap();
+    let doc = lopdf::Document::load_mem(&output).expect("Output should load");
+    let image = doc.objects.values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .find(|stream| stream.dict.get(b"Subtype").and_then(|o| o.as_name()).ok() == Some(b"Image"))
+        .expect("Image XObject should survive");
+    
+    assert_eq!(image.dict.get(b"Filter").and_then(|o| o.as_name()).unwrap(), b"FlateDecode",
+               "Separation image should only be flate-compressed");
+    let family = image.dict.get(b"ColorSpace").and_then(|o| o.as_array()).unwrap()[0].as_name().unwrap();
+    assert_eq!(family, b"Separation");
+    
+    let mut decoded = Vec::new();
+    flate2::read::ZlibDecoder::new(&image.content[..]).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, tints, "Tint values must be preserved exactly");
+}
+
+#[test]
+fn test_merge_and_compress_pdfs() {
+    let first = generate_minimal_pdf();
+    let second = generate_minimal_pdf();
+    
+    let merged = merge_and_compress_pdfs(&[first.clone(), second.clone()], 75).unwrap();
+    let doc = lopdf::Document::load_mem(&merged).expect("Merged PDF should load");
+    assert_eq!(doc.get_pages().len(), 2);
+    for page_id in doc.get_pages().values() {
+        assert_eq!(doc.get_page_fonts(*page_id).len(), 1, "Inherited resources should still resolve");
+    }
+    
+    // The same image in both inputs is stored once
+    let with_image = generate_pdf_with_image();
+    let merged = merge_and_compress_pdfs(&[with_image.clone(), with_image.clone()], 75).unwrap();
+    assert!(merged.len() < with_image.len() * 2,
+            "Merged output ({}) should be smaller than the naive sum ({})", merged.len(), with_image.len() * 2);
+    let doc = lopdf::Document::load_mem(&merged).unwrap();
+    assert_eq!(doc.get_pages().len(), 2);
+    let image_count = doc.objects.values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .filter(|stream| stream.dict.get(b"Subtype").and_then(|o| o.as_name()).ok() == Some(b"Image"))
+        .count();
+    assert_eq!(image_count, 1, "Identical images should be deduplicated");
+    
+    assert!(merge_and_compress_pdfs(&[], 75).is_err());
+    assert!(merge_and_compress_pdfs(&[first, generate_corrupted_pdf()], 75).is_err());
+}
+
+#[test]
+fn test_split_and_compress_pages() {
+    use lopdf::Object;
+    
+    let input = images_to_pdf(&[generate_jpeg_image(300, 200), generate_jpeg_image(150, 400)], 75).unwrap();
+    
+    let pages = split_and_compress(&input, 75).unwrap();
+    assert_eq!(pages.len(), 2);
+    for (index, (page, width)) in pages.iter().zip([300, 150]).enumerate() {
+        let doc = lopdf::Document::load_mem(page).expect("Each page should be a loadable PDF");
+        assert_eq!(doc.get_pages().len(), 1);
         
-        let compressed = result.unwrap();
-        assert!(compressed.len() > 0);
-        assert!(compressed.starts_with(b"%PDF"));
+        // Only this page's image comes along
+        let images: Vec<i64> = doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .filter(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec())))
+            .map(|stream| stream.dict.get(b"Width").unwrap().as_i64().unwrap())
+            .collect();
+        assert_eq!(images, [width]);
+        
+        // Naive extraction: delete the other page and save everything else
+        let mut naive = lopdf::Document::load_mem(&input).unwrap();
+        naive.delete_pages(&[2 - index as u32]);
+        let mut naive_bytes = Vec::new();
+        naive.save_to(&mut naive_bytes).unwrap();
+        assert!(page.len() <= naive_bytes.len(), "Page {}: {} vs naive {} bytes", index + 1, page.len(), naive_bytes.len());
     }
     
-    // The fact that this completes quickly without deadlocks confirms
-    // that atomic operations are being used instead of mutex locks
-    println!("✓ Parallel stream compression uses lock-free atomic operations");
+    assert!(split_and_compress(&generate_corrupted_pdf(), 75).is_err());
+}
+
+#[test]
+fn test_flate_bomb_streams_are_kept_unchanged() {
+    use flate2::write::ZlibEncoder;
+    use lopdf::{Dictionary, Object};
+    use std::io::Write;
+    
+    // 32 MiB of zeros deflates to a few dozen KB
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder.write_all(&vec![0u8; 32 * 1024 * 1024]).unwrap();
+    let bomb = encoder.finish().unwrap();
+    
+    let mut image_dict = image_xobject_dict(10, 10, "DeviceRGB");
+    image_dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+    let mut generic_dict = Dictionary::new();
+    generic_dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+    
+    for dict in [image_dict, generic_dict] {
+        let input = generate_pdf_with_image_xobject(lopdf::Stream::new(dict, bomb.clone()));
+        let settings = CompressionSettings { stream_timeout_ms: 2_000, ..Default::default() };
+        
+        let started = std::time::Instant::now();
+        let output = compress_pdf_with_settings(&input, &settings).unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(10),
+                "Bomb should be rejected quickly, took {:?}", started.elapsed());
+        
+        let doc = lopdf::Document::load_mem(&output).expect("Output should load");
+        assert!(doc.objects.values().filter_map(|obj| obj.as_stream().ok()).any(|stream| stream.content == bomb),
+                "Bomb stream should be kept as-is");
+    }
 }
 
+#[test]
+fn test_inline_images_are_recompressed() {
+    let page_content = |pdf: &[u8]| {
+        let doc = lopdf::Document::load_mem(pdf).expect("Output should load");
+        let page_id = *doc.get_pages().values().next().unwrap();
+        doc.get_page_content(page_id).unwrap()
+    };
+    
+    // A single inline image is re-encoded in place
+    let input = generate_pdf_with_inline_image(64, 64, 1);
+    let original = page_content(&input);
+    let (output, report) = compress_pdf_bytes_reported(&input, 75).unwrap();
+    assert_eq!(report.inline_images, 1);
+    
+    let content = page_content(&output);
+    let content_text = String::from_utf8_lossy(&content);
+    assert!(content_text.contains("/F /DCT ID"), "Inline image should be JPEG encoded");
+    assert!(content_text.contains("(BI not an image EI) Tj"), "Text operators should be untouched");
+    assert!(content.len() < original.len(), "Inline image data should shrink: {} -> {}", original.len(), content.len());
+    
+    // Repeated copies are drawn from one shared XObject instead
+    let input = generate_pdf_with_inline_image(64, 64, 3);
+    let (output, report) = compress_pdf_bytes_reported(&input, 75).unwrap();
+    assert_eq!(report.inline_images, 3);
+    
+    let content = String::from_utf8_lossy(&page_content(&output)).into_owned();
+    assert!(!content.contains("BI /W"), "Repeated inline images should be promoted");
+    assert_eq!(content.matches("/InlineIm1 Do").count(), 3);
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    let jpegs = doc.objects.values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .filter(|stream| matches!(stream.dict.get(b"Filter"), Ok(lopdf::Object::Name(name)) if name == b"DCTDecode"))
+        .count();
+    assert_eq!(jpegs, 1);
+}
+
+#[test]
+fn test_inline_images_in_shared_content_stream_stay_inline() {
+    use lopdf::{Dictionary, Document, Object};
+    
+    // A second page drawing the same content stream, with resources of its own
+    let mut doc = Document::load_mem(&generate_pdf_with_inline_image(64, 64, 3)).unwrap();
+    let first_page = *doc.get_pages().values().next().unwrap();
+    let mut second_page = doc.get_dictionary(first_page).unwrap().clone();
+    second_page.set("Resources", Object::Dictionary(Dictionary::new()));
+    let second_page = doc.add_object(second_page);
+    let pages_id = doc.catalog().unwrap().get(b"Pages").unwrap().as_reference().unwrap();
+    let pages = doc.get_object_mut(pages_id).unwrap().as_dict_mut().unwrap();
+    pages.get_mut(b"Kids").unwrap().as_array_mut().unwrap().push(Object::Reference(second_page));
+    pages.set("Count", Object::Integer(2));
+    let mut input = Vec::new();
+    doc.save_to(&mut input).unwrap();
+    
+    let (output, report) = compress_pdf_bytes_reported(&input, 75).unwrap();
+    assert_eq!(report.inline_images, 3, "The shared stream should be rewritten once");
+    
+    let doc = Document::load_mem(&output).unwrap();
+    for page_id in doc.get_pages().into_values() {
+        let content = String::from_utf8_lossy(&doc.get_page_content(page_id).unwrap()).into_owned();
+        assert!(!content.contains(" Do"), "No page should draw an XObject only one page has");
+        assert_eq!(content.matches("/F /DCT ID").count(), 3, "Images should be re-encoded inline");
+    }
+}
+
+#[test]
+fn test_deeply_nested_inline_image_parameters_are_rejected() {
+    // Without a depth limit each bracket costs a stack frame, and the process aborts
+    let mut content = b"BI /W 1 /H 1 /BPC 8 /CS /G /Foo ".to_vec();
+    content.extend(std::iter::repeat_n(b'[', 100_000));
+    content.extend(std::iter::repeat_n(b']', 100_000));
+    content.extend_from_slice(b" ID \x80 EI\nq 100 0 0 100 50 650 cm /Im1 Do Q");
+    let image = lopdf::Stream::new(image_xobject_dict(1, 1, "DeviceGray"), vec![0x80]);
+    let input = generate_pdf_with_placed_image(image, &content);
+    
+    let (output, report) = compress_pdf_bytes_reported(&input, 75).expect("Compression should succeed");
+    assert_eq!(report.inline_images, 0, "The nested image should be skipped");
+    lopdf::Document::load_mem(&output).expect("Output should load");
+}
+
+#[test]
+fn test_progressive_jpeg_output() {
+    // SOF2 marks a progressive frame, SOF0 a baseline one
+    let has_marker = |data: &[u8], marker: u8| data.windows(2).any(|w| w == [0xFF, marker]);
+    let progressive = CompressionSettings { progressive: true, ..Default::default() };
+    
+    let input = generate_jpeg_image(320, 240);
+    let (output, ext) = compress_image_with_settings(&input, Some("jpg"), &progressive).unwrap();
+    assert_eq!(ext, "jpg");
+    assert!(has_marker(&output, 0xC2), "Standalone JPEG should be progressive");
+    image::load_from_memory(&output).expect("Progressive JPEG should decode");
+    
+    // mozjpeg picks progressive scans on its own
+    #[cfg(not(feature = "mozjpeg"))]
+    {
+        let (baseline, _) = compress_image_with_settings(&input, Some("jpg"), &CompressionSettings::default()).unwrap();
+        assert!(!has_marker(&baseline, 0xC2), "Default output should stay baseline");
+    }
+    
+    // Noisy RGB pixels, which flate can't beat JPEG on
+    let mut seed: u32 = 7;
+    let pixels: Vec<u8> = (0..200 * 200 * 3).map(|_| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (seed >> 16) as u8
+    }).collect();
+    let stream = lopdf::Stream::new(image_xobject_dict(200, 200, "DeviceRGB"), pixels);
+    let output = compress_pdf_with_settings(&generate_pdf_with_image_xobject(stream), &progressive).unwrap();
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    let jpeg = doc.objects.values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .find(|stream| stream.dict.get(b"Filter").and_then(|o| o.as_name()).ok() == Some(b"DCTDecode"))
+        .expect("Image stream should be JPEG encoded");
+    assert!(has_marker(&jpeg.content, 0xC2), "Image stream should be progressive");
+}
+
+#[test]
+fn test_optimized_huffman_jpeg_decodes_identically() {
+    let decode = |data: &[u8]| image::load_from_memory(data).expect("JPEG should decode").into_bytes();
+    
+    // Subsampled color, and single-channel noise with restart markers between MCU rows
+    let mut seed: u32 = 3;
+    let noise: Vec<u8> = (0..96 * 80).map(|_| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (seed >> 16) as u8
+    }).collect();
+    let mut gray = Vec::new();
+    let mut encoder = jpeg_encoder::Encoder::new(&mut gray, 85);
+    encoder.set_restart_interval(12);
+    encoder.encode(&noise, 96, 80, jpeg_encoder::ColorType::Luma).unwrap();
+    
+    for input in [generate_jpeg_image(320, 240), gray] {
+        let optimized = optimize_jpeg_huffman(&input).unwrap();
+        assert!(optimized.len() <= input.len(), "{} -> {} bytes", input.len(), optimized.len());
+        assert_eq!(decode(&optimized), decode(&input), "Pixels should be unchanged");
+    }
+    
+    let progressive = CompressionSettings { progressive: true, ..Default::default() };
+    let (progressive, _) = compress_image_with_settings(&generate_jpeg_image(64, 64), Some("jpg"), &progressive).unwrap();
+    assert!(optimize_jpeg_huffman(&progressive).is_err(), "Progressive JPEGs are not re-packed");
+    
+    // Existing DCTDecode images shrink without being re-encoded
+    let jpeg = generate_jpeg_image(320, 240);
+    let mut dict = image_xobject_dict(320, 240, "DeviceRGB");
+    dict.set("Filter", lopdf::Object::Name(b"DCTDecode".to_vec()));
+    let input = generate_pdf_with_image_xobject(lopdf::Stream::new(dict, jpeg.clone()));
+    let settings = CompressionSettings::builder().optimize_huffman(true).build();
+    let output = compress_pdf_with_settings(&input, &settings).unwrap();
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    let image = doc.objects.values()
+        .filter_map(|obj| obj.as_stream().ok())
+        .find(|stream| stream.dict.get(b"Filter").and_then(|o| o.as_name()).ok() == Some(b"DCTDecode"))
+        .expect("Image should stay JPEG");
+    assert!(image.content.len() < jpeg.len());
+    assert_eq!(decode(&image.content), decode(&jpeg));
+}
+
+#[cfg(feature = "mozjpeg")]
+#[test]
+fn test_mozjpeg_output_is_valid_jpeg() {
+    assert_eq!(PDFcompressor::jpeg_backend(), "mozjpeg");
+    
+    let input = generate_jpeg_image(640, 480);
+    let (output, ext) = compress_image_with_settings(&input, Some("jpg"), &CompressionSettings::default()).unwrap();
+    assert_eq!(ext, "jpg");
+    assert_eq!(&output[..2], &[0xFF, 0xD8], "Output should start with SOI");
+    let decoded = image::load_from_memory_with_format(&output, image::ImageFormat::Jpeg).expect("mozjpeg output should decode");
+    assert_eq!((decoded.width(), decoded.height()), (640, 480));
+    
+    // Grayscale stays single-channel
+    let gray = CompressionSettings { force_grayscale: true, ..Default::default() };
+    let (output, _) = compress_image_with_settings(&input, Some("jpg"), &gray).unwrap();
+    let decoded = image::load_from_memory(&output).unwrap();
+    assert_eq!(decoded.color(), image::ColorType::L8);
+}
+
+#[test]
+fn test_load_preview_image() {
+    let preview = PDFcompressor::load_preview_image(&generate_png_image(400, 300), 200).unwrap();
+    assert_eq!((preview.width, preview.height), (200, 150), "Long edge should be capped, keeping aspect");
+    assert_eq!(preview.rgba.len(), 200 * 150 * 4);
+    
+    let small = PDFcompressor::load_preview_image(&generate_jpeg_image(64, 48), 200).unwrap();
+    assert_eq!((small.width, small.height), (64, 48), "Small images keep their size");
+    
+    assert!(PDFcompressor::load_preview_image(&generate_minimal_pdf(), 200).is_err());
+    assert!(PDFcompressor::load_preview_image(&generate_corrupted_image(), 200).is_err());
+}
+
+#[test]
+fn test_outline_and_links_survive_compression() {
+    let input = generate_pdf_with_outline();
+    
+    let output = compress_pdf_bytes(&input, 90).unwrap();
+    let doc = lopdf::Document::load_mem(&output).expect("Output should load");
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let points_at_page_1 = |dict: &lopdf::Dictionary| {
+        dict.get(b"Dest").and_then(|o| o.as_array()).unwrap()[0].as_reference().unwrap() == page_id
+    };
+    
+    let outlines_id = doc.catalog().unwrap().get(b"Outlines").and_then(|o| o.as_reference())
+        .expect("Catalog should keep /Outlines");
+    let outlines = doc.get_dictionary(outlines_id).expect("Outline root should resolve");
+    let item_id = outlines.get(b"First").and_then(|o| o.as_reference()).unwrap();
+    let item = doc.get_dictionary(item_id).expect("Outline item should resolve");
+    assert_eq!(item.get(b"Title").and_then(|o| o.as_str()).unwrap(), b"Chapter 1");
+    assert!(points_at_page_1(item), "Outline entry should still target page 1");
+    
+    let annots = doc.get_dictionary(page_id).unwrap().get(b"Annots").and_then(|o| o.as_array()).unwrap();
+    let link = doc.get_dictionary(annots[0].as_reference().unwrap()).expect("Link annotation should resolve");
+    assert!(points_at_page_1(link), "Link should still target page 1");
+}
+
+#[test]
+fn test_resample_filters_produce_requested_dimensions() {
+    let png = generate_png_image(600, 400);
+    let raw = image::load_from_memory(&png).unwrap().to_rgb8().into_raw();
+    
+    for filter in [ResampleFilter::Nearest, ResampleFilter::Triangle, ResampleFilter::CatmullRom, ResampleFilter::Lanczos3] {
+        let settings = CompressionSettings { resample_filter: filter, max_dimension: Some(150), ..Default::default() };
+        
+        let (output, _) = compress_image_with_settings(&png, Some("jpg"), &settings).unwrap();
+        let decoded = image::load_from_memory(&output).expect("Resampled image should decode");
+        assert_eq!((decoded.width(), decoded.height()), (150, 100), "{:?} standalone", filter);
+        
+        let stream = lopdf::Stream::new(image_xobject_dict(600, 400, "DeviceRGB"), raw.clone());
+        let output = compress_pdf_with_settings(&generate_pdf_with_image_xobject(stream), &settings).unwrap();
+        let doc = lopdf::Document::load_mem(&output).unwrap();
+        let image = doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").and_then(|o| o.as_name()).ok() == Some(b"Image"))
+            .unwrap();
+        let width = image.dict.get(b"Width").and_then(|o| o.as_i64()).unwrap();
+        let height = image.dict.get(b"Height").and_then(|o| o.as_i64()).unwrap();
+        assert_eq!((width, height), (150, 100), "{:?} image stream", filter);
+        let decoded = image::load_from_memory(&image.content).expect("Image stream should be a valid JPEG");
+        assert_eq!((decoded.width(), decoded.height()), (150, 100));
+    }
+    
+    assert_eq!("CatmullRom".parse::<ResampleFilter>().unwrap(), ResampleFilter::CatmullRom);
+    assert!("bicubic".parse::<ResampleFilter>().is_err());
+}
+
+#[test]
+fn test_estimate_pdf_compression_tracks_real_size() {
+    // Photo-like image: smooth gradient with mild noise, large enough to be downsampled
+    let (width, height) = (1600u32, 1200u32);
+    let mut seed: u32 = 11;
+    let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let noise = (seed >> 16) % 24;
+            pixels.extend_from_slice(&[
+                ((x * 255 / width + noise) % 256) as u8,
+                ((y * 255 / height + noise) % 256) as u8,
+                (((x + y) * 128 / (width + height)) + noise) as u8,
+            ]);
+        }
+    }
+    let stream = lopdf::Stream::new(image_xobject_dict(width as i64, height as i64, "DeviceRGB"), pixels);
+    let input = generate_pdf_with_image_xobject(stream);
+    
+    for level in [30, 75, 95] {
+        let started = std::time::Instant::now();
+        let estimate = PDFcompressor::estimate_pdf_compression(&input, level).unwrap();
+        let estimate_time = started.elapsed();
+        let started = std::time::Instant::now();
+        let actual = compress_pdf_bytes(&input, level).unwrap().len() as u64;
+        println!("Level {}: estimate {} bytes in {:?}, actual {} bytes in {:?}",
+                 level, estimate, estimate_time, actual, started.elapsed());
+        
+        assert!(estimate <= actual * 2 && actual <= estimate * 2,
+                "Level {}: estimate {} should be within 2x of actual {}", level, estimate, actual);
+    }
+    
+    assert!(PDFcompressor::estimate_pdf_compression(&generate_corrupted_pdf(), 75).is_err());
+}
+
+#[test]
+fn test_images_to_pdf_two_jpegs() {
+    let images = vec![generate_jpeg_image(300, 200), generate_jpeg_image(150, 400)];
+    
+    let pdf = images_to_pdf(&images, 75).expect("Images to PDF should succeed");
+    assert!(pdf.starts_with(b"%PDF"));
+    
+    let doc = lopdf::Document::load_mem(&pdf).expect("Output should be loadable");
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 2, "One page per image");
+    
+    // Pages are sized to their images
+    let first_page = doc.get_object(pages[&1]).unwrap().as_dict().unwrap();
+    let media_box = first_page.get(b"MediaBox").unwrap().as_array().unwrap();
+    assert_eq!(media_box[2].as_i64().unwrap(), 300);
+    assert_eq!(media_box[3].as_i64().unwrap(), 200);
+}
+
+#[test]
+fn test_images_to_pdf_rejects_empty_and_invalid() {
+    assert!(images_to_pdf(&[], 75).is_err());
+    assert!(images_to_pdf(&[generate_corrupted_image()], 75).is_err());
+}
+
+// ============================================================================
+// Compression Settings Tests
+// ============================================================================
+
+#[test]
+fn test_compression_settings_creation() {
+    let settings = CompressionSettings { quality: 75, ..Default::default() };
+    assert_eq!(settings.quality, 75);
+    assert_eq!(settings.flate_level, FlateLevel::Best);
+}
+
+#[test]
+fn test_compression_settings_clone() {
+    let settings = CompressionSettings { quality: 80, ..Default::default() };
+    let cloned = settings.clone();
+    assert_eq!(cloned.quality, 80);
+}
+
+#[test]
+fn test_flate_levels_produce_valid_flate_streams() {
+    let input = generate_pdf_with_large_content(5000);
+    let expected_content = large_content_stream(5000);
+    
+    for flate_level in [FlateLevel::Fast, FlateLevel::Default, FlateLevel::Best] {
+        let settings = CompressionSettings { flate_level, ..Default::default() };
+        let compressed = compress_pdf_with_settings(&input, &settings)
+            .unwrap_or_else(|e| panic!("{:?} compression failed: {}", flate_level, e));
+        assert!(compressed.len() < input.len(), "{:?} should shrink the content stream", flate_level);
+        
+        // Output must still be valid FlateDecode regardless of backend or level
+        let doc = lopdf::Document::load_mem(&compressed).unwrap();
+        let page_id = *doc.get_pages().values().next().unwrap();
+        let content_ids = doc.get_page_contents(page_id);
+        let stream = doc.get_object(content_ids[0]).unwrap().as_stream().unwrap();
+        assert_eq!(stream.dict.get(b"Filter").unwrap().as_name().unwrap(), b"FlateDecode");
+        assert_eq!(stream.decompressed_content().unwrap(), expected_content);
+    }
+    
+    println!("Flate backend: {}", PDFcompressor::flate_backend());
+}
+
+#[test]
+fn test_existing_flate_streams_skipped_by_default() {
+    use flate2::write::ZlibEncoder;
+    use lopdf::{Dictionary, Object};
+    use std::io::Write;
+    
+    let content = large_content_stream(5000);
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder.write_all(&content).unwrap();
+    let fast_deflated = encoder.finish().unwrap();
+    let mut dict = Dictionary::new();
+    dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+    let input = generate_pdf_with_image_xobject(lopdf::Stream::new(dict, fast_deflated.clone()));
+    
+    let find_stream = |pdf: &[u8]| {
+        let doc = lopdf::Document::load_mem(pdf).unwrap();
+        doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .find(|stream| stream.decompressed_content().ok().as_deref() == Some(content.as_slice()))
+            .map(|stream| stream.content.clone())
+            .expect("Flate stream should survive")
+    };
+    
+    // Off: the flate stream is left byte-for-byte alone
+    let (output, report) = compress_pdf_with_settings_reported(&input, &CompressionSettings::default()).unwrap();
+    assert_eq!(find_stream(&output), fast_deflated);
+    assert_eq!(report.flate_bytes_saved, 0);
+    
+    // On: it is re-deflated at the configured level
+    let settings = CompressionSettings { recompress_existing_flate: true, ..Default::default() };
+    let (output, report) = compress_pdf_with_settings_reported(&input, &settings).unwrap();
+    assert!(find_stream(&output).len() < fast_deflated.len());
+    assert!(report.flate_bytes_saved > 0);
+    
+    // Raw streams are deflated either way
+    let raw = generate_pdf_with_large_content(5000);
+    assert!(compress_pdf_with_settings(&raw, &CompressionSettings::default()).unwrap().len() < raw.len() / 2);
+}
+
+#[test]
+fn test_low_memory_mode_matches_parallel_output() {
+    for input in [generate_pdf_with_image(), generate_pdf_with_large_content(5000), generate_pdf_with_duplicate_fonts()] {
+        let (parallel, parallel_report) = compress_pdf_with_settings_reported(&input, &CompressionSettings::default()).unwrap();
+        let settings = CompressionSettings { low_memory: true, ..Default::default() };
+        let (sequential, sequential_report) = compress_pdf_with_settings_reported(&input, &settings).unwrap();
+        
+        assert_eq!(sequential, parallel, "Low-memory mode should produce identical output");
+        assert_eq!(sequential_report.image_bytes_saved, parallel_report.image_bytes_saved);
+        assert_eq!(sequential_report.flate_bytes_saved, parallel_report.flate_bytes_saved);
+    }
+}
+
+#[test]
+fn test_protected_objects_left_untouched() {
+    let input = generate_pdf_with_image();
+    let doc = lopdf::Document::load_mem(&input).unwrap();
+    let (image_id, original_image) = doc.objects.iter()
+        .find_map(|(id, obj)| match obj {
+            lopdf::Object::Stream(s) if s.dict.get(b"Subtype").and_then(|o| o.as_name()).ok() == Some(b"Image".as_slice()) => {
+                Some((*id, s.clone()))
+            }
+            _ => None,
+        })
+        .expect("Fixture should contain an image");
+    
+    let mut settings = CompressionSettings::default();
+    settings.protected_objects.insert(image_id);
+    let compressed = compress_pdf_with_settings(&input, &settings).unwrap();
+    
+    let out = lopdf::Document::load_mem(&compressed).unwrap();
+    let image = out.get_object(image_id).unwrap().as_stream().unwrap();
+    assert_eq!(image.content, original_image.content, "Protected image content must be byte-identical");
+    assert_eq!(image.dict.get(b"Filter").ok(), original_image.dict.get(b"Filter").ok());
+}
+
+// ============================================================================
+// Integration Tests - Real-world Scenarios
+// ============================================================================
+
+#[test]
+fn test_batch_pdf_compression() {
+    let pdfs = [generate_minimal_pdf(),
+        generate_pdf_with_image(),
+        generate_minimal_pdf()];
+    
+    for (i, pdf) in pdfs.iter().enumerate() {
+        let result = compress_pdf_bytes(pdf, 75);
+        assert!(result.is_ok(), "Batch PDF {} should compress successfully", i);
+        println!("Batch PDF {}: {} -> {} bytes", i, pdf.len(), result.unwrap().len());
+    }
+}
+
+#[test]
+fn test_batch_image_compression() {
+    let images = [generate_jpeg_image(200, 200),
+        generate_png_image(300, 200),
+        generate_jpeg_image(150, 150)];
+    
+    for (i, img) in images.iter().enumerate() {
+        let result = compress_image_bytes(img, 75, None);
+        assert!(result.is_ok(), "Batch image {} should compress successfully", i);
+        
+        let (compressed, ext) = result.unwrap();
+        println!("Batch image {}: {} -> {} bytes ({})", i, img.len(), compressed.len(), ext);
+    }
+}
+
+#[test]
+fn test_mixed_quality_compression() {
+    let input = generate_pdf_with_image();
+    
+    // Compress at different quality levels and ensure all succeed
+    for quality in [10, 30, 50, 70, 90].iter() {
+        let result = compress_pdf_bytes(&input, *quality);
+        assert!(result.is_ok(), "Quality {} should work", quality);
+    }
+}
+
+#[test]
+fn test_compression_preserves_pdf_structure() {
+    let input = generate_minimal_pdf();
+    let result = compress_pdf_bytes(&input, 75).unwrap();
+    
+    // Verify the compressed PDF can be loaded again
+    let doc = lopdf::Document::load_mem(&result);
+    assert!(doc.is_ok(), "Compressed PDF should be loadable");
+    
+    let doc = doc.unwrap();
+    assert!(!doc.get_pages().is_empty(), "Should preserve pages");
+}
+
+// ============================================================================
+// Edge Case Tests
+// ============================================================================
+
+#[test]
+fn test_verify_detects_page_changes() {
+    use lopdf::{Document, Object};
+    
+    let input = generate_pdf_with_outline();
+    let settings = CompressionSettings { verify: true, ..Default::default() };
+    let output = compress_pdf_with_settings(&input, &settings).expect("Intact output should verify");
+    assert!(verify_pdf_structure(&input, &output).is_ok());
+    
+    // Simulate a pruning bug that shrinks a page
+    let mut doc = Document::load_mem(&output).unwrap();
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    doc.get_dictionary_mut(page_id).unwrap()
+        .set("MediaBox", Object::Array(vec![0.into(), 0.into(), 300.into(), 300.into()]));
+    let mut resized = Vec::new();
+    doc.save_to(&mut resized).unwrap();
+    let err = verify_pdf_structure(&input, &resized).unwrap_err();
+    assert!(err.contains("page 1 MediaBox"), "Unexpected error: {}", err);
+    
+    // And one that loses a page: the input has two, the output one
+    let mut doc = Document::load_mem(&input).unwrap();
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let copy_id = doc.add_object(doc.get_dictionary(page_id).unwrap().clone());
+    let pages_id = doc.get_dictionary(page_id).unwrap().get(b"Parent").unwrap().as_reference().unwrap();
+    let pages = doc.get_dictionary_mut(pages_id).unwrap();
+    pages.set("Kids", Object::Array(vec![Object::Reference(page_id), Object::Reference(copy_id)]));
+    pages.set("Count", 2);
+    let mut two_pages = Vec::new();
+    doc.save_to(&mut two_pages).unwrap();
+    let err = verify_pdf_structure(&two_pages, &output).unwrap_err();
+    assert!(err.contains("page count changed from 2 to 1"), "Unexpected error: {}", err);
+    
+    assert!(verify_pdf_structure(&input, b"not a pdf").is_err());
+}
+
+#[test]
+fn test_xref_stream_and_hybrid_layouts() {
+    for layout in [XrefLayout::Table, XrefLayout::Stream, XrefLayout::Hybrid] {
+        let input = generate_pdf_with_xref_layout(layout);
+        let settings = CompressionSettings { verify: true, ..Default::default() };
+        let (output, report) = compress_pdf_with_settings_reported(&input, &settings)
+            .unwrap_or_else(|e| panic!("{:?} layout should compress: {}", layout, e));
+        assert!(output.len() < input.len(), "{:?} layout should shrink: {} -> {}", layout, input.len(), output.len());
+        assert!(report.flate_bytes_saved > 0);
+        
+        let doc = lopdf::Document::load_mem(&output).expect("Output should load");
+        let pages = doc.get_pages();
+        assert_eq!(pages.len(), 1, "{:?} layout should keep its page", layout);
+        let content = doc.get_page_content(pages[&1]).unwrap();
+        assert_eq!(content, large_content_stream(200));
+        
+        // Offsets and encodings of the input's xref must not leak into the rewritten trailer
+        for key in [&b"XRefStm"[..], b"Prev", b"DecodeParms"] {
+            assert!(!doc.trailer.has(key), "{:?} layout left /{} in the trailer", layout, String::from_utf8_lossy(key));
+        }
+        assert!(!doc.objects.values().any(|obj| obj.type_name().ok() == Some("ObjStm")));
+    }
+}
+
+#[test]
+fn test_very_small_pdf() {
+    // Already minimal PDF
+    let input = generate_minimal_pdf();
+    let result = compress_pdf_bytes(&input, 90);
+    assert!(result.is_ok(), "Small PDF should compress");
+    
+    let compressed = result.unwrap();
+    // Even with high compression, structure overhead means it might not shrink much
+    assert!(!compressed.is_empty());
+}
+
+#[test]
+fn test_very_small_image() {
+    let input = generate_jpeg_image(10, 10);
+    let result = compress_image_bytes(&input, 90, None);
+    assert!(result.is_ok(), "Tiny image should compress");
+}
+
+#[test]
+fn test_extreme_compression_levels() {
+    let input = generate_jpeg_image(200, 200);
+    
+    // Test boundary values
+    let min_result = compress_image_bytes(&input, 10, None);
+    assert!(min_result.is_ok(), "Min compression level should work");
+    
+    let max_result = compress_image_bytes(&input, 95, None);
+    assert!(max_result.is_ok(), "Max compression level should work");
+}
+
+/// Serializes tests that set PDF_COMPRESSION_ROUNDS, which is process-global
+static ROUNDS_ENV: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[test]
+fn test_compression_with_env_var() {
+    // Test that PDF_COMPRESSION_ROUNDS env var is respected
+    let _env = ROUNDS_ENV.lock().unwrap_or_else(|e| e.into_inner());
+    std::env::set_var("PDF_COMPRESSION_ROUNDS", "1");
+    
+    let input = generate_minimal_pdf();
+    let result = compress_pdf_bytes(&input, 50);
+    assert!(result.is_ok(), "Should work with custom compression rounds");
+    
+    std::env::remove_var("PDF_COMPRESSION_ROUNDS");
+}
+
+#[test]
+fn test_settings_rounds_ignore_the_env_var() {
+    let _env = ROUNDS_ENV.lock().unwrap_or_else(|e| e.into_inner());
+    std::env::set_var("PDF_COMPRESSION_ROUNDS", "1");
+    let input = generate_pdf_with_large_content(200);
+    // No convergence check, so every configured round runs
+    let settings = CompressionSettings { round_convergence_pct: 0.0, ..Default::default() };
+    
+    let explicit = CompressionSettings { rounds: Some(3), ..settings.clone() };
+    let (_, report) = compress_pdf_with_settings_reported(&input, &explicit).unwrap();
+    assert_eq!(report.rounds_run, 3, "The rounds setting should win over the env var");
+    
+    let (_, report) = compress_pdf_with_settings_reported(&input, &settings).unwrap();
+    assert_eq!(report.rounds_run, 2, "Unset rounds should use the default, not the env var");
+    
+    // The level-based entry points keep honoring it
+    let (_, report) = compress_pdf_bytes_reported(&input, 75).unwrap();
+    assert_eq!(report.rounds_run, 1);
+    
+    std::env::remove_var("PDF_COMPRESSION_ROUNDS");
+}
+
+#[test]
+fn test_compress_pdf_streams_to_a_file() {
+    let input = generate_pdf_with_large_content(500);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.pdf");
+    
+    let file = std::fs::File::create(&path).unwrap();
+    compress_pdf(std::io::Cursor::new(&input), file, &CompressionSettings::default()).unwrap();
+    let written = std::fs::read(&path).unwrap();
+    assert!(written.starts_with(b"%PDF"));
+    assert!(written.len() < input.len(), "{} -> {} bytes", input.len(), written.len());
+    let doc = lopdf::Document::load_mem(&written).unwrap();
+    assert_eq!(doc.get_pages().len(), 1);
+    
+    // Settings that judge the finished output still hold back anything that fails them
+    let settings = CompressionSettings { min_reduction_pct: Some(100.0), ..Default::default() };
+    let mut output = Vec::new();
+    compress_pdf(input.as_slice(), &mut output, &settings).unwrap();
+    assert_eq!(output, input, "Unmet min_reduction_pct writes the original");
+    
+    assert!(compress_pdf(&b"not a pdf"[..], std::io::sink(), &CompressionSettings::default()).is_err());
+}
+
+#[test]
+fn test_zopfli_flate_is_valid_and_not_larger() {
+    let input = generate_pdf_with_large_content(2000);
+    let content_stream = |output: &[u8]| {
+        let doc = lopdf::Document::load_mem(output).unwrap();
+        let page_id = *doc.get_pages().get(&1).unwrap();
+        let content_id = doc.get_page_contents(page_id)[0];
+        doc.get_object(content_id).unwrap().as_stream().unwrap().clone()
+    };
+    
+    let flate2 = content_stream(&compress_pdf_with_settings(&input, &CompressionSettings::default()).unwrap());
+    let settings = CompressionSettings { zopfli_min_bytes: Some(16 * 1024), ..Default::default() };
+    let zopfli = content_stream(&compress_pdf_with_settings(&input, &settings).unwrap());
+    
+    assert_eq!(zopfli.dict.get(b"Filter").unwrap().as_name().unwrap(), b"FlateDecode");
+    assert_eq!(zopfli.decompressed_content().unwrap(), large_content_stream(2000));
+    assert!(zopfli.content.len() <= flate2.content.len(), "zopfli {} vs flate2 {} bytes", zopfli.content.len(), flate2.content.len());
+    #[cfg(feature = "zopfli")]
+    assert!(zopfli.content.len() < flate2.content.len(), "zopfli should find a smaller encoding");
+}
+
+#[test]
+fn test_manifest_digests_match_input_and_output() {
+    use sha2::{Digest, Sha256};
+    
+    let hex = |data: &[u8]| Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    let input = generate_pdf_with_large_content(500);
+    let settings = CompressionSettings { quality: 60, ..Default::default() };
+    
+    let (output, report, manifest) = compress_pdf_with_manifest(&input, &settings).unwrap();
+    assert_eq!(manifest.input_sha256, hex(&input));
+    assert_eq!(manifest.output_sha256, hex(&output));
+    assert_ne!(manifest.input_sha256, manifest.output_sha256);
+    assert_eq!((manifest.input_size, manifest.output_size), (report.original_size, report.final_size));
+    assert_eq!(manifest.crate_version, env!("CARGO_PKG_VERSION"));
+    
+    let json = serde_json::to_value(&manifest).unwrap();
+    assert_eq!(json["settings"]["quality"], 60);
+    assert_eq!(json["settings"]["flate_level"], "best");
+}
+
+#[test]
+fn test_cmyk_jpeg_colors_are_preserved() {
+    use jpeg_encoder::{ColorType, Encoder};
+    use lopdf::Object;
+    
+    // Pure cyan, written with an Adobe APP14 marker as plain CMYK and as YCCK
+    let pixels: Vec<u8> = (0..64 * 64).flat_map(|_| [255u8, 0, 0, 0]).collect();
+    for color_type in [ColorType::Cmyk, ColorType::CmykAsYcck] {
+        let mut jpeg = Vec::new();
+        Encoder::new(&mut jpeg, 95).encode(&pixels, 64, 64, color_type).unwrap();
+        
+        let mut dict = image_xobject_dict(64, 64, "DeviceCMYK");
+        dict.set("Filter", Object::Name(b"DCTDecode".to_vec()));
+        let input = generate_pdf_with_image_xobject(lopdf::Stream::new(dict, jpeg.clone()).with_compression(false));
+        let settings = CompressionSettings { quality: 30, max_dimension: Some(32), ..Default::default() };
+        let output = compress_pdf_with_settings(&input, &settings).unwrap();
+        
+        let doc = lopdf::Document::load_mem(&output).unwrap();
+        let image = doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec())))
+            .unwrap();
+        assert_eq!(image.content, jpeg, "{:?} JPEG inside a PDF is left untouched", color_type);
+        assert_eq!(image.dict.get(b"ColorSpace").unwrap().as_name().unwrap(), b"DeviceCMYK");
+        
+        // Standalone, the decoder applies the Adobe transform, so colours survive conversion to RGB
+        let (output, _) = compress_image_with_settings(&jpeg, Some("jpg"), &settings).unwrap();
+        let decoded = image::load_from_memory(&output).unwrap().to_rgb8();
+        let pixel = decoded.get_pixel(decoded.width() / 2, decoded.height() / 2);
+        assert!(pixel[0] < 30 && pixel[1] > 225 && pixel[2] > 225, "{:?} came out as {:?}", color_type, pixel);
+    }
+}
+
+#[test]
+fn test_lzw_streams_are_recompressed_as_flate() {
+    let original = large_content_stream(5000);
+    let lzw = lzw_encode(&original);
+    let mut doc = lopdf::Document::load_mem(&generate_pdf_with_large_content(5000)).unwrap();
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let content_id = doc.get_page_contents(page_id)[0];
+    let stream = doc.get_object_mut(content_id).unwrap().as_stream_mut().unwrap();
+    stream.dict.set("Filter", lopdf::Object::Name(b"LZWDecode".to_vec()));
+    stream.set_content(lzw.clone());
+    let mut input = Vec::new();
+    doc.save_to(&mut input).unwrap();
+    
+    let compressed = compress_pdf_bytes(&input, 75).unwrap();
+    
+    let doc = lopdf::Document::load_mem(&compressed).unwrap();
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let stream = doc.get_object(doc.get_page_contents(page_id)[0]).unwrap().as_stream().unwrap();
+    assert_eq!(stream.dict.get(b"Filter").unwrap().as_name().unwrap(), b"FlateDecode");
+    assert!(stream.content.len() < lzw.len(),
+            "Flate stream ({} bytes) should be smaller than the LZW one ({} bytes)", stream.content.len(), lzw.len());
+    assert_eq!(stream.decompressed_content().unwrap(), original);
+}
+
+#[test]
+fn test_cascaded_filters_are_decoded_to_a_single_filter() {
+    use lopdf::Object;
+    use std::io::{Read, Write};
+    
+    let deflate = |data: &[u8]| {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    };
+    // lopdf refuses to decode image streams itself
+    let inflate = |data: &[u8]| {
+        let mut decoded = Vec::new();
+        flate2::read::ZlibDecoder::new(data).read_to_end(&mut decoded).unwrap();
+        decoded
+    };
+    let chain = |names: &[&str]| Object::Array(names.iter().map(|name| Object::Name(name.as_bytes().to_vec())).collect());
+    
+    // Samples that only look like an image once ASCII85 and then flate are undone
+    let (width, height) = (64u32, 48u32);
+    let pixels: Vec<u8> = (0..width * height).flat_map(|i| [(i % width * 4) as u8, (i / width * 5) as u8, 128]).collect();
+    let mut image = lopdf::Stream::new(image_xobject_dict(width as i64, height as i64, "DeviceRGB"), ascii85_encode(&deflate(&pixels)));
+    image.dict.set("Filter", chain(&["ASCII85Decode", "FlateDecode"]));
+    image.dict.set("DecodeParms", Object::Array(vec![Object::Null, Object::Null]));
+    let mut doc = lopdf::Document::load_mem(&generate_pdf_with_image_xobject(image)).unwrap();
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let content_id = doc.get_page_contents(page_id)[0];
+    let content = doc.get_object(content_id).unwrap().as_stream().unwrap().content.clone();
+    let stream = doc.get_object_mut(content_id).unwrap().as_stream_mut().unwrap();
+    stream.dict.set("Filter", chain(&["ASCIIHexDecode", "FlateDecode"]));
+    stream.set_content(deflate(&content).iter().map(|byte| format!("{:02X}", byte)).collect::<String>().into_bytes());
+    let mut input = Vec::new();
+    doc.save_to(&mut input).unwrap();
+    
+    let image_of = |output: &[u8]| {
+        let doc = lopdf::Document::load_mem(output).unwrap();
+        let content = doc.get_object(doc.get_page_contents(page_id)[0]).unwrap().as_stream().unwrap().clone();
+        let image = doc.objects.values()
+            .filter_map(|object| object.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image"))
+            .unwrap()
+            .clone();
+        (content, image)
+    };
+    
+    // Lossless: both chains collapse into plain flate holding the same bytes
+    let settings = CompressionSettings { lossless: true, ..Default::default() };
+    let (content_stream, image_stream) = image_of(&compress_pdf_with_settings(&input, &settings).unwrap());
+    assert_eq!(content_stream.dict.get(b"Filter").unwrap().as_name().unwrap(), b"FlateDecode");
+    assert_eq!(content_stream.decompressed_content().unwrap(), content);
+    assert_eq!(image_stream.dict.get(b"Filter").unwrap().as_name().unwrap(), b"FlateDecode");
+    assert!(!image_stream.dict.has(b"DecodeParms"));
+    assert_eq!(inflate(&image_stream.content), pixels);
+    
+    // Lossy: the decoded samples are re-encoded, not the ASCII85 text
+    let (_, image_stream) = image_of(&compress_pdf_bytes(&input, 75).unwrap());
+    if image_stream.dict.get(b"Filter").unwrap().as_name().unwrap() == b"DCTDecode" {
+        let decoded = image::load_from_memory(&image_stream.content).unwrap().to_rgb8();
+        assert_eq!(decoded.dimensions(), (width, height));
+        let pixel = decoded.get_pixel(32, 24);
+        assert!(pixel[0].abs_diff(128) < 12 && pixel[1].abs_diff(120) < 12 && pixel[2].abs_diff(128) < 12, "{:?}", pixel);
+    } else {
+        assert_eq!(inflate(&image_stream.content), pixels);
+    }
+    
+    // JPEG data wrapped in ASCII85 comes out as plain DCTDecode
+    let jpeg = generate_jpeg_image(64, 48);
+    let mut wrapped = lopdf::Stream::new(image_xobject_dict(64, 48, "DeviceRGB"), ascii85_encode(&jpeg));
+    wrapped.dict.set("Filter", chain(&["ASCII85Decode", "DCTDecode"]));
+    let (_, image_stream) = image_of(&compress_pdf_bytes(&generate_pdf_with_image_xobject(wrapped), 75).unwrap());
+    assert_eq!(image_stream.dict.get(b"Filter").unwrap().as_name().unwrap(), b"DCTDecode");
+    assert_eq!(image_stream.content, jpeg);
+}
+
+#[test]
+fn test_settings_builder_keeps_defaults_for_unset_fields() {
+    let settings = CompressionSettings::builder()
+        .quality(70)
+        .rounds(3)
+        .force_grayscale(true)
+        .build();
+    
+    let expected = CompressionSettings {
+        quality: 70,
+        rounds: Some(3),
+        force_grayscale: true,
+        ..CompressionSettings::default()
+    };
+    assert_eq!(serde_json::to_value(&settings).unwrap(), serde_json::to_value(&expected).unwrap());
+    
+    let by_level = CompressionSettings::builder().level(90).build();
+    assert_eq!(by_level.quality, PDFcompressor::compression_level_to_quality(90));
+}
+
+#[test]
+fn test_predicted_flate_streams_round_trip() {
+    use lopdf::{Dictionary, Object};
+    use std::io::Write;
+    
+    const COLUMNS: usize = 64;
+    let mut original = large_content_stream(3000);
+    original.resize(original.len().next_multiple_of(COLUMNS), b' ');
+    
+    // TIFF predictor 2 and PNG Sub rows (predictor 11) both store byte differences
+    let tiff: Vec<u8> = original.chunks(COLUMNS)
+        .flat_map(|row| (0..COLUMNS).map(|i| if i == 0 { row[0] } else { row[i].wrapping_sub(row[i - 1]) }).collect::<Vec<_>>())
+        .collect();
+    let png: Vec<u8> = tiff.chunks(COLUMNS).flat_map(|row| std::iter::once(1).chain(row.iter().copied())).collect();
+    
+    for (predictor, predicted) in [(2, tiff), (11, png)] {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&predicted).unwrap();
+        let stored = encoder.finish().unwrap();
+        
+        let mut doc = lopdf::Document::load_mem(&generate_pdf_with_large_content(10)).unwrap();
+        let page_id = *doc.get_pages().get(&1).unwrap();
+        let stream = doc.get_object_mut(doc.get_page_contents(page_id)[0]).unwrap().as_stream_mut().unwrap();
+        stream.dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+        stream.dict.set("DecodeParms", Dictionary::from_iter(vec![
+            ("Predictor", Object::Integer(predictor)),
+            ("Columns", Object::Integer(COLUMNS as i64)),
+        ]));
+        stream.set_content(stored.clone());
+        let mut input = Vec::new();
+        doc.save_to(&mut input).unwrap();
+        
+        let settings = CompressionSettings { recompress_existing_flate: true, ..Default::default() };
+        let compressed = compress_pdf_with_settings(&input, &settings).unwrap();
+        
+        let doc = lopdf::Document::load_mem(&compressed).unwrap();
+        let page_id = *doc.get_pages().get(&1).unwrap();
+        let stream = doc.get_object(doc.get_page_contents(page_id)[0]).unwrap().as_stream().unwrap();
+        assert!(stream.content.len() < stored.len(), "Predictor {}: stream should have been recompressed", predictor);
+        assert!(!stream.dict.has(b"DecodeParms"), "Predictor {}: stale DecodeParms left behind", predictor);
+        assert_eq!(stream.decompressed_content().unwrap(), original, "Predictor {}: content changed", predictor);
+    }
+}
+
+#[test]
+fn test_single_thread_pool_matches_shared_pool() {
+    let input = generate_pdf_with_image();
+    
+    let shared = compress_pdf_with_settings(&input, &CompressionSettings::default()).unwrap();
+    let settings = CompressionSettings::builder().max_threads(1).build();
+    let single = compress_pdf_with_settings(&input, &settings).unwrap();
+    
+    assert_eq!(single, shared);
+    verify_pdf_structure(&input, &single).unwrap();
+}
+
+#[test]
+fn test_producer_records_crate_version() {
+    let input = generate_pdf_with_large_content(500);
+    
+    let output = compress_pdf_bytes(&input, 75).unwrap();
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    let info_id = doc.trailer.get(b"Info").unwrap().as_reference().unwrap();
+    let producer = doc.get_dictionary(info_id).unwrap().get(b"Producer").unwrap().as_str().unwrap();
+    let producer = String::from_utf8_lossy(producer);
+    // The number is the JPEG quality level 75 maps to, not the level itself
+    assert_eq!(producer, format!("{} {} (JPEG quality 50)", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")));
+    
+    let settings = CompressionSettings::builder().set_producer(false).build();
+    let output = compress_pdf_with_settings(&input, &settings).unwrap();
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    assert!(doc.trailer.get(b"Info").is_err());
+}
+
+#[test]
+fn test_is_better_compressed_prefers_smaller_equivalent() {
+    use std::cmp::Ordering;
+    
+    let original = generate_pdf_with_large_content(2000);
+    let compressed = compress_pdf_bytes(&original, 75).unwrap();
+    assert!(compressed.len() < original.len());
+    
+    assert_eq!(is_better_compressed(&compressed, &original).unwrap(), Ordering::Greater);
+    assert_eq!(is_better_compressed(&original, &compressed).unwrap(), Ordering::Less);
+    assert_eq!(is_better_compressed(&compressed, &compressed).unwrap(), Ordering::Equal);
+    
+    // A file with different pages is not an equivalent, whatever its size
+    let two_pages = merge_and_compress_pdfs(&[generate_minimal_pdf(), generate_minimal_pdf()], 75).unwrap();
+    assert!(two_pages.len() < original.len());
+    assert!(is_better_compressed(&two_pages, &original).unwrap_err().contains("page count"));
+    assert!(is_better_compressed(&generate_corrupted_pdf(), &original).is_err());
+}
+
+#[test]
+fn test_convert_to_srgb_maps_adobe_rgb_pixels() {
+    use moxcms::{ColorProfile, Layout, TransformOptions};
+    
+    let adobe_rgb = ColorProfile::new_adobe_rgb();
+    let (width, height) = (32u32, 32u32);
+    let pixels: Vec<u8> = (0..width * height).flat_map(|i| [(i % 256) as u8, 160, 60]).collect();
+    
+    let mut info = png::Info::with_size(width, height);
+    info.color_type = png::ColorType::Rgb;
+    info.bit_depth = png::BitDepth::Eight;
+    info.icc_profile = Some(adobe_rgb.encode().unwrap().into());
+    let mut input = Vec::new();
+    let mut writer = png::Encoder::with_info(&mut input, info).unwrap().write_header().unwrap();
+    writer.write_image_data(&pixels).unwrap();
+    writer.finish().unwrap();
+    
+    let mut expected = vec![0u8; pixels.len()];
+    adobe_rgb.create_transform_8bit(Layout::Rgb, &ColorProfile::new_srgb(), Layout::Rgb, TransformOptions::default())
+        .unwrap()
+        .transform(&pixels, &mut expected)
+        .unwrap();
+    
+    let settings = CompressionSettings::builder().lossless(true).convert_to_srgb(true).build();
+    let (output, _) = compress_image_with_settings(&input, Some("png"), &settings).unwrap();
+    let converted = image::load_from_memory(&output).unwrap().to_rgb8().into_raw();
+    
+    // Adobe RGB has a wider gamut, so the same values are more saturated colors than in sRGB
+    let distance = |a: &[u8], b: &[u8]| a.iter().zip(b).map(|(x, y)| (*x as i32 - *y as i32).abs()).max().unwrap();
+    assert!(distance(&converted, &expected) <= 1, "Output should match an Adobe RGB to sRGB transform");
+    assert!(distance(&converted, &pixels) > 10, "Pixels should have moved toward sRGB");
+    
+    // Without the option the pixels are kept as they are
+    let settings = CompressionSettings::builder().lossless(true).build();
+    let (output, _) = compress_image_with_settings(&input, Some("png"), &settings).unwrap();
+    assert_eq!(image::load_from_memory(&output).unwrap().to_rgb8().into_raw(), pixels);
+}
+
+#[test]
+fn test_report_lists_each_image() {
+    let input = generate_pdf_with_image();
+    
+    let (output, report) = compress_pdf_bytes_reported(&input, 75).unwrap();
+    
+    assert_eq!(report.images.len(), 1, "{:?}", report.images);
+    let image = &report.images[0];
+    assert_eq!((image.original_width, image.original_height), (10, 10));
+    assert_eq!((image.output_width, image.output_height), (10, 10));
+    assert!(!image.downsampled);
+    assert_eq!(image.original_filter, None);
+    assert_eq!(image.original_bytes, 10 * 10 * 3);
+    
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    let stream = doc.objects.values()
+        .filter_map(|object| object.as_stream().ok())
+        .find(|stream| stream.dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Image"))
+        .unwrap();
+    assert_eq!(image.output_bytes, stream.content.len() as u64);
+}
+
+#[test]
+fn test_image_bomb_is_rejected_before_decoding() {
+    let bomb = generate_png_bomb(60_000, 60_000);
+    assert!(bomb.len() < 1024);
+    
+    let error = compress_image_bytes(&bomb, 75, None).unwrap_err();
+    assert!(error.contains("Image too large: 60000x60000"), "Unexpected error: {}", error);
+    
+    // The budget is configurable, down to rejecting ordinary images
+    let settings = CompressionSettings::builder().max_image_pixels(100 * 100).build();
+    let error = compress_image_with_settings(&generate_png_image(200, 200), None, &settings).unwrap_err();
+    assert!(error.contains("exceeds 10000 pixels"), "Unexpected error: {}", error);
+    assert!(compress_image_with_settings(&generate_png_image(100, 100), None, &settings).is_ok());
+}
+
+#[test]
+fn test_raster_only_keeps_content_streams_byte_identical() {
+    let (width, height) = (400u32, 300u32);
+    let pixels: Vec<u8> = (0..width * height)
+        .flat_map(|i| [(i % width) as u8, (i / width) as u8, ((i * 7) % 251) as u8])
+        .collect();
+    let image = lopdf::Stream::new(image_xobject_dict(width as i64, height as i64, "DeviceRGB"), pixels.clone());
+    let mut content = b"q 400 0 0 300 0 0 cm /Im1 Do Q\n".to_vec();
+    content.extend(large_content_stream(2000));
+    let input = generate_pdf_with_placed_image(image, &content);
+    
+    let page_streams = |output: &[u8]| {
+        let doc = lopdf::Document::load_mem(output).unwrap();
+        let page_id = *doc.get_pages().get(&1).unwrap();
+        let content = doc.get_object(doc.get_page_contents(page_id)[0]).unwrap().as_stream().unwrap().clone();
+        let image = doc.objects.values()
+            .filter_map(|object| object.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Image"))
+            .unwrap()
+            .clone();
+        (content, image)
+    };
+    
+    let settings = CompressionSettings::builder().raster_only(true).build();
+    let (kept, image) = page_streams(&compress_pdf_with_settings(&input, &settings).unwrap());
+    assert_eq!(kept.content, content);
+    assert!(!kept.dict.has(b"Filter"));
+    assert!(image.content.len() < pixels.len() / 4, "Image should still be compressed ({} bytes)", image.content.len());
+    
+    // By default the same content stream gets deflated
+    let (deflated, _) = page_streams(&compress_pdf_with_settings(&input, &CompressionSettings::default()).unwrap());
+    assert!(deflated.content.len() < content.len());
+}
+
+#[test]
+fn test_slow_pdf_load_gives_up_at_timeout() {
+    // Hundreds of thousands of objects keep the parser busy far longer than the budget
+    let mut doc = lopdf::Document::load_mem(&generate_minimal_pdf()).unwrap();
+    for i in 0..300_000 {
+        doc.add_object(lopdf::Object::Integer(i));
+    }
+    let mut input = Vec::new();
+    doc.save_to(&mut input).unwrap();
+    
+    let settings = CompressionSettings::builder().load_timeout_ms(1).build();
+    let started = std::time::Instant::now();
+    let error = compress_pdf_with_settings(&input, &settings).unwrap_err();
+    assert!(error.contains("Failed to load PDF: gave up after 1 ms"), "Unexpected error: {}", error);
+    assert!(started.elapsed() < std::time::Duration::from_secs(1), "Should return as soon as the budget runs out");
+    
+    // The timed-out parser may still be running and sharing the CPU, so allow the retry more time
+    let relaxed = CompressionSettings::builder().load_timeout_ms(60_000).build();
+    assert!(compress_pdf_with_settings(&input, &relaxed).is_ok());
+}
+
+#[test]
+fn test_remove_images_leaves_text_only() {
+    use lopdf::{Dictionary, Object};
+    
+    // generate_pdf_with_image draws its image only; give the page some text as well
+    let mut doc = lopdf::Document::load_mem(&generate_pdf_with_image()).unwrap();
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let content_id = doc.get_page_contents(page_id)[0];
+    let text = b"BT /F1 12 Tf 72 720 Td (Hello text) Tj ET\n";
+    let content = doc.get_object_mut(content_id).unwrap().as_stream_mut().unwrap();
+    let mut with_text = text.to_vec();
+    with_text.extend_from_slice(&content.content);
+    content.set_content(with_text);
+    let font_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Font".to_vec())),
+        ("Subtype", Object::Name(b"Type1".to_vec())),
+        ("BaseFont", Object::Name(b"Helvetica".to_vec())),
+    ]));
+    let resources_id = doc.get_dictionary(page_id).unwrap().get(b"Resources").unwrap().as_reference().unwrap();
+    doc.get_dictionary_mut(resources_id).unwrap()
+        .set("Font", Dictionary::from_iter(vec![("F1", Object::Reference(font_id))]));
+    let mut input = Vec::new();
+    doc.save_to(&mut input).unwrap();
+    
+    let settings = CompressionSettings::builder().remove_images(true).build();
+    let (output, report) = compress_pdf_with_settings_reported(&input, &settings).unwrap();
+    assert_eq!(report.removed_images, 1);
+    assert!(output.len() < input.len());
+    
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    assert!(!doc.objects.values().any(|object| object.as_stream()
+        .is_ok_and(|stream| stream.dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Image"))));
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let content = lopdf::content::Content::decode(&doc.get_page_content(page_id).unwrap()).unwrap();
+    assert!(content.operations.iter().all(|operation| operation.operator != "Do"));
+    assert!(content.operations.iter().any(|operation| operation.operator == "Tj"
+        && operation.operands.first().and_then(|text| text.as_str().ok()) == Some(b"Hello text".as_slice())));
+    let resources_id = doc.get_dictionary(page_id).unwrap().get(b"Resources").unwrap().as_reference().unwrap();
+    let xobjects = doc.get_dictionary(resources_id).unwrap().get(b"XObject").unwrap().as_dict().unwrap();
+    assert!(!xobjects.has(b"Im1"), "The resource entry should go with the image");
+}
+
+#[test]
+fn test_images_inside_form_xobjects_are_found() {
+    use lopdf::Object;
+    
+    let mut seed = 17u32;
+    let pixels: Vec<u8> = (0..600 * 400 * 3).map(|_| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 24) as u8
+    }).collect();
+    let image = lopdf::Stream::new(image_xobject_dict(600, 400, "DeviceRGB"), pixels);
+    let find_image = |output: &[u8]| {
+        let doc = lopdf::Document::load_mem(output).unwrap();
+        doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Image".to_vec())))
+            .map(|image| (image.dict.get(b"Width").unwrap().as_i64().unwrap(), image.dict.get(b"Height").unwrap().as_i64().unwrap()))
+    };
+    
+    // The form's Matrix draws the image at 2in x 1.33in, so 150 DPI halves it
+    for form_resources in [true, false] {
+        let input = generate_pdf_with_image_in_form(image.clone(), [144, 0, 0, 96, 0, 0], form_resources);
+        let settings = CompressionSettings { target_dpi: Some(150), ..Default::default() };
+        let (output, report) = compress_pdf_with_settings_reported(&input, &settings).unwrap();
+        assert_eq!(find_image(&output), Some((300, 200)), "form_resources: {}", form_resources);
+        assert!(report.image_bytes_saved > 0);
+        
+        // Removal reaches the Do inside the form, whichever resources it draws from
+        let settings = CompressionSettings::builder().remove_images(true).build();
+        let (output, report) = compress_pdf_with_settings_reported(&input, &settings).unwrap();
+        assert_eq!(report.removed_images, 1, "form_resources: {}", form_resources);
+        assert_eq!(find_image(&output), None);
+        let doc = lopdf::Document::load_mem(&output).unwrap();
+        let form = doc.objects.values()
+            .filter_map(|obj| obj.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").ok() == Some(&Object::Name(b"Form".to_vec())))
+            .expect("Form XObject should remain");
+        let content = lopdf::content::Content::decode(&form.decompressed_content().unwrap_or(form.content.clone())).unwrap();
+        assert!(content.operations.iter().all(|operation| operation.operator != "Do"));
+    }
+}
+
+#[test]
+fn test_detect_pdf_kind_tells_scans_from_digital_documents() {
+    let load = |pdf: &[u8]| lopdf::Document::load_mem(pdf).expect("Fixture should load");
+    assert_eq!(detect_pdf_kind(&load(&generate_minimal_pdf())), PdfKind::Digital);
+    assert_eq!(detect_pdf_kind(&load(&generate_scanned_pdf(3, 200, 200))), PdfKind::Scanned);
+    // A small image on a page is still a digital document
+    assert_eq!(detect_pdf_kind(&load(&generate_pdf_with_image())), PdfKind::Digital);
+    
+    // Auto strategy downsamples scans by resolution: 2000 px stretched over 11 in is about 180 DPI,
+    // which comes down to 1650 px at 150 DPI
+    // Generous stream budget: a 2000 px image takes a while in debug builds under parallel tests
+    let settings = CompressionSettings { auto_strategy: true, stream_timeout_ms: 60_000, ..Default::default() };
+    let (output, report) = compress_pdf_with_settings_reported(&generate_scanned_pdf(1, 2000, 2000), &settings)
+        .expect("Compression should succeed");
+    assert_eq!(report.detected_kind, Some(PdfKind::Scanned));
+    assert!(report.images.iter().all(|image| image.output_width == 1650), "{:?}", report.images);
+    assert!(load(&output).get_pages().len() == 1);
+    
+    let (_, report) = compress_pdf_with_settings_reported(&generate_minimal_pdf(), &settings).expect("Compression should succeed");
+    assert_eq!(report.detected_kind, Some(PdfKind::Digital));
+}
+
+#[test]
+fn test_resource_entries_of_deleted_images_are_pruned() {
+    // An image deleted without its resource entry, leaving /Im1 pointing nowhere
+    let mut doc = lopdf::Document::load_mem(&generate_pdf_with_image_xobject(
+        lopdf::Stream::new(image_xobject_dict(10, 10, "DeviceRGB"), vec![128; 300]),
+    )).unwrap();
+    let image_id = *doc.objects.iter()
+        .find(|(_, object)| object.as_stream().is_ok_and(|stream| stream.dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Image")))
+        .unwrap().0;
+    doc.objects.remove(&image_id);
+    let mut input = Vec::new();
+    doc.save_to(&mut input).unwrap();
+    let xobject_names = |output: &[u8]| {
+        let doc = lopdf::Document::load_mem(output).unwrap();
+        let page_id = *doc.get_pages().get(&1).unwrap();
+        let resources = doc.get_dictionary(page_id).unwrap().get(b"Resources").unwrap().as_dict().unwrap();
+        let xobjects = resources.get(b"XObject").unwrap().as_dict().unwrap();
+        let dangling = xobjects.iter().any(|(_, value)| doc.get_object(value.as_reference().unwrap()).is_err());
+        (xobjects.len(), dangling)
+    };
+    
+    let (output, report) = compress_pdf_with_settings_reported(&input, &CompressionSettings::default()).unwrap();
+    assert_eq!(xobject_names(&output), (0, false), "The deleted image should leave the XObject dictionary");
+    assert_eq!(report.dangling_resources_removed, 1);
+    
+    let settings = CompressionSettings::builder().prune_resources(false).build();
+    let (output, report) = compress_pdf_with_settings_reported(&input, &settings).unwrap();
+    assert_eq!(xobject_names(&output), (1, true));
+    assert_eq!(report.dangling_resources_removed, 0);
+}
+
+#[test]
+fn test_conservative_prune_keeps_placeholder_streams() {
+    use lopdf::{Dictionary, Object, Stream};
+    
+    // An application keeps an empty stream in the catalog to fill in later
+    let mut doc = lopdf::Document::load_mem(&generate_minimal_pdf()).unwrap();
+    let placeholder_id = doc.add_object(Stream::new(Dictionary::new(), Vec::new()));
+    doc.add_object(Dictionary::from_iter(vec![("Note", Object::string_literal("unused"))]));
+    let catalog_id = doc.trailer.get(b"Root").unwrap().as_reference().unwrap();
+    doc.get_dictionary_mut(catalog_id).unwrap().set("AppData", Object::Reference(placeholder_id));
+    let mut input = Vec::new();
+    doc.save_to(&mut input).unwrap();
+    
+    let placeholder = |output: &[u8]| {
+        let doc = lopdf::Document::load_mem(output).unwrap();
+        let catalog = doc.catalog().unwrap();
+        let kept = catalog.get(b"AppData").ok()
+            .and_then(|entry| entry.as_reference().ok())
+            .is_some_and(|id| doc.get_object(id).and_then(|object| object.as_stream()).is_ok());
+        let orphan_kept = doc.objects.values().any(|object| object.as_dict().is_ok_and(|dict| dict.has(b"Note")));
+        (kept, orphan_kept)
+    };
+    
+    let aggressive = compress_pdf_with_settings(&input, &CompressionSettings::default()).unwrap();
+    assert_eq!(placeholder(&aggressive), (false, false), "Aggressive pruning drops the empty stream and its reference");
+    
+    let settings = CompressionSettings::builder().aggressive_prune(false).build();
+    let conservative = compress_pdf_with_settings(&input, &settings).unwrap();
+    assert_eq!(placeholder(&conservative), (true, false), "Only the unreferenced object should go");
+}
+
+#[test]
+fn test_detect_input_type_finds_pdf_after_bom() {
+    let mut input = b"\xEF\xBB\xBF".to_vec();
+    input.extend(generate_pdf_with_large_content(500));
+    assert_eq!(detect_input_type(&input, None), InputType::Pdf);
+    assert_eq!(detect_input_type(&input, Some("upload.bin")), InputType::Pdf);
+    
+    let output = compress_pdf_bytes(&input, 75).expect("Leading junk should be skipped when loading");
+    assert!(output.starts_with(b"%PDF"));
+    assert!(verify_pdf_structure(&input, &output).is_ok());
+}
+
+#[test]
+fn test_preserve_pdfa_keeps_output_intent_metadata_and_icc_images() {
+    let input = generate_pdfa_pdf();
+    let catalog_entry = |doc: &lopdf::Document, key: &[u8]| doc.catalog().unwrap().get(key).ok().cloned();
+    let image_color_space = |doc: &lopdf::Document| doc.objects.values()
+        .filter_map(|object| object.as_stream().ok())
+        .find(|stream| stream.dict.get(b"Subtype").and_then(|o| o.as_name()).ok() == Some(b"Image"))
+        .and_then(|stream| stream.dict.get(b"ColorSpace").ok().cloned())
+        .unwrap();
+    
+    let settings = CompressionSettings { preserve_pdfa: true, ..Default::default() };
+    let (output, report) = compress_pdf_with_settings_reported(&input, &settings).expect("Compression should succeed");
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    assert!(report.pdfa_note.as_deref().is_some_and(|note| note.starts_with("PDF/A-2b")), "{:?}", report.pdfa_note);
+    
+    let intents = catalog_entry(&doc, b"OutputIntents").expect("OutputIntents should survive");
+    let intent = doc.dereference(&intents.as_array().unwrap()[0]).unwrap().1.as_dict().unwrap();
+    assert_eq!(intent.get(b"S").unwrap().as_name().unwrap(), b"GTS_PDFA1");
+    assert!(doc.dereference(intent.get(b"DestOutputProfile").unwrap()).is_ok(), "The output profile should survive");
+    
+    let metadata = catalog_entry(&doc, b"Metadata").expect("XMP metadata should be kept");
+    let xmp = doc.dereference(&metadata).unwrap().1.as_stream().unwrap();
+    assert!(xmp.dict.get(b"Filter").is_err(), "The XMP packet should stay unfiltered");
+    assert!(xmp.content.windows(11).any(|window| window == b"pdfaid:part"));
+    assert!(matches!(image_color_space(&doc), lopdf::Object::Array(ref items) if items[0].as_name().unwrap() == b"ICCBased"));
+    
+    // Without the flag, compression strips the claim and re-encodes the image in a device space
+    let (output, report) = compress_pdf_with_settings_reported(&input, &CompressionSettings::default()).unwrap();
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    assert!(report.pdfa_note.is_none());
+    assert!(catalog_entry(&doc, b"Metadata").and_then(|metadata| doc.dereference(&metadata).ok().map(|_| ())).is_none());
+    assert!(matches!(image_color_space(&doc), lopdf::Object::Name(_)));
+    
+    // Documents without a claim compress as usual with the flag on
+    let (_, report) = compress_pdf_with_settings_reported(&generate_scanned_pdf(1, 50, 50), &settings).unwrap();
+    assert!(report.pdfa_note.is_none());
+}
+
+#[test]
+fn test_validate_input_reads_pdf_and_image_metadata() {
+    let pdf = generate_scanned_pdf(3, 20, 20);
+    assert_eq!(validate_input(&pdf), Ok(InputKind::Pdf { version: "1.5".to_string(), pages: Some(3) }));
+    
+    // Cross-reference streams are found too; the page tree inside an object stream isn't read
+    let table = validate_input(&generate_pdf_with_xref_layout(XrefLayout::Table)).unwrap();
+    assert!(matches!(table, InputKind::Pdf { pages: Some(1), .. }), "{:?}", table);
+    let stream = validate_input(&generate_pdf_with_xref_layout(XrefLayout::Stream)).unwrap();
+    assert!(matches!(stream, InputKind::Pdf { pages: None, .. }), "{:?}", stream);
+    
+    assert_eq!(
+        validate_input(&generate_png_image(64, 48)),
+        Ok(InputKind::Image { format: "png", width: 64, height: 48 })
+    );
+    assert_eq!(
+        validate_input(&generate_jpeg_image(120, 80)),
+        Ok(InputKind::Image { format: "jpg", width: 120, height: 80 })
+    );
+}
+
+#[test]
+fn test_validate_input_rejects_corrupt_files() {
+    let pdf = generate_minimal_pdf();
+    let truncated = validate_input(&pdf[..pdf.len() / 2]).unwrap_err();
+    assert!(truncated.contains("%%EOF"), "{}", truncated);
+    
+    let no_xref = validate_input(&generate_corrupted_pdf()).unwrap_err();
+    assert!(no_xref.contains("startxref"), "{}", no_xref);
+    
+    // A startxref past the end of the file
+    let mut bad_offset = pdf[..pdf.len() - 64.min(pdf.len())].to_vec();
+    bad_offset.extend_from_slice(b"\nstartxref\n99999999\n%%EOF\n");
+    assert!(validate_input(&bad_offset).unwrap_err().contains("cross-reference"));
+    
+    // A startxref of usize::MAX, behind junk before the header, can't overflow the header adjustment
+    let mut huge_offset = b"junk\n".to_vec();
+    huge_offset.extend_from_slice(&pdf[..pdf.len() - 64.min(pdf.len())]);
+    huge_offset.extend_from_slice(format!("\nstartxref\n{}\n%%EOF\n", usize::MAX).as_bytes());
+    assert!(validate_input(&huge_offset).unwrap_err().contains("cross-reference"));
+    
+    assert!(validate_input(&generate_corrupted_image()).unwrap_err().contains("Corrupt"));
+    assert!(validate_input(b"plain text, not a document").unwrap_err().contains("Unsupported"));
+    assert!(validate_input(b"").is_err());
+}
+
+#[test]
+fn test_broken_page_tree_is_rebuilt_from_page_objects() {
+    use lopdf::{Document, Object, ObjectId};
+    
+    let mut doc = Document::load_mem(&generate_scanned_pdf(3, 200, 200)).unwrap();
+    let page_ids: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let pages_id = doc.catalog().unwrap().get(b"Pages").unwrap().as_reference().unwrap();
+    let pages = doc.get_object_mut(pages_id).unwrap().as_dict_mut().unwrap();
+    pages.set("Kids", Object::Array(Vec::new()));
+    pages.set("Count", Object::Integer(0));
+    let save = |doc: &mut Document| {
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).unwrap();
+        bytes
+    };
+    let empty_kids = save(&mut doc);
+    assert!(Document::load_mem(&empty_kids).unwrap().get_pages().is_empty());
+    
+    let output = compress_pdf_bytes(&empty_kids, 75).expect("Pages should be recovered");
+    let recovered = Document::load_mem(&output).unwrap();
+    assert_eq!(recovered.get_pages().into_values().collect::<Vec<_>>().len(), 3);
+    for page_id in recovered.get_pages().into_values() {
+        let (resources, _) = recovered.get_page_resources(page_id);
+        assert!(resources.is_some_and(|resources| resources.has(b"XObject")), "Each page should keep its image");
+    }
+    
+    // A dangling /Root is rebuilt too
+    doc.trailer.set("Root", Object::Reference((9999, 0)));
+    let dangling_root = save(&mut doc);
+    let output = compress_pdf_bytes(&dangling_root, 75).expect("Root should be recovered");
+    assert_eq!(Document::load_mem(&output).unwrap().get_pages().len(), 3);
+    
+    // Without page objects there is nothing to rebuild from
+    for page_id in page_ids {
+        doc.objects.remove(&page_id);
+    }
+    let error = compress_pdf_bytes(&save(&mut doc), 75).unwrap_err();
+    assert!(error.contains("no pages"), "{}", error);
+}
+
+#[test]
+fn test_detect_input_type_uses_extension_for_headerless_jpeg() {
+    // A stray byte after SOI hides the JPEG signature, but decoders skip it like libjpeg does
+    let jpeg = generate_jpeg_image(120, 80);
+    let mut input = jpeg[..2].to_vec();
+    input.push(0x00);
+    input.extend_from_slice(&jpeg[2..]);
+    assert_eq!(detect_input_type(&input, None), InputType::Unknown);
+    assert_eq!(detect_input_type(&input, Some("photo.JPG")), InputType::Image(image::ImageFormat::Jpeg));
+    
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("photo.jpg");
+    std::fs::write(&path, &input).unwrap();
+    let (output, ext) = PDFcompressor::compress_path(&path, 75).expect("Named JPEG should compress");
+    assert_eq!(ext, "jpg");
+    assert_eq!(image::load_from_memory(&output).unwrap().width(), 120);
+}
+
+#[test]
+fn test_grayscale_images_stay_device_gray() {
+    let (width, height) = (300u32, 300u32);
+    // Noisy scan-like pixels, so JPEG wins over flate
+    let mut state = 0x2545F491u32;
+    let gray: Vec<u8> = (0..width * height)
+        .map(|i| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            ((i % width) as u8 / 2).wrapping_add((state % 64) as u8)
+        })
+        .collect();
+    let rgb: Vec<u8> = gray.iter().flat_map(|&value| [value; 3]).collect();
+    
+    let compressed_image = |color_space: &str, samples: Vec<u8>| {
+        let image = lopdf::Stream::new(image_xobject_dict(width as i64, height as i64, color_space), samples);
+        let input = generate_pdf_with_placed_image(image, b"q 300 0 0 300 0 0 cm /Im1 Do Q");
+        let output = compress_pdf_with_settings(&input, &CompressionSettings::default()).unwrap();
+        let doc = lopdf::Document::load_mem(&output).unwrap();
+        doc.objects.values()
+            .filter_map(|object| object.as_stream().ok())
+            .find(|stream| stream.dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Image"))
+            .unwrap()
+            .clone()
+    };
+    
+    let gray_output = compressed_image("DeviceGray", gray);
+    assert_eq!(gray_output.dict.get(b"ColorSpace").unwrap().as_name().unwrap(), b"DeviceGray");
+    assert_eq!(gray_output.dict.get(b"Filter").unwrap().as_name().unwrap(), b"DCTDecode");
+    let decoded = image::load_from_memory_with_format(&gray_output.content, image::ImageFormat::Jpeg).unwrap();
+    assert_eq!(decoded.color(), image::ColorType::L8, "The JPEG itself should have one channel");
+    
+    // The same pixels as RGB stand in for the old promote-to-RGB path
+    let rgb_output = compressed_image("DeviceRGB", rgb);
+    assert!(gray_output.content.len() < rgb_output.content.len(),
+            "Gray JPEG ({} bytes) should be smaller than the RGB one ({} bytes)", gray_output.content.len(), rgb_output.content.len());
+}
+
+#[test]
+fn test_normalize_pdf_keeps_content_and_image_encoding() {
+    use lopdf::{Document, Object, Stream};
+    
+    let jpeg = generate_jpeg_image(200, 150);
+    let mut dict = image_xobject_dict(200, 150, "DeviceRGB");
+    dict.set("Filter", Object::Name(b"DCTDecode".to_vec()));
+    let input = generate_pdf_with_image_copies(Stream::new(dict, jpeg.clone()), 2);
+    
+    // Mess it up: an orphaned stream and an indirect /Length on the page content
+    let mut messy = Document::load_mem(&input).unwrap();
+    let orphan_id = messy.add_object(Stream::new(lopdf::Dictionary::new(), b"garbage nobody uses".to_vec()));
+    let page_id = *messy.get_pages().values().next().unwrap();
+    let content_id = messy.get_page_contents(page_id)[0];
+    let length = messy.get_object(content_id).unwrap().as_stream().unwrap().content.len() as i64;
+    let length_id = messy.add_object(Object::Integer(length));
+    messy.get_object_mut(content_id).unwrap().as_stream_mut().unwrap().dict.set("Length", Object::Reference(length_id));
+    let mut input = Vec::new();
+    messy.save_to(&mut input).unwrap();
+    let original = Document::load_mem(&input).unwrap();
+    
+    let output = PDFcompressor::normalize_pdf(&input).expect("Normalizing should succeed");
+    let normalized = Document::load_mem(&output).expect("Output should be a valid PDF");
+    assert!(normalized.get_object(orphan_id).is_err(), "The orphaned stream should be pruned");
+    assert!(normalized.get_object(length_id).is_err(), "The /Length object should be inlined and pruned");
+    
+    let content = |doc: &Document| {
+        let page_id = *doc.get_pages().values().next().unwrap();
+        doc.get_page_content(page_id).unwrap()
+    };
+    assert_eq!(content(&normalized), content(&original), "Page content should be unchanged");
+    
+    let images = |doc: &Document| -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut images: Vec<_> = doc.objects.values()
+            .filter_map(|object| object.as_stream().ok())
+            .filter(|stream| stream.dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Image"))
+            .map(|stream| (stream.dict.get(b"Filter").unwrap().as_name().unwrap().to_vec(), stream.content.clone()))
+            .collect();
+        images.sort();
+        images
+    };
+    let normalized_images = images(&normalized);
+    assert_eq!(normalized_images.len(), 2);
+    assert_eq!(normalized_images, images(&original), "Images should keep their filters and bytes");
+    assert!(normalized_images.iter().all(|(filter, data)| filter == b"DCTDecode" && *data == jpeg));
+}
+
+#[test]
+fn test_compression_rounds_stop_at_convergence() {
+    let input = generate_minimal_pdf();
+    
+    let converging = CompressionSettings { rounds: Some(5), ..Default::default() };
+    let (converged_output, report) = compress_pdf_with_settings_reported(&input, &converging).unwrap();
+    assert!(report.rounds_run >= 1 && report.rounds_run < 5, "Expected early convergence, ran {} rounds", report.rounds_run);
+    
+    // A zero threshold runs every round, for the same result
+    let exhaustive = CompressionSettings { rounds: Some(5), round_convergence_pct: 0.0, ..Default::default() };
+    let (output, report) = compress_pdf_with_settings_reported(&input, &exhaustive).unwrap();
+    assert_eq!(report.rounds_run, 5);
+    assert_eq!(output, converged_output);
+    
+    // The hard cap still applies
+    let capped = CompressionSettings { rounds: Some(50), round_convergence_pct: 0.0, ..Default::default() };
+    assert_eq!(compress_pdf_with_settings_reported(&input, &capped).unwrap().1.rounds_run, 5);
+}
+
+#[test]
+fn test_flatten_forms_removes_acroform() {
+    use lopdf::Object;
+    
+    let input = generate_pdf_with_form_field();
+    
+    let (output, report) = compress_pdf_with_settings_reported(&input, &CompressionSettings::default()).unwrap();
+    assert_eq!(report.flattened_form_fields, 0);
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    assert!(doc.catalog().unwrap().has(b"AcroForm"), "Forms are kept unless flattening is asked for");
+    
+    let settings = CompressionSettings { flatten_forms: true, ..Default::default() };
+    let (output, report) = compress_pdf_with_settings_reported(&input, &settings).unwrap();
+    assert_eq!(report.flattened_form_fields, 1);
+    
+    let doc = lopdf::Document::load_mem(&output).unwrap();
+    assert!(!doc.catalog().unwrap().has(b"AcroForm"));
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let annots = doc.get_page_annotations(page_id);
+    assert_eq!(annots.len(), 1, "Only the link annotation should be left");
+    assert_eq!(annots[0].get(b"Subtype").unwrap().as_name().unwrap(), b"Link");
+    assert!(!doc.objects.values().any(|obj| {
+        obj.as_dict().is_ok_and(|dict| dict.get(b"Subtype").ok() == Some(&Object::Name(b"Widget".to_vec())))
+    }), "The field dictionary should be pruned");
+    
+    // The appearance is now a form XObject drawn over the widget's Rect
+    let content = String::from_utf8(doc.get_page_content(page_id).unwrap()).unwrap();
+    assert!(content.contains("(Form) Tj"), "Original content is kept");
+    assert!(content.contains("1 0 0 1 100 600 cm /FlatForm"), "Appearance drawn at the widget: {}", content);
+    let (resources, _) = doc.get_page_resources(page_id);
+    let xobjects = resources.unwrap().get(b"XObject").unwrap().as_dict().unwrap();
+    let (_, appearance) = xobjects.iter().next().unwrap();
+    let appearance = doc.get_object(appearance.as_reference().unwrap()).unwrap().as_stream().unwrap();
+    assert_eq!(appearance.dict.get(b"Subtype").unwrap().as_name().unwrap(), b"Form");
+}
+
+#[test]
+fn test_concurrent_compression() {
+    use std::thread;
+    
+    let handles: Vec<_> = (0..4).map(|i| {
+        thread::spawn(move || {
+            let input = if i % 2 == 0 {
+                generate_minimal_pdf()
+            } else {
+                generate_jpeg_image(200, 200)
+            };
+            
+            if i % 2 == 0 {
+                compress_pdf_bytes(&input, 75)
+            } else {
+                compress_image_bytes(&input, 75, None).map(|(data, _)| data)
+            }
+        })
+    }).collect();
+    
+    for (i, handle) in handles.into_iter().enumerate() {
+        let result = handle.join();
+        assert!(result.is_ok(), "Thread {} should not panic", i);
+        assert!(result.unwrap().is_ok(), "Thread {} compression should succeed", i);
+    }
+}
+
+#[test]
+fn test_parallel_stream_compression_no_lock_contention() {
+    // Test that parallel stream compression uses atomics instead of mutexes
+    // This test compresses a PDF with multiple streams to verify no lock contention
+    let pdf_with_image = generate_pdf_with_image();
+    
+    // Run multiple times to stress test the parallel processing
+    for _ in 0..3 {
+        let result = compress_pdf_bytes(&pdf_with_image, 75);
+        assert!(result.is_ok(), "Parallel stream compression should succeed");
+        
+        let compressed = result.unwrap();
+        assert!(!compressed.is_empty());
+        assert!(compressed.starts_with(b"%PDF"));
+    }
+    
+    // The fact that this completes quickly without deadlocks confirms
+    // that atomic operations are being used instead of mutex locks
+    println!("✓ Parallel stream compression uses lock-free atomic operations");
+}
+
+#[test]
+fn test_quality_presets_map_to_settings() {
+    let expected = [
+        (QualityPreset::Screen, 90, 72, Some(1280)),
+        (QualityPreset::Ebook, 75, 150, Some(2048)),
+        (QualityPreset::Printer, 40, 300, Some(4096)),
+        (QualityPreset::Prepress, 20, 300, None),
+    ];
+    for (preset, level, dpi, max_dimension) in expected {
+        let settings = preset.settings();
+        assert_eq!(settings.quality, compression_level_to_quality(level), "{:?}", preset);
+        assert_eq!(settings.target_dpi, Some(dpi), "{:?}", preset);
+        assert_eq!(settings.max_dimension, max_dimension, "{:?}", preset);
+        assert!(!settings.force_grayscale, "{:?} should keep colour", preset);
+        
+        let name = format!("{:?}", preset).to_lowercase();
+        assert_eq!(name.parse::<QualityPreset>(), Ok(preset));
+        assert_eq!(format!("/{}", name.to_uppercase()).parse::<QualityPreset>(), Ok(preset));
+    }
+    assert!("default".parse::<QualityPreset>().is_err());
+    
+    // Other fields set on the builder survive the preset
+    let settings = CompressionSettings::builder().lossless(true).preset(QualityPreset::Ebook).build();
+    assert!(settings.lossless);
+}
+
+#[test]
+fn test_screen_preset_output_is_smaller_than_prepress() {
+    let input = generate_scanned_pdf(2, 1600, 2000);
+    let compress = |preset: QualityPreset| {
+        let settings = CompressionSettings { stream_timeout_ms: 60_000, ..preset.settings() };
+        compress_pdf_with_settings_reported(&input, &settings).expect("Compression should succeed")
+    };
+    
+    let (screen, screen_report) = compress(QualityPreset::Screen);
+    let (prepress, prepress_report) = compress(QualityPreset::Prepress);
+    println!("Screen: {} bytes, prepress: {} bytes", screen.len(), prepress.len());
+    assert!(screen.len() * 4 < prepress.len(), "Screen ({}) should be far smaller than prepress ({})", screen.len(), prepress.len());
+    
+    // A full US Letter page at 72 DPI is 792 pixels tall
+    assert!(screen_report.images.iter().all(|image| image.output_height <= 792));
+    assert!(prepress_report.images.iter().all(|image| image.output_width == 1600), "Prepress should keep scan resolution");
+}
+
+#[test]
+fn test_extract_images_returns_each_image_with_placement() {
+    let images = extract_images(&generate_pdf_with_image()).expect("Extraction should succeed");
+    assert_eq!(images.len(), 1);
+    let image = &images[0];
+    assert_eq!((image.width, image.height), (10, 10));
+    assert_eq!(image.extension, "png");
+    assert_eq!(image.pages, [1]);
+    assert_eq!(image.placed_size, Some((100.0, 100.0)));
+    
+    let decoded = image::load_from_memory_with_format(&image.data, image::ImageFormat::Png).expect("Should be a PNG");
+    assert_eq!((decoded.width(), decoded.height()), (10, 10));
+    assert_eq!(decoded.to_rgb8().get_pixel(5, 5).0, [255, 0, 0]);
+    
+    // JPEG data comes out exactly as stored
+    let jpeg = generate_jpeg_image(64, 48);
+    let mut dict = image_xobject_dict(64, 48, "DeviceRGB");
+    dict.set("Filter", lopdf::Object::Name(b"DCTDecode".to_vec()));
+    let pdf = generate_pdf_with_image_xobject(lopdf::Stream::new(dict, jpeg.clone()));
+    let images = extract_images(&pdf).unwrap();
+    assert_eq!((images[0].extension, images[0].width, images[0].height), ("jpg", 64, 48));
+    assert_eq!(images[0].data, jpeg);
+}
+
+#[test]
+fn test_no_resize_keeps_image_dimensions() {
+    let input = generate_scanned_pdf(1, 2000, 2000);
+    let level_90 = CompressionSettings { quality: compression_level_to_quality(90), stream_timeout_ms: 60_000, ..Default::default() };
+    
+    let (_, report) = compress_pdf_with_settings_reported(&input, &level_90).expect("Compression should succeed");
+    assert!(report.images[0].output_width < 2000, "Level 90 should downsample without no_resize");
+    
+    let settings = CompressionSettings { no_resize: true, ..level_90 };
+    let (output, report) = compress_pdf_with_settings_reported(&input, &settings).expect("Compression should succeed");
+    let image = &report.images[0];
+    assert_eq!((image.output_width, image.output_height), (2000, 2000));
+    assert!(!image.downsampled);
+    assert_eq!(image.output_filter.as_deref(), Some("DCTDecode"), "Pixels should still be re-encoded");
+    assert!(output.len() < input.len() / 4);
+}
@@ -0,0 +1,54 @@
+// Page rendering tests - need the pdf-render feature and the pdfium library
+// (PDFIUM_LIB_PATH pointing at its directory, or installed system-wide)
+#![cfg(feature = "pdf-render")]
+
+mod common;
+
+use common::*;
+use PDFcompressor::pdf_pages_to_images;
+
+#[test]
+fn test_pdf_pages_to_images_renders_one_png_per_page() {
+    let pdf = generate_minimal_pdf();
+    let pages = pdf_pages_to_images(&pdf, 75, "png").expect("Rendering should succeed");
+    
+    assert_eq!(pages.len(), 1);
+    let (image, extension) = &pages[0];
+    assert_eq!(extension, "png");
+    assert!(!image.is_empty());
+    let decoded = image::load_from_memory_with_format(image, image::ImageFormat::Png).expect("Page should be a valid PNG");
+    assert!(decoded.width() > 0 && decoded.height() > 0);
+}
+
+#[test]
+fn test_pdf_pages_to_images_rejects_unknown_formats() {
+    let error = pdf_pages_to_images(&generate_minimal_pdf(), 75, "gif").unwrap_err();
+    assert!(error.contains("gif"), "{}", error);
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_http_pdf_to_images_returns_a_zip() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use PDFcompressor::api::{create_router_with_state, AppState};
+    use tower::ServiceExt;
+    
+    let pdf = generate_scanned_pdf(2, 40, 40);
+    let (content_type, body) = multipart_body(&[("file", Some("doc.pdf"), &pdf), ("format", None, b"webp")]);
+    let request = Request::builder()
+        .method("POST")
+        .uri("/api/pdf-to-images")
+        .header("Content-Type", content_type)
+        .body(Body::from(body))
+        .unwrap();
+    let response = create_router_with_state(AppState::new(2)).oneshot(request).await.unwrap();
+    
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "application/zip");
+    assert_eq!(response.headers()["X-Page-Count"], "2");
+    let archive = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(archive.to_vec())).expect("Response should be a zip");
+    assert_eq!(archive.len(), 2);
+    assert_eq!(archive.by_index(1).unwrap().name(), "page-002.webp");
+}